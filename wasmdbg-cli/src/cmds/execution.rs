@@ -1,6 +1,6 @@
 use super::Debugger;
-use wasmdbg::vm::{CodePosition, Trap};
-use wasmdbg::{Breakpoint, BreakpointTrigger, Value};
+use wasmdbg::vm::{CodePosition, Trap, WatchpointLocation};
+use wasmdbg::{Breakpoint, BreakpointTrigger};
 
 use super::context;
 use super::{CmdArg, CmdArgOptionExt, CmdResult, Command, Commands};
@@ -32,6 +32,13 @@ pub fn add_cmds(commands: &mut Commands) {
                 .help("Set a breakpoint at the specified function and instruction. If no instruction is specified the breakpoint is set to the function start. When execution reaches a breakpoint it will pause.")
             .requires_file()
         );
+    commands.add(
+        Command::new("fbreak", cmd_function_break)
+            .takes_args("FUNC_INDEX:u32")
+            .description("Set a breakpoint on function entry")
+            .help("Pause every time the function with index FUNC_INDEX is called, including recursive and indirect calls.")
+            .requires_file(),
+    );
     commands.add(
         Command::new_subcommand("watch")
             .description("Set a watchpoint")
@@ -56,6 +63,19 @@ pub fn add_cmds(commands: &mut Commands) {
             .help("Delete the breakpoint with the specified index.")
             .requires_file(),
     );
+    commands.add(
+        Command::new("enable", cmd_enable)
+            .description("Enable a breakpoint")
+            .takes_args("BREAKPOINT_INDEX:u32")
+            .requires_file(),
+    );
+    commands.add(
+        Command::new("disable", cmd_disable)
+            .description("Disable a breakpoint")
+            .takes_args("BREAKPOINT_INDEX:u32")
+            .help("Disabled breakpoints are skipped but keep their index and hit count.")
+            .requires_file(),
+    );
     commands.add(
         Command::new("continue", cmd_continue)
             .alias("c")
@@ -102,34 +122,10 @@ fn cmd_start(dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
 }
 
 fn cmd_call(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
-    let module = dbg.get_file()?.module();
     let func_index = args[0].as_u32();
-    let args = &args[1..];
-
-    let func_type = module
-        .get_func(func_index)
-        .ok_or_else(|| format_err!("No function with index {}", func_index))?
-        .func_type();
-
-    if args.len() != func_type.params().len() {
-        bail!(
-            "Invalid number of arguments. Function #{} takes {} args but got {}",
-            func_index,
-            func_type.params().len(),
-            args.len()
-        );
-    }
-
-    let mut args_parsed = Vec::new();
-    for (arg, value_type) in args.iter().zip(func_type.params().iter()) {
-        if let Some(arg_parsed) = Value::from_str(&arg.as_string(), *value_type) {
-            args_parsed.push(arg_parsed);
-        } else {
-            bail!("Failed to parse argument \"{}\" as {}", arg.as_string(), value_type);
-        }
-    }
-
-    print_run_result(dbg.call(func_index, &args_parsed)?, dbg)
+    let args: Vec<String> = args[1..].iter().map(CmdArg::as_string).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    print_run_result(dbg.call_with_str_args(func_index, &args)?, dbg)
 }
 
 fn cmd_reset(dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
@@ -149,6 +145,13 @@ fn cmd_break(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
     Ok(())
 }
 
+fn cmd_function_break(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
+    let func_index = args[0].as_u32();
+    let index = dbg.add_function_breakpoint(func_index)?;
+    println!("Set breakpoint {} on entry of function {}", index, func_index);
+    Ok(())
+}
+
 fn cmd_watch_memory(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
     let addr = args[0].as_u32();
     let trigger = match args.get(1) {
@@ -174,7 +177,11 @@ fn cmd_watch_global(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
         },
         None => BreakpointTrigger::ReadWrite,
     };
-    let index = dbg.add_breakpoint(Breakpoint::Global(trigger, index))?;
+    let index = dbg.add_breakpoint(Breakpoint::Global {
+        trigger,
+        index,
+        cond: None,
+    })?;
     println!("Set watchpoint {} at global {}", index, index);
     Ok(())
 }
@@ -193,6 +200,16 @@ fn cmd_delete(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
     Ok(())
 }
 
+fn cmd_enable(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
+    dbg.set_breakpoint_enabled(args[0].as_u32(), true)?;
+    Ok(())
+}
+
+fn cmd_disable(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
+    dbg.set_breakpoint_enabled(args[0].as_u32(), false)?;
+    Ok(())
+}
+
 fn cmd_continue(dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
     print_run_result(dbg.continue_execution()?, dbg)
 }
@@ -218,10 +235,18 @@ fn cmd_next(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
 }
 
 fn cmd_finish(dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
-    if let Some(trap) = dbg.execute_step_out()? {
-        print_run_result(trap, dbg)
-    } else {
-        context::print_context(dbg)
+    let result = dbg.finish()?;
+    match result.trap {
+        Some(Trap::ExecutionFinished) | None => {
+            for value in &result.return_values {
+                println!("Returned {}", value);
+            }
+            match result.trap {
+                Some(trap) => print_run_result(trap, dbg),
+                None => context::print_context(dbg),
+            }
+        }
+        Some(trap) => print_run_result(trap, dbg),
     }
 }
 
@@ -241,8 +266,32 @@ fn print_run_result(trap: Trap, dbg: &mut Debugger) -> CmdResult {
         Trap::WatchpointReached(index) => {
             context::print_context(dbg)?;
             println!("Reached watchpoint {}", index);
+            match dbg.last_watchpoint_hit().map(|hit| hit.location) {
+                Some(WatchpointLocation::Global {
+                    old_value, new_value, ..
+                }) => {
+                    println!("Old value: {}", old_value);
+                    println!("New value: {}", new_value);
+                }
+                Some(WatchpointLocation::Memory { address, size, write }) => {
+                    let access = if write { "Write" } else { "Read" };
+                    println!("{} of {} bytes at 0x{:08x}", access, size, address);
+                }
+                None => (),
+            }
+        }
+        Trap::Caught(trap) => {
+            context::print_context(dbg)?;
+            println!("Stopped before trap: {}", trap);
+            println!("Continue to deliver the trap");
+        }
+        _ => {
+            match dbg.trap_position()? {
+                Some(pos) => println!("Trap at {}: {}", pos, trap),
+                None => println!("Trap: {}", trap),
+            }
+            println!("Cause: {}", trap.explanation());
         }
-        _ => println!("Trap: {}", trap),
     }
     Ok(())
 }
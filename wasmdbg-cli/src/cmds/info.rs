@@ -37,6 +37,11 @@ pub fn add_cmds(commands: &mut Commands) {
             .add_subcommand(Command::new("start", cmd_info_start).description("Print start section"))
             .add_subcommand(Command::new("elements", cmd_info_elements).description("Print element section"))
             .add_subcommand(Command::new("data", cmd_info_data).description("Print data section"))
+            .add_subcommand(
+                Command::new("all", cmd_info_all)
+                    .description("Print the whole state of the current wasm instance")
+                    .requires_running(),
+            )
             .add_subcommand(
                 Command::new("custom", cmd_info_custom)
                     .takes_args("[INDEX:u32|NAME:str]")
@@ -156,7 +161,14 @@ fn cmd_info_break(dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
         match breakpoint {
             Breakpoint::Code(pos) => println!("{}\tbreakpoint\t{}\t{}", i, pos.func_index, pos.instr_index),
             Breakpoint::Memory(trigger, addr) => println!("{}\twatchpoint\tMemory\t0x{:>08x}\t{}", i, addr, trigger),
-            Breakpoint::Global(trigger, index) => println!("{}\twatchpoint\tGlobal\t{}\t{}", i, index, trigger),
+            Breakpoint::MemoryRange { start, len, access } => {
+                println!("{}\twatchpoint\tMemory\t0x{:>08x}+{}\t{}", i, start, len, access)
+            }
+            Breakpoint::Global { trigger, index, cond } => match cond {
+                Some(cond) => println!("{}\twatchpoint\tGlobal\t{}\t{} if {}", i, index, trigger, cond),
+                None => println!("{}\twatchpoint\tGlobal\t{}\t{}", i, index, trigger),
+            },
+            Breakpoint::Function(index) => println!("{}\tbreakpoint\tFunction\t{}", i, index),
         }
     }
 
@@ -327,6 +339,11 @@ fn cmd_info_custom(_dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
     Ok(())
 }
 
+fn cmd_info_all(dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
+    println!("{}", dbg.dump_state()?);
+    Ok(())
+}
+
 fn cmd_status(dbg: &mut Debugger, _args: &[CmdArg]) -> CmdResult {
     if let Some(trap) = dbg.get_vm()?.trap() {
         if let Trap::ExecutionFinished = trap {
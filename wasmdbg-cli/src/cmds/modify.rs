@@ -3,6 +3,7 @@ use std::convert::TryFrom;
 use super::Debugger;
 use bwasm::ValueType;
 use wasmdbg::value::Integer;
+use wasmdbg::Value;
 
 use super::context;
 use super::{CmdArg, CmdResult, Command, Commands};
@@ -99,12 +100,7 @@ fn cmd_set_stack(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
 
     ensure!(index < stack.len(), "Index out of range");
 
-    match stack[index].value_type() {
-        ValueType::I32 => stack[index] = (i64::from_str_with_radix(&val)? as u32).into(),
-        ValueType::I64 => stack[index] = (i128::from_str_with_radix(&val)? as u64).into(),
-        ValueType::F32 => stack[index] = val.parse::<f32>()?.into(),
-        ValueType::F64 => stack[index] = val.parse::<f64>()?.into(),
-    }
+    stack[index] = parse_like(stack[index], &val)?;
 
     context::print_context(dbg)
 }
@@ -116,12 +112,7 @@ fn cmd_set_local(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
 
     ensure!(index < locals.len(), "Index out of range");
 
-    match locals[index].value_type() {
-        ValueType::I32 => locals[index] = (i64::from_str_with_radix(&val)? as u32).into(),
-        ValueType::I64 => locals[index] = (i128::from_str_with_radix(&val)? as u64).into(),
-        ValueType::F32 => locals[index] = val.parse::<f32>()?.into(),
-        ValueType::F64 => locals[index] = val.parse::<f64>()?.into(),
-    }
+    locals[index] = parse_like(locals[index], &val)?;
 
     context::print_context(dbg)
 }
@@ -133,12 +124,22 @@ fn cmd_set_global(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
 
     ensure!(index < globals.len(), "Index out of range");
 
-    match globals[index].value_type() {
-        ValueType::I32 => globals[index] = (i64::from_str_with_radix(&val)? as u32).into(),
-        ValueType::I64 => globals[index] = (i128::from_str_with_radix(&val)? as u64).into(),
-        ValueType::F32 => globals[index] = val.parse::<f32>()?.into(),
-        ValueType::F64 => globals[index] = val.parse::<f64>()?.into(),
-    }
+    globals[index] = parse_like(globals[index], &val)?;
 
     Ok(())
 }
+
+/// Parses `val` as a value of the same type as `old`.
+fn parse_like(old: Value, val: &str) -> anyhow::Result<Value> {
+    Ok(match old.value_type() {
+        Some(ValueType::I32) => (i64::from_str_with_radix(val)? as u32).into(),
+        Some(ValueType::I64) => (i128::from_str_with_radix(val)? as u64).into(),
+        Some(ValueType::F32) => val.parse::<f32>()?.into(),
+        Some(ValueType::F64) => val.parse::<f64>()?.into(),
+        None => parse_v128(val)?,
+    })
+}
+
+fn parse_v128(val: &str) -> anyhow::Result<Value> {
+    Value::parse_v128(val).ok_or_else(|| format_err!("Invalid v128 value: {}", val))
+}
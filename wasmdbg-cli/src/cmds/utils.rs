@@ -35,6 +35,9 @@ fn cmd_load(dbg: &mut Debugger, args: &[CmdArg]) -> CmdResult {
         println!("{}", error);
     } else {
         println!("Loaded \"{}\"", file_path);
+        for warning in dbg.load_warnings() {
+            println!("Warning: {}", warning);
+        }
     }
     Ok(())
 }
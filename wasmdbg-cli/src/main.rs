@@ -32,6 +32,9 @@ fn main() {
             println!("{}", error);
         } else {
             println!("Loaded \"{}\"", file_path);
+            for warning in dbg.load_warnings() {
+                println!("Warning: {}", warning);
+            }
         }
     }
 
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+use wasmdbg::vm::Trap;
+use wasmdbg::{DebuggerResult, WasiDebugger};
+
+use crate::protocol::write_message;
+
+/// The program runs on a single thread
+const THREAD_ID: u64 = 1;
+/// `variablesReference` of the globals. Locals of frame `n` use `LOCALS_REFERENCE + n`.
+const GLOBALS_REFERENCE: u64 = 1;
+const LOCALS_REFERENCE: u64 = 2;
+
+/// What to do once the response to a request is sent
+enum Action {
+    SendInitialized,
+    Start,
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+}
+
+type StepFn = fn(&mut WasiDebugger) -> DebuggerResult<Option<Trap>>;
+
+pub struct Adapter<W: Write> {
+    out: W,
+    seq: u64,
+    dbg: WasiDebugger,
+    /// The function to call if the module has no start function
+    entry: Option<u32>,
+    stop_on_entry: bool,
+    /// Breakpoint indices set for each source file by `setBreakpoints`
+    source_breakpoints: HashMap<String, Vec<u32>>,
+}
+
+impl<W: Write> Adapter<W> {
+    pub fn new(out: W) -> Self {
+        let mut dbg = WasiDebugger::new();
+        // stdout belongs to the protocol, the output of the program is sent as events instead
+        dbg.capture_output();
+        Adapter {
+            out,
+            seq: 0,
+            dbg,
+            entry: None,
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+        }
+    }
+
+    /// Handles one message from the client. Returns `false` once the session is over.
+    pub fn handle(&mut self, message: &Value) -> io::Result<bool> {
+        if message["type"] != "request" {
+            return Ok(true);
+        }
+        let command = message["command"].as_str().unwrap_or_default();
+        let args = &message["arguments"];
+        let mut action = None;
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+            })),
+            "launch" => self.launch(args).map(|body| {
+                action = Some(Action::SendInitialized);
+                body
+            }),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "configurationDone" => {
+                action = Some(Action::Start);
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => self.stack_trace().map_err(|err| err.to_string()),
+            "scopes" => Ok(self.scopes(args)),
+            "variables" => self.variables(args).map_err(|err| err.to_string()),
+            "evaluate" => self.evaluate(args),
+            "continue" => {
+                action = Some(Action::Continue);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                action = Some(Action::Next);
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                action = Some(Action::StepIn);
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                action = Some(Action::StepOut);
+                Ok(Value::Null)
+            }
+            "disconnect" => {
+                self.respond(message, command, Ok(Value::Null))?;
+                return Ok(false);
+            }
+            _ => Err(format!("Unsupported request \"{}\"", command)),
+        };
+        self.respond(message, command, result)?;
+        if let Some(action) = action {
+            self.perform(action)?;
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or_else(|| "Missing \"program\" in the launch arguments".to_owned())?;
+        self.dbg
+            .load_file(program)
+            .map_err(|err| format!("Failed to load \"{}\": {}", program, err))?;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        // WASI commands export `_start` instead of having a start function
+        let entry = match args["entry"].as_str() {
+            Some(entry) => Some(entry),
+            None if self.dbg.start_function().map_err(|err| err.to_string())?.is_none() => Some("_start"),
+            None => None,
+        };
+        self.entry = match entry {
+            Some(entry) => Some(self.dbg.find_function(entry).map_err(|err| err.to_string())?),
+            None => None,
+        };
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or_else(|| "Missing \"source.path\"".to_owned())?;
+        for index in self.source_breakpoints.remove(path).unwrap_or_default() {
+            self.dbg.delete_breakpoint(index).map_err(|err| err.to_string())?;
+        }
+        let lines = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut indices = Vec::new();
+        let mut breakpoints = Vec::new();
+        for line in lines.iter().filter_map(|breakpoint| breakpoint["line"].as_u64()) {
+            match self.dbg.add_breakpoint_at_line(path, line as u32) {
+                Ok(added) => {
+                    indices.extend(added.iter().map(|(index, _)| *index));
+                    let actual_line = added
+                        .first()
+                        .and_then(|(_, pos)| self.dbg.source_location(*pos))
+                        .map_or(line as u32, |location| location.line);
+                    breakpoints.push(json!({ "verified": true, "line": actual_line }));
+                }
+                Err(err) => breakpoints.push(json!({ "verified": false, "line": line, "message": err.to_string() })),
+            }
+        }
+        self.source_breakpoints.insert(path.to_owned(), indices);
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self) -> DebuggerResult<Value> {
+        let frames: Vec<Value> = self
+            .dbg
+            .backtrace_detailed()?
+            .into_iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let name = frame.func_name.unwrap_or_else(|| format!("func[{}]", frame.func_index));
+                match frame.source {
+                    Some(location) => json!({
+                        "id": id,
+                        "name": name,
+                        "source": { "path": location.file },
+                        "line": location.line,
+                        "column": location.column.max(1),
+                    }),
+                    None => json!({
+                        "id": id,
+                        "name": format!("{} @ {}", name, frame.instr_index),
+                        // Lines and columns are 1-based
+                        "line": 1,
+                        "column": 1,
+                        "presentationHint": "subtle",
+                    }),
+                }
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn scopes(&self, args: &Value) -> Value {
+        let frame_id = args["frameId"].as_u64().unwrap_or(0);
+        json!({ "scopes": [
+            { "name": "Locals", "variablesReference": LOCALS_REFERENCE + frame_id, "expensive": false },
+            { "name": "Globals", "variablesReference": GLOBALS_REFERENCE, "expensive": false },
+        ] })
+    }
+
+    fn variables(&self, args: &Value) -> DebuggerResult<Value> {
+        let reference = args["variablesReference"].as_u64().unwrap_or(0);
+        let variables: Vec<Value> = if reference == GLOBALS_REFERENCE {
+            self.dbg
+                .globals()?
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    let name = self.dbg.global_name(index as u32).cloned();
+                    variable(name.unwrap_or_else(|| format!("g{}", index)), value)
+                })
+                .collect()
+        } else if reference >= LOCALS_REFERENCE {
+            self.dbg
+                .frame_locals((reference - LOCALS_REFERENCE) as usize)?
+                .locals
+                .into_iter()
+                .enumerate()
+                .map(|(index, (name, value))| variable(name.unwrap_or_else(|| format!("l{}", index)), &value))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let expression = args["expression"].as_str().unwrap_or_default();
+        let value = self.dbg.evaluate(expression).map_err(|err| err.to_string())?;
+        Ok(json!({
+            "result": value.to_string(),
+            "type": value.type_name(),
+            "variablesReference": 0,
+        }))
+    }
+
+    fn perform(&mut self, action: Action) -> io::Result<()> {
+        let pause_reason = match action {
+            Action::Start => "entry",
+            _ => "step",
+        };
+        let result = match action {
+            Action::SendInitialized => return self.event("initialized", Value::Null),
+            Action::Start => self.start(),
+            Action::Continue => self.dbg.continue_execution().map(Some),
+            Action::Next => self.step_line(WasiDebugger::execute_step_over),
+            Action::StepIn => self.step_line(WasiDebugger::step_into),
+            Action::StepOut => self.dbg.execute_step_out(),
+        };
+        let output = self.dbg.take_output();
+        if !output.is_empty() {
+            self.event("output", json!({ "category": "stdout", "output": output }))?;
+        }
+        match result {
+            Ok(None) => self.stopped(pause_reason, None),
+            Ok(Some(Trap::BreakpointReached(_))) => self.stopped("breakpoint", None),
+            Ok(Some(Trap::WatchpointReached(_))) => self.stopped("data breakpoint", None),
+            Ok(Some(Trap::ExecutionFinished)) => self.exited(0),
+            Ok(Some(Trap::ProcExit(code))) => self.exited(code),
+            Ok(Some(trap)) => self.stopped("exception", Some(trap.to_string())),
+            Err(err) => self.event(
+                "output",
+                json!({ "category": "stderr", "output": format!("{}\n", err) }),
+            ),
+        }
+    }
+
+    /// `None` if paused on the first instruction of the program
+    fn start(&mut self) -> DebuggerResult<Option<Trap>> {
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None if self.stop_on_entry => return self.dbg.start(),
+            None => return self.dbg.run().map(Some),
+        };
+        if !self.stop_on_entry {
+            return self.dbg.call(entry, &[]).map(Some);
+        }
+        let index = self.dbg.add_function_breakpoint(entry)?;
+        let trap = self.dbg.call(entry, &[])?;
+        self.dbg.delete_breakpoint(index)?;
+        Ok(match trap {
+            Trap::BreakpointReached(reached) if reached == index => None,
+            trap => Some(trap),
+        })
+    }
+
+    /// Steps until execution reaches a different source line. Without debug info at the
+    /// current position this is a single step.
+    fn step_line(&mut self, step: StepFn) -> DebuggerResult<Option<Trap>> {
+        let start = self.current_line()?;
+        loop {
+            if let Some(trap) = step(&mut self.dbg)? {
+                return Ok(Some(trap));
+            }
+            let line = self.current_line()?;
+            if start.is_none() || (line.is_some() && line != start) {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn current_line(&self) -> DebuggerResult<Option<(String, u32)>> {
+        let pos = self.dbg.get_vm()?.ip();
+        Ok(self
+            .dbg
+            .source_location(pos)
+            .map(|location| (location.file, location.line)))
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = Value::String(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn exited(&mut self, code: i32) -> io::Result<()> {
+        self.event("exited", json!({ "exitCode": code }))?;
+        self.event("terminated", Value::Null)
+    }
+
+    fn respond(&mut self, request: &Value, command: &str, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => (),
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = Value::String(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.out, &message)
+    }
+}
+
+fn variable(name: String, value: &wasmdbg::Value) -> Value {
+    json!({
+        "name": name,
+        "value": value.to_string(),
+        "type": value.type_name(),
+        "variablesReference": 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::read_message;
+    use std::io::Cursor;
+
+    // The start function: i32.const 7, local.set 0, end
+    const LOCAL_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00,
+        0x08, 0x01, 0x00, 0x0a, 0x0a, 0x01, 0x08, 0x01, 0x01, 0x7f, 0x41, 0x07, 0x21, 0x00, 0x0b,
+    ];
+
+    fn request(adapter: &mut Adapter<Vec<u8>>, command: &str, arguments: Value) -> Vec<Value> {
+        let message = json!({ "seq": 1, "type": "request", "command": command, "arguments": arguments });
+        assert!(adapter.handle(&message).unwrap());
+        let mut input = Cursor::new(std::mem::take(&mut adapter.out));
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut input).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn step_through_start_function() {
+        let path = std::env::temp_dir().join(format!("wasmdbg-dap-{}.wasm", std::process::id()));
+        std::fs::write(&path, LOCAL_WASM).unwrap();
+        let mut adapter = Adapter::new(Vec::new());
+
+        let messages = request(&mut adapter, "initialize", json!({ "adapterID": "wasmdbg" }));
+        assert_eq!(messages[0]["success"], true);
+        assert_eq!(messages[0]["body"]["supportsConfigurationDoneRequest"], true);
+
+        let program = path.to_str().unwrap();
+        let messages = request(
+            &mut adapter,
+            "launch",
+            json!({ "program": program, "stopOnEntry": true }),
+        );
+        assert_eq!(messages[0]["success"], true);
+        assert_eq!(messages[1]["event"], "initialized");
+
+        let messages = request(&mut adapter, "configurationDone", Value::Null);
+        assert_eq!(messages[1]["event"], "stopped");
+        assert_eq!(messages[1]["body"]["reason"], "entry");
+
+        let messages = request(&mut adapter, "stackTrace", json!({ "threadId": THREAD_ID }));
+        let frame = &messages[0]["body"]["stackFrames"][0];
+        assert_eq!(frame["name"], "func[0] @ 0");
+        assert_eq!(frame["line"], 1);
+        assert_eq!(frame["presentationHint"], "subtle");
+
+        // Without debug info every step is one instruction
+        request(&mut adapter, "next", json!({ "threadId": THREAD_ID }));
+        let messages = request(&mut adapter, "next", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages[1]["body"]["reason"], "step");
+
+        let messages = request(&mut adapter, "scopes", json!({ "frameId": 0 }));
+        let locals = messages[0]["body"]["scopes"][0]["variablesReference"].clone();
+        let messages = request(&mut adapter, "variables", json!({ "variablesReference": locals }));
+        let variable = &messages[0]["body"]["variables"][0];
+        assert_eq!(variable["name"], "l0");
+        assert_eq!(variable["value"], wasmdbg::Value::I32(7).to_string());
+
+        let messages = request(&mut adapter, "evaluate", json!({ "expression": "l0 == 7" }));
+        assert_eq!(messages[0]["body"]["result"], wasmdbg::Value::I32(1).to_string());
+
+        let messages = request(&mut adapter, "continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(messages[1]["event"], "exited");
+        assert_eq!(messages[1]["body"]["exitCode"], 0);
+        assert_eq!(messages[2]["event"], "terminated");
+
+        let disconnect = json!({ "seq": 2, "type": "request", "command": "disconnect" });
+        assert!(!adapter.handle(&disconnect).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_requests() {
+        let mut adapter = Adapter::new(Vec::new());
+        let messages = request(&mut adapter, "launch", json!({ "program": "/nonexistent.wasm" }));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["success"], false);
+
+        let messages = request(&mut adapter, "attach", Value::Null);
+        assert_eq!(messages[0]["success"], false);
+        assert_eq!(messages[0]["message"], "Unsupported request \"attach\"");
+
+        let messages = request(&mut adapter, "stackTrace", Value::Null);
+        assert_eq!(messages[0]["success"], false);
+    }
+}
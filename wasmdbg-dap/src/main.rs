@@ -0,0 +1,17 @@
+mod adapter;
+mod protocol;
+
+use adapter::Adapter;
+use std::io;
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut adapter = Adapter::new(io::stdout());
+    while let Some(message) = protocol::read_message(&mut input)? {
+        if !adapter.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! Framing of Debug Adapter Protocol messages: a `Content-Length` header, an empty line, then
+//! the JSON body.
+
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The next message, or `None` once the input is closed.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                let len = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|err| invalid_data(err.to_string()))?;
+                content_length = Some(len);
+            }
+        }
+    }
+    let mut body = vec![0; content_length.unwrap()];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| invalid_data(err.to_string()))
+}
+
+pub fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let first = json!({"seq": 1, "type": "request", "command": "initialize"});
+        let second = json!({"seq": 2, "type": "request", "command": "launch", "arguments": {"program": "ü.wasm"}});
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &first).unwrap();
+        write_message(&mut buffer, &second).unwrap();
+
+        let mut input = Cursor::new(buffer);
+        assert_eq!(read_message(&mut input).unwrap(), Some(first));
+        assert_eq!(read_message(&mut input).unwrap(), Some(second));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut input = Cursor::new(b"Content-Length: many\r\n\r\n{}".to_vec());
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,81 +1,519 @@
-use std::thread;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
 
-use crate::grpc::wasm_debugger_grpc::{self, wasm_dap_client::WasmDapClient, RunImportFunctionRequest};
-use tokio::runtime;
+use crate::grpc::wasm_debugger_grpc::{
+    self, wasm_dap_client::WasmDapClient, RunImportFunctionReply, RunImportFunctionRequest,
+};
+use tokio::runtime::{self, Runtime};
+use tonic::transport::Channel;
 use tonic::Request;
-use wasmdbg::vm::{import_func::ImportFunctionHandler, Trap, VMResult, VM};
+use wasmdbg::vm::import_func::{import_function_name, ImportFunctionHandler, ImportFuture};
+use wasmdbg::vm::{Trap, VMResult, VM};
+use wasmdbg::Value;
 
+type CallResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// The client DAP calls go through and, for calls from outside of a tokio runtime, the runtime
+/// they run on. Both are set up on first use.
 #[derive(Default)]
+struct DapConnection {
+    runtime: Mutex<Option<Runtime>>,
+    client: Mutex<Option<WasmDapClient<Channel>>>,
+}
+
+impl DapConnection {
+    async fn run_import_function(
+        &self,
+        dap_addr: String,
+        request: RunImportFunctionRequest,
+    ) -> CallResult<RunImportFunctionReply> {
+        let client = self.client.lock().unwrap().clone();
+        let mut client = match client {
+            Some(client) => client,
+            None => {
+                let client = WasmDapClient::connect(dap_addr).await?;
+                *self.client.lock().unwrap() = Some(client.clone());
+                client
+            }
+        };
+        Ok(client.run_import_function(Request::new(request)).await?.into_inner())
+    }
+}
+
+impl Drop for DapConnection {
+    fn drop(&mut self) {
+        // The last handler may be dropped inside the server's runtime, where blocking isn't allowed
+        if let Some(runtime) = self.runtime.get_mut().unwrap().take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Forwards import calls to a DAP server. Clones share the connection, so import calls of all
+/// instances reuse one runtime and one client.
+#[derive(Default, Clone)]
 pub struct GrpcImportHandler {
     dap_addr: String,
+    connection: Arc<DapConnection>,
 }
 
 impl GrpcImportHandler {
+    /// `addr` defaults to http if it has no scheme, e.g. `[::1]:50052`.
     pub fn set_dap_addr(&mut self, addr: &str) {
-        self.dap_addr = String::from(addr);
+        self.dap_addr = if addr.contains("://") {
+            String::from(addr)
+        } else {
+            format!("http://{}", addr)
+        };
+        self.connection = Arc::default();
+    }
+
+    pub fn dap_addr(&self) -> &str {
+        &self.dap_addr
+    }
+
+    async fn run_import_function(&self, request: RunImportFunctionRequest) -> CallResult<RunImportFunctionReply> {
+        self.connection
+            .run_import_function(self.dap_addr.clone(), request)
+            .await
+    }
+
+    /// The runtime of the calling thread, or one owned by the connection for threads outside of
+    /// any runtime.
+    fn runtime_handle(&self) -> CallResult<runtime::Handle> {
+        if let Ok(handle) = runtime::Handle::try_current() {
+            return Ok(handle);
+        }
+        let mut runtime = self.connection.runtime.lock().unwrap();
+        if runtime.is_none() {
+            *runtime = Some(
+                runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .enable_all()
+                    .build()?,
+            );
+        }
+        Ok(runtime.as_ref().unwrap().handle().clone())
     }
 }
 
-impl ImportFunctionHandler for GrpcImportHandler {
-    fn handle_import_function(vm: &mut VM<Self>) -> VMResult<()> {
-        let func_index = vm.ip().func_index;
-        let args = vm
-            .function_stack()
-            .last()
-            .unwrap()
-            .locals
-            .iter()
-            .map(wasm_debugger_grpc::Value::from_value)
-            .collect();
-        let globals = vm.globals().iter().map(wasm_debugger_grpc::Value::from_value).collect();
-        let memory = Vec::from(vm.default_memory()?.data());
-        let dap_addr = vm.import_function_handler_mut().dap_addr.clone();
-
-        let response = thread::spawn(move || {
-            runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap()
-                .block_on(async {
-                    let mut client = match WasmDapClient::connect(dap_addr).await {
-                        Ok(client) => client,
-                        Err(err) => {
-                            eprintln!("import function call failed due to {err}");
-                            return Err(Trap::UnsupportedCallToImportedFunction(func_index));
-                        }
-                    };
-                    let request = Request::new(RunImportFunctionRequest {
-                        func_index,
-                        args,
-                        globals,
-                        memory,
-                    });
-                    let response = match client.run_import_function(request).await {
-                        Ok(response) => response,
-                        Err(err) => {
-                            eprintln!("import function call failed due to {err}");
-                            return Err(Trap::UnsupportedCallToImportedFunction(func_index));
-                        }
-                    };
-                    Ok(response)
-                })
-        })
-        .join()
-        .map_err(|_| Trap::UnsupportedCallToImportedFunction(func_index))?
-        .map_err(|_| Trap::UnsupportedCallToImportedFunction(func_index))?
-        .into_inner();
+/// The `module.field` name of the import `vm` calls.
+fn import_name(vm: &VM<GrpcImportHandler>) -> String {
+    let func_index = vm.ip().func_index;
+    match import_function_name(vm.module(), func_index) {
+        Some((module, field)) => format!("{}.{}", module, field),
+        None => format!("function {}", func_index),
+    }
+}
+
+/// The trap for a failed DAP call. The DAP server answers calls of imports it has no handler for
+/// with `NOT_FOUND` or `UNIMPLEMENTED`.
+fn call_failed(import_name: String, err: Box<dyn Error + Send + Sync>) -> Trap {
+    match err.downcast_ref::<tonic::Status>().map(tonic::Status::code) {
+        Some(tonic::Code::NotFound | tonic::Code::Unimplemented) => Trap::MissingImportHandler(import_name),
+        _ => Trap::ImportCallFailed {
+            import: import_name,
+            reason: err.to_string(),
+        },
+    }
+}
+
+/// The request for the import call `vm` is at.
+fn import_request(vm: &VM<GrpcImportHandler>) -> VMResult<RunImportFunctionRequest> {
+    let func_index = vm.ip().func_index;
+    let args = vm
+        .function_stack()
+        .last()
+        .unwrap()
+        .locals
+        .iter()
+        .map(wasm_debugger_grpc::Value::from_value)
+        .collect();
+    let globals = vm.globals().iter().map(wasm_debugger_grpc::Value::from_value).collect();
+    // Modules without a memory send an empty one
+    let memory = match vm.default_memory() {
+        Ok(memory) => Vec::from(memory.data()),
+        Err(Trap::NoMemory) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    Ok(RunImportFunctionRequest {
+        func_index,
+        args,
+        globals,
+        memory,
+    })
+}
+
+fn reply_value(import_name: &str, value: &wasm_debugger_grpc::Value) -> VMResult<Value> {
+    if value.value.is_none() {
+        return Err(Trap::ImportProtocolError(format!(
+            "{} returned a value without a type",
+            import_name
+        )));
+    }
+    Ok(value.to_value())
+}
 
-        if let Some(return_value) = response.return_value {
-            vm.value_stack_mut().push(return_value.to_value());
+fn type_list<'a>(type_names: impl Iterator<Item = &'a str>) -> String {
+    format!("[{}]", type_names.collect::<Vec<_>>().join(", "))
+}
+
+/// Checks the reply of the DAP server against the signature of the import and the globals of the
+/// module and applies it to `vm`.
+fn apply_reply(vm: &mut VM<GrpcImportHandler>, import_name: &str, response: RunImportFunctionReply) -> VMResult<()> {
+    // Check the reply before changing anything
+    let return_values = response
+        .return_value
+        .iter()
+        .chain(&response.return_values)
+        .map(|value| reply_value(import_name, value))
+        .collect::<VMResult<Vec<_>>>()?;
+    let return_types: Vec<_> = vm
+        .module()
+        .get_func(vm.ip().func_index)
+        .map(|func| func.func_type().return_type().into_iter().collect())
+        .unwrap_or_default();
+    if return_values.len() != return_types.len()
+        || return_values
+            .iter()
+            .zip(&return_types)
+            .any(|(value, value_type)| value.value_type() != Some(*value_type))
+    {
+        return Err(Trap::ImportProtocolError(format!(
+            "{} returned {}, expected {}",
+            import_name,
+            type_list(return_values.iter().map(Value::type_name)),
+            type_list(
+                return_types
+                    .iter()
+                    .map(|value_type| Value::default(*value_type).type_name())
+            )
+        )));
+    }
+    if response.globals.len() > vm.globals().len() {
+        return Err(Trap::ImportProtocolError(format!(
+            "{} returned {} globals, the module has {}",
+            import_name,
+            response.globals.len(),
+            vm.globals().len()
+        )));
+    }
+    let globals = response
+        .globals
+        .iter()
+        .map(|value| reply_value(import_name, value))
+        .collect::<VMResult<Vec<_>>>()?;
+    for (index, (value, global)) in globals.iter().zip(vm.module().globals()).enumerate() {
+        if value.value_type() != Some(global.value_type()) {
+            return Err(Trap::ImportProtocolError(format!(
+                "{} returned an {} for global {} of type {}",
+                import_name,
+                value.type_name(),
+                index,
+                Value::default(global.value_type()).type_name()
+            )));
         }
-        for (i, global) in response.globals.iter().enumerate() {
-            vm.globals_mut()[i] = global.to_value()
+        if !global.is_mutable() && *value != vm.globals()[index] {
+            return Err(Trap::ImportProtocolError(format!(
+                "{} changed the immutable global {}",
+                import_name, index
+            )));
         }
-        for (i, v) in response.memory.iter().enumerate() {
-            vm.default_memory_mut()?.data_mut()[i] = *v;
+    }
+    if !response.memory.is_empty() {
+        let memory_len = match vm.default_memory() {
+            Ok(memory) => memory.data().len(),
+            Err(Trap::NoMemory) => 0,
+            Err(err) => return Err(err),
+        };
+        if response.memory.len() != memory_len {
+            return Err(Trap::ImportProtocolError(format!(
+                "{} returned {} bytes of memory, the memory has {}",
+                import_name,
+                response.memory.len(),
+                memory_len
+            )));
         }
-        Ok(())
+    }
+
+    vm.value_stack_mut().extend(return_values);
+    vm.globals_mut()[..globals.len()].copy_from_slice(&globals);
+    if !response.memory.is_empty() {
+        vm.default_memory_mut()?.data_mut().copy_from_slice(&response.memory);
+    }
+    Ok(())
+}
+
+impl ImportFunctionHandler for GrpcImportHandler {
+    fn handle_import_function(vm: &mut VM<Self>) -> ImportFuture<'_> {
+        Box::pin(async move {
+            let import_name = import_name(vm);
+            let request = import_request(vm)?;
+            let handler = vm.import_function_handler_mut().clone();
+            match handler.run_import_function(request).await {
+                Ok(response) => apply_reply(vm, &import_name, response),
+                Err(err) => Err(call_failed(import_name, err)),
+            }
+        })
+    }
+
+    /// Awaits the call on the tokio runtime of the calling thread, which must be a blocking thread
+    /// like those of `spawn_blocking`, as the server runs the VM on. Threads outside of any runtime
+    /// use a runtime owned by the connection.
+    fn handle_import_function_blocking(vm: &mut VM<Self>) -> VMResult<()> {
+        let handle = vm
+            .import_function_handler_mut()
+            .runtime_handle()
+            .map_err(|err| Trap::ImportCallFailed {
+                import: import_name(vm),
+                reason: format!("no runtime for the call: {}", err),
+            })?;
+        handle.block_on(Self::handle_import_function(vm))
     }
 }
 
 pub type Debugger = wasmdbg::Debugger<GrpcImportHandler>;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+    use tonic::{Response, Status};
+
+    use super::*;
+    use crate::debugger_server::WasmDebuggerImpl;
+    use crate::grpc::wasm_debugger_grpc::wasm_dap_server::{WasmDap, WasmDapServer};
+    use crate::grpc::wasm_debugger_grpc::wasm_debugger_server::WasmDebugger;
+    use crate::grpc::wasm_debugger_grpc::{LoadRequest, RunCodeRequest, RunCodeType, RunImportFunctionReply};
+
+    // (import "env" "inc" (func (param i32) (result i32))) (global (mut i32) (i32.const 7))
+    // (func $start (global.set 0 (call 0 (i32.const 41)))) (start $start)
+    const NO_MEMORY_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x09, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x00, // type section
+        0x02, 0x0b, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x03, 0x69, 0x6e, 0x63, 0x00, 0x00, // import section
+        0x03, 0x02, 0x01, 0x01, // function section
+        0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x07, 0x0b, // global section
+        0x08, 0x01, 0x01, // start section
+        0x0a, 0x0a, 0x01, 0x08, 0x00, 0x41, 0x29, 0x10, 0x00, 0x24, 0x00, 0x0b, // code section
+    ];
+
+    /// Returns the first argument plus one and records every request.
+    #[derive(Default)]
+    struct IncrementDap {
+        requests: Arc<Mutex<Vec<RunImportFunctionRequest>>>,
+        /// Appended to the globals of the request in the reply
+        extra_globals: usize,
+        reply_memory: Vec<u8>,
+        /// Reply with `return_values` instead of the legacy `return_value`
+        use_return_values: bool,
+        /// Changes the reply before it is sent
+        tamper: Option<fn(&mut RunImportFunctionReply)>,
+        /// Fails every call with this code instead of replying
+        error: Option<tonic::Code>,
+    }
+
+    #[tonic::async_trait]
+    impl WasmDap for IncrementDap {
+        async fn run_import_function(
+            &self,
+            request: Request<RunImportFunctionRequest>,
+        ) -> Result<Response<RunImportFunctionReply>, Status> {
+            let request = request.into_inner();
+            if let Some(code) = self.error {
+                return Err(Status::new(code, "no reply"));
+            }
+            let result = request.args[0].to_value().add(&wasmdbg::Value::I32(1)).unwrap();
+            let mut globals = request.globals.clone();
+            globals.extend(
+                (0..self.extra_globals).map(|_| wasm_debugger_grpc::Value::from_value(&wasmdbg::Value::I32(0))),
+            );
+            let result = wasm_debugger_grpc::Value::from_value(&result);
+            let (return_value, return_values) = if self.use_return_values {
+                (None, vec![result])
+            } else {
+                (Some(result), Vec::new())
+            };
+            let mut reply = RunImportFunctionReply {
+                return_value,
+                globals,
+                memory: self.reply_memory.clone(),
+                return_values,
+            };
+            if let Some(tamper) = self.tamper {
+                tamper(&mut reply);
+            }
+            self.requests.lock().unwrap().push(request);
+            Ok(Response::new(reply))
+        }
+    }
+
+    /// Serves an `IncrementDap` on the current runtime, returning its address and the requests it gets.
+    async fn spawn_dap() -> (String, Arc<Mutex<Vec<RunImportFunctionRequest>>>) {
+        spawn_dap_with(IncrementDap::default()).await
+    }
+
+    async fn spawn_dap_with(dap: IncrementDap) -> (String, Arc<Mutex<Vec<RunImportFunctionRequest>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dap_addr = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::clone(&dap.requests);
+        tokio::spawn(
+            Server::builder()
+                .add_service(WasmDapServer::new(dap))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        (dap_addr, requests)
+    }
+
+    fn write_no_memory_module() -> String {
+        let path = std::env::temp_dir().join(format!("wasmdbg-grpc-{}-no-memory.wasm", std::process::id()));
+        std::fs::write(&path, NO_MEMORY_WASM).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn load_no_memory_module(dap_addr: &str) -> Debugger {
+        let mut dbg = Debugger::new();
+        dbg.import_function_handler_mut().set_dap_addr(dap_addr);
+        dbg.load_file(&write_no_memory_module()).unwrap();
+        dbg
+    }
+
+    #[test]
+    fn import_call_without_memory() {
+        let runtime = runtime::Runtime::new().unwrap();
+        let (dap_addr, requests) = runtime.block_on(spawn_dap());
+        let mut dbg = load_no_memory_module(&dap_addr);
+        dbg.start().unwrap();
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.get_vm().unwrap().globals()[0], wasmdbg::Value::I32(42));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].func_index, 0);
+        assert_eq!(requests[0].args[0].to_value(), wasmdbg::Value::I32(41));
+        assert_eq!(requests[0].globals[0].to_value(), wasmdbg::Value::I32(7));
+        assert!(requests[0].memory.is_empty());
+    }
+
+    #[test]
+    fn import_reply_with_return_values() {
+        let runtime = runtime::Runtime::new().unwrap();
+        let dap = IncrementDap {
+            use_return_values: true,
+            ..Default::default()
+        };
+        let (dap_addr, _) = runtime.block_on(spawn_dap_with(dap));
+        let mut dbg = load_no_memory_module(&dap_addr);
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.get_vm().unwrap().globals()[0], wasmdbg::Value::I32(42));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn import_calls_share_connection() {
+        let (dap_addr, requests) = spawn_dap().await;
+        // Run from the blocking pool of the test runtime, like the server's run commands
+        let mut dbg = tokio::task::spawn_blocking(move || {
+            let mut dbg = load_no_memory_module(&dap_addr);
+            assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+            assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+            dbg
+        })
+        .await
+        .unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        let connection = &dbg.import_function_handler_mut().connection;
+        assert!(connection.client.lock().unwrap().is_some());
+        // The calls ran on the test runtime
+        assert!(connection.runtime.lock().unwrap().is_none());
+    }
+
+    /// The server and the DAP server share the one thread of the runtime, so the import call
+    /// must not block it.
+    #[tokio::test]
+    async fn server_import_call_on_current_thread_runtime() {
+        let (dap_addr, requests) = spawn_dap().await;
+        let server = WasmDebuggerImpl::new(&dap_addr);
+        let file_name = write_no_memory_module();
+        server
+            .load_module(Request::new(LoadRequest { file_name }))
+            .await
+            .unwrap();
+        for run_code_type in [RunCodeType::Start, RunCodeType::Continue] {
+            let request = Request::new(RunCodeRequest {
+                run_code_type: run_code_type as i32,
+                timeout_ms: None,
+            });
+            tokio::time::timeout(Duration::from_secs(5), server.run_code(request))
+                .await
+                .expect("the import call blocked the runtime")
+                .unwrap();
+        }
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn invalid_import_replies() {
+        let runtime = runtime::Runtime::new().unwrap();
+        let too_many_globals = IncrementDap {
+            extra_globals: 2,
+            ..Default::default()
+        };
+        let oversized_memory = IncrementDap {
+            reply_memory: vec![0; 4],
+            ..Default::default()
+        };
+        let tampered = |tamper: fn(&mut RunImportFunctionReply)| IncrementDap {
+            tamper: Some(tamper),
+            ..Default::default()
+        };
+        let i64_value = || wasm_debugger_grpc::Value::from_value(&wasmdbg::Value::I64(0));
+        let wrong_result_type = tampered(|reply| reply.return_value = Some(i64_value()));
+        let too_many_results = tampered(|reply| reply.return_values = reply.return_value.iter().cloned().collect());
+        let no_result = tampered(|reply| reply.return_value = None);
+        let untyped_result = tampered(|reply| reply.return_value = Some(wasm_debugger_grpc::Value { value: None }));
+        let wrong_global_type = tampered(|reply| reply.globals[0] = i64_value());
+        for (dap, message) in [
+            (too_many_globals, "env.inc returned 3 globals, the module has 1"),
+            (oversized_memory, "env.inc returned 4 bytes of memory, the memory has 0"),
+            (wrong_result_type, "env.inc returned [i64], expected [i32]"),
+            (too_many_results, "env.inc returned [i32, i32], expected [i32]"),
+            (no_result, "env.inc returned [], expected [i32]"),
+            (untyped_result, "env.inc returned a value without a type"),
+            (wrong_global_type, "env.inc returned an i64 for global 0 of type i32"),
+        ] {
+            let (dap_addr, _) = runtime.block_on(spawn_dap_with(dap));
+            let mut dbg = load_no_memory_module(&dap_addr);
+            assert_eq!(dbg.run().unwrap(), Trap::ImportProtocolError(String::from(message)));
+            // Nothing of the reply was applied
+            let vm = dbg.get_vm().unwrap();
+            assert_eq!(vm.globals()[0], wasmdbg::Value::I32(7));
+        }
+    }
+
+    #[test]
+    fn failed_import_calls() {
+        let runtime = runtime::Runtime::new().unwrap();
+        let failing = |code| IncrementDap {
+            error: Some(code),
+            ..Default::default()
+        };
+        let (dap_addr, _) = runtime.block_on(spawn_dap_with(failing(tonic::Code::NotFound)));
+        let mut dbg = load_no_memory_module(&dap_addr);
+        assert_eq!(dbg.run().unwrap(), Trap::MissingImportHandler(String::from("env.inc")));
+
+        let (dap_addr, _) = runtime.block_on(spawn_dap_with(failing(tonic::Code::Internal)));
+        let mut dbg = load_no_memory_module(&dap_addr);
+        assert!(matches!(dbg.run().unwrap(), Trap::ImportCallFailed { import, .. } if import == "env.inc"));
+
+        // Nothing listens on the port
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dap_addr = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let mut dbg = load_no_memory_module(&dap_addr);
+        assert!(matches!(dbg.run().unwrap(), Trap::ImportCallFailed { import, .. } if import == "env.inc"));
+    }
+}
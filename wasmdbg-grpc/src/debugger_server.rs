@@ -1,35 +1,288 @@
 use crate::grpc::wasm_debugger_grpc::{
-    self, wasm_debugger_server::WasmDebugger, AddBreakpointReply, DeleteBreakpointRequest, GetCallStackReply,
-    GetGlobalReply, GetLocalReply, GetLocalRequest, GetValueStackReply, LoadRequest, NormalReply, NullRequest,
-    RunCodeRequest,
+    self, wasm_debugger_server::WasmDebugger, AddBreakpointReply, DeleteBreakpointRequest, DisassembleReply,
+    DisassembleRequest, EvaluateReply, EvaluateRequest, GetCallStackReply, GetCallStackRequest, GetCoverageReply,
+    GetCurrentPositionReply, GetGlobalReply, GetLocalReply, GetLocalRequest, GetTableReply, GetTableRequest,
+    GetValueStackReply, InterruptRequest, ListBreakpointsReply, ListDataSegmentsReply, ListElementSegmentsReply,
+    ListExportsReply, ListFunctionsReply, ListImportsReply, LoadRequest, ModuleSummaryReply, NormalReply, NullRequest,
+    ReadTypedArrayReply, ReadTypedArrayRequest, RunCodeReply, RunCodeRequest, RunEvent, SearchMemoryReply,
+    SearchMemoryRequest, SetBreakpointsReply, SetBreakpointsRequest, ToggleBreakpointRequest,
 };
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
 use tonic::{Request, Response};
 use wasmdbg::{
-    vm::{CodePosition, Trap},
-    Breakpoint,
+    vm::{CodePosition, InterruptHandle, Trap},
+    Breakpoint, DebuggerResult,
 };
 
 use crate::debugger::Debugger;
 
+pub const DEFAULT_YIELD_INTERVAL: u64 = 10_000;
+/// Keeps a client that adds breakpoints in a loop from exhausting the server's memory.
+pub const DEFAULT_BREAKPOINT_LIMIT: usize = 10_000;
+
+/// How often a timed out run is asked again to stop until it does.
+const INTERRUPT_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
 pub struct WasmDebuggerImpl {
-    client_addr: String,
-    dbg: Mutex<Debugger>,
+    dbg: Arc<Mutex<Debugger>>,
+    poison_reported: Arc<AtomicBool>,
+    yield_interval: u64,
+    interrupt: InterruptHandle,
+}
+
+type RunResult = DebuggerResult<Option<Trap>>;
+
+/// Locks the debugger even if a request panicked while holding the lock, so that one panic
+/// doesn't take down every later request. The first request to find the lock poisoned fails
+/// with an internal error, the following ones go on with the debugger as the panic left it.
+fn lock_debugger<'a>(
+    dbg: &'a Mutex<Debugger>,
+    poison_reported: &AtomicBool,
+) -> Result<MutexGuard<'a, Debugger>, tonic::Status> {
+    match dbg.lock() {
+        Ok(dbg) => Ok(dbg),
+        Err(poisoned) => {
+            let dbg = poisoned.into_inner();
+            if poison_reported.swap(true, Ordering::SeqCst) {
+                Ok(dbg)
+            } else {
+                Err(tonic::Status::internal(
+                    "An earlier request panicked while using the debugger, its state may be inconsistent",
+                ))
+            }
+        }
+    }
 }
 
 impl WasmDebuggerImpl {
+    /// Import calls of every instance are forwarded to the DAP server at `client_addr`.
     pub fn new(client_addr: &str) -> Self {
+        let mut dbg = Debugger::new();
+        dbg.enable_coverage();
+        dbg.set_breakpoint_limit(Some(DEFAULT_BREAKPOINT_LIMIT));
+        dbg.import_function_handler_mut().set_dap_addr(client_addr);
         Self {
-            dbg: Mutex::new(Debugger::new()),
-            client_addr: String::from(client_addr),
+            interrupt: dbg.interrupt_handle(),
+            dbg: Arc::new(Mutex::new(dbg)),
+            poison_reported: Arc::new(AtomicBool::new(false)),
+            yield_interval: DEFAULT_YIELD_INTERVAL,
+        }
+    }
+
+    /// Number of instructions a `Continue` executes before releasing the debugger lock.
+    pub fn with_yield_interval(mut self, yield_interval: u64) -> Self {
+        self.yield_interval = yield_interval.max(1);
+        self
+    }
+
+    /// Maximum number of breakpoints of a loaded module. `None` means unlimited.
+    pub fn with_breakpoint_limit(self, limit: Option<usize>) -> Self {
+        // Locking only fails after a request panicked, which can't happen before the server runs
+        if let Ok(mut dbg) = self.lock() {
+            dbg.set_breakpoint_limit(limit);
+        }
+        self
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Debugger>, tonic::Status> {
+        lock_debugger(&self.dbg, &self.poison_reported)
+    }
+
+    /// Runs `run` with the debugger locked on a blocking thread. The VM must not run on the async
+    /// workers because import calls block on the runtime until the DAP server replies.
+    async fn run_blocking<T: Send + 'static>(
+        &self,
+        run: impl FnOnce(&mut Debugger) -> T + Send + 'static,
+    ) -> Result<T, tonic::Status> {
+        let dbg = Arc::clone(&self.dbg);
+        let poison_reported = Arc::clone(&self.poison_reported);
+        tokio::task::spawn_blocking(move || Ok(run(&mut lock_debugger(&dbg, &poison_reported)?)))
+            .await
+            .map_err(run_panicked)?
+    }
+
+    async fn continue_cooperatively(&self) -> Result<RunResult, tonic::Status> {
+        self.interrupt.clear();
+        let yield_interval = self.yield_interval;
+        loop {
+            match self
+                .run_blocking(move |dbg| dbg.continue_execution_for(yield_interval))
+                .await?
+            {
+                Ok(None) => (),
+                run_result => return Ok(run_result),
+            }
+        }
+    }
+
+    /// Runs like `run_code` but interrupts the VM once `timeout` has passed.
+    /// Returns whether the run was cut short by the timeout.
+    async fn run_with_timeout(
+        &self,
+        run_code_type: wasm_debugger_grpc::RunCodeType,
+        timeout: Duration,
+    ) -> Result<(RunResult, bool), tonic::Status> {
+        let run = match BlockingRun::new(run_code_type) {
+            Some(run) => run,
+            // The lock is only held within a slice, dropping the future stops after the current one
+            None => {
+                return match tokio::time::timeout(timeout, self.continue_cooperatively()).await {
+                    Ok(run_result) => Ok((run_result?, false)),
+                    Err(_) => Ok((Ok(Some(Trap::Interrupted)), true)),
+                }
+            }
+        };
+
+        self.interrupt.clear();
+        let dbg = Arc::clone(&self.dbg);
+        let poison_reported = Arc::clone(&self.poison_reported);
+        let mut worker = tokio::task::spawn_blocking(move || -> Result<RunResult, tonic::Status> {
+            Ok(run_once(&mut lock_debugger(&dbg, &poison_reported)?, run))
+        });
+        let (run_result, timed_out) = match tokio::time::timeout(timeout, &mut worker).await {
+            Ok(run_result) => (run_result.map_err(run_panicked)??, false),
+            // A worker that has not started running yet clears the request, so repeat it until the run stops
+            Err(_) => loop {
+                self.interrupt.request();
+                if let Ok(run_result) = tokio::time::timeout(INTERRUPT_RETRY_INTERVAL, &mut worker).await {
+                    let run_result = run_result.map_err(run_panicked)??;
+                    let timed_out = matches!(run_result, Ok(Some(Trap::Interrupted)));
+                    break (run_result, timed_out);
+                }
+            },
+        };
+        // The run may have finished before it noticed the request
+        self.interrupt.clear();
+        Ok((run_result, timed_out))
+    }
+}
+
+fn run_panicked(err: tokio::task::JoinError) -> tonic::Status {
+    tonic::Status::internal(format!("The run panicked: {}", err))
+}
+
+/// The run types that hold the debugger lock until they stop.
+#[derive(Clone, Copy)]
+enum BlockingRun {
+    Start,
+    Step,
+    StepInto,
+    StepOut,
+    StepOver,
+}
+
+impl BlockingRun {
+    /// `None` for `Continue`, which needs to yield the debugger lock.
+    fn new(run_code_type: wasm_debugger_grpc::RunCodeType) -> Option<Self> {
+        match run_code_type {
+            wasm_debugger_grpc::RunCodeType::Start => Some(BlockingRun::Start),
+            wasm_debugger_grpc::RunCodeType::Step => Some(BlockingRun::Step),
+            wasm_debugger_grpc::RunCodeType::StepInto => Some(BlockingRun::StepInto),
+            wasm_debugger_grpc::RunCodeType::StepOut => Some(BlockingRun::StepOut),
+            wasm_debugger_grpc::RunCodeType::StepOver => Some(BlockingRun::StepOver),
+            wasm_debugger_grpc::RunCodeType::Continue => None,
+        }
+    }
+}
+
+fn run_once(dbg: &mut Debugger, run: BlockingRun) -> RunResult {
+    match run {
+        BlockingRun::Start => dbg.start(),
+        BlockingRun::Step => dbg.execute_step(),
+        BlockingRun::StepInto => dbg.step_into(),
+        BlockingRun::StepOut => dbg.execute_step_out(),
+        BlockingRun::StepOver => dbg.execute_step_over(),
+    }
+}
+
+/// The event that ends a `RunCodeStream`.
+fn stop_event(dbg: &Debugger, run_result: RunResult) -> RunEvent {
+    use wasm_debugger_grpc::run_event::{self, Event};
+
+    let pos = || {
+        dbg.get_vm().ok().map(|vm| wasm_debugger_grpc::CodePosition {
+            func_index: vm.ip().func_index,
+            instr_index: vm.ip().instr_index,
+        })
+    };
+    let event = match run_result {
+        Ok(None) | Ok(Some(Trap::Interrupted)) => Event::Stopped(run_event::Stopped { pos: pos() }),
+        Ok(Some(Trap::ExecutionFinished)) => Event::Finished(run_event::Finished {}),
+        Ok(Some(Trap::BreakpointReached(index))) => {
+            Event::BreakpointHit(run_event::BreakpointHit { index, pos: pos() })
+        }
+        Ok(Some(Trap::WatchpointReached(index))) => match dbg.last_watchpoint_hit() {
+            Some(hit) => Event::WatchpointHit(wasm_debugger_grpc::WatchpointHit::from_watchpoint_hit(&hit)),
+            None => Event::Trapped(run_event::Trapped {
+                reason: format!("{}", Trap::WatchpointReached(index)),
+            }),
+        },
+        Ok(Some(trap)) => Event::Trapped(run_event::Trapped {
+            reason: format!("{}: {}", trap, trap.explanation()),
+        }),
+        Err(err) => Event::Trapped(run_event::Trapped {
+            reason: format!("{}", err),
+        }),
+    };
+    RunEvent { event: Some(event) }
+}
+
+/// Stream of `RunCodeStream` events. Dropping it stops the execution.
+pub struct RunEventStream {
+    events: ReceiverStream<Result<RunEvent, tonic::Status>>,
+    cancelled: Arc<AtomicBool>,
+    interrupt: InterruptHandle,
+    worker: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Stream for RunEventStream {
+    type Item = Result<RunEvent, tonic::Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+impl Drop for RunEventStream {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let worker = match self.worker.take() {
+            Some(worker) if !worker.is_finished() => worker,
+            _ => return,
+        };
+        let interrupt = self.interrupt.clone();
+        match tokio::runtime::Handle::try_current() {
+            // A worker that has not started running yet clears the request, so repeat it until the run stops
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    let mut worker = worker;
+                    while !worker.is_finished() {
+                        interrupt.request();
+                        let _ = tokio::time::timeout(INTERRUPT_RETRY_INTERVAL, &mut worker).await;
+                    }
+                    interrupt.clear();
+                });
+            }
+            Err(_) => interrupt.request(),
         }
     }
 }
 
 #[tonic::async_trait]
 impl WasmDebugger for WasmDebuggerImpl {
+    type RunCodeStreamStream = RunEventStream;
+
     async fn load_module(&self, request: Request<LoadRequest>) -> Result<Response<NormalReply>, tonic::Status> {
-        let mut dbg = self.dbg.lock().unwrap();
+        let mut dbg = self.lock()?;
         let file_name = request.into_inner().file_name;
         let mut error_reason = None;
         let mut status = wasm_debugger_grpc::Status::Ok;
@@ -42,107 +295,190 @@ impl WasmDebugger for WasmDebuggerImpl {
             error_reason,
         }))
     }
-    async fn run_code(&self, request: Request<RunCodeRequest>) -> Result<Response<NormalReply>, tonic::Status> {
-        let mut dbg = self.dbg.lock().unwrap();
-
-        let run_code_type = wasm_debugger_grpc::RunCodeType::from_i32(request.into_inner().run_code_type);
+    async fn run_code(&self, request: Request<RunCodeRequest>) -> Result<Response<RunCodeReply>, tonic::Status> {
+        let request = request.into_inner();
+        let run_code_type = wasm_debugger_grpc::RunCodeType::from_i32(request.run_code_type);
         let run_code_type = match run_code_type {
             Some(run_code_type) => run_code_type,
             None => {
-                return Ok(Response::new(NormalReply {
+                return Ok(Response::new(RunCodeReply {
                     status: wasm_debugger_grpc::Status::Nok as i32,
                     error_reason: Some(String::from("invalud proto")),
+                    watchpoint_hit: None,
+                    position: None,
                 }))
             }
         };
-        let run_result = match run_code_type {
-            wasm_debugger_grpc::RunCodeType::Start => dbg.start().and_then(|ret| {
-                let client_addr = &__self.client_addr;
-                dbg.get_vm_mut()
-                    .unwrap()
-                    .import_function_handler_mut()
-                    .set_dap_addr(client_addr);
-                Ok(ret)
-            }),
-            wasm_debugger_grpc::RunCodeType::Step => dbg.execute_step(),
-            wasm_debugger_grpc::RunCodeType::StepOut => dbg.execute_step_out(),
-            wasm_debugger_grpc::RunCodeType::StepOver => dbg.execute_step_over(),
-            wasm_debugger_grpc::RunCodeType::Continue => dbg.continue_execution().and_then(|ret| Ok(Some(ret))),
+        if run_code_type != wasm_debugger_grpc::RunCodeType::Start && self.lock()?.is_finished() {
+            return Ok(Response::new(RunCodeReply {
+                status: wasm_debugger_grpc::Status::Nok as i32,
+                error_reason: Some(String::from("Execution already finished")),
+                watchpoint_hit: None,
+                position: None,
+            }));
+        }
+        let (run_result, timed_out) = match request.timeout_ms {
+            Some(timeout_ms) => {
+                self.run_with_timeout(run_code_type, Duration::from_millis(timeout_ms))
+                    .await?
+            }
+            None => match BlockingRun::new(run_code_type) {
+                None => (self.continue_cooperatively().await?, false),
+                Some(run) => (self.run_blocking(move |dbg| run_once(dbg, run)).await?, false),
+            },
         };
+        if timed_out {
+            let position = self.lock()?.get_vm().ok().map(|vm| wasm_debugger_grpc::CodePosition {
+                func_index: vm.ip().func_index,
+                instr_index: vm.ip().instr_index,
+            });
+            return Ok(Response::new(RunCodeReply {
+                status: wasm_debugger_grpc::Status::Timeout as i32,
+                error_reason: None,
+                watchpoint_hit: None,
+                position,
+            }));
+        }
+        let mut watchpoint_hit = None;
         let (status, error_reason) = match run_result {
             Ok(trap) => match trap {
                 Some(trap) => match trap {
                     Trap::ExecutionFinished => (wasm_debugger_grpc::Status::Finish, None),
-                    Trap::WatchpointReached(_) => (wasm_debugger_grpc::Status::Ok, None),
-                    Trap::BreakpointReached(_) => (wasm_debugger_grpc::Status::Ok, None),
-                    other_trap => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", other_trap))),
+                    Trap::WatchpointReached(_) => {
+                        watchpoint_hit = self
+                            .lock()?
+                            .last_watchpoint_hit()
+                            .map(|hit| wasm_debugger_grpc::WatchpointHit::from_watchpoint_hit(&hit));
+                        (wasm_debugger_grpc::Status::Ok, None)
+                    }
+                    Trap::BreakpointReached(_) | Trap::Interrupted => (wasm_debugger_grpc::Status::Ok, None),
+                    other_trap => (
+                        wasm_debugger_grpc::Status::Nok,
+                        Some(format!("{}: {}", other_trap, other_trap.explanation())),
+                    ),
                 },
                 None => (wasm_debugger_grpc::Status::Ok, None),
             },
             Err(error_message) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", error_message))),
         };
 
-        Ok(Response::new(NormalReply {
+        Ok(Response::new(RunCodeReply {
             status: status as i32,
             error_reason,
+            watchpoint_hit,
+            position: None,
         }))
     }
 
-    async fn get_local(&self, request: Request<GetLocalRequest>) -> Result<Response<GetLocalReply>, tonic::Status> {
-        let func_level = request.into_inner().call_stack;
-        let dbg = self.dbg.lock().unwrap();
-
-        let mut status = wasm_debugger_grpc::Status::Ok;
-        let mut error_reason = None;
-        let mut func_index = None;
+    async fn run_code_stream(
+        &self,
+        request: Request<RunCodeRequest>,
+    ) -> Result<Response<Self::RunCodeStreamStream>, tonic::Status> {
+        let run_code_type = wasm_debugger_grpc::RunCodeType::from_i32(request.into_inner().run_code_type)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid run code type"))?;
+        if run_code_type != wasm_debugger_grpc::RunCodeType::Start && self.lock()?.is_finished() {
+            return Err(tonic::Status::failed_precondition("Execution already finished"));
+        }
+        let (sender, receiver) = mpsc::channel(16);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        if run_code_type == wasm_debugger_grpc::RunCodeType::Continue {
+            self.interrupt.clear();
+        }
 
-        let locals = (|| -> Result<Vec<wasm_debugger_grpc::Value>, String> {
-            let vm = match dbg.get_vm() {
-                Ok(vm) => vm,
-                Err(err) => return Err(format!("{}", err)),
+        let dbg = Arc::clone(&self.dbg);
+        let poison_reported = Arc::clone(&self.poison_reported);
+        let yield_interval = self.yield_interval;
+        let worker_cancelled = Arc::clone(&cancelled);
+        let worker = tokio::task::spawn_blocking(move || {
+            let started = RunEvent {
+                event: Some(wasm_debugger_grpc::run_event::Event::Started(
+                    wasm_debugger_grpc::run_event::Started {},
+                )),
             };
-            let function_stack = vm.function_stack();
-            let index = (function_stack.len() as i32 + func_level) as usize;
-            if index >= function_stack.len() {
-                return Err(String::from("index should be negative and less than call stack depth"));
+            if sender.blocking_send(Ok(started)).is_err() {
+                return;
             }
-            let curr_func = &function_stack[index];
-            func_index = if func_level == -1 {
-                Some(vm.ip().func_index)
-            } else {
-                Some(function_stack[index + 1].ret_addr.func_index)
+            let lock = || lock_debugger(&dbg, &poison_reported);
+            // `None` if the stream was dropped
+            let run = || -> Result<Option<RunEvent>, tonic::Status> {
+                let run_result = match BlockingRun::new(run_code_type) {
+                    None => loop {
+                        if worker_cancelled.load(Ordering::SeqCst) {
+                            return Ok(None);
+                        }
+                        match lock()?.continue_execution_for(yield_interval) {
+                            Ok(None) => continue,
+                            result => break result,
+                        }
+                    },
+                    Some(run) => run_once(&mut lock()?, run),
+                };
+                Ok(Some(stop_event(&lock()?, run_result)))
             };
-            Ok(curr_func
-                .locals
-                .iter()
-                .map(wasm_debugger_grpc::Value::from_value)
-                .collect())
-        })();
+            let event = match run() {
+                Ok(Some(event)) => Ok(event),
+                Ok(None) => return,
+                Err(status) => Err(status),
+            };
+            // The client may have dropped the stream in the meantime
+            let _ = sender.blocking_send(event);
+        });
 
-        let locals = match locals {
-            Ok(locals) => (locals),
-            Err(error_message) => {
-                status = wasm_debugger_grpc::Status::Nok;
-                error_reason = Some(error_message);
-                Vec::new()
-            }
+        Ok(Response::new(RunEventStream {
+            events: ReceiverStream::new(receiver),
+            cancelled,
+            interrupt: self.interrupt.clone(),
+            worker: Some(worker),
+        }))
+    }
+
+    async fn get_local(&self, request: Request<GetLocalRequest>) -> Result<Response<GetLocalReply>, tonic::Status> {
+        let call_stack = request.into_inner().call_stack;
+        let frame_locals = match usize::try_from(-i64::from(call_stack) - 1) {
+            Ok(frame_from_top) => self
+                .lock()?
+                .frame_locals(frame_from_top)
+                .map_err(|err| format!("{}", err)),
+            Err(_) => Err(format!(
+                "Invalid call stack index {}, -1 is the innermost frame",
+                call_stack
+            )),
         };
 
-        Ok(Response::new(GetLocalReply {
-            status: status as i32,
-            error_reason,
-            func_index,
-            locals,
+        Ok(Response::new(match frame_locals {
+            Ok(frame_locals) => GetLocalReply {
+                status: wasm_debugger_grpc::Status::Ok as i32,
+                error_reason: None,
+                func_index: Some(frame_locals.func_index),
+                locals: frame_locals
+                    .locals
+                    .iter()
+                    .map(|(_, value)| wasm_debugger_grpc::Value::from_value(value))
+                    .collect(),
+                local_names: frame_locals
+                    .locals
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(index, (name, _))| Some((index as u32, name?)))
+                    .collect(),
+            },
+            Err(error_reason) => GetLocalReply {
+                status: wasm_debugger_grpc::Status::Nok as i32,
+                error_reason: Some(error_reason),
+                func_index: None,
+                locals: Vec::new(),
+                local_names: HashMap::new(),
+            },
         }))
     }
 
     async fn get_global(&self, _request: Request<NullRequest>) -> Result<Response<GetGlobalReply>, tonic::Status> {
-        let dbg = self.dbg.lock().unwrap();
+        let dbg = self.lock()?;
 
         let mut status = wasm_debugger_grpc::Status::Ok;
         let mut error_reason = None;
 
-        let globals = dbg
+        let globals: Vec<_> = dbg
             .get_vm()
             .map(|vm| {
                 vm.globals()
@@ -154,11 +490,88 @@ impl WasmDebugger for WasmDebuggerImpl {
                 (status, error_reason) = (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)));
                 Vec::new()
             });
+        let global_names = (0..globals.len() as u32)
+            .filter_map(|index| dbg.global_name(index).map(|name| (index, name.clone())))
+            .collect();
 
         Ok(Response::new(GetGlobalReply {
             status: status as i32,
             error_reason,
             globals,
+            global_names,
+        }))
+    }
+
+    async fn get_coverage(&self, _request: Request<NullRequest>) -> Result<Response<GetCoverageReply>, tonic::Status> {
+        let dbg = self.lock()?;
+        let (status, error_reason, report) = match dbg.get_vm() {
+            Ok(_) => (wasm_debugger_grpc::Status::Ok, None, dbg.coverage()),
+            Err(err) => (
+                wasm_debugger_grpc::Status::Nok,
+                Some(format!("{}", err)),
+                Default::default(),
+            ),
+        };
+        Ok(Response::new(GetCoverageReply {
+            status: status as i32,
+            error_reason,
+            percentage: report.percentage(),
+            functions: report
+                .functions
+                .iter()
+                .map(|func| wasm_debugger_grpc::FunctionCoverage {
+                    func_index: func.func_index,
+                    instr_count: func.instr_count,
+                    reached: func.reached.clone(),
+                    percentage: func.percentage(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_current_position(
+        &self,
+        _request: Request<NullRequest>,
+    ) -> Result<Response<GetCurrentPositionReply>, tonic::Status> {
+        let (status, error_reason, position) = match self.lock()?.current_position() {
+            Ok(position) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                Some(wasm_debugger_grpc::CurrentPosition::from_current_position(&position)),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), None),
+        };
+        Ok(Response::new(GetCurrentPositionReply {
+            status: status as i32,
+            error_reason,
+            position,
+        }))
+    }
+
+    async fn get_table(&self, request: Request<GetTableRequest>) -> Result<Response<GetTableReply>, tonic::Status> {
+        let table_index = request.into_inner().table_index;
+        let dbg = self.lock()?;
+
+        let mut status = wasm_debugger_grpc::Status::Ok;
+        let mut error_reason = None;
+
+        let elements = dbg
+            .table(table_index)
+            .map(|elements| {
+                elements
+                    .into_iter()
+                    .map(|func_index| wasm_debugger_grpc::TableElement { func_index })
+                    .collect()
+            })
+            .unwrap_or_else(|err| {
+                (status, error_reason) = (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)));
+                Vec::new()
+            });
+
+        Ok(Response::new(GetTableReply {
+            status: status as i32,
+            error_reason,
+            elements,
         }))
     }
 
@@ -166,7 +579,7 @@ impl WasmDebugger for WasmDebuggerImpl {
         &self,
         _request: Request<NullRequest>,
     ) -> Result<Response<GetValueStackReply>, tonic::Status> {
-        let dbg = self.dbg.lock().unwrap();
+        let dbg = self.lock()?;
         let mut status = wasm_debugger_grpc::Status::Ok;
         let mut error_reason = None;
 
@@ -192,9 +605,10 @@ impl WasmDebugger for WasmDebuggerImpl {
 
     async fn get_call_stack(
         &self,
-        _request: Request<NullRequest>,
+        request: Request<GetCallStackRequest>,
     ) -> Result<Response<GetCallStackReply>, tonic::Status> {
-        let dbg = self.dbg.lock().unwrap();
+        let detailed = request.into_inner().detailed;
+        let dbg = self.lock()?;
         let mut status = wasm_debugger_grpc::Status::Ok;
         let mut error_reason = None;
 
@@ -221,10 +635,248 @@ impl WasmDebugger for WasmDebuggerImpl {
                 Vec::new()
             }
         };
+        let frames = match dbg.backtrace_detailed() {
+            Ok(frames) if detailed => frames.iter().map(wasm_debugger_grpc::Frame::from_frame).collect(),
+            _ => Vec::new(),
+        };
         Ok(Response::new(GetCallStackReply {
             status: status as i32,
             error_reason,
             stacks,
+            frames,
+        }))
+    }
+
+    async fn read_typed_array(
+        &self,
+        request: Request<ReadTypedArrayRequest>,
+    ) -> Result<Response<ReadTypedArrayReply>, tonic::Status> {
+        let request = request.into_inner();
+        let dbg = self.lock()?;
+        let mut status = wasm_debugger_grpc::Status::Ok;
+        let mut error_reason = None;
+
+        let values = (|| -> Result<Vec<wasm_debugger_grpc::Value>, String> {
+            let value_type = match wasm_debugger_grpc::ValueType::from_i32(request.value_type) {
+                Some(value_type) => value_type.to_value_type(),
+                None => return Err(String::from("invalid value type")),
+            };
+            let values = dbg
+                .read_typed(request.address, value_type, request.count)
+                .map_err(|err| format!("{}", err))?;
+            Ok(values.iter().map(wasm_debugger_grpc::Value::from_value).collect())
+        })();
+
+        let values = match values {
+            Ok(values) => values,
+            Err(error_message) => {
+                status = wasm_debugger_grpc::Status::Nok;
+                error_reason = Some(error_message);
+                Vec::new()
+            }
+        };
+        Ok(Response::new(ReadTypedArrayReply {
+            status: status as i32,
+            error_reason,
+            values,
+        }))
+    }
+
+    async fn search_memory(
+        &self,
+        request: Request<SearchMemoryRequest>,
+    ) -> Result<Response<SearchMemoryReply>, tonic::Status> {
+        let request = request.into_inner();
+        let dbg = self.lock()?;
+        let (status, error_reason, address) = match dbg.search_memory(&request.pattern, request.start) {
+            Ok(address) => (wasm_debugger_grpc::Status::Ok, None, address),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), None),
+        };
+        Ok(Response::new(SearchMemoryReply {
+            status: status as i32,
+            error_reason,
+            address,
+        }))
+    }
+
+    async fn evaluate(&self, request: Request<EvaluateRequest>) -> Result<Response<EvaluateReply>, tonic::Status> {
+        let expr = request.into_inner().expr;
+        let dbg = self.lock()?;
+        let (status, error_reason, value) = match dbg.evaluate(&expr) {
+            Ok(value) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                Some(wasm_debugger_grpc::Value::from_value(&value)),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), None),
+        };
+        Ok(Response::new(EvaluateReply {
+            status: status as i32,
+            error_reason,
+            value,
+        }))
+    }
+
+    async fn disassemble(
+        &self,
+        request: Request<DisassembleRequest>,
+    ) -> Result<Response<DisassembleReply>, tonic::Status> {
+        let func_index = request.get_ref().func_index;
+        let dbg = self.lock()?;
+
+        let (status, error_reason, instrs) = match dbg.disassemble_function(func_index) {
+            Ok(instrs) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                instrs
+                    .into_iter()
+                    .map(|instr| wasm_debugger_grpc::DisassembledInstr {
+                        instr_index: instr.instr_index,
+                        mnemonic: instr.mnemonic,
+                        operands: instr.operands,
+                        has_breakpoint: instr.has_breakpoint,
+                    })
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(DisassembleReply {
+            status: status as i32,
+            error_reason,
+            instrs,
+        }))
+    }
+
+    async fn list_functions(
+        &self,
+        _request: Request<NullRequest>,
+    ) -> Result<Response<ListFunctionsReply>, tonic::Status> {
+        let dbg = self.lock()?;
+
+        let to_proto_types = |types: &[bwasm::ValueType]| -> Vec<i32> {
+            types
+                .iter()
+                .map(|value_type| wasm_debugger_grpc::ValueType::from_value_type(*value_type) as i32)
+                .collect()
+        };
+        let (status, error_reason, functions) = match dbg.list_functions() {
+            Ok(functions) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                functions
+                    .into_iter()
+                    .map(|function| wasm_debugger_grpc::FunctionInfo {
+                        index: function.index,
+                        name: function.name,
+                        param_types: to_proto_types(&function.param_types),
+                        return_types: to_proto_types(&function.return_types),
+                        is_imported: function.is_imported,
+                    })
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(ListFunctionsReply {
+            status: status as i32,
+            error_reason,
+            functions,
+        }))
+    }
+
+    async fn list_exports(&self, _request: Request<NullRequest>) -> Result<Response<ListExportsReply>, tonic::Status> {
+        let (status, error_reason, exports) = match self.lock()?.exports() {
+            Ok(exports) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                exports
+                    .iter()
+                    .map(wasm_debugger_grpc::ExportInfo::from_export_info)
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(ListExportsReply {
+            status: status as i32,
+            error_reason,
+            exports,
+        }))
+    }
+
+    async fn list_imports(&self, _request: Request<NullRequest>) -> Result<Response<ListImportsReply>, tonic::Status> {
+        let (status, error_reason, imports) = match self.lock()?.imports() {
+            Ok(imports) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                imports
+                    .iter()
+                    .map(wasm_debugger_grpc::ImportInfo::from_import_info)
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(ListImportsReply {
+            status: status as i32,
+            error_reason,
+            imports,
+        }))
+    }
+
+    async fn get_module_summary(
+        &self,
+        _request: Request<NullRequest>,
+    ) -> Result<Response<ModuleSummaryReply>, tonic::Status> {
+        let reply = match self.lock()?.module_summary() {
+            Ok(summary) => ModuleSummaryReply::from_module_summary(&summary),
+            Err(err) => ModuleSummaryReply {
+                status: wasm_debugger_grpc::Status::Nok as i32,
+                error_reason: Some(format!("{}", err)),
+                ..Default::default()
+            },
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn list_data_segments(
+        &self,
+        _request: Request<NullRequest>,
+    ) -> Result<Response<ListDataSegmentsReply>, tonic::Status> {
+        let (status, error_reason, data_segments) = match self.lock()?.data_segments() {
+            Ok(data_segments) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                data_segments
+                    .iter()
+                    .map(wasm_debugger_grpc::DataSegmentInfo::from_data_segment_info)
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(ListDataSegmentsReply {
+            status: status as i32,
+            error_reason,
+            data_segments,
+        }))
+    }
+
+    async fn list_element_segments(
+        &self,
+        _request: Request<NullRequest>,
+    ) -> Result<Response<ListElementSegmentsReply>, tonic::Status> {
+        let (status, error_reason, element_segments) = match self.lock()?.element_segments() {
+            Ok(element_segments) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                element_segments
+                    .iter()
+                    .map(wasm_debugger_grpc::ElementSegmentInfo::from_element_segment_info)
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(ListElementSegmentsReply {
+            status: status as i32,
+            error_reason,
+            element_segments,
         }))
     }
 
@@ -233,7 +885,7 @@ impl WasmDebugger for WasmDebuggerImpl {
         request: Request<wasm_debugger_grpc::CodePosition>,
     ) -> Result<Response<AddBreakpointReply>, tonic::Status> {
         let code_position = request.get_ref();
-        let mut dbg = self.dbg.lock().unwrap();
+        let mut dbg = self.lock()?;
         let mut status = wasm_debugger_grpc::Status::Ok;
         let mut error_reason = None;
 
@@ -261,7 +913,7 @@ impl WasmDebugger for WasmDebuggerImpl {
         request: Request<DeleteBreakpointRequest>,
     ) -> Result<Response<NormalReply>, tonic::Status> {
         let index = request.get_ref().breakpoint_index;
-        let mut dbg = self.dbg.lock().unwrap();
+        let mut dbg = self.lock()?;
 
         let (status, error_reason) = dbg.delete_breakpoint(index).map_or_else(
             |err| (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err))),
@@ -282,4 +934,442 @@ impl WasmDebugger for WasmDebuggerImpl {
             error_reason,
         }))
     }
+
+    async fn toggle_breakpoint(
+        &self,
+        request: Request<ToggleBreakpointRequest>,
+    ) -> Result<Response<NormalReply>, tonic::Status> {
+        let request = request.into_inner();
+        let (status, error_reason) = match self
+            .lock()?
+            .set_breakpoint_enabled(request.breakpoint_index, request.enabled)
+        {
+            Ok(()) => (wasm_debugger_grpc::Status::Ok, None),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err))),
+        };
+        Ok(Response::new(NormalReply {
+            status: status as i32,
+            error_reason,
+        }))
+    }
+
+    async fn list_breakpoints(
+        &self,
+        _request: Request<NullRequest>,
+    ) -> Result<Response<ListBreakpointsReply>, tonic::Status> {
+        let (status, error_reason, breakpoints) = match self.lock()?.list_breakpoints() {
+            Ok(breakpoints) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                breakpoints
+                    .iter()
+                    .map(wasm_debugger_grpc::BreakpointInfo::from_breakpoint_info)
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(ListBreakpointsReply {
+            status: status as i32,
+            error_reason,
+            breakpoints,
+        }))
+    }
+
+    async fn set_breakpoints(
+        &self,
+        request: Request<SetBreakpointsRequest>,
+    ) -> Result<Response<SetBreakpointsReply>, tonic::Status> {
+        let positions: Vec<CodePosition> = request
+            .into_inner()
+            .positions
+            .iter()
+            .map(|position| CodePosition::new(position.func_index, position.instr_index))
+            .collect();
+        let (status, error_reason, breakpoints) = match self.lock()?.replace_code_breakpoints(&positions) {
+            Ok(results) => (
+                wasm_debugger_grpc::Status::Ok,
+                None,
+                results
+                    .into_iter()
+                    .map(|result| match result {
+                        Ok(index) => wasm_debugger_grpc::SetBreakpointResult {
+                            breakpoint_index: Some(index),
+                            error_reason: None,
+                        },
+                        Err(err) => wasm_debugger_grpc::SetBreakpointResult {
+                            breakpoint_index: None,
+                            error_reason: Some(format!("{}", err)),
+                        },
+                    })
+                    .collect(),
+            ),
+            Err(err) => (wasm_debugger_grpc::Status::Nok, Some(format!("{}", err)), Vec::new()),
+        };
+        Ok(Response::new(SetBreakpointsReply {
+            status: status as i32,
+            error_reason,
+            breakpoints,
+        }))
+    }
+
+    async fn interrupt(&self, _request: Request<InterruptRequest>) -> Result<Response<NormalReply>, tonic::Status> {
+        // Doesn't take the debugger lock, which a running continue holds
+        self.interrupt.request();
+        Ok(Response::new(NormalReply {
+            status: wasm_debugger_grpc::Status::Ok as i32,
+            error_reason: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // (func $start (loop (br 0))) (start $start)
+    const INFINITE_LOOP_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x08, 0x01, 0x00, // start section
+        0x0a, 0x09, 0x01, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b, // code section
+    ];
+
+    // (memory 1) (data (i32.const 0) "\01\00\00\00\02\00\00\00\03\00\00\00\fc\ff\ff\ff")
+    // (func $start) (start $start)
+    const I32_ARRAY_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section
+        0x08, 0x01, 0x00, // start section
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code section
+        0x0b, 0x16, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x10, // data section
+        0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0xfc, 0xff, 0xff, 0xff,
+    ];
+
+    // (import "env" "inc" (func (param i32) (result i32)))
+    const IMPORT_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // type section
+        0x02, 0x0b, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x03, 0x69, 0x6e, 0x63, 0x00, 0x00, // import section
+    ];
+
+    fn write_module(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("wasmdbg-grpc-{}-{}.wasm", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn run_request(run_code_type: wasm_debugger_grpc::RunCodeType) -> Request<RunCodeRequest> {
+        Request::new(RunCodeRequest {
+            run_code_type: run_code_type as i32,
+            timeout_ms: None,
+        })
+    }
+
+    async fn load(server: &WasmDebuggerImpl, name: &str, bytes: &[u8]) {
+        let file_name = write_module(name, bytes);
+        let reply = server
+            .load_module(Request::new(LoadRequest { file_name }))
+            .await
+            .unwrap();
+        assert_eq!(reply.get_ref().status, wasm_debugger_grpc::Status::Ok as i32);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn interrupt_stops_long_continue() {
+        let server = Arc::new(WasmDebuggerImpl::new("http://127.0.0.1:0").with_yield_interval(100));
+        load(&server, "interrupt", INFINITE_LOOP_WASM).await;
+        let reply = server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap();
+        assert_eq!(reply.get_ref().status, wasm_debugger_grpc::Status::Ok as i32);
+
+        let executed_before = server.dbg.lock().unwrap().get_vm().unwrap().executed_instructions();
+
+        let continue_server = Arc::clone(&server);
+        let handle = tokio::spawn(async move {
+            continue_server
+                .run_code(run_request(wasm_debugger_grpc::RunCodeType::Continue))
+                .await
+        });
+        // An interrupt requested before the run starts is dropped
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(dbg) = server.dbg.try_lock() {
+                    if dbg.get_vm().unwrap().executed_instructions() > executed_before {
+                        break;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("continue did not start");
+        server.interrupt.request();
+
+        let reply = tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("continue did not stop after interrupt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply.get_ref().status, wasm_debugger_grpc::Status::Ok as i32);
+        assert!(server.dbg.lock().unwrap().get_vm().unwrap().trap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn run_code_timeout() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0").with_yield_interval(100);
+        load(&server, "timeout", INFINITE_LOOP_WASM).await;
+        server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap();
+
+        for run_code_type in [
+            wasm_debugger_grpc::RunCodeType::Continue,
+            wasm_debugger_grpc::RunCodeType::StepOut,
+        ] {
+            let request = Request::new(RunCodeRequest {
+                run_code_type: run_code_type as i32,
+                timeout_ms: Some(50),
+            });
+            let reply = tokio::time::timeout(Duration::from_secs(5), server.run_code(request))
+                .await
+                .expect("run did not stop after the timeout")
+                .unwrap()
+                .into_inner();
+            assert_eq!(reply.status, wasm_debugger_grpc::Status::Timeout as i32);
+            assert_eq!(reply.position.unwrap().func_index, 0);
+            assert!(server.dbg.try_lock().is_ok());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn dropping_run_stream_stops_step_out() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0");
+        load(&server, "drop-stream", INFINITE_LOOP_WASM).await;
+        server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap();
+
+        let mut events = server
+            .run_code_stream(run_request(wasm_debugger_grpc::RunCodeType::StepOut))
+            .await
+            .unwrap()
+            .into_inner();
+        let started = tokio_stream::StreamExt::next(&mut events).await.unwrap().unwrap();
+        assert!(matches!(
+            started.event,
+            Some(wasm_debugger_grpc::run_event::Event::Started(_))
+        ));
+        // step out holds the lock until it stops
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.dbg.try_lock().is_ok() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("step out did not start");
+        drop(events);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.dbg.try_lock().is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("step out did not stop after the stream was dropped");
+    }
+
+    #[tokio::test]
+    async fn breakpoint_limit() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0").with_breakpoint_limit(Some(1));
+        load(&server, "breakpoint_limit", INFINITE_LOOP_WASM).await;
+        let add = |instr_index| {
+            server.add_breakpoint(Request::new(wasm_debugger_grpc::CodePosition {
+                func_index: 0,
+                instr_index,
+            }))
+        };
+        assert_eq!(
+            add(0).await.unwrap().get_ref().status,
+            wasm_debugger_grpc::Status::Ok as i32
+        );
+        let reply = add(1).await.unwrap().into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Nok as i32);
+        assert_eq!(reply.breakpoint_index, None);
+    }
+
+    #[tokio::test]
+    async fn run_after_finish() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0");
+        load(&server, "finished", I32_ARRAY_WASM).await;
+        server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap();
+        let reply = server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Continue))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Finish as i32);
+
+        let reply = server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Step))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Nok as i32);
+        assert_eq!(reply.error_reason.as_deref(), Some("Execution already finished"));
+
+        let reply = server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Ok as i32);
+    }
+
+    fn read_request(address: u32, count: u32) -> Request<ReadTypedArrayRequest> {
+        Request::new(ReadTypedArrayRequest {
+            address,
+            value_type: wasm_debugger_grpc::ValueType::I32 as i32,
+            count,
+        })
+    }
+
+    #[tokio::test]
+    async fn read_typed_array() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0");
+        load(&server, "typed-array", I32_ARRAY_WASM).await;
+        server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap();
+
+        let reply = server.read_typed_array(read_request(0, 4)).await.unwrap().into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Ok as i32);
+        let values: Vec<_> = reply.values.iter().map(|value| value.to_value()).collect();
+        assert_eq!(
+            values,
+            vec![
+                wasmdbg::Value::I32(1),
+                wasmdbg::Value::I32(2),
+                wasmdbg::Value::I32(3),
+                wasmdbg::Value::I32(-4)
+            ]
+        );
+
+        let reply = server
+            .read_typed_array(read_request(0xfff0, 8))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Nok as i32);
+        assert!(reply.values.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_module_summary() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0");
+        let reply = server
+            .get_module_summary(Request::new(NullRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Nok as i32);
+
+        load(&server, "summary", I32_ARRAY_WASM).await;
+        let reply = server
+            .get_module_summary(Request::new(NullRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Ok as i32);
+        assert_eq!(reply.defined_functions, 1);
+        assert_eq!(reply.imported_functions, 0);
+        assert_eq!(
+            reply.memories,
+            vec![wasm_debugger_grpc::ResourceLimits { min: 1, max: None }]
+        );
+        assert!(reply.tables.is_empty());
+        assert!(reply.has_start);
+    }
+
+    #[tokio::test]
+    async fn list_imports() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0");
+        load(&server, "imports", IMPORT_WASM).await;
+        let reply = server
+            .list_imports(Request::new(NullRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Ok as i32);
+        let i32_type = vec![wasm_debugger_grpc::ValueType::I32 as i32];
+        assert_eq!(
+            reply.imports,
+            vec![wasm_debugger_grpc::ImportInfo {
+                module: String::from("env"),
+                field: String::from("inc"),
+                kind: wasm_debugger_grpc::ExportKind::Function as i32,
+                param_types: i32_type.clone(),
+                return_types: i32_type,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn dap_addr_reaches_every_instance() {
+        let server = WasmDebuggerImpl::new("[::1]:50099");
+        load(&server, "dap-addr", I32_ARRAY_WASM).await;
+        server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap();
+        let mut dbg = server.dbg.lock().unwrap();
+        assert_eq!(dbg.import_function_handler_mut().dap_addr(), "http://[::1]:50099");
+        assert_eq!(
+            dbg.get_vm_mut().unwrap().import_function_handler_mut().dap_addr(),
+            "http://[::1]:50099"
+        );
+    }
+
+    #[tokio::test]
+    async fn poisoned_lock_fails_one_request() {
+        let server = WasmDebuggerImpl::new("http://127.0.0.1:0");
+        let dbg = Arc::clone(&server.dbg);
+        std::thread::spawn(move || {
+            let _dbg = dbg.lock().unwrap();
+            panic!("handler panicked");
+        })
+        .join()
+        .unwrap_err();
+        assert!(server.dbg.is_poisoned());
+
+        let status = server.get_global(Request::new(NullRequest {})).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        let reply = server
+            .get_global(Request::new(NullRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.status, wasm_debugger_grpc::Status::Nok as i32);
+        assert_eq!(reply.error_reason.unwrap(), "The binary is not being run");
+
+        // The debugger is still usable
+        load(&server, "poisoned", I32_ARRAY_WASM).await;
+        let reply = server
+            .run_code(run_request(wasm_debugger_grpc::RunCodeType::Start))
+            .await
+            .unwrap();
+        assert_eq!(reply.get_ref().status, wasm_debugger_grpc::Status::Ok as i32);
+    }
 }
@@ -3,7 +3,7 @@ mod debugger_server;
 mod grpc;
 mod utils;
 use clap::{App, Arg};
-use debugger_server::WasmDebuggerImpl;
+use debugger_server::{WasmDebuggerImpl, DEFAULT_BREAKPOINT_LIMIT, DEFAULT_YIELD_INTERVAL};
 use grpc::wasm_debugger_grpc::wasm_debugger_server::WasmDebuggerServer;
 use std::net::SocketAddr;
 use tonic::transport::Server;
@@ -13,9 +13,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = App::new("wasmdbg-grpc")
         .arg(Arg::from_usage("-s --server-port <PORT>"))
         .arg(Arg::from_usage("-c --client-port <PORT>"))
+        .arg(Arg::from_usage(
+            "-y --yield-interval [INSTRUCTIONS] 'Instructions executed per continue slice'",
+        ))
+        .arg(Arg::from_usage(
+            "-b --breakpoint-limit [COUNT] 'Maximum number of breakpoints, 0 for unlimited'",
+        ))
         .get_matches();
     if let (Some(server), Some(client)) = (matches.value_of("server-port"), matches.value_of("client-port")) {
-        let debugger = WasmDebuggerImpl::new(client);
+        let yield_interval = match matches.value_of("yield-interval") {
+            Some(interval) => interval.parse::<u64>()?,
+            None => DEFAULT_YIELD_INTERVAL,
+        };
+        let breakpoint_limit = match matches.value_of("breakpoint-limit") {
+            Some(limit) => Some(limit.parse::<usize>()?).filter(|limit| *limit != 0),
+            None => Some(DEFAULT_BREAKPOINT_LIMIT),
+        };
+        let debugger = WasmDebuggerImpl::new(client)
+            .with_yield_interval(yield_interval)
+            .with_breakpoint_limit(breakpoint_limit);
         Server::builder()
             .add_service(WasmDebuggerServer::new(debugger))
             .serve(server.parse::<SocketAddr>()?)
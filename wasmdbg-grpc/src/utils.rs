@@ -9,6 +9,7 @@ impl wasm_debugger_grpc::Value {
                 wasmdbg::Value::I64(v) => ProtoValue::I64(*v),
                 wasmdbg::Value::F32(v) => ProtoValue::F32(f32::from(*v)),
                 wasmdbg::Value::F64(v) => ProtoValue::F64(f64::from(*v)),
+                wasmdbg::Value::V128(v) => ProtoValue::V128(v.to_vec()),
             }),
         }
     }
@@ -19,6 +20,233 @@ impl wasm_debugger_grpc::Value {
             ProtoValue::I64(v) => wasmdbg::Value::I64(*v),
             ProtoValue::F32(v) => wasmdbg::Value::F32(wasmdbg::F32::from(*v)),
             ProtoValue::F64(v) => wasmdbg::Value::F64(wasmdbg::F64::from(*v)),
+            ProtoValue::V128(v) => {
+                let mut bytes = [0; 16];
+                let len = v.len().min(16);
+                bytes[..len].copy_from_slice(&v[..len]);
+                wasmdbg::Value::V128(bytes)
+            }
         }
     }
 }
+
+impl wasm_debugger_grpc::ValueType {
+    pub fn from_value_type(value_type: bwasm::ValueType) -> Self {
+        match value_type {
+            bwasm::ValueType::I32 => wasm_debugger_grpc::ValueType::I32,
+            bwasm::ValueType::I64 => wasm_debugger_grpc::ValueType::I64,
+            bwasm::ValueType::F32 => wasm_debugger_grpc::ValueType::F32,
+            bwasm::ValueType::F64 => wasm_debugger_grpc::ValueType::F64,
+        }
+    }
+    pub fn to_value_type(self) -> bwasm::ValueType {
+        match self {
+            wasm_debugger_grpc::ValueType::I32 => bwasm::ValueType::I32,
+            wasm_debugger_grpc::ValueType::I64 => bwasm::ValueType::I64,
+            wasm_debugger_grpc::ValueType::F32 => bwasm::ValueType::F32,
+            wasm_debugger_grpc::ValueType::F64 => bwasm::ValueType::F64,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::ExportKind {
+    pub fn from_export_kind(kind: wasmdbg::ExportKind) -> Self {
+        match kind {
+            wasmdbg::ExportKind::Function => wasm_debugger_grpc::ExportKind::Function,
+            wasmdbg::ExportKind::Table => wasm_debugger_grpc::ExportKind::Table,
+            wasmdbg::ExportKind::Memory => wasm_debugger_grpc::ExportKind::Memory,
+            wasmdbg::ExportKind::Global => wasm_debugger_grpc::ExportKind::Global,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::ExportInfo {
+    pub fn from_export_info(export: &wasmdbg::ExportInfo) -> Self {
+        Self {
+            name: export.name.clone(),
+            kind: wasm_debugger_grpc::ExportKind::from_export_kind(export.kind) as i32,
+            index: export.index,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::ImportInfo {
+    pub fn from_import_info(import: &wasmdbg::ImportInfo) -> Self {
+        let to_proto_types = |types: &[bwasm::ValueType]| -> Vec<i32> {
+            types
+                .iter()
+                .map(|value_type| wasm_debugger_grpc::ValueType::from_value_type(*value_type) as i32)
+                .collect()
+        };
+        Self {
+            module: import.module.clone(),
+            field: import.field.clone(),
+            kind: wasm_debugger_grpc::ExportKind::from_export_kind(import.kind) as i32,
+            param_types: to_proto_types(&import.param_types),
+            return_types: to_proto_types(&import.return_types),
+        }
+    }
+}
+
+impl wasm_debugger_grpc::ModuleSummaryReply {
+    pub fn from_module_summary(summary: &wasmdbg::ModuleSummary) -> Self {
+        let limits = |limits: &wasmdbg::ResourceLimits| wasm_debugger_grpc::ResourceLimits {
+            min: limits.min,
+            max: limits.max,
+        };
+        Self {
+            status: wasm_debugger_grpc::Status::Ok as i32,
+            error_reason: None,
+            imported_functions: summary.imported_functions,
+            defined_functions: summary.defined_functions,
+            globals: summary.globals,
+            memories: summary.memories.iter().map(limits).collect(),
+            tables: summary.tables.iter().map(limits).collect(),
+            imports: summary.imports,
+            exports: summary.exports,
+            has_start: summary.has_start,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::DataSegmentInfo {
+    pub fn from_data_segment_info(segment: &wasmdbg::DataSegmentInfo) -> Self {
+        Self {
+            index: segment.index,
+            memory_index: segment.memory_index,
+            offset_expr: segment.offset_expr.clone(),
+            offset: segment.offset,
+            len: segment.len,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::ElementSegmentInfo {
+    pub fn from_element_segment_info(segment: &wasmdbg::ElementSegmentInfo) -> Self {
+        Self {
+            index: segment.index,
+            table_index: segment.table_index,
+            offset_expr: segment.offset_expr.clone(),
+            offset: segment.offset,
+            functions: segment.functions.clone(),
+        }
+    }
+}
+
+impl wasm_debugger_grpc::BreakpointInfo {
+    pub fn from_breakpoint_info(info: &wasmdbg::BreakpointInfo) -> Self {
+        type Kind = wasm_debugger_grpc::BreakpointKind;
+        let (kind, position, target, len) = match info.breakpoint {
+            wasmdbg::Breakpoint::Code(position) => (
+                Kind::Code,
+                Some(wasm_debugger_grpc::CodePosition {
+                    func_index: position.func_index,
+                    instr_index: position.instr_index,
+                }),
+                None,
+                None,
+            ),
+            wasmdbg::Breakpoint::Function(func_index) => (Kind::Function, None, Some(func_index), None),
+            wasmdbg::Breakpoint::Memory(_, address) => (Kind::Memory, None, Some(address), Some(1)),
+            wasmdbg::Breakpoint::MemoryRange { start, len, .. } => (Kind::Memory, None, Some(start), Some(len)),
+            wasmdbg::Breakpoint::Global { index, .. } => (Kind::Global, None, Some(index), None),
+        };
+        Self {
+            index: info.index,
+            kind: kind as i32,
+            position,
+            target,
+            len,
+            enabled: info.enabled,
+            hit_count: info.hit_count,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::Frame {
+    pub fn from_frame(frame: &wasmdbg::Frame) -> Self {
+        Self {
+            func_index: frame.func_index,
+            func_name: frame.func_name.clone(),
+            instr_index: frame.instr_index,
+            source: frame
+                .source
+                .as_ref()
+                .map(wasm_debugger_grpc::SourceLocation::from_source_location),
+            locals_count: frame.locals_count as u32,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::SourceLocation {
+    pub fn from_source_location(source: &wasmdbg::SourceLocation) -> Self {
+        Self {
+            file: source.file.clone(),
+            line: source.line,
+            column: source.column,
+        }
+    }
+}
+
+impl wasm_debugger_grpc::CurrentPosition {
+    pub fn from_current_position(position: &wasmdbg::CurrentPosition) -> Self {
+        Self {
+            func_index: position.func_index,
+            func_name: position.func_name.clone(),
+            instr_index: position.instr_index,
+            instruction_text: position.instruction_text.clone(),
+            source: position
+                .source
+                .as_ref()
+                .map(wasm_debugger_grpc::SourceLocation::from_source_location),
+        }
+    }
+}
+
+impl wasm_debugger_grpc::WatchpointHit {
+    pub fn from_watchpoint_hit(hit: &wasmdbg::vm::WatchpointHit) -> Self {
+        type ProtoLocation = wasm_debugger_grpc::watchpoint_hit::Location;
+        let location = match &hit.location {
+            wasmdbg::vm::WatchpointLocation::Global {
+                index,
+                old_value,
+                new_value,
+            } => ProtoLocation::Global(wasm_debugger_grpc::GlobalWatchpointHit {
+                global_index: *index,
+                old_value: Some(wasm_debugger_grpc::Value::from_value(old_value)),
+                new_value: Some(wasm_debugger_grpc::Value::from_value(new_value)),
+            }),
+            wasmdbg::vm::WatchpointLocation::Memory { address, size, write } => {
+                ProtoLocation::Memory(wasm_debugger_grpc::MemoryWatchpointHit {
+                    address: *address,
+                    size: *size,
+                    write: *write,
+                })
+            }
+        };
+        Self {
+            breakpoint_index: hit.breakpoint_index,
+            location: Some(location),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v128_round_trip() {
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8 * 17;
+        }
+        let value = wasmdbg::Value::V128(bytes);
+        let proto = wasm_debugger_grpc::Value::from_value(&value);
+        assert_eq!(
+            proto.value,
+            Some(wasm_debugger_grpc::value::Value::V128(bytes.to_vec()))
+        );
+        assert_eq!(proto.to_value(), value);
+    }
+}
@@ -0,0 +1,37 @@
+//! Steps 100k instructions through a tight loop, run with `cargo bench -p wasmdbg`.
+
+use std::time::Instant;
+
+use wasmdbg::DefaultDebugger;
+
+const STEPS: u32 = 100_000;
+
+// (func $start (local i32)
+//   (loop (br_if 0 (i32.lt_u (local.tee 0 (i32.add (local.get 0) (i32.const 1))) (i32.const 1000000)))))
+// (start $start)
+const LOOP_WASM: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+    0x03, 0x02, 0x01, 0x00, // function section
+    0x08, 0x01, 0x00, // start section
+    0x0a, 0x17, 0x01, 0x15, 0x01, 0x01, 0x7f, // code section, locals
+    0x03, 0x40, 0x20, 0x00, 0x41, 0x01, 0x6a, 0x22, 0x00, 0x41, 0xc0, 0x84, 0x3d, 0x49, 0x0d, 0x00, 0x0b, 0x0b,
+];
+
+fn main() {
+    let mut dbg = DefaultDebugger::new();
+    dbg.load_bytes("loop.wasm", LOOP_WASM).unwrap();
+    dbg.start().unwrap();
+
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        assert_eq!(dbg.execute_step().unwrap(), None);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "stepped {} instructions in {:?} ({:?} per step)",
+        STEPS,
+        elapsed,
+        elapsed / STEPS
+    );
+}
@@ -0,0 +1,343 @@
+//! Minimal raw scan of a wasm binary for the byte-level information that the parsed module
+//! doesn't keep: where each instruction is located and the contents of custom sections.
+
+const CUSTOM_SECTION: u8 = 0;
+const IMPORT_SECTION: u8 = 2;
+const CODE_SECTION: u8 = 10;
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    pub fn read_leb_u32(&mut self) -> Option<u32> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift > 28 {
+                return None;
+            }
+        }
+        if result > u64::from(u32::MAX) {
+            return None;
+        }
+        Some(result as u32)
+    }
+
+    pub fn read_leb_i64(&mut self) -> Option<i64> {
+        let mut result: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift < 64 {
+                result |= i64::from(byte & 0x7f) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                return Some(result);
+            }
+            if shift > 63 {
+                return None;
+            }
+        }
+    }
+
+    pub fn read_name(&mut self) -> Option<String> {
+        let len = self.read_leb_u32()? as usize;
+        let bytes = self.read_bytes(len)?;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn skip_limits(&mut self) -> Option<()> {
+        let flags = self.read_u8()?;
+        self.read_leb_u32()?;
+        if flags & 1 != 0 {
+            self.read_leb_u32()?;
+        }
+        Some(())
+    }
+
+    /// Skips one instruction, including its immediates.
+    pub fn skip_instruction(&mut self) -> Option<()> {
+        match self.read_u8()? {
+            0x00 | 0x01 | 0x05 | 0x0b | 0x0f | 0x1a | 0x1b => (),
+            0x02..=0x04 => {
+                self.read_leb_i64()?;
+            }
+            0x0c | 0x0d | 0x10 | 0x20..=0x24 => {
+                self.read_leb_u32()?;
+            }
+            0x0e => {
+                let count = self.read_leb_u32()?;
+                for _ in 0..=count {
+                    self.read_leb_u32()?;
+                }
+            }
+            0x11 => {
+                self.read_leb_u32()?;
+                self.read_u8()?;
+            }
+            0x28..=0x3e => {
+                self.read_leb_u32()?;
+                self.read_leb_u32()?;
+            }
+            0x3f | 0x40 => {
+                self.read_u8()?;
+            }
+            0x41 | 0x42 => {
+                self.read_leb_i64()?;
+            }
+            0x43 => {
+                self.read_bytes(4)?;
+            }
+            0x44 => {
+                self.read_bytes(8)?;
+            }
+            0x45..=0xc4 => (),
+            0xfc => match self.read_leb_u32()? {
+                0..=7 => (),
+                8 => {
+                    self.read_leb_u32()?;
+                    self.read_u8()?;
+                }
+                9 | 13 | 15..=17 => {
+                    self.read_leb_u32()?;
+                }
+                10 => {
+                    self.read_u8()?;
+                    self.read_u8()?;
+                }
+                11 => {
+                    self.read_u8()?;
+                }
+                12 | 14 => {
+                    self.read_leb_u32()?;
+                    self.read_leb_u32()?;
+                }
+                _ => return None,
+            },
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+pub(crate) struct FunctionBody {
+    /// Offset of every instruction relative to the code section payload.
+    pub instr_offsets: Vec<u32>,
+    /// The encoded body without its size: local declarations followed by the instructions.
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Default)]
+pub(crate) struct BinaryLayout {
+    pub imported_function_count: u32,
+    /// Offset of the code section payload in the module.
+    pub code_section_offset: usize,
+    pub bodies: Vec<FunctionBody>,
+    pub custom_sections: Vec<(String, Vec<u8>)>,
+}
+
+impl BinaryLayout {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let mut layout = BinaryLayout::default();
+        let mut reader = Reader::new(bytes);
+        reader.read_bytes(8)?;
+        while !reader.is_empty() {
+            let id = reader.read_u8()?;
+            let size = reader.read_leb_u32()? as usize;
+            let offset = reader.pos();
+            let payload = reader.read_bytes(size)?;
+            match id {
+                CUSTOM_SECTION => {
+                    let mut section = Reader::new(payload);
+                    let name = section.read_name()?;
+                    layout.custom_sections.push((name, payload[section.pos()..].to_vec()));
+                }
+                IMPORT_SECTION => layout.imported_function_count = count_imported_functions(payload)?,
+                CODE_SECTION => {
+                    layout.code_section_offset = offset;
+                    layout.bodies = parse_code_section(payload)?;
+                }
+                _ => (),
+            }
+        }
+        Some(layout)
+    }
+
+    pub fn function_body(&self, func_index: u32) -> Option<&FunctionBody> {
+        let index = func_index.checked_sub(self.imported_function_count)?;
+        self.bodies.get(index as usize)
+    }
+
+    pub fn instruction_offset(&self, func_index: u32, instr_index: u32) -> Option<u32> {
+        self.function_body(func_index)?
+            .instr_offsets
+            .get(instr_index as usize)
+            .copied()
+    }
+
+    /// The instruction whose encoding contains `offset`, as `(func_index, instr_index)`.
+    pub fn instruction_at(&self, offset: u32) -> Option<(u32, u32)> {
+        // Every body ends with the one byte `end`, so its last offset is its last byte
+        let body_index = self.bodies.iter().position(|body| {
+            let offsets = &body.instr_offsets;
+            !offsets.is_empty() && offsets[0] <= offset && offset <= offsets[offsets.len() - 1]
+        })?;
+        let instr_offsets = &self.bodies[body_index].instr_offsets;
+        let instr_index = instr_offsets.partition_point(|instr_offset| *instr_offset <= offset) - 1;
+        Some((self.imported_function_count + body_index as u32, instr_index as u32))
+    }
+
+    pub fn custom_section(&self, name: &str) -> Option<&[u8]> {
+        self.custom_sections
+            .iter()
+            .find(|(section_name, _)| section_name == name)
+            .map(|(_, payload)| &payload[..])
+    }
+}
+
+/// The signed LEB128 encoding of `value` padded to exactly `len` bytes, or `None` if it needs more.
+pub(crate) fn encode_leb_i64(mut value: i64, len: usize) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(len);
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        bytes.push(byte | 0x80);
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            break;
+        }
+    }
+    if bytes.len() > len {
+        return None;
+    }
+    let fill = if value < 0 { 0xff } else { 0x80 };
+    bytes.resize(len, fill);
+    *bytes.last_mut()? &= 0x7f;
+    Some(bytes)
+}
+
+fn count_imported_functions(payload: &[u8]) -> Option<u32> {
+    let mut reader = Reader::new(payload);
+    let mut functions = 0;
+    for _ in 0..reader.read_leb_u32()? {
+        reader.read_name()?;
+        reader.read_name()?;
+        match reader.read_u8()? {
+            0x00 => {
+                reader.read_leb_u32()?;
+                functions += 1;
+            }
+            0x01 => {
+                reader.read_u8()?;
+                reader.skip_limits()?;
+            }
+            0x02 => reader.skip_limits()?,
+            0x03 => {
+                reader.read_u8()?;
+                reader.read_u8()?;
+            }
+            _ => return None,
+        }
+    }
+    Some(functions)
+}
+
+fn parse_code_section(payload: &[u8]) -> Option<Vec<FunctionBody>> {
+    let mut reader = Reader::new(payload);
+    let count = reader.read_leb_u32()?;
+    let mut bodies = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let size = reader.read_leb_u32()? as usize;
+        let start = reader.pos();
+        let end = start + size;
+        for _ in 0..reader.read_leb_u32()? {
+            reader.read_leb_u32()?;
+            reader.read_u8()?;
+        }
+        let mut instr_offsets = Vec::new();
+        while reader.pos() < end {
+            instr_offsets.push(reader.pos() as u32);
+            reader.skip_instruction()?;
+        }
+        if reader.pos() != end {
+            return None;
+        }
+        bodies.push(FunctionBody {
+            instr_offsets,
+            bytes: payload[start..end].to_vec(),
+        });
+    }
+    Some(bodies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn instruction_offsets() {
+        // i32.const 300, drop, end
+        let bytes = single_func_module(&[], &[], &[(1, I32)], &[0x41, 0xac, 0x02, 0x1a, 0x0b], false);
+        let layout = BinaryLayout::parse(&bytes).unwrap();
+        let body = layout.function_body(0).unwrap();
+        // count, body size, local declarations (3 bytes), then the code
+        assert_eq!(body.instr_offsets, vec![5, 8, 9]);
+        assert_eq!(body.bytes, vec![0x01, 0x01, I32, 0x41, 0xac, 0x02, 0x1a, 0x0b]);
+        assert_eq!(layout.instruction_offset(0, 3), None);
+        assert_eq!(layout.instruction_at(5), Some((0, 0)));
+        assert_eq!(layout.instruction_at(7), Some((0, 0)));
+        assert_eq!(layout.instruction_at(9), Some((0, 2)));
+        assert_eq!(layout.instruction_at(4), None);
+        assert_eq!(layout.instruction_at(10), None);
+        assert_eq!(layout.code_section_offset, bytes.len() - 10);
+    }
+
+    #[test]
+    fn padded_leb() {
+        assert_eq!(encode_leb_i64(300, 2), Some(vec![0xac, 0x02]));
+        assert_eq!(encode_leb_i64(1, 3), Some(vec![0x81, 0x80, 0x00]));
+        assert_eq!(encode_leb_i64(-1, 1), Some(vec![0x7f]));
+        assert_eq!(encode_leb_i64(-1, 2), Some(vec![0xff, 0x7f]));
+        assert_eq!(encode_leb_i64(64, 1), None);
+        for value in [0, 63, -64, 300, -300, i64::from(i32::MIN), i64::MAX] {
+            let bytes = encode_leb_i64(value, 10).unwrap();
+            assert_eq!(Reader::new(&bytes).read_leb_i64(), Some(value));
+        }
+    }
+}
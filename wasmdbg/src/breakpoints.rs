@@ -1,10 +1,13 @@
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::iter;
 
 use crate::vm::CodePosition;
+use crate::wasm::{DisplayMode, Value};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BreakpointTrigger {
     Read,
     Write,
@@ -36,33 +39,131 @@ impl fmt::Display for BreakpointTrigger {
     }
 }
 
+/// A comparison of a value against a constant. Integers compare as signed numbers.
+/// Values of a different type than the constant never satisfy the condition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ValueCond {
+    Eq(Value),
+    Ne(Value),
+    Lt(Value),
+    Le(Value),
+    Gt(Value),
+    Ge(Value),
+}
+
+impl ValueCond {
+    pub fn operand(&self) -> Value {
+        match *self {
+            ValueCond::Eq(value)
+            | ValueCond::Ne(value)
+            | ValueCond::Lt(value)
+            | ValueCond::Le(value)
+            | ValueCond::Gt(value)
+            | ValueCond::Ge(value) => value,
+        }
+    }
+
+    pub fn holds(&self, value: Value) -> bool {
+        if value.value_type() != self.operand().value_type() {
+            return false;
+        }
+        let ordering = value.compare(&self.operand());
+        match self {
+            ValueCond::Eq(_) => ordering == Some(Ordering::Equal),
+            ValueCond::Ne(_) => ordering != Some(Ordering::Equal),
+            ValueCond::Lt(_) => ordering == Some(Ordering::Less),
+            ValueCond::Le(_) => matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal)),
+            ValueCond::Gt(_) => ordering == Some(Ordering::Greater),
+            ValueCond::Ge(_) => matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal)),
+        }
+    }
+}
+
+impl fmt::Display for ValueCond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            ValueCond::Eq(_) => "==",
+            ValueCond::Ne(_) => "!=",
+            ValueCond::Lt(_) => "<",
+            ValueCond::Le(_) => "<=",
+            ValueCond::Gt(_) => ">",
+            ValueCond::Ge(_) => ">=",
+        };
+        write!(f, "{} {}", op, self.operand().format(DisplayMode::Decimal))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Breakpoint {
     Code(CodePosition),
     Memory(BreakpointTrigger, u32),
-    Global(BreakpointTrigger, u32),
+    /// Watches every byte in `start..start + len`.
+    MemoryRange {
+        start: u32,
+        len: u32,
+        access: BreakpointTrigger,
+    },
+    /// Watches accesses of the global with this index. With a condition, only accesses where
+    /// the value read or written satisfies it trigger the watchpoint.
+    Global {
+        trigger: BreakpointTrigger,
+        index: u32,
+        cond: Option<ValueCond>,
+    },
+    /// Triggers on entry of every invocation of the function with this index,
+    /// before its first instruction is executed.
+    Function(u32),
 }
 
 #[derive(Default)]
 pub struct Breakpoints {
-    code: HashSet<CodePosition>,
+    code: HashMap<CodePosition, u32>,
+    functions: HashMap<u32, u32>,
     memory_read: HashSet<u32>,
     memory_write: HashSet<u32>,
+    memory_ranges: HashSet<u32>,
     global_read: HashSet<u32>,
     global_write: HashSet<u32>,
     index_map: HashMap<u32, Breakpoint>,
+    hit_counts: HashMap<u32, u64>,
+    disabled: HashSet<u32>,
     next_index: u32,
+    limit: Option<usize>,
 }
 
 impl Breakpoints {
     pub fn new() -> Self {
         Breakpoints {
-            code: HashSet::new(),
+            code: HashMap::new(),
+            functions: HashMap::new(),
             memory_read: HashSet::new(),
             memory_write: HashSet::new(),
+            memory_ranges: HashSet::new(),
             global_read: HashSet::new(),
             global_write: HashSet::new(),
             index_map: HashMap::new(),
+            hit_counts: HashMap::new(),
+            disabled: HashSet::new(),
             next_index: 0,
+            limit: None,
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Sets the maximum number of breakpoints. `None` means unlimited.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn is_full(&self) -> bool {
+        match self.limit {
+            Some(limit) => self.len() >= limit,
+            None => false,
         }
     }
 
@@ -74,20 +175,69 @@ impl Breakpoints {
         self.index_map.len()
     }
 
-    pub fn find_code(&self, pos: CodePosition) -> Option<u32> {
-        if self.code.contains(&pos) {
-            for (index, breakpoint) in self {
-                if let Breakpoint::Code(break_pos) = breakpoint {
-                    if *break_pos == pos {
-                        return Some(*index);
-                    }
-                }
+    pub fn is_enabled(&self, index: u32) -> bool {
+        !self.disabled.contains(&index)
+    }
+
+    /// Disabled breakpoints are skipped by the VM but keep their index and hit count.
+    /// Returns false if there is no breakpoint with this index.
+    pub fn set_enabled(&mut self, index: u32, enabled: bool) -> bool {
+        let breakpoint = match self.index_map.get(&index) {
+            Some(breakpoint) => breakpoint.clone(),
+            None => return false,
+        };
+        if enabled {
+            self.disabled.remove(&index);
+        } else {
+            self.disabled.insert(index);
+        }
+        match breakpoint {
+            Breakpoint::Code(position) => {
+                let first = self.first_enabled(|other| *other == Breakpoint::Code(position));
+                match first {
+                    Some(first) => self.code.insert(position, first),
+                    None => self.code.remove(&position),
+                };
+            }
+            Breakpoint::Function(func_index) => {
+                let first = self.first_enabled(|other| *other == Breakpoint::Function(func_index));
+                match first {
+                    Some(first) => self.functions.insert(func_index, first),
+                    None => self.functions.remove(&func_index),
+                };
             }
+            _ => (),
         }
-        None
+        true
+    }
+
+    fn first_enabled<P: Fn(&Breakpoint) -> bool>(&self, matches: P) -> Option<u32> {
+        self.index_map
+            .iter()
+            .filter(|(index, breakpoint)| self.is_enabled(**index) && matches(breakpoint))
+            .map(|(index, _)| *index)
+            .min()
+    }
+
+    /// How often the VM stopped at the breakpoint.
+    pub fn hit_count(&self, index: u32) -> u64 {
+        self.hit_counts.get(&index).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn record_hit(&mut self, index: u32) {
+        *self.hit_counts.entry(index).or_insert(0) += 1;
+    }
+
+    pub fn find_code(&self, pos: CodePosition) -> Option<u32> {
+        self.code.get(&pos).copied()
+    }
+
+    pub fn find_function(&self, func_index: u32) -> Option<u32> {
+        self.functions.get(&func_index).copied()
     }
 
-    pub fn find_global(&self, global: u32, write: bool) -> Option<u32> {
+    /// `value` is the value read or written.
+    pub fn find_global(&self, global: u32, write: bool, value: Value) -> Option<u32> {
         let found = if write {
             self.global_write.contains(&global)
         } else {
@@ -95,8 +245,15 @@ impl Breakpoints {
         };
         if found {
             for (index, breakpoint) in self {
-                if let Breakpoint::Global(_, break_global) = breakpoint {
-                    if *break_global == global {
+                if let Breakpoint::Global {
+                    trigger,
+                    index: break_global,
+                    cond,
+                } = breakpoint
+                {
+                    let triggered = if write { trigger.is_write() } else { trigger.is_read() };
+                    let holds = cond.map_or(true, |cond| cond.holds(value));
+                    if *break_global == global && triggered && holds && self.is_enabled(*index) {
                         return Some(*index);
                     }
                 }
@@ -106,25 +263,45 @@ impl Breakpoints {
     }
 
     pub fn find_memory(&self, start: u32, len: u32, write: bool) -> Option<u32> {
+        let end = u64::from(start) + u64::from(len);
         let watchpoints = if write { &self.memory_write } else { &self.memory_read };
         for &addr in watchpoints {
-            if start <= addr && addr < start + len {
+            if start <= addr && u64::from(addr) < end {
                 for (index, breakpoint) in self {
-                    if let Breakpoint::Memory(_, break_addr) = breakpoint {
-                        if *break_addr == addr {
+                    if let Breakpoint::Memory(trigger, break_addr) = breakpoint {
+                        let triggered = if write { trigger.is_write() } else { trigger.is_read() };
+                        if *break_addr == addr && triggered && self.is_enabled(*index) {
                             return Some(*index);
                         }
                     }
                 }
             }
         }
+        for index in &self.memory_ranges {
+            if let Some(Breakpoint::MemoryRange {
+                start: range_start,
+                len: range_len,
+                access,
+            }) = self.index_map.get(index)
+            {
+                let triggered = if write { access.is_write() } else { access.is_read() };
+                let range_end = u64::from(*range_start) + u64::from(*range_len);
+                if triggered && self.is_enabled(*index) && u64::from(*range_start) < end && u64::from(start) < range_end
+                {
+                    return Some(*index);
+                }
+            }
+        }
         None
     }
 
     pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> u32 {
         match breakpoint {
             Breakpoint::Code(position) => {
-                self.code.insert(position);
+                self.code.entry(position).or_insert(self.next_index);
+            }
+            Breakpoint::Function(func_index) => {
+                self.functions.entry(func_index).or_insert(self.next_index);
             }
             Breakpoint::Memory(trigger, addr) => {
                 if trigger.is_read() {
@@ -134,7 +311,10 @@ impl Breakpoints {
                     self.memory_write.insert(addr);
                 }
             }
-            Breakpoint::Global(trigger, index) => {
+            Breakpoint::MemoryRange { .. } => {
+                self.memory_ranges.insert(self.next_index);
+            }
+            Breakpoint::Global { trigger, index, .. } => {
                 if trigger.is_read() {
                     self.global_read.insert(index);
                 }
@@ -150,41 +330,110 @@ impl Breakpoints {
     }
 
     pub fn delete_breakpoint(&mut self, index: u32) -> bool {
-        if let Some(breakpoint) = self.index_map.get(&index) {
-            match breakpoint {
-                Breakpoint::Code(position) => {
-                    self.code.remove(position);
+        self.remove_breakpoint(index).is_some()
+    }
+
+    /// Removes every breakpoint for which `keep` returns false and returns the removed ones.
+    /// The remaining breakpoints keep their indices.
+    pub fn retain<P: FnMut(&Breakpoint) -> bool>(&mut self, mut keep: P) -> Vec<(u32, Breakpoint)> {
+        let mut removed: Vec<u32> = self
+            .index_map
+            .iter()
+            .filter(|(_, breakpoint)| !keep(breakpoint))
+            .map(|(index, _)| *index)
+            .collect();
+        removed.sort_unstable();
+        removed
+            .into_iter()
+            .filter_map(|index| Some((index, self.remove_breakpoint(index)?)))
+            .collect()
+    }
+
+    fn remove_breakpoint(&mut self, index: u32) -> Option<Breakpoint> {
+        let breakpoint = self.index_map.remove(&index)?;
+        match &breakpoint {
+            // Another breakpoint at the same location takes over, like in `set_enabled`
+            Breakpoint::Code(position) => {
+                if self.code.get(position) == Some(&index) {
+                    match self.first_enabled(|other| *other == breakpoint) {
+                        Some(other) => self.code.insert(*position, other),
+                        None => self.code.remove(position),
+                    };
                 }
-                Breakpoint::Memory(trigger, addr) => {
-                    if trigger.is_read() {
-                        self.memory_read.remove(addr);
-                    }
-                    if trigger.is_write() {
-                        self.memory_write.remove(addr);
-                    }
+            }
+            Breakpoint::Function(func_index) => {
+                if self.functions.get(func_index) == Some(&index) {
+                    match self.first_enabled(|other| *other == breakpoint) {
+                        Some(other) => self.functions.insert(*func_index, other),
+                        None => self.functions.remove(func_index),
+                    };
                 }
-                Breakpoint::Global(trigger, index) => {
-                    if trigger.is_read() {
-                        self.global_read.remove(index);
-                    }
-                    if trigger.is_write() {
-                        self.global_write.remove(index);
-                    }
+            }
+            // Other watchpoints may still watch the same address or global
+            Breakpoint::Memory(trigger, addr) => {
+                if trigger.is_read() && !self.watches_address(*addr, false, index) {
+                    self.memory_read.remove(addr);
                 }
-            };
-            self.index_map.remove(&index);
-            return true;
-        }
-        false
+                if trigger.is_write() && !self.watches_address(*addr, true, index) {
+                    self.memory_write.remove(addr);
+                }
+            }
+            Breakpoint::MemoryRange { .. } => {
+                self.memory_ranges.remove(&index);
+            }
+            Breakpoint::Global {
+                trigger, index: global, ..
+            } => {
+                if trigger.is_read() && !self.watches_global(*global, false, index) {
+                    self.global_read.remove(global);
+                }
+                if trigger.is_write() && !self.watches_global(*global, true, index) {
+                    self.global_write.remove(global);
+                }
+            }
+        };
+        self.hit_counts.remove(&index);
+        self.disabled.remove(&index);
+        Some(breakpoint)
+    }
+
+    /// Whether a watchpoint other than `except` triggers on accesses of `addr`.
+    fn watches_address(&self, addr: u32, write: bool, except: u32) -> bool {
+        self.index_map.iter().any(|(index, breakpoint)| match breakpoint {
+            Breakpoint::Memory(trigger, other_addr) => {
+                *index != except && *other_addr == addr && if write { trigger.is_write() } else { trigger.is_read() }
+            }
+            _ => false,
+        })
+    }
+
+    /// Whether a watchpoint other than `except` triggers on accesses of `global`.
+    fn watches_global(&self, global: u32, write: bool, except: u32) -> bool {
+        self.index_map.iter().any(|(index, breakpoint)| match breakpoint {
+            Breakpoint::Global {
+                trigger,
+                index: other_global,
+                ..
+            } => {
+                *index != except
+                    && *other_global == global
+                    && if write { trigger.is_write() } else { trigger.is_read() }
+            }
+            _ => false,
+        })
     }
 
     pub fn clear(&mut self) {
         self.code.clear();
+        self.functions.clear();
         self.memory_read.clear();
         self.memory_write.clear();
+        self.memory_ranges.clear();
         self.global_read.clear();
         self.global_write.clear();
         self.index_map.clear();
+        self.hit_counts.clear();
+        self.disabled.clear();
     }
 
     pub fn iter(&self) -> <&Self as iter::IntoIterator>::IntoIter {
@@ -200,3 +449,103 @@ impl<'a> iter::IntoIterator for &'a Breakpoints {
         self.index_map.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{F32, F64};
+
+    #[test]
+    fn code_lookup_is_keyed_by_position() {
+        let mut breakpoints = Breakpoints::new();
+        let pos = CodePosition::new(1, 0);
+        let duplicates: Vec<_> = (0..1000)
+            .map(|_| breakpoints.add_breakpoint(Breakpoint::Code(pos)))
+            .collect();
+        // Duplicates share one entry, so a lookup doesn't depend on the breakpoint count
+        assert_eq!(breakpoints.code.len(), 1);
+        assert_eq!(breakpoints.find_code(pos), Some(duplicates[0]));
+        // The lowest remaining index takes over
+        assert!(breakpoints.delete_breakpoint(duplicates[0]));
+        assert_eq!(breakpoints.find_code(pos), Some(duplicates[1]));
+
+        for i in 0..1000 {
+            breakpoints.add_breakpoint(Breakpoint::Code(CodePosition::new(2, i)));
+        }
+        assert_eq!(breakpoints.code.len(), 1001);
+        assert_eq!(breakpoints.find_code(CodePosition::new(0, 0)), None);
+    }
+
+    #[test]
+    fn duplicate_code_breakpoints() {
+        let mut breakpoints = Breakpoints::new();
+        let pos = CodePosition::new(0, 3);
+        let first = breakpoints.add_breakpoint(Breakpoint::Code(pos));
+        let second = breakpoints.add_breakpoint(Breakpoint::Code(pos));
+        assert_eq!(breakpoints.find_code(pos), Some(first));
+        assert!(breakpoints.delete_breakpoint(first));
+        assert_eq!(breakpoints.find_code(pos), Some(second));
+        assert!(breakpoints.delete_breakpoint(second));
+        assert_eq!(breakpoints.find_code(pos), None);
+    }
+
+    #[test]
+    fn memory_range_watchpoints() {
+        let mut breakpoints = Breakpoints::new();
+        let index = breakpoints.add_breakpoint(Breakpoint::MemoryRange {
+            start: 16,
+            len: 8,
+            access: BreakpointTrigger::Write,
+        });
+        // an i64 store straddling the start of the range
+        assert_eq!(breakpoints.find_memory(12, 8, true), Some(index));
+        assert_eq!(breakpoints.find_memory(23, 1, true), Some(index));
+        assert_eq!(breakpoints.find_memory(8, 8, true), None);
+        assert_eq!(breakpoints.find_memory(24, 4, true), None);
+        // write-only watchpoints ignore reads
+        assert_eq!(breakpoints.find_memory(16, 4, false), None);
+        assert!(breakpoints.delete_breakpoint(index));
+        assert_eq!(breakpoints.find_memory(16, 4, true), None);
+    }
+
+    #[test]
+    fn deleting_one_of_several_watchpoints() {
+        let mut breakpoints = Breakpoints::new();
+        let below = breakpoints.add_breakpoint(Breakpoint::Global {
+            trigger: BreakpointTrigger::Write,
+            index: 2,
+            cond: Some(ValueCond::Lt(Value::I32(0))),
+        });
+        let above = breakpoints.add_breakpoint(Breakpoint::Global {
+            trigger: BreakpointTrigger::ReadWrite,
+            index: 2,
+            cond: Some(ValueCond::Gt(Value::I32(100))),
+        });
+        assert_eq!(breakpoints.find_global(2, true, Value::I32(-1)), Some(below));
+        assert!(breakpoints.delete_breakpoint(below));
+        assert_eq!(breakpoints.find_global(2, true, Value::I32(-1)), None);
+        assert_eq!(breakpoints.find_global(2, true, Value::I32(101)), Some(above));
+        assert_eq!(breakpoints.find_global(2, false, Value::I32(101)), Some(above));
+        assert!(breakpoints.delete_breakpoint(above));
+        assert_eq!(breakpoints.find_global(2, true, Value::I32(101)), None);
+
+        let read = breakpoints.add_breakpoint(Breakpoint::Memory(BreakpointTrigger::Read, 8));
+        let write = breakpoints.add_breakpoint(Breakpoint::Memory(BreakpointTrigger::ReadWrite, 8));
+        assert!(breakpoints.delete_breakpoint(read));
+        assert_eq!(breakpoints.find_memory(8, 4, false), Some(write));
+        assert_eq!(breakpoints.find_memory(8, 4, true), Some(write));
+    }
+
+    #[test]
+    fn value_conditions() {
+        assert!(ValueCond::Lt(Value::I32(0)).holds(Value::I32(-1)));
+        assert!(!ValueCond::Lt(Value::I32(0)).holds(Value::I32(0)));
+        assert!(ValueCond::Ge(Value::I64(-3)).holds(Value::I64(-3)));
+        assert!(ValueCond::Gt(Value::F64(F64::from_float(1.5))).holds(Value::F64(F64::from_float(2.0))));
+        let nan = Value::F32(F32::from_float(f32::NAN));
+        assert!(!ValueCond::Eq(nan).holds(nan));
+        assert!(ValueCond::Ne(nan).holds(nan));
+        assert!(!ValueCond::Ne(Value::I32(0)).holds(Value::I64(1)));
+        assert_eq!(ValueCond::Le(Value::I32(-2)).to_string(), "<= -2");
+    }
+}
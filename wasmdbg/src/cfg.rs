@@ -0,0 +1,205 @@
+use std::collections::BTreeSet;
+
+use bwasm::Instruction;
+
+/// Instructions `start..end` of a function, entered only at `start` and left only after `end - 1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Branch,
+    BranchIf,
+    BranchTable,
+    /// From an `if` into its first branch.
+    IfTrue,
+    /// From an `if` to its `else` branch, or to its `end` if there is none.
+    IfFalse,
+}
+
+/// `from` and `to` are indices into `Cfg::blocks`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CfgEdge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// The control flow graph of a function. Branches to a `block` or `if` label target its `end`,
+/// branches to a `loop` label the first instruction of the loop body. Blocks ending in `return`
+/// or `unreachable` and the block ending the function have no outgoing edges.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<CfgEdge>,
+}
+
+impl Cfg {
+    pub fn block_containing(&self, instr_index: u32) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|block| block.start <= instr_index && instr_index < block.end)
+    }
+
+    pub fn successors(&self, block: usize) -> impl Iterator<Item = &CfgEdge> {
+        self.edges.iter().filter(move |edge| edge.from == block)
+    }
+}
+
+struct Scopes {
+    /// The matching `end` of every `block`, `loop` and `if`, indexed by the opening instruction.
+    ends: Vec<Option<u32>>,
+    elses: Vec<Option<u32>>,
+}
+
+fn match_scopes(instructions: &[Instruction]) -> Scopes {
+    let mut ends = vec![None; instructions.len()];
+    let mut elses = vec![None; instructions.len()];
+    let mut open = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => open.push(index),
+            Instruction::Else => {
+                if let Some(&start) = open.last() {
+                    elses[start] = Some(index as u32);
+                }
+            }
+            Instruction::End => {
+                if let Some(start) = open.pop() {
+                    ends[start] = Some(index as u32);
+                }
+            }
+            _ => (),
+        }
+    }
+    Scopes { ends, elses }
+}
+
+/// Instruction indices `instr_index` may continue at, together with the kind of each edge.
+fn targets(
+    instructions: &[Instruction],
+    scopes: &Scopes,
+    labels: &[usize],
+    instr_index: usize,
+) -> Vec<(u32, EdgeKind)> {
+    let function_end = instructions.len() as u32 - 1;
+    let label_target = |depth: u32| -> u32 {
+        match labels.len().checked_sub(depth as usize + 1) {
+            Some(label) => {
+                let start = labels[label];
+                match instructions[start] {
+                    Instruction::Loop(_) => start as u32 + 1,
+                    _ => scopes.ends[start].unwrap_or(function_end),
+                }
+            }
+            // The label of the function itself
+            None => function_end,
+        }
+    };
+    let next = instr_index as u32 + 1;
+    match &instructions[instr_index] {
+        Instruction::Br(depth) => vec![(label_target(*depth), EdgeKind::Branch)],
+        Instruction::BrIf(depth) => vec![
+            (label_target(*depth), EdgeKind::BranchIf),
+            (next, EdgeKind::Fallthrough),
+        ],
+        Instruction::BrTable(table_data) => {
+            let targets: BTreeSet<u32> = table_data
+                .table
+                .iter()
+                .chain(std::iter::once(&table_data.default))
+                .map(|depth| label_target(*depth))
+                .collect();
+            targets
+                .into_iter()
+                .map(|target| (target, EdgeKind::BranchTable))
+                .collect()
+        }
+        Instruction::If(_) => {
+            let otherwise = match scopes.elses[instr_index] {
+                Some(else_index) => else_index + 1,
+                None => scopes.ends[instr_index].unwrap_or(function_end),
+            };
+            vec![(next, EdgeKind::IfTrue), (otherwise, EdgeKind::IfFalse)]
+        }
+        // Reaching `else` means the first branch of the `if` is done
+        Instruction::Else => {
+            let end = labels.last().and_then(|start| scopes.ends[*start]);
+            vec![(end.unwrap_or(function_end), EdgeKind::Branch)]
+        }
+        Instruction::Return | Instruction::Unreachable => Vec::new(),
+        _ if next > function_end => Vec::new(),
+        _ => vec![(next, EdgeKind::Fallthrough)],
+    }
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Br(_)
+            | Instruction::BrIf(_)
+            | Instruction::BrTable(_)
+            | Instruction::If(_)
+            | Instruction::Else
+            | Instruction::Return
+            | Instruction::Unreachable
+    )
+}
+
+pub fn build(instructions: &[Instruction]) -> Cfg {
+    if instructions.is_empty() {
+        return Cfg::default();
+    }
+    let scopes = match_scopes(instructions);
+
+    // Walk the body once with a label stack to resolve every branch
+    let mut labels = Vec::new();
+    let mut successors = Vec::with_capacity(instructions.len());
+    for (index, instruction) in instructions.iter().enumerate() {
+        successors.push(targets(instructions, &scopes, &labels, index));
+        match instruction {
+            Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => labels.push(index),
+            Instruction::End => {
+                labels.pop();
+            }
+            _ => (),
+        }
+    }
+
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    for (index, instruction) in instructions.iter().enumerate() {
+        if ends_block(instruction) {
+            leaders.insert(index as u32 + 1);
+        }
+        for (target, kind) in &successors[index] {
+            if *kind != EdgeKind::Fallthrough {
+                leaders.insert(*target);
+            }
+        }
+    }
+    let len = instructions.len() as u32;
+    let leaders: Vec<u32> = leaders.into_iter().filter(|leader| *leader < len).collect();
+
+    let blocks: Vec<BasicBlock> = leaders
+        .iter()
+        .enumerate()
+        .map(|(block, &start)| BasicBlock {
+            start,
+            end: leaders.get(block + 1).copied().unwrap_or(len),
+        })
+        .collect();
+    let mut edges = Vec::new();
+    for (from, block) in blocks.iter().enumerate() {
+        for (target, kind) in &successors[block.end as usize - 1] {
+            if let Ok(to) = leaders.binary_search(target) {
+                edges.push(CfgEdge { from, to, kind: *kind });
+            }
+        }
+    }
+    Cfg { blocks, edges }
+}
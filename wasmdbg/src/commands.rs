@@ -0,0 +1,388 @@
+//! Line based commands to drive a `Debugger` from a script, see `Debugger::execute_command`.
+//!
+//! Numbers are decimal or hexadecimal with a `0x` prefix. Empty lines and lines starting with
+//! `#` do nothing.
+
+use std::fmt::Write;
+
+use crate::vm::import_func::ImportFunctionHandler;
+use crate::vm::{CodePosition, Trap};
+use crate::{Breakpoint, BreakpointTrigger, Debugger, DebuggerError, DebuggerResult};
+
+type Handler<F> = fn(&mut Debugger<F>, &[&str]) -> DebuggerResult<String>;
+
+struct Command<F: ImportFunctionHandler> {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    handler: Handler<F>,
+}
+
+fn commands<F: ImportFunctionHandler>() -> Vec<Command<F>> {
+    vec![
+        Command {
+            name: "run",
+            aliases: &["r"],
+            usage: "run",
+            handler: cmd_run,
+        },
+        Command {
+            name: "start",
+            aliases: &[],
+            usage: "start",
+            handler: cmd_start,
+        },
+        Command {
+            name: "call",
+            aliases: &[],
+            usage: "call FUNC_INDEX [ARGS...]",
+            handler: cmd_call,
+        },
+        Command {
+            name: "continue",
+            aliases: &["c"],
+            usage: "continue",
+            handler: cmd_continue,
+        },
+        Command {
+            name: "step",
+            aliases: &["s"],
+            usage: "step [COUNT]",
+            handler: cmd_step,
+        },
+        Command {
+            name: "next",
+            aliases: &["n"],
+            usage: "next [COUNT]",
+            handler: cmd_next,
+        },
+        Command {
+            name: "finish",
+            aliases: &[],
+            usage: "finish",
+            handler: cmd_finish,
+        },
+        Command {
+            name: "break",
+            aliases: &["b"],
+            usage:
+                "break func FUNC_INDEX [INSTR_INDEX] | break global INDEX [read|write] | break mem ADDR [read|write]",
+            handler: cmd_break,
+        },
+        Command {
+            name: "delete",
+            aliases: &[],
+            usage: "delete all|BREAKPOINT_INDEX",
+            handler: cmd_delete,
+        },
+        Command {
+            name: "print",
+            aliases: &["p"],
+            usage: "print EXPR",
+            handler: cmd_print,
+        },
+        Command {
+            name: "mem",
+            aliases: &["x"],
+            usage: "mem ADDR LEN",
+            handler: cmd_mem,
+        },
+        Command {
+            name: "backtrace",
+            aliases: &["bt"],
+            usage: "backtrace",
+            handler: cmd_backtrace,
+        },
+    ]
+}
+
+pub(crate) fn execute<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, line: &str) -> DebuggerResult<String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, _)) if name.starts_with('#') => return Ok(String::new()),
+        Some((name, args)) => (*name, args),
+        None => return Ok(String::new()),
+    };
+    let commands = commands::<F>();
+    let command = commands
+        .iter()
+        .find(|cmd| cmd.name == name || cmd.aliases.contains(&name))
+        .ok_or_else(|| DebuggerError::UnknownCommand(name.to_owned()))?;
+    (command.handler)(dbg, args).map_err(|err| match err {
+        DebuggerError::InvalidCommandArgs { .. } => DebuggerError::InvalidCommandArgs { usage: command.usage },
+        err => err,
+    })
+}
+
+/// Signals wrong arguments, `execute` fills in the usage of the command.
+fn invalid_args() -> DebuggerError {
+    DebuggerError::InvalidCommandArgs { usage: "" }
+}
+
+fn parse_u32(arg: &str) -> DebuggerResult<u32> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|_| invalid_args())
+}
+
+fn optional_count(args: &[&str]) -> DebuggerResult<u32> {
+    match args {
+        [] => Ok(1),
+        [count] => parse_u32(count),
+        _ => Err(invalid_args()),
+    }
+}
+
+fn parse_trigger(args: &[&str]) -> DebuggerResult<BreakpointTrigger> {
+    match args {
+        [] => Ok(BreakpointTrigger::ReadWrite),
+        ["read"] => Ok(BreakpointTrigger::Read),
+        ["write"] => Ok(BreakpointTrigger::Write),
+        _ => Err(invalid_args()),
+    }
+}
+
+fn no_args(args: &[&str]) -> DebuggerResult<()> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        Err(invalid_args())
+    }
+}
+
+fn paused_at<F: ImportFunctionHandler>(dbg: &Debugger<F>) -> DebuggerResult<CodePosition> {
+    Ok(dbg.get_vm()?.ip())
+}
+
+fn describe_trap<F: ImportFunctionHandler>(dbg: &Debugger<F>, trap: Trap) -> DebuggerResult<String> {
+    Ok(match trap {
+        Trap::ExecutionFinished => match dbg.get_vm()?.value_stack().first() {
+            Some(result) => format!("Finished execution => {}", result),
+            None => "Finished execution".to_owned(),
+        },
+        Trap::BreakpointReached(index) => format!("Reached breakpoint {} at {}", index, paused_at(dbg)?),
+        Trap::WatchpointReached(index) => format!("Reached watchpoint {} at {}", index, paused_at(dbg)?),
+        trap => match dbg.trap_position()? {
+            Some(pos) => format!("Trap at {}: {}", pos, trap),
+            None => format!("Trap: {}", trap),
+        },
+    })
+}
+
+fn describe_stop<F: ImportFunctionHandler>(dbg: &Debugger<F>, trap: Option<Trap>) -> DebuggerResult<String> {
+    match trap {
+        Some(trap) => describe_trap(dbg, trap),
+        None => Ok(format!("Paused at {}", paused_at(dbg)?)),
+    }
+}
+
+fn cmd_run<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    no_args(args)?;
+    let trap = dbg.run()?;
+    describe_trap(dbg, trap)
+}
+
+fn cmd_start<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    no_args(args)?;
+    let trap = dbg.start()?;
+    describe_stop(dbg, trap)
+}
+
+fn cmd_call<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    let (index, args) = args.split_first().ok_or_else(invalid_args)?;
+    let trap = dbg.call_with_str_args(parse_u32(index)?, args)?;
+    describe_trap(dbg, trap)
+}
+
+fn cmd_continue<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    no_args(args)?;
+    let trap = dbg.continue_execution()?;
+    describe_trap(dbg, trap)
+}
+
+fn cmd_step<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    for _ in 0..optional_count(args)? {
+        if let Some(trap) = dbg.execute_step()? {
+            return describe_trap(dbg, trap);
+        }
+    }
+    describe_stop(dbg, None)
+}
+
+fn cmd_next<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    for _ in 0..optional_count(args)? {
+        if let Some(trap) = dbg.execute_step_over()? {
+            return describe_trap(dbg, trap);
+        }
+    }
+    describe_stop(dbg, None)
+}
+
+fn cmd_finish<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    no_args(args)?;
+    let result = dbg.finish()?;
+    // The return values are listed below instead
+    let mut output = match result.trap {
+        Some(Trap::ExecutionFinished) => "Finished execution".to_owned(),
+        trap => describe_stop(dbg, trap)?,
+    };
+    for value in result.return_values {
+        write!(output, "\nReturned {}", value).unwrap();
+    }
+    Ok(output)
+}
+
+fn cmd_break<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    let breakpoint = match args {
+        ["func", func_index] => Breakpoint::Code(CodePosition::new(parse_u32(func_index)?, 0)),
+        ["func", func_index, instr_index] => {
+            Breakpoint::Code(CodePosition::new(parse_u32(func_index)?, parse_u32(instr_index)?))
+        }
+        ["global", index, trigger @ ..] => Breakpoint::Global {
+            trigger: parse_trigger(trigger)?,
+            index: parse_u32(index)?,
+            cond: None,
+        },
+        ["mem", addr, trigger @ ..] => Breakpoint::Memory(parse_trigger(trigger)?, parse_u32(addr)?),
+        _ => return Err(invalid_args()),
+    };
+    let index = dbg.add_breakpoint(breakpoint.clone())?;
+    Ok(match breakpoint {
+        Breakpoint::Code(pos) => format!("Breakpoint {} at {}", index, pos),
+        _ => format!("Watchpoint {}", index),
+    })
+}
+
+fn cmd_delete<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    match args {
+        ["all"] => {
+            dbg.clear_breakpoints()?;
+            Ok("Deleted all breakpoints".to_owned())
+        }
+        [index] => {
+            let index = parse_u32(index)?;
+            if dbg.delete_breakpoint(index)? {
+                Ok(format!("Deleted breakpoint {}", index))
+            } else {
+                Err(DebuggerError::NoBreakpointWithIndex(index))
+            }
+        }
+        _ => Err(invalid_args()),
+    }
+}
+
+fn cmd_print<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    if args.is_empty() {
+        return Err(invalid_args());
+    }
+    Ok(dbg.evaluate(&args.join(" "))?.to_string())
+}
+
+fn cmd_mem<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    let (address, len) = match args {
+        [address, len] => (parse_u32(address)?, parse_u32(len)?),
+        _ => return Err(invalid_args()),
+    };
+    let bytes = dbg.read_memory(address, len)?;
+    let lines: Vec<String> = bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{:#010x}: {}", address as usize + line * 16, hex.join(" "))
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+fn cmd_backtrace<F: ImportFunctionHandler>(dbg: &mut Debugger<F>, args: &[&str]) -> DebuggerResult<String> {
+    no_args(args)?;
+    let lines: Vec<String> = dbg
+        .backtrace()?
+        .iter()
+        .enumerate()
+        .map(|(index, pos)| match dbg.function_name(pos.func_index) {
+            Some(name) => format!("#{} {} ({})", index, pos, name),
+            None => format!("#{} {}", index, pos),
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{DebuggerError, Value};
+
+    // global 0 = 5. Start function: g0 += 1, then store 42 at address 16
+    fn counter_module() -> Vec<u8> {
+        let code = [
+            0x23, 0x00, 0x41, 0x01, 0x6a, 0x24, 0x00, 0x41, 0x10, 0x41, 0x2a, 0x36, 0x02, 0x00, 0x0b,
+        ];
+        module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(GLOBAL_SECTION, &vector(&[vec![I32, 0x01, 0x41, 0x05, 0x0b]])),
+            section(START_SECTION, &leb(0)),
+            section(CODE_SECTION, &vector(&[func_body(&[], &code)])),
+        ])
+    }
+
+    #[test]
+    fn break_print_and_continue() {
+        let mut dbg = load(&counter_module());
+        let mut exec = |cmd: &str| dbg.execute_command(cmd).unwrap();
+        assert_eq!(exec("break func 0 4"), "Breakpoint 0 at 0:4");
+        assert_eq!(exec("run"), "Reached breakpoint 0 at 0:4");
+        assert_eq!(exec("print g0"), Value::I32(6).to_string());
+        assert_eq!(exec("print g0 == 6"), Value::I32(1).to_string());
+        assert_eq!(exec("mem 0x10 4"), "0x00000010: 00 00 00 00");
+        assert_eq!(exec("step 2"), "Paused at 0:6");
+        assert_eq!(exec("bt"), "#0 0:6");
+        assert_eq!(exec("continue"), "Finished execution");
+        assert_eq!(exec("x 16 4"), "0x00000010: 2a 00 00 00");
+    }
+
+    #[test]
+    fn watchpoints_and_delete() {
+        let mut dbg = load(&counter_module());
+        let mut exec = |cmd: &str| dbg.execute_command(cmd).unwrap();
+        assert_eq!(exec("# set up"), "");
+        assert_eq!(exec(""), "");
+        assert_eq!(exec("break global 0 write"), "Watchpoint 0");
+        assert_eq!(exec("break mem 0x10"), "Watchpoint 1");
+        assert_eq!(exec("run"), "Reached watchpoint 0 at 0:4");
+        assert_eq!(exec("delete 0"), "Deleted breakpoint 0");
+        assert_eq!(exec("c"), "Reached watchpoint 1 at 0:7");
+        assert_eq!(exec("delete all"), "Deleted all breakpoints");
+        assert_eq!(exec("continue"), "Finished execution");
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let mut dbg = load(&counter_module());
+        assert!(matches!(
+            dbg.execute_command("frobnicate"),
+            Err(DebuggerError::UnknownCommand(name)) if name == "frobnicate"
+        ));
+        assert!(matches!(
+            dbg.execute_command("break func x"),
+            Err(DebuggerError::InvalidCommandArgs { usage }) if usage.starts_with("break func")
+        ));
+        assert!(matches!(
+            dbg.execute_command("mem 16"),
+            Err(DebuggerError::InvalidCommandArgs { usage: "mem ADDR LEN" })
+        ));
+        assert!(matches!(
+            dbg.execute_command("continue"),
+            Err(DebuggerError::NoRunningInstance)
+        ));
+        assert!(matches!(
+            dbg.execute_command("delete 3"),
+            Err(DebuggerError::NoBreakpointWithIndex(3))
+        ));
+    }
+}
@@ -1,12 +1,28 @@
-use std::sync::{Arc, MutexGuard};
+use std::cmp::Reverse;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use bwasm::{LoadError, Module};
+use bwasm::{External, InitExpr, Instruction, Internal, LoadError, Module, ValueType};
 use thiserror::Error;
 
+use crate::binary::{self, BinaryLayout};
+use crate::cfg::{self, Cfg};
+use crate::commands;
 use crate::debuginfo::DebugInfo;
-use crate::vm::import_func::DefaultImportFunctionHandler;
-use crate::vm::{import_func, CodePosition, InitError, Memory, Trap, VM};
-use crate::{Breakpoint, Breakpoints, File, Value};
+use crate::eval::{self, EvalError};
+use crate::linker::{self, FunctionLink, LinkError};
+use crate::validate;
+use crate::vm::import_func::{DefaultImportFunctionHandler, RegistryImportFunctionHandler};
+use crate::vm::{
+    import_func, value_type_size, CodePosition, CostModel, CoverageReport, InitError, InstructionHook, InterruptHandle,
+    MemInitPattern, Memory, StateDiff, TableElement, Trace, Trap, TrapCategory, VMResult, VmSnapshot, WatchpointHit,
+    DEFAULT_MAX_CALL_DEPTH, DEFAULT_TRACE_LIMIT, VM,
+};
+use crate::{
+    Breakpoint, BreakpointTrigger, Breakpoints, DisplayMode, Endianness, File, SourceLocation, Value,
+    WasiImportFunctionHandler, F32, F64,
+};
 
 #[derive(Error, Clone, Debug)]
 pub enum DebuggerError {
@@ -14,39 +30,412 @@ pub enum DebuggerError {
     InitError(#[from] InitError),
     #[error("No binary file loaded")]
     NoFileLoaded,
+    #[error("Failed to load binary: {0}")]
+    LoadFailed(String),
     #[error("The binary is not being run")]
     NoRunningInstance,
     #[error("No memory present")]
     NoMemory,
+    #[error("No table present")]
+    NoTable,
+    #[error("Memory access of {len} bytes at {address:#010x} is out of bounds")]
+    MemoryOutOfBounds { address: u32, len: u64 },
     #[error("Invalid brekapoint position")]
     InvalidBreakpointPosition,
     #[error("Invalid global for watchpoint")]
     InvalidWatchpointGlobal,
+    #[error("Can't reinterpret {value} as {value_type}, the sizes differ")]
+    InvalidReinterpretation { value: String, value_type: ValueType },
+    #[error("Validation failed at {pos}: {message}")]
+    ValidationFailed { pos: CodePosition, message: String },
+    #[error("Watchpoint condition doesn't match the type of global {0}")]
+    WatchpointConditionTypeMismatch(u32),
+    #[error("Reached the maximum of {0} breakpoints")]
+    TooManyBreakpoints(usize),
+    #[error("No function with index {0}")]
+    NoFunctionWithIndex(u32),
+    #[error("Function {0} is imported and has no body")]
+    ImportedFunction(u32),
+    #[error("Failed to locate the body of function {0} in the binary")]
+    FunctionBodyNotFound(u32),
+    #[error("Nothing to restart, start or call a function first")]
+    NothingToRestart,
+    #[error("No breakpoint with index {0}")]
+    NoBreakpointWithIndex(u32),
+    #[error("No instruction at {0}")]
+    NoInstructionAt(CodePosition),
+    #[error("No instruction at code section offset {0:#x}")]
+    NoInstructionAtOffset(u32),
+    #[error("No constant at {0}")]
+    NoConstAt(CodePosition),
+    #[error("The constant at {pos} is an {expected}, it can't be replaced with {value}")]
+    ConstTypeMismatch {
+        pos: CodePosition,
+        expected: ValueType,
+        value: String,
+    },
+    #[error("{value} doesn't fit into the {len} bytes of the constant at {pos}")]
+    ConstTooLong {
+        pos: CodePosition,
+        value: String,
+        len: usize,
+    },
+    #[error("The next instruction is no call_indirect with a function in its table slot")]
+    NoIndirectCallTarget,
+    #[error("No function named \"{0}\"")]
+    FunctionNotFound(String),
+    #[error("Argument mismatch. Expected {expected:?}, got {got:?}")]
+    ArgumentMismatch {
+        expected: Vec<ValueType>,
+        got: Vec<ValueType>,
+    },
+    #[error("{import} is imported as {expected:?}, the host function has {found:?}")]
+    HostFunctionSignatureMismatch {
+        import: String,
+        /// Parameter and return types
+        expected: (Vec<ValueType>, Vec<ValueType>),
+        found: (Vec<ValueType>, Vec<ValueType>),
+    },
+    #[error("Argument {0} is a v128, which no parameter of the function can take")]
+    V128Argument(usize),
+    #[error("Failed to parse argument \"{value}\" as {value_type}")]
+    InvalidArgument { value: String, value_type: ValueType },
+    #[error("Failed to evaluate expression: {0}")]
+    EvalError(#[from] EvalError),
+    #[error("Failed to link modules: {0}")]
+    LinkError(#[from] LinkError),
+    #[error("No frame {index} in a call stack of {depth} frames")]
+    InvalidFrameIndex { index: usize, depth: usize },
+    #[error("The snapshot was taken from a different module")]
+    SnapshotMismatch,
+    #[error("Failed to access \"{path}\": {reason}")]
+    FileAccess { path: String, reason: String },
+    #[error("Unknown command \"{0}\"")]
+    UnknownCommand(String),
+    #[error("Usage: {usage}")]
+    InvalidCommandArgs { usage: &'static str },
     #[error("This feature is still unimplemented")]
     Unimplemented,
 }
 
 pub type DebuggerResult<T> = Result<T, DebuggerError>;
 
+/// Entries per list printed by `Debugger::dump_state`.
+pub const DEFAULT_DUMP_LIMIT: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionInfo {
+    pub index: u32,
+    pub name: Option<String>,
+    pub param_types: Vec<ValueType>,
+    pub return_types: Vec<ValueType>,
+    pub is_imported: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportKind {
+    Function,
+    Table,
+    Memory,
+    Global,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportInfo {
+    pub name: String,
+    pub kind: ExportKind,
+    pub index: u32,
+}
+
+/// `kind` is what is imported. The types are empty unless a function is imported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportInfo {
+    pub module: String,
+    pub field: String,
+    pub kind: ExportKind,
+    pub param_types: Vec<ValueType>,
+    pub return_types: Vec<ValueType>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResourceLimits {
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+/// See `Debugger::module_summary`. Memory limits are in pages, table limits in elements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleSummary {
+    pub imported_functions: u32,
+    pub defined_functions: u32,
+    pub globals: u32,
+    pub memories: Vec<ResourceLimits>,
+    pub tables: Vec<ResourceLimits>,
+    pub imports: u32,
+    pub exports: u32,
+    pub has_start: bool,
+}
+
+/// See `Debugger::data_segments`. `offset` is `None` if the offset expression isn't a constant,
+/// e.g. `global.get`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataSegmentInfo {
+    pub index: u32,
+    pub memory_index: u32,
+    pub offset_expr: String,
+    pub offset: Option<u32>,
+    pub len: u32,
+}
+
+/// See `Debugger::element_segments`. `offset` is `None` if the offset expression isn't a constant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementSegmentInfo {
+    pub index: u32,
+    pub table_index: u32,
+    pub offset_expr: String,
+    pub offset: Option<u32>,
+    pub functions: Vec<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstr {
+    pub instr_index: u32,
+    pub mnemonic: String,
+    pub operands: String,
+    pub has_breakpoint: bool,
+}
+
+/// An immediate operand of an instruction, with the name of the item it refers to if known.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Function {
+        index: u32,
+        name: Option<String>,
+    },
+    Local {
+        index: u32,
+        name: Option<String>,
+    },
+    Global {
+        index: u32,
+        name: Option<String>,
+    },
+    /// Relative depth of the label a branch targets
+    BranchDepth(u32),
+    /// Signature of a `call_indirect`
+    Type(u32),
+    /// `align` is the log2 of the alignment hint.
+    Memory {
+        offset: u32,
+        align: u32,
+    },
+    Const(Value),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let named = |f: &mut fmt::Formatter<'_>, kind: &str, index: u32, name: &Option<String>| match name {
+            Some(name) => write!(f, "${} ({} {})", name, kind, index),
+            None => write!(f, "{} {}", kind, index),
+        };
+        match self {
+            Operand::Function { index, name } => named(f, "func", *index, name),
+            Operand::Local { index, name } => named(f, "local", *index, name),
+            Operand::Global { index, name } => named(f, "global", *index, name),
+            Operand::BranchDepth(depth) => write!(f, "{}", depth),
+            Operand::Type(index) => write!(f, "type {}", index),
+            Operand::Memory { offset, align } => write!(f, "offset={} align={}", offset, 1u64 << align),
+            Operand::Const(value) => write!(f, "{}", value.format(DisplayMode::Decimal)),
+        }
+    }
+}
+
+/// The decoded instruction at `position`, see `Debugger::current_instruction`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstructionInfo {
+    pub position: CodePosition,
+    pub mnemonic: String,
+    pub operands: Vec<Operand>,
+}
+
+impl fmt::Display for InstructionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for operand in &self.operands {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of `Debugger::finish` and `Debugger::call_with_results`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinishResult {
+    /// `None` if the function returned to its caller, `ExecutionFinished` if it was the outermost.
+    pub trap: Option<Trap>,
+    /// Empty if the function didn't return, e.g. because it hit a breakpoint.
+    pub return_values: Vec<Value>,
+}
+
+/// See `Debugger::list_breakpoints`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BreakpointInfo {
+    pub index: u32,
+    pub breakpoint: Breakpoint,
+    pub enabled: bool,
+    pub hit_count: u64,
+}
+
+/// One entry of `Debugger::backtrace_detailed`, innermost frame first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub func_index: u32,
+    pub func_name: Option<String>,
+    pub instr_index: u32,
+    pub source: Option<SourceLocation>,
+    pub locals_count: usize,
+}
+
+/// The locals of a frame with their names, see `Debugger::frame_locals`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameLocals {
+    pub func_index: u32,
+    pub locals: Vec<(Option<String>, Value)>,
+}
+
+/// A value stack slot, see `Debugger::value_stack_annotated`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackSlot {
+    /// Index from the bottom of the stack
+    pub index: usize,
+    /// Index from the top of the stack, 0 is the top
+    pub depth: usize,
+    pub value: Value,
+    pub call_argument: Option<CallArgument>,
+}
+
+/// Marks a slot as argument `param_index` of the call to `func_index` the next instruction makes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallArgument {
+    pub func_index: u32,
+    pub param_index: u32,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedBreakpoint {
+    breakpoint: Breakpoint,
+    enabled: bool,
+}
+
+/// The contents of a file written by `Debugger::save_breakpoints`, keyed by module path.
+#[cfg(feature = "serde")]
+type BreakpointSession = std::collections::BTreeMap<String, Vec<SavedBreakpoint>>;
+
+#[cfg(feature = "serde")]
+fn read_session(path: &str) -> DebuggerResult<BreakpointSession> {
+    let file_access = |reason: String| DebuggerError::FileAccess {
+        path: path.to_owned(),
+        reason,
+    };
+    let json = std::fs::read_to_string(path).map_err(|err| file_access(err.to_string()))?;
+    serde_json::from_str(&json).map_err(|err| file_access(err.to_string()))
+}
+
+/// Snapshot of the paused VM for external tools, see `Debugger::state_json`.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct DebuggerStateJson {
+    pub ip: CodePosition,
+    /// Innermost frame first, like `Debugger::backtrace`
+    pub backtrace: Vec<CodePosition>,
+    pub value_stack: Vec<Value>,
+    /// Of the innermost frame
+    pub locals: Vec<Value>,
+    pub globals: Vec<Value>,
+    pub memory_pages: Option<u32>,
+    pub executed_instructions: u64,
+}
+
+/// Where the paused VM is, see `Debugger::current_position`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CurrentPosition {
+    pub func_index: u32,
+    pub func_name: Option<String>,
+    pub instr_index: u32,
+    /// `None` if the ip doesn't point at an instruction, e.g. in an imported function.
+    pub instruction_text: Option<String>,
+    pub source: Option<SourceLocation>,
+}
+
+/// How the last instance was started, see `Debugger::restart`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunEntry {
+    Start,
+    Run,
+    Call { func_index: u32, args: Vec<Value> },
+}
+
 pub type DefaultDebugger = Debugger<DefaultImportFunctionHandler>;
+pub type WasiDebugger = Debugger<WasiImportFunctionHandler>;
+pub type RegistryDebugger = Debugger<RegistryImportFunctionHandler>;
 pub struct Debugger<F>
 where
     F: import_func::ImportFunctionHandler,
 {
     file: Option<File>,
+    libraries: Vec<File>,
     vm: Option<VM<F>>,
     info: Option<DebugInfo>,
+    load_warnings: Vec<String>,
+    /// The binary of the main module, kept for `patch_const`.
+    module_bytes: Vec<u8>,
+    import_function_handler: F,
+    interrupt: InterruptHandle,
+    fuel: Option<u64>,
+    cost_model: CostModel,
+    profiling: bool,
+    coverage: bool,
+    break_on_trap: bool,
+    skip_start: bool,
+    max_memory_pages: Option<u32>,
+    breakpoint_limit: Option<usize>,
+    memory_init_pattern: MemInitPattern,
+    max_call_depth: usize,
+    last_run: Option<RunEntry>,
+    pre_instruction_hook: Option<Arc<Mutex<InstructionHook<F>>>>,
+    trace: Option<Arc<Mutex<Trace>>>,
+    trace_limit: u64,
 }
 
 impl<F> Debugger<F>
 where
     F: import_func::ImportFunctionHandler,
 {
-    pub const fn new() -> Self {
+    pub fn new() -> Self {
         Debugger {
             file: None,
+            libraries: Vec::new(),
             vm: None,
             info: None,
+            load_warnings: Vec::new(),
+            module_bytes: Vec::new(),
+            import_function_handler: F::default(),
+            interrupt: InterruptHandle::default(),
+            fuel: None,
+            cost_model: CostModel::default(),
+            profiling: false,
+            coverage: false,
+            break_on_trap: false,
+            skip_start: false,
+            max_memory_pages: None,
+            breakpoint_limit: None,
+            memory_init_pattern: MemInitPattern::Zero,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            last_run: None,
+            pre_instruction_hook: None,
+            trace: None,
+            trace_limit: DEFAULT_TRACE_LIMIT,
         }
     }
 
@@ -58,16 +447,119 @@ where
         self.vm.as_ref()
     }
 
-    pub fn load_file(&mut self, file_path: &str) -> Result<(), LoadError> {
-        let module = Module::from_file(file_path)?;
+    /// The handler that newly created VMs start with. Changes don't affect a running VM.
+    pub fn import_function_handler_mut(&mut self) -> &mut F {
+        &mut self.import_function_handler
+    }
 
-        self.info = Some(DebugInfo::new(&file_path[..]));
-        self.file = Some(File::new(file_path.to_owned(), module));
-        self.vm = None;
+    pub fn load_file(&mut self, file_path: &str) -> DebuggerResult<()> {
+        let bytes = std::fs::read(file_path).map_err(|err| DebuggerError::LoadFailed(err.to_string()))?;
+        self.load_bytes(file_path, &bytes)
+            .map_err(|err| DebuggerError::LoadFailed(err.to_string()))
+    }
+
+    /// Loads a module from memory. `name` takes the place of the file path.
+    pub fn load_bytes(&mut self, name: &str, bytes: &[u8]) -> Result<(), LoadError> {
+        let module = Module::from_bytes(bytes)?;
+        self.set_module(name, module, bytes);
+        Ok(())
+    }
 
+    /// Loads a library module whose exports the function imports of the other modules link to,
+    /// see `linker::resolve`. Imports refer to it by its file name without extension.
+    /// Fails without keeping the module if linking fails.
+    pub fn load_additional(&mut self, path: &str) -> DebuggerResult<()> {
+        let module = Module::from_file(path).map_err(|err| DebuggerError::LoadFailed(err.to_string()))?;
+        let name = linker::module_name(path);
+        if self
+            .libraries
+            .iter()
+            .any(|library| linker::module_name(library.file_path()) == name)
+        {
+            return Err(LinkError::DuplicateModuleName(name).into());
+        }
+        self.libraries.push(File::new(path.to_owned(), module));
+        if let Err(err) = self.resolve_links() {
+            self.libraries.pop();
+            return Err(err);
+        }
+        self.vm = None;
         Ok(())
     }
 
+    pub fn additional_files(&self) -> &[File] {
+        &self.libraries
+    }
+
+    /// The links of the main module and of every library.
+    fn resolve_links(&self) -> DebuggerResult<(Vec<FunctionLink>, Vec<Vec<FunctionLink>>)> {
+        let libraries: Vec<(String, &Module)> = self
+            .libraries
+            .iter()
+            .map(|library| (linker::module_name(library.file_path()), &**library.module()))
+            .collect();
+        let main_links = match &self.file {
+            Some(file) => linker::resolve(file.module(), &libraries, None)?,
+            None => Vec::new(),
+        };
+        let library_links = libraries
+            .iter()
+            .enumerate()
+            .map(|(index, (_, module))| linker::resolve(module, &libraries, Some(index)))
+            .collect::<Result<_, _>>()?;
+        Ok((main_links, library_links))
+    }
+
+    fn set_module(&mut self, name: &str, module: Module, bytes: &[u8]) {
+        self.load_debug_info(bytes);
+        self.module_bytes = bytes.to_vec();
+        let file = File::new(name.to_owned(), module);
+        file.breakpoints_and_unlock().set_limit(self.breakpoint_limit);
+        self.file = Some(file);
+        self.vm = None;
+        self.last_run = None;
+    }
+
+    /// Reloads the current file from disk, keeping the breakpoints that are still valid in the
+    /// new module. Returns the breakpoints that had to be dropped together with their indices.
+    pub fn reload(&mut self) -> DebuggerResult<Vec<(u32, Breakpoint)>> {
+        let file = self.get_file()?;
+        let file_path = file.file_path().clone();
+        let breakpoints = Arc::clone(file.breakpoints());
+        let bytes = std::fs::read(&file_path).map_err(|err| DebuggerError::LoadFailed(err.to_string()))?;
+        let module = Module::from_bytes(&bytes).map_err(|err| DebuggerError::LoadFailed(err.to_string()))?;
+
+        let dropped = breakpoints
+            .lock()
+            .unwrap()
+            .retain(|breakpoint| Self::check_breakpoint(&module, breakpoint).is_ok());
+        self.load_debug_info(&bytes);
+        self.module_bytes = bytes;
+        self.file = Some(File::with_breakpoints(file_path, module, breakpoints));
+        self.vm = None;
+
+        Ok(dropped)
+    }
+
+    fn load_debug_info(&mut self, bytes: &[u8]) {
+        self.load_warnings.clear();
+        self.info = match DebugInfo::new(bytes) {
+            Ok(info) => {
+                self.load_warnings.extend_from_slice(info.warnings());
+                Some(info)
+            }
+            Err(err) => {
+                self.load_warnings.push(format!("Failed to load debug info: {}", err));
+                None
+            }
+        };
+    }
+
+    /// Problems with the debug info of the last loaded module. The module itself loaded fine.
+    pub fn load_warnings(&self) -> &[String] {
+        &self.load_warnings
+    }
+
     pub fn backtrace(&self) -> DebuggerResult<Vec<CodePosition>> {
         let vm = self.get_vm()?;
         let mut backtrace = vec![vm.ip()];
@@ -77,6 +569,196 @@ where
         Ok(backtrace)
     }
 
+    pub fn backtrace_detailed(&self) -> DebuggerResult<Vec<Frame>> {
+        let function_stack = self.get_vm()?.function_stack();
+        Ok(self
+            .backtrace()?
+            .into_iter()
+            .zip(function_stack.iter().rev())
+            .map(|(pos, frame)| Frame {
+                func_index: pos.func_index,
+                func_name: self.function_name(pos.func_index).cloned(),
+                instr_index: pos.instr_index,
+                source: self.source_location(pos),
+                locals_count: frame.locals.len(),
+            })
+            .collect())
+    }
+
+    /// The locals of the frame `frame_from_top` frames below the innermost one.
+    pub fn frame_locals(&self, frame_from_top: usize) -> DebuggerResult<FrameLocals> {
+        let function_stack = self.get_vm()?.function_stack();
+        let (pos, frame) = self
+            .backtrace()?
+            .into_iter()
+            .zip(function_stack.iter().rev())
+            .nth(frame_from_top)
+            .ok_or(DebuggerError::InvalidFrameIndex {
+                index: frame_from_top,
+                depth: function_stack.len(),
+            })?;
+        Ok(FrameLocals {
+            func_index: pos.func_index,
+            locals: frame
+                .locals
+                .iter()
+                .enumerate()
+                .map(|(index, value)| (self.local_name(pos.func_index, index as u32).cloned(), *value))
+                .collect(),
+        })
+    }
+
+    /// The declared types of the locals of the frame `frame_from_top` frames below the innermost
+    /// one: the parameters followed by the locals of the function body. The values in
+    /// `frame_locals` always have these types.
+    pub fn frame_local_types(&self, frame_from_top: usize) -> DebuggerResult<Vec<ValueType>> {
+        let depth = self.get_vm()?.function_stack().len();
+        let pos =
+            self.backtrace()?
+                .into_iter()
+                .take(depth)
+                .nth(frame_from_top)
+                .ok_or(DebuggerError::InvalidFrameIndex {
+                    index: frame_from_top,
+                    depth,
+                })?;
+        let func = self
+            .get_file()?
+            .module()
+            .get_func(pos.func_index)
+            .ok_or(DebuggerError::NoFunctionWithIndex(pos.func_index))?;
+        Ok(func.func_type().params().iter().chain(func.locals()).copied().collect())
+    }
+
+    /// The value stack from bottom to top. If the VM is paused on a call, the slots holding the
+    /// arguments are marked, for a `call_indirect` only if its callee can be resolved.
+    pub fn value_stack_annotated(&self) -> DebuggerResult<Vec<StackSlot>> {
+        let vm = self.get_vm()?;
+        let ip = vm.ip();
+        let next_instr = self
+            .get_file()?
+            .module()
+            .get_func(ip.func_index)
+            .and_then(|func| func.instructions().get(ip.instr_index as usize));
+        // The callee and the depth of its last argument
+        let pending_call = match next_instr {
+            Some(Instruction::Call(func_index)) => Some((*func_index, 0)),
+            Some(Instruction::CallIndirect(..)) => vm.indirect_call_target().map(|func_index| (func_index, 1)),
+            _ => None,
+        };
+        let param_count = match pending_call {
+            Some((func_index, _)) => self.param_types(func_index)?.len(),
+            None => 0,
+        };
+
+        let stack = vm.value_stack();
+        Ok(stack
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let depth = stack.len() - 1 - index;
+                let call_argument = pending_call.and_then(|(func_index, last_arg_depth)| {
+                    let arg_from_last = depth.checked_sub(last_arg_depth).filter(|arg| *arg < param_count)?;
+                    Some(CallArgument {
+                        func_index,
+                        param_index: (param_count - 1 - arg_from_last) as u32,
+                    })
+                });
+                StackSlot {
+                    index,
+                    depth,
+                    value: *value,
+                    call_argument,
+                }
+            })
+            .collect())
+    }
+
+    /// Saves the breakpoints of the main module to the session file at `path`, under the path
+    /// of the module. Entries of other modules in the file are kept.
+    #[cfg(feature = "serde")]
+    pub fn save_breakpoints(&self, path: &str) -> DebuggerResult<()> {
+        let file = self.get_file()?;
+        let mut session = if std::path::Path::new(path).exists() {
+            read_session(path)?
+        } else {
+            BreakpointSession::new()
+        };
+        let saved = self
+            .list_breakpoints()?
+            .into_iter()
+            .map(|info| SavedBreakpoint {
+                breakpoint: info.breakpoint,
+                enabled: info.enabled,
+            })
+            .collect();
+        session.insert(file.file_path().clone(), saved);
+        let json = serde_json::to_string_pretty(&session).expect("breakpoints only contain serializable types");
+        std::fs::write(path, json).map_err(|err| DebuggerError::FileAccess {
+            path: path.to_owned(),
+            reason: err.to_string(),
+        })
+    }
+
+    /// Adds the breakpoints saved for the main module in the session file at `path` to the
+    /// current ones. Returns the breakpoints that were skipped because they don't resolve in
+    /// the module or the breakpoint limit was reached.
+    #[cfg(feature = "serde")]
+    pub fn load_breakpoints(&mut self, path: &str) -> DebuggerResult<Vec<Breakpoint>> {
+        let file_path = self.get_file()?.file_path().clone();
+        let saved = read_session(path)?.remove(&file_path).unwrap_or_default();
+        let mut skipped = Vec::new();
+        for SavedBreakpoint { breakpoint, enabled } in saved {
+            match self.add_breakpoint(breakpoint.clone()) {
+                Ok(index) => self.set_breakpoint_enabled(index, enabled)?,
+                Err(_) => skipped.push(breakpoint),
+            }
+        }
+        Ok(skipped)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn state_json(&self) -> DebuggerResult<String> {
+        let vm = self.get_vm()?;
+        let state = DebuggerStateJson {
+            ip: vm.ip(),
+            backtrace: self.backtrace()?,
+            value_stack: vm.value_stack().to_vec(),
+            locals: vm.locals().map(<[Value]>::to_vec).unwrap_or_default(),
+            globals: vm.globals().to_vec(),
+            memory_pages: vm.default_memory().ok().map(Memory::page_count),
+            executed_instructions: vm.executed_instructions(),
+        };
+        Ok(serde_json::to_string(&state).expect("the state only contains serializable types"))
+    }
+
+    /// False if there is no running instance, e.g. after `reset_vm`.
+    pub fn is_finished(&self) -> bool {
+        self.vm.as_ref().map_or(false, |vm| vm.is_finished())
+    }
+
+    /// Where the fault the VM stopped with was raised, e.g. the `unreachable` a panic ends in.
+    pub fn trap_position(&self) -> DebuggerResult<Option<CodePosition>> {
+        Ok(self.get_vm()?.trap_position())
+    }
+
+    pub fn current_position(&self) -> DebuggerResult<CurrentPosition> {
+        let ip = self.get_vm()?.ip();
+        let instruction_text = self
+            .get_file()?
+            .module()
+            .get_func(ip.func_index)
+            .and_then(|func| func.instructions().get(ip.instr_index as usize))
+            .map(ToString::to_string);
+        Ok(CurrentPosition {
+            func_index: ip.func_index,
+            func_name: self.function_name(ip.func_index).cloned(),
+            instr_index: ip.instr_index,
+            instruction_text,
+            source: self.source_location(ip),
+        })
+    }
+
     pub fn function_name(&self, func_index: u32) -> Option<&String> {
         if let Some(info) = &self.info {
             return info.function_name_map().get(&func_index);
@@ -93,132 +775,3589 @@ where
         None
     }
 
-    pub fn globals(&self) -> DebuggerResult<&[Value]> {
-        Ok(self.get_vm()?.globals())
+    pub fn global_name(&self, index: u32) -> Option<&String> {
+        self.info.as_ref()?.global_name_map().get(&index)
     }
 
-    pub fn memory(&self) -> DebuggerResult<&Memory> {
-        match self.get_vm()?.default_memory() {
-            Ok(memory) => Ok(memory),
-            Err(Trap::NoMemory) => Err(DebuggerError::NoMemory),
-            Err(_) => unreachable!(),
+    pub fn memory_name(&self, index: u32) -> Option<&String> {
+        self.info.as_ref()?.memory_name_map().get(&index)
+    }
+
+    pub fn source_location(&self, pos: CodePosition) -> Option<SourceLocation> {
+        self.info.as_ref()?.source_location(pos.func_index, pos.instr_index)
+    }
+
+    /// The offset of an instruction relative to the start of the code section payload, the
+    /// address DWARF and `addr2line`-style tools use for it.
+    pub fn instr_offset(&self, func_index: u32, instr_index: u32) -> DebuggerResult<u32> {
+        self.get_file()?;
+        self.info
+            .as_ref()
+            .and_then(|info| info.instruction_offset(func_index, instr_index))
+            .ok_or_else(|| DebuggerError::NoInstructionAt(CodePosition::new(func_index, instr_index)))
+    }
+
+    /// The inverse of `instr_offset`. An offset within the immediates of an instruction maps to
+    /// that instruction.
+    pub fn offset_to_instr(&self, offset: u32) -> DebuggerResult<CodePosition> {
+        self.get_file()?;
+        self.info
+            .as_ref()
+            .and_then(|info| info.instruction_at_offset(offset))
+            .ok_or(DebuggerError::NoInstructionAtOffset(offset))
+    }
+
+    /// Name and byte length of every custom section in module order. Sections sharing a name are
+    /// all listed.
+    pub fn custom_sections(&self) -> DebuggerResult<Vec<(String, usize)>> {
+        self.get_file()?;
+        Ok(self
+            .info
+            .iter()
+            .flat_map(DebugInfo::custom_sections)
+            .map(|(name, payload)| (name.clone(), payload.len()))
+            .collect())
+    }
+
+    /// The payload of the first custom section called `name`, without the name itself.
+    pub fn custom_section(&self, name: &str) -> DebuggerResult<Option<&[u8]>> {
+        self.get_file()?;
+        Ok(self
+            .info
+            .iter()
+            .flat_map(DebugInfo::custom_sections)
+            .find(|(section_name, _)| section_name == name)
+            .map(|(_, payload)| &payload[..]))
+    }
+
+    /// The encoded body of a defined function as it is in the code section: the local
+    /// declarations followed by the instructions, without the size prefix. Together with
+    /// `custom_section` this is enough to rebuild a module around a single function.
+    pub fn function_bytes(&self, func_index: u32) -> DebuggerResult<Vec<u8>> {
+        let func = self
+            .get_file()?
+            .module()
+            .get_func(func_index)
+            .ok_or(DebuggerError::NoFunctionWithIndex(func_index))?;
+        if func.is_imported() {
+            return Err(DebuggerError::ImportedFunction(func_index));
         }
+        self.info
+            .as_ref()
+            .and_then(|info| info.function_bytes(func_index))
+            .map(<[u8]>::to_vec)
+            .ok_or(DebuggerError::FunctionBodyNotFound(func_index))
     }
 
-    pub fn breakpoints(&self) -> DebuggerResult<MutexGuard<Breakpoints>> {
-        Ok(self.get_file()?.breakpoints_and_unlock())
+    /// Replaces the immediate of the `i32.const`, `i64.const`, `f32.const` or `f64.const` at
+    /// `pos` with `new_value`, which must have the type of the constant, and instantiates the
+    /// patched module. The immediate is overwritten in place, so integers whose encoding is
+    /// longer than the current one are rejected. Only the module in memory is patched, the file
+    /// stays untouched and `reload` undoes the patch. Breakpoints are kept.
+    pub fn patch_const(&mut self, pos: CodePosition, new_value: Value) -> DebuggerResult<()> {
+        let file = self.get_file()?;
+        let instr = file
+            .module()
+            .get_func(pos.func_index)
+            .filter(|func| !func.is_imported())
+            .and_then(|func| func.instructions().get(pos.instr_index as usize))
+            .ok_or(DebuggerError::NoInstructionAt(pos))?;
+        let expected = match instr {
+            Instruction::I32Const(_) => ValueType::I32,
+            Instruction::I64Const(_) => ValueType::I64,
+            Instruction::F32Const(_) => ValueType::F32,
+            Instruction::F64Const(_) => ValueType::F64,
+            _ => return Err(DebuggerError::NoConstAt(pos)),
+        };
+        if new_value.value_type() != Some(expected) {
+            return Err(DebuggerError::ConstTypeMismatch {
+                pos,
+                expected,
+                value: new_value.to_string(),
+            });
+        }
+        let file_path = file.file_path().clone();
+        let breakpoints = Arc::clone(file.breakpoints());
+
+        // A const is never the last instruction of a body, so the next offset ends the immediate
+        let layout =
+            BinaryLayout::parse(&self.module_bytes).ok_or(DebuggerError::FunctionBodyNotFound(pos.func_index))?;
+        let immediate_range = layout
+            .instruction_offset(pos.func_index, pos.instr_index)
+            .zip(layout.instruction_offset(pos.func_index, pos.instr_index + 1))
+            .map(|(start, end)| {
+                layout.code_section_offset + start as usize + 1..layout.code_section_offset + end as usize
+            })
+            .ok_or(DebuggerError::FunctionBodyNotFound(pos.func_index))?;
+        let len = immediate_range.len();
+        let immediate = match new_value {
+            Value::I32(val) => binary::encode_leb_i64(val.into(), len),
+            Value::I64(val) => binary::encode_leb_i64(val, len),
+            Value::F32(val) => Some(val.to_bits().to_le_bytes().to_vec()),
+            Value::F64(val) => Some(val.to_bits().to_le_bytes().to_vec()),
+            _ => None,
+        }
+        .ok_or(DebuggerError::ConstTooLong {
+            pos,
+            value: new_value.to_string(),
+            len,
+        })?;
+
+        let mut bytes = self.module_bytes.clone();
+        bytes[immediate_range].copy_from_slice(&immediate);
+        let module = Module::from_bytes(&bytes).map_err(|err| DebuggerError::LoadFailed(err.to_string()))?;
+        self.load_debug_info(&bytes);
+        self.module_bytes = bytes;
+        self.file = Some(File::with_breakpoints(file_path, module, breakpoints));
+        self.create_vm()?;
+        Ok(())
     }
 
-    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> DebuggerResult<u32> {
-        let file = self.get_file_mut()?;
-        match breakpoint {
-            Breakpoint::Code(pos) => {
-                if file
-                    .module()
-                    .get_func(pos.func_index)
-                    .and_then(|func| func.instructions().get(pos.instr_index as usize))
-                    .is_none()
-                {
-                    return Err(DebuggerError::InvalidBreakpointPosition);
+    pub fn list_functions(&self) -> DebuggerResult<Vec<FunctionInfo>> {
+        let module = self.get_file()?.module();
+        Ok(module
+            .functions()
+            .iter()
+            .enumerate()
+            .map(|(index, func)| {
+                let index = index as u32;
+                FunctionInfo {
+                    index,
+                    name: self.function_name(index).cloned(),
+                    param_types: func.func_type().params().to_vec(),
+                    return_types: func.func_type().return_type().into_iter().collect(),
+                    is_imported: func.is_imported(),
                 }
-            }
-            Breakpoint::Memory(..) => (),
-            Breakpoint::Global(_, index) => {
-                if index as usize >= file.module().globals().len() {
-                    return Err(DebuggerError::InvalidWatchpointGlobal);
+            })
+            .collect())
+    }
+
+    /// The entries of the export section, in the order they are declared.
+    pub fn exports(&self) -> DebuggerResult<Vec<ExportInfo>> {
+        let module = self.get_file()?.module();
+        Ok(module
+            .exports()
+            .iter()
+            .map(|entry| {
+                let (kind, index) = match *entry.internal() {
+                    Internal::Function(index) => (ExportKind::Function, index),
+                    Internal::Table(index) => (ExportKind::Table, index),
+                    Internal::Memory(index) => (ExportKind::Memory, index),
+                    Internal::Global(index) => (ExportKind::Global, index),
+                };
+                ExportInfo {
+                    name: entry.field().to_owned(),
+                    kind,
+                    index,
                 }
-            }
-        }
-        Ok(file.breakpoints_and_unlock().add_breakpoint(breakpoint))
+            })
+            .collect())
     }
 
-    pub fn delete_breakpoint(&mut self, index: u32) -> DebuggerResult<bool> {
-        Ok(self.get_file()?.breakpoints_and_unlock().delete_breakpoint(index))
+    /// The import entries in module order, which is what a host has to provide before running.
+    pub fn imports(&self) -> DebuggerResult<Vec<ImportInfo>> {
+        let module = self.get_file()?.module();
+        let mut func_index = 0;
+        Ok(module
+            .imports()
+            .iter()
+            .map(|entry| {
+                let mut info = ImportInfo {
+                    module: entry.module().to_owned(),
+                    field: entry.field().to_owned(),
+                    kind: ExportKind::Function,
+                    param_types: Vec::new(),
+                    return_types: Vec::new(),
+                };
+                match entry.external() {
+                    External::Function(_) => {
+                        let func_type = module.get_func(func_index).unwrap().func_type();
+                        info.param_types = func_type.params().to_vec();
+                        info.return_types = func_type.return_type().into_iter().collect();
+                        func_index += 1;
+                    }
+                    External::Table(_) => info.kind = ExportKind::Table,
+                    External::Memory(_) => info.kind = ExportKind::Memory,
+                    External::Global(_) => info.kind = ExportKind::Global,
+                }
+                info
+            })
+            .collect())
     }
 
-    pub fn clear_breakpoints(&mut self) -> DebuggerResult<()> {
-        self.get_file()?.breakpoints_and_unlock().clear();
+    /// Reinterprets the bits of `value`, e.g. an `i32` read from memory as the `f32` it encodes.
+    pub fn reinterpret(value: Value, as_type: ValueType) -> DebuggerResult<Value> {
+        value
+            .reinterpret(as_type)
+            .ok_or_else(|| DebuggerError::InvalidReinterpretation {
+                value: value.to_string(),
+                value_type: as_type,
+            })
+    }
+
+    /// Type-checks the stack effects of every function body and reports the first problem.
+    /// Kept apart from loading so that modules failing it can still be inspected.
+    pub fn validate(&self) -> DebuggerResult<()> {
+        let module = self.get_file()?.module();
+        for (func_index, func) in module.functions().iter().enumerate() {
+            if func.is_imported() {
+                continue;
+            }
+            let func_type = func.func_type();
+            validate::validate_body(
+                module,
+                func_type.params(),
+                func_type.return_type(),
+                func.locals(),
+                func.instructions(),
+            )
+            .map_err(|(instr_index, message)| DebuggerError::ValidationFailed {
+                pos: CodePosition::new(func_index as u32, instr_index),
+                message,
+            })?;
+        }
         Ok(())
     }
 
-    pub fn run(&mut self) -> DebuggerResult<Trap> {
-        Ok(self.create_vm()?.run())
+    pub fn module_summary(&self) -> DebuggerResult<ModuleSummary> {
+        let module = self.get_file()?.module();
+        let imported_functions = module.functions().iter().filter(|func| func.is_imported()).count() as u32;
+        Ok(ModuleSummary {
+            imported_functions,
+            defined_functions: module.functions().len() as u32 - imported_functions,
+            globals: module.globals().len() as u32,
+            memories: module
+                .memories()
+                .iter()
+                .map(|memory| ResourceLimits {
+                    min: memory.limits().initial(),
+                    max: memory.limits().maximum(),
+                })
+                .collect(),
+            tables: module
+                .tables()
+                .iter()
+                .map(|table| ResourceLimits {
+                    min: table.limits().initial(),
+                    max: table.limits().maximum(),
+                })
+                .collect(),
+            imports: module.imports().len() as u32,
+            exports: module.exports().len() as u32,
+            has_start: module.start_func().is_some(),
+        })
     }
 
-    pub fn start(&mut self) -> DebuggerResult<Option<Trap>> {
-        Ok(self.create_vm()?.start().err())
+    /// The entries of the data section, in the order they are declared. The parser only keeps
+    /// active segments, which are copied into the memory on instantiation.
+    pub fn data_segments(&self) -> DebuggerResult<Vec<DataSegmentInfo>> {
+        let module = self.get_file()?.module();
+        Ok(module
+            .memory_inits()
+            .iter()
+            .enumerate()
+            .map(|(index, init)| {
+                let (offset_expr, offset) = describe_offset(init.offset());
+                DataSegmentInfo {
+                    index: index as u32,
+                    memory_index: init.index(),
+                    offset_expr,
+                    offset,
+                    len: init.data().len() as u32,
+                }
+            })
+            .collect())
     }
 
-    pub fn call(&mut self, index: u32, args: &[Value]) -> DebuggerResult<Trap> {
-        Ok(self.ensure_vm()?.run_func(index, args))
+    /// The entries of the element section, in the order they are declared. The parser only keeps
+    /// active segments, which are installed into the table on instantiation.
+    pub fn element_segments(&self) -> DebuggerResult<Vec<ElementSegmentInfo>> {
+        let module = self.get_file()?.module();
+        Ok(module
+            .table_inits()
+            .iter()
+            .enumerate()
+            .map(|(index, init)| {
+                let (offset_expr, offset) = describe_offset(init.offset());
+                ElementSegmentInfo {
+                    index: index as u32,
+                    table_index: init.index(),
+                    offset_expr,
+                    offset,
+                    functions: init.entries().to_vec(),
+                }
+            })
+            .collect())
     }
 
-    pub fn reset_vm(&mut self) -> DebuggerResult<()> {
-        self.vm = None;
-        Ok(())
+    /// Positions of the `call` instructions calling the function with index `target`.
+    pub fn call_sites(&self, target: u32) -> DebuggerResult<Vec<CodePosition>> {
+        let file = self.get_file()?;
+        if file.module().get_func(target).is_none() {
+            return Err(DebuggerError::NoFunctionWithIndex(target));
+        }
+        Ok(file.call_sites(|call_sites| call_sites.direct.get(&target).cloned().unwrap_or_default()))
     }
 
-    pub fn continue_execution(&mut self) -> DebuggerResult<Trap> {
-        Ok(self.get_vm_mut()?.continue_execution())
+    /// Positions of the `call_indirect` instructions that could call the function with index
+    /// `target` because they expect its type. Whether the function is in the table isn't checked.
+    pub fn indirect_call_sites(&self, target: u32) -> DebuggerResult<Vec<CodePosition>> {
+        let file = self.get_file()?;
+        let type_ref = file
+            .module()
+            .get_func(target)
+            .ok_or(DebuggerError::NoFunctionWithIndex(target))?
+            .func_type()
+            .type_ref();
+        Ok(file.call_sites(|call_sites| call_sites.indirect.get(&type_ref).cloned().unwrap_or_default()))
     }
 
-    pub fn execute_step(&mut self) -> DebuggerResult<Option<Trap>> {
-        Ok(self.get_vm_mut()?.execute_step().err())
+    pub fn control_flow_graph(&self, func_index: u32) -> DebuggerResult<Cfg> {
+        let func = self
+            .get_file()?
+            .module()
+            .get_func(func_index)
+            .ok_or(DebuggerError::NoFunctionWithIndex(func_index))?;
+        if func.is_imported() {
+            return Err(DebuggerError::ImportedFunction(func_index));
+        }
+        Ok(cfg::build(func.instructions()))
     }
 
-    pub fn execute_step_over(&mut self) -> DebuggerResult<Option<Trap>> {
-        Ok(self.get_vm_mut()?.execute_step_over().err())
+    pub fn disassemble_function(&self, func_index: u32) -> DebuggerResult<Vec<DisassembledInstr>> {
+        let file = self.get_file()?;
+        let func = file
+            .module()
+            .get_func(func_index)
+            .ok_or(DebuggerError::NoFunctionWithIndex(func_index))?;
+        let breakpoints = file.breakpoints_and_unlock();
+        Ok(func
+            .instructions()
+            .iter()
+            .enumerate()
+            .map(|(instr_index, instr)| {
+                let instr_index = instr_index as u32;
+                let text = instr.to_string();
+                let (mnemonic, operands) = split_instruction_text(&text);
+                DisassembledInstr {
+                    instr_index,
+                    mnemonic: mnemonic.to_owned(),
+                    operands: operands.to_owned(),
+                    has_breakpoint: breakpoints
+                        .find_code(CodePosition::new(func_index, instr_index))
+                        .is_some(),
+                }
+            })
+            .collect())
     }
 
-    pub fn execute_step_out(&mut self) -> DebuggerResult<Option<Trap>> {
-        Ok(self.get_vm_mut()?.execute_step_out().err())
+    /// The instruction the VM is paused on with its immediates, see `Operand`.
+    pub fn current_instruction(&self) -> DebuggerResult<InstructionInfo> {
+        let position = self.get_vm()?.ip();
+        let instr = self
+            .get_file()?
+            .module()
+            .get_func(position.func_index)
+            .and_then(|func| func.instructions().get(position.instr_index as usize))
+            .ok_or(DebuggerError::NoInstructionAt(position))?;
+
+        let function = |index: u32| Operand::Function {
+            index,
+            name: self.function_name(index).cloned(),
+        };
+        let local = |index: u32| Operand::Local {
+            index,
+            name: self.local_name(position.func_index, index).cloned(),
+        };
+        let global = |index: u32| Operand::Global {
+            index,
+            name: self.global_name(index).cloned(),
+        };
+        let operands = match instr {
+            Instruction::Call(index) => vec![function(*index)],
+            Instruction::CallIndirect(signature, _) => vec![Operand::Type(*signature)],
+            Instruction::GetLocal(index) | Instruction::SetLocal(index) | Instruction::TeeLocal(index) => {
+                vec![local(*index)]
+            }
+            Instruction::GetGlobal(index) | Instruction::SetGlobal(index) => vec![global(*index)],
+            Instruction::Br(depth) | Instruction::BrIf(depth) => vec![Operand::BranchDepth(*depth)],
+            Instruction::BrTable(table_data) => table_data
+                .table
+                .iter()
+                .chain(std::iter::once(&table_data.default))
+                .map(|depth| Operand::BranchDepth(*depth))
+                .collect(),
+            Instruction::I32Load(align, offset)
+            | Instruction::I64Load(align, offset)
+            | Instruction::F32Load(align, offset)
+            | Instruction::F64Load(align, offset)
+            | Instruction::I32Load8S(align, offset)
+            | Instruction::I32Load8U(align, offset)
+            | Instruction::I32Load16S(align, offset)
+            | Instruction::I32Load16U(align, offset)
+            | Instruction::I64Load8S(align, offset)
+            | Instruction::I64Load8U(align, offset)
+            | Instruction::I64Load16S(align, offset)
+            | Instruction::I64Load16U(align, offset)
+            | Instruction::I64Load32S(align, offset)
+            | Instruction::I64Load32U(align, offset)
+            | Instruction::I32Store(align, offset)
+            | Instruction::I64Store(align, offset)
+            | Instruction::F32Store(align, offset)
+            | Instruction::F64Store(align, offset)
+            | Instruction::I32Store8(align, offset)
+            | Instruction::I32Store16(align, offset)
+            | Instruction::I64Store8(align, offset)
+            | Instruction::I64Store16(align, offset)
+            | Instruction::I64Store32(align, offset) => vec![Operand::Memory {
+                offset: *offset,
+                align: *align,
+            }],
+            Instruction::I32Const(val) => vec![Operand::Const(Value::I32(*val))],
+            Instruction::I64Const(val) => vec![Operand::Const(Value::I64(*val))],
+            Instruction::F32Const(bits) => vec![Operand::Const(Value::F32(F32::from_bits(*bits)))],
+            Instruction::F64Const(bits) => vec![Operand::Const(Value::F64(F64::from_bits(*bits)))],
+            _ => Vec::new(),
+        };
+        Ok(InstructionInfo {
+            position,
+            mnemonic: split_instruction_text(&instr.to_string()).0.to_owned(),
+            operands,
+        })
     }
 
-    fn create_vm(&mut self) -> DebuggerResult<&mut VM<F>> {
-        let file = self.file.as_ref().ok_or(DebuggerError::NoFileLoaded)?;
-        let module = Arc::clone(file.module());
-        let breakpoints = Arc::clone(file.breakpoints());
-        self.vm = Some(VM::new(module, breakpoints).map_err(DebuggerError::InitError)?);
-        Ok(self.vm.as_mut().unwrap())
+    pub fn globals(&self) -> DebuggerResult<&[Value]> {
+        Ok(self.get_vm()?.globals())
     }
 
-    fn ensure_vm(&mut self) -> DebuggerResult<&mut VM<F>> {
-        if let Some(ref mut vm) = self.vm {
-            Ok(vm)
-        } else {
-            self.create_vm()
+    pub fn memory(&self) -> DebuggerResult<&Memory> {
+        match self.get_vm()?.default_memory() {
+            Ok(memory) => Ok(memory),
+            Err(Trap::NoMemory) => Err(DebuggerError::NoMemory),
+            Err(_) => unreachable!(),
         }
     }
 
-    pub fn get_vm(&self) -> DebuggerResult<&VM<F>> {
-        if let Some(ref vm) = self.vm {
-            Ok(vm)
-        } else {
-            Err(DebuggerError::NoRunningInstance)
+    /// The size of the default memory in pages.
+    pub fn memory_size(&self) -> DebuggerResult<u32> {
+        Ok(self.memory()?.page_count())
+    }
+
+    pub fn memory_size_pages(&self) -> DebuggerResult<u32> {
+        self.memory_size()
+    }
+
+    /// Grows the default memory like `memory.grow`, returning the previous page count or -1 if
+    /// the new size exceeds the module's maximum or the cap set with `set_max_memory_pages`.
+    pub fn grow_memory(&mut self, delta_pages: u32) -> DebuggerResult<i32> {
+        let cap = self.max_memory_pages;
+        let memory = self.memory_mut()?;
+        if let Some(cap) = cap {
+            if u64::from(memory.page_count()) + u64::from(delta_pages) > u64::from(cap) {
+                return Ok(-1);
+            }
         }
+        Ok(memory.grow(delta_pages))
     }
 
-    pub fn get_vm_mut(&mut self) -> DebuggerResult<&mut VM<F>> {
-        if let Some(ref mut vm) = self.vm {
-            Ok(vm)
-        } else {
-            Err(DebuggerError::NoRunningInstance)
+    fn memory_mut(&mut self) -> DebuggerResult<&mut Memory> {
+        match self.get_vm_mut()?.default_memory_mut() {
+            Ok(memory) => Ok(memory),
+            Err(Trap::NoMemory) => Err(DebuggerError::NoMemory),
+            Err(_) => unreachable!(),
         }
     }
 
-    pub fn get_file(&self) -> DebuggerResult<&File> {
-        if let Some(ref file) = self.file {
-            Ok(file)
-        } else {
-            Err(DebuggerError::NoFileLoaded)
+    fn check_memory_range(memory: &Memory, address: u32, len: u64) -> DebuggerResult<std::ops::Range<usize>> {
+        let end = u64::from(address) + len;
+        if end > memory.data().len() as u64 {
+            return Err(DebuggerError::MemoryOutOfBounds { address, len });
         }
+        Ok(address as usize..end as usize)
     }
 
-    pub fn get_file_mut(&mut self) -> DebuggerResult<&mut File> {
-        if let Some(ref mut file) = self.file {
-            Ok(file)
-        } else {
-            Err(DebuggerError::NoFileLoaded)
+    pub fn read_memory(&self, address: u32, len: u32) -> DebuggerResult<&[u8]> {
+        let memory = self.memory()?;
+        let range = Self::check_memory_range(memory, address, u64::from(len))?;
+        Ok(&memory.data()[range])
+    }
+
+    pub fn write_memory(&mut self, address: u32, bytes: &[u8]) -> DebuggerResult<()> {
+        let memory = self.memory_mut()?;
+        let range = Self::check_memory_range(memory, address, bytes.len() as u64)?;
+        memory.data_mut()[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Writes the raw bytes of a memory range to the file at `path`.
+    pub fn dump_memory_to_file(&self, address: u32, len: u32, path: &str) -> DebuggerResult<()> {
+        let bytes = self.read_memory(address, len)?;
+        std::fs::write(path, bytes).map_err(|err| DebuggerError::FileAccess {
+            path: path.to_owned(),
+            reason: err.to_string(),
+        })
+    }
+
+    /// Writes the contents of the file at `path` to memory at `address`. Fails without
+    /// writing anything if the file doesn't fit.
+    pub fn load_memory_from_file(&mut self, path: &str, address: u32) -> DebuggerResult<()> {
+        let bytes = std::fs::read(path).map_err(|err| DebuggerError::FileAccess {
+            path: path.to_owned(),
+            reason: err.to_string(),
+        })?;
+        self.write_memory(address, &bytes)
+    }
+
+    /// Returns the address of the first occurrence of `pattern` at or after `start`.
+    pub fn search_memory(&self, pattern: &[u8], start: u32) -> DebuggerResult<Option<u32>> {
+        let data = self.memory()?.data();
+        if pattern.is_empty() {
+            return Ok(Some(start));
         }
+        Ok(data
+            .get(start as usize..)
+            .and_then(|data| data.windows(pattern.len()).position(|window| window == pattern))
+            .map(|offset| start + offset as u32))
+    }
+
+    /// Reads `count` consecutive values of type `value_type` starting at `address`.
+    pub fn read_typed(&self, address: u32, value_type: ValueType, count: u32) -> DebuggerResult<Vec<Value>> {
+        self.read_typed_endian(address, value_type, count, Endianness::Little)
+    }
+
+    /// Like `read_typed`, but decodes every value in the given byte order. Only meant for
+    /// display, the VM always loads little-endian.
+    pub fn read_typed_endian(
+        &self,
+        address: u32,
+        value_type: ValueType,
+        count: u32,
+        endianness: Endianness,
+    ) -> DebuggerResult<Vec<Value>> {
+        let memory = self.memory()?;
+        let len = u64::from(count) * u64::from(value_type_size(value_type));
+        Self::check_memory_range(memory, address, len)?;
+        let values = memory
+            .load_values(address, value_type, count)
+            .expect("range was checked");
+        Ok(match endianness {
+            Endianness::Little => values,
+            Endianness::Big => values.into_iter().map(Value::byte_swapped).collect(),
+        })
+    }
+
+    /// Evaluates an expression of the language described in `eval` against the paused VM.
+    pub fn evaluate(&self, expr: &str) -> DebuggerResult<Value> {
+        Ok(eval::evaluate(self.get_vm()?, expr)?)
+    }
+
+    pub fn breakpoints(&self) -> DebuggerResult<MutexGuard<Breakpoints>> {
+        Ok(self.get_file()?.breakpoints_and_unlock())
+    }
+
+    fn check_breakpoint(module: &Module, breakpoint: &Breakpoint) -> DebuggerResult<()> {
+        match *breakpoint {
+            Breakpoint::Code(pos) => {
+                if module
+                    .get_func(pos.func_index)
+                    .and_then(|func| func.instructions().get(pos.instr_index as usize))
+                    .is_none()
+                {
+                    return Err(DebuggerError::InvalidBreakpointPosition);
+                }
+            }
+            Breakpoint::Function(func_index) => {
+                if module.get_func(func_index).is_none() {
+                    return Err(DebuggerError::NoFunctionWithIndex(func_index));
+                }
+            }
+            Breakpoint::Memory(..) | Breakpoint::MemoryRange { .. } => (),
+            Breakpoint::Global { index, cond, .. } => {
+                let global = match module.globals().get(index as usize) {
+                    Some(global) => global,
+                    None => return Err(DebuggerError::InvalidWatchpointGlobal),
+                };
+                if cond.map_or(false, |cond| cond.operand().value_type() != Some(global.value_type())) {
+                    return Err(DebuggerError::WatchpointConditionTypeMismatch(index));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> DebuggerResult<u32> {
+        let file = self.get_file_mut()?;
+        Self::check_breakpoint(file.module(), &breakpoint)?;
+        let mut breakpoints = file.breakpoints_and_unlock();
+        if breakpoints.is_full() {
+            return Err(DebuggerError::TooManyBreakpoints(breakpoints.len()));
+        }
+        Ok(breakpoints.add_breakpoint(breakpoint))
+    }
+
+    /// See `Breakpoints::set_enabled`.
+    pub fn set_breakpoint_enabled(&mut self, index: u32, enabled: bool) -> DebuggerResult<()> {
+        if self.get_file()?.breakpoints_and_unlock().set_enabled(index, enabled) {
+            Ok(())
+        } else {
+            Err(DebuggerError::NoBreakpointWithIndex(index))
+        }
+    }
+
+    /// All breakpoints and watchpoints, ordered by index.
+    pub fn list_breakpoints(&self) -> DebuggerResult<Vec<BreakpointInfo>> {
+        let breakpoints = self.get_file()?.breakpoints_and_unlock();
+        let mut list: Vec<_> = breakpoints
+            .iter()
+            .map(|(index, breakpoint)| BreakpointInfo {
+                index: *index,
+                breakpoint: breakpoint.clone(),
+                enabled: breakpoints.is_enabled(*index),
+                hit_count: breakpoints.hit_count(*index),
+            })
+            .collect();
+        list.sort_unstable_by_key(|info| info.index);
+        Ok(list)
+    }
+
+    /// Replaces all code breakpoints with breakpoints at `positions` without releasing the
+    /// breakpoints in between. Returns the index or the reason it couldn't be set per position.
+    pub fn replace_code_breakpoints(&mut self, positions: &[CodePosition]) -> DebuggerResult<Vec<DebuggerResult<u32>>> {
+        let file = self.get_file()?;
+        let mut breakpoints = file.breakpoints_and_unlock();
+        breakpoints.retain(|breakpoint| !matches!(breakpoint, Breakpoint::Code(_)));
+        Ok(positions
+            .iter()
+            .map(|pos| {
+                let breakpoint = Breakpoint::Code(*pos);
+                Self::check_breakpoint(file.module(), &breakpoint)?;
+                if breakpoints.is_full() {
+                    return Err(DebuggerError::TooManyBreakpoints(breakpoints.len()));
+                }
+                Ok(breakpoints.add_breakpoint(breakpoint))
+            })
+            .collect())
+    }
+
+    /// Breaks whenever the function is called, directly, indirectly or recursively.
+    pub fn add_function_breakpoint(&mut self, func_index: u32) -> DebuggerResult<u32> {
+        self.add_breakpoint(Breakpoint::Function(func_index))
+    }
+
+    /// Sets a breakpoint in every function with code for `line` of `file`, or the nearest
+    /// following line that has code. Returns the indices of the new breakpoints with their
+    /// positions. If one of them can't be added, none are kept.
+    pub fn add_breakpoint_at_line(&mut self, file: &str, line: u32) -> DebuggerResult<Vec<(u32, CodePosition)>> {
+        self.get_file()?;
+        let positions = match &self.info {
+            Some(info) => info.code_positions_at_line(file, line),
+            None => Vec::new(),
+        };
+        if positions.is_empty() {
+            return Err(DebuggerError::InvalidBreakpointPosition);
+        }
+        let mut added = Vec::new();
+        for pos in positions {
+            match self.add_breakpoint(Breakpoint::Code(pos)) {
+                Ok(index) => added.push((index, pos)),
+                Err(err) => {
+                    for (index, _) in added {
+                        self.delete_breakpoint(index)?;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    /// Details about the watchpoint behind the most recent `Trap::WatchpointReached`.
+    pub fn last_watchpoint_hit(&self) -> Option<WatchpointHit> {
+        self.vm.as_ref()?.last_watchpoint_hit().cloned()
+    }
+
+    pub fn add_memory_watchpoint(&mut self, start: u32, len: u32, access: BreakpointTrigger) -> DebuggerResult<u32> {
+        self.add_breakpoint(Breakpoint::MemoryRange { start, len, access })
+    }
+
+    pub fn delete_breakpoint(&mut self, index: u32) -> DebuggerResult<bool> {
+        Ok(self.get_file()?.breakpoints_and_unlock().delete_breakpoint(index))
+    }
+
+    pub fn clear_breakpoints(&mut self) -> DebuggerResult<()> {
+        self.get_file()?.breakpoints_and_unlock().clear();
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> DebuggerResult<Trap> {
+        let trap = self.create_vm()?.run();
+        self.last_run = Some(RunEntry::Run);
+        Ok(trap)
+    }
+
+    /// Starts a new instance paused on the first instruction of the start function, so that
+    /// initialization can be stepped through. A breakpoint on that instruction isn't reported,
+    /// breakpoints further into the start function are hit as usual once execution resumes.
+    ///
+    /// With `set_skip_start`, the start function runs right away instead and the trap it ended
+    /// with is returned, which is `BreakpointReached` if it hit a breakpoint.
+    pub fn start(&mut self) -> DebuggerResult<Option<Trap>> {
+        let skip_start = self.skip_start;
+        let vm = self.create_vm()?;
+        let trap = if skip_start { Some(vm.run()) } else { vm.start().err() };
+        self.last_run = Some(RunEntry::Start);
+        Ok(trap)
+    }
+
+    pub fn start_function(&self) -> DebuggerResult<Option<u32>> {
+        Ok(self.get_file()?.module().start_func())
+    }
+
+    pub fn set_skip_start(&mut self, skip: bool) {
+        self.skip_start = skip;
+    }
+
+    pub fn call(&mut self, index: u32, args: &[Value]) -> DebuggerResult<Trap> {
+        let expected = self.param_types(index)?;
+        if let Some(index) = args.iter().position(Value::is_v128) {
+            return Err(DebuggerError::V128Argument(index));
+        }
+        let got: Vec<ValueType> = args.iter().filter_map(Value::value_type).collect();
+        if expected != got {
+            return Err(DebuggerError::ArgumentMismatch { expected, got });
+        }
+        self.last_run = Some(RunEntry::Call {
+            func_index: index,
+            args: args.to_vec(),
+        });
+        Ok(self.ensure_vm()?.run_func(index, args))
+    }
+
+    /// Calls `hook` before each instruction the main module executes, for the running instance
+    /// and every instance started later. It runs in the hot path and should be cheap.
+    pub fn set_pre_instruction_hook(&mut self, hook: InstructionHook<F>) {
+        self.pre_instruction_hook = Some(Arc::new(Mutex::new(hook)));
+        if let Some(vm) = &mut self.vm {
+            vm.set_pre_instruction_hook(self.pre_instruction_hook.clone());
+        }
+    }
+
+    pub fn clear_pre_instruction_hook(&mut self) {
+        self.pre_instruction_hook = None;
+        if let Some(vm) = &mut self.vm {
+            vm.set_pre_instruction_hook(None);
+        }
+    }
+
+    /// Writes a line for each instruction the main module executes to `out`, see `Trace` for the
+    /// format. Tracing stops after `set_trace_limit` instructions, `DEFAULT_TRACE_LIMIT` by default.
+    pub fn enable_trace(&mut self, out: Box<dyn Write + Send>) {
+        self.disable_trace();
+        self.trace = Some(Arc::new(Mutex::new(Trace::new(out, self.trace_limit))));
+        if let Some(vm) = &mut self.vm {
+            vm.set_trace(self.trace.clone());
+        }
+    }
+
+    pub fn disable_trace(&mut self) {
+        if let Some(trace) = self.trace.take() {
+            trace.lock().unwrap().flush();
+        }
+        if let Some(vm) = &mut self.vm {
+            vm.set_trace(None);
+        }
+    }
+
+    /// Also applies to the active trace, counting the lines it has written so far.
+    pub fn set_trace_limit(&mut self, max_lines: u64) {
+        self.trace_limit = max_lines;
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().set_max_lines(max_lines);
+        }
+    }
+
+    /// Starts a new instance the same way as the last `start`, `run` or `call`.
+    /// Breakpoints are kept.
+    pub fn restart(&mut self) -> DebuggerResult<Option<Trap>> {
+        match self.last_run.clone().ok_or(DebuggerError::NothingToRestart)? {
+            RunEntry::Start => self.start(),
+            RunEntry::Run => self.run().map(Some),
+            RunEntry::Call { func_index, args } => {
+                self.create_vm()?;
+                self.call(func_index, &args).map(Some)
+            }
+        }
+    }
+
+    pub fn last_run(&self) -> Option<&RunEntry> {
+        self.last_run.as_ref()
+    }
+
+    /// Like `call`, but parses each argument as the type of the matching parameter.
+    pub fn call_with_str_args(&mut self, index: u32, args: &[&str]) -> DebuggerResult<Trap> {
+        let expected = self.param_types(index)?;
+        if expected.len() != args.len() {
+            let got = args
+                .iter()
+                .filter_map(|arg| Value::parse_inferred(arg))
+                .filter_map(|value| value.value_type())
+                .collect();
+            return Err(DebuggerError::ArgumentMismatch { expected, got });
+        }
+        let mut parsed = Vec::with_capacity(args.len());
+        for (arg, value_type) in args.iter().zip(expected) {
+            parsed.push(
+                Value::from_str(arg, value_type).ok_or_else(|| DebuggerError::InvalidArgument {
+                    value: (*arg).to_owned(),
+                    value_type,
+                })?,
+            );
+        }
+        self.call(index, &parsed)
+    }
+
+    pub fn call_by_name(&mut self, name: &str, args: &[Value]) -> DebuggerResult<Trap> {
+        let func_index = self.find_function(name)?;
+        self.call(func_index, args)
+    }
+
+    /// Resolves a function name using the name section first and the exports second.
+    pub fn find_function(&self, name: &str) -> DebuggerResult<u32> {
+        let file = self.get_file()?;
+        if let Some(info) = &self.info {
+            if let Some((index, _)) = info
+                .function_name_map()
+                .iter()
+                .find(|(_, function_name)| *function_name == name)
+            {
+                return Ok(*index);
+            }
+        }
+        file.module()
+            .exports()
+            .iter()
+            .find_map(|entry| match entry.internal() {
+                Internal::Function(index) if entry.field() == name => Some(*index),
+                _ => None,
+            })
+            .ok_or_else(|| DebuggerError::FunctionNotFound(name.to_owned()))
+    }
+
+    fn param_types(&self, func_index: u32) -> DebuggerResult<Vec<ValueType>> {
+        let func = self
+            .get_file()?
+            .module()
+            .get_func(func_index)
+            .ok_or(DebuggerError::NoFunctionWithIndex(func_index))?;
+        Ok(func.func_type().params().to_vec())
+    }
+
+    pub fn save_state(&self) -> DebuggerResult<VmSnapshot> {
+        Ok(self.get_vm()?.snapshot())
+    }
+
+    pub fn load_state(&mut self, snapshot: &VmSnapshot) -> DebuggerResult<()> {
+        if !Arc::ptr_eq(snapshot.module(), self.get_file()?.module()) {
+            return Err(DebuggerError::SnapshotMismatch);
+        }
+        self.ensure_vm()?.restore(snapshot);
+        Ok(())
+    }
+
+    /// What the running instance changed since `snapshot` was taken.
+    pub fn diff_since(&self, snapshot: &VmSnapshot) -> DebuggerResult<StateDiff> {
+        if !Arc::ptr_eq(snapshot.module(), self.get_file()?.module()) {
+            return Err(DebuggerError::SnapshotMismatch);
+        }
+        Ok(snapshot.diff(&self.save_state()?))
+    }
+
+    pub fn reset_vm(&mut self) -> DebuggerResult<()> {
+        self.vm = None;
+        Ok(())
+    }
+
+    /// Makes the run command that is currently executing stop with `Trap::Interrupted`.
+    /// Use `interrupt_handle` to interrupt from another thread.
+    pub fn request_interrupt(&self) {
+        self.interrupt.request();
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Refuels the running instance and every instance started later. See `VM::set_fuel`.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+        if let Some(vm) = &mut self.vm {
+            vm.set_fuel(fuel);
+        }
+    }
+
+    pub fn fuel_remaining(&self) -> DebuggerResult<Option<u64>> {
+        Ok(self.get_vm()?.fuel_remaining())
+    }
+
+    /// Stops before a faulting instruction instead of trapping, see `VM::set_break_on_trap`.
+    /// Applies to the running instance and every instance started later.
+    pub fn set_break_on_trap(&mut self, enabled: bool) {
+        self.break_on_trap = enabled;
+        if let Some(vm) = &mut self.vm {
+            vm.set_break_on_trap(enabled);
+        }
+    }
+
+    /// Caps the growth of memory by the running instance and every instance started later,
+    /// see `VM::set_max_memory_pages`. `grow_memory` returns -1 instead of growing past it.
+    pub fn set_max_memory_pages(&mut self, max_pages: Option<u32>) {
+        self.max_memory_pages = max_pages;
+        if let Some(vm) = &mut self.vm {
+            vm.set_max_memory_pages(max_pages);
+        }
+    }
+
+    /// Caps the number of breakpoints of the loaded file and of every file loaded later, see
+    /// `Breakpoints::set_limit`. `add_breakpoint` fails instead of adding more.
+    pub fn set_breakpoint_limit(&mut self, limit: Option<usize>) {
+        self.breakpoint_limit = limit;
+        if let Some(file) = &self.file {
+            file.breakpoints_and_unlock().set_limit(limit);
+        }
+    }
+
+    /// What memory of instances started later is filled with before data segments are applied
+    /// and when it grows. `Poison` helps to spot reads of uninitialized memory.
+    pub fn set_memory_init_pattern(&mut self, pattern: MemInitPattern) {
+        self.memory_init_pattern = pattern;
+    }
+
+    /// Applies to the running instance and every instance started later, see
+    /// `VM::set_max_call_depth`.
+    pub fn set_max_call_depth(&mut self, max_depth: usize) {
+        self.max_call_depth = max_depth;
+        if let Some(vm) = &mut self.vm {
+            vm.set_max_call_depth(max_depth);
+        }
+    }
+
+    /// Profiles the running instance and every instance started later.
+    pub fn enable_profiling(&mut self) {
+        self.profiling = true;
+        if let Some(vm) = &mut self.vm {
+            vm.enable_profiling();
+        }
+    }
+
+    /// Execution count per instruction of the current instance, most executed first.
+    pub fn profile_report(&self) -> Vec<(CodePosition, u64)> {
+        let profile = match self.vm.as_ref().and_then(VM::profile) {
+            Some(profile) => profile,
+            None => return Vec::new(),
+        };
+        let mut report: Vec<_> = profile.iter().map(|(pos, count)| (*pos, *count)).collect();
+        report.sort_by_key(|(pos, count)| (Reverse(*count), pos.func_index, pos.instr_index));
+        report
+    }
+
+    /// Tracks coverage of the running instance and every instance started later.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = true;
+        if let Some(vm) = &mut self.vm {
+            vm.enable_coverage();
+        }
+    }
+
+    /// The instructions executed by the current instance. Empty if coverage tracking is off.
+    pub fn coverage(&self) -> CoverageReport {
+        self.vm.as_ref().and_then(VM::coverage).unwrap_or_default()
+    }
+
+    pub fn set_cost_model(&mut self, cost_model: CostModel) {
+        if let Some(vm) = &mut self.vm {
+            vm.set_cost_model(cost_model.clone());
+        }
+        self.cost_model = cost_model;
+    }
+
+    pub fn continue_execution(&mut self) -> DebuggerResult<Trap> {
+        Ok(self.vm_for_run()?.continue_execution())
+    }
+
+    /// Unlike the other run commands this keeps a pending interrupt, so that an interrupt
+    /// requested between two slices of a continue isn't lost.
+    pub fn continue_execution_for(&mut self, max_steps: u64) -> DebuggerResult<Option<Trap>> {
+        Ok(self.get_vm_mut()?.continue_execution_for(max_steps).err())
+    }
+
+    pub fn continue_with_limit(&mut self, max_instrs: u64) -> DebuggerResult<Option<Trap>> {
+        Ok(self.vm_for_run()?.run_with_limit(max_instrs).err())
+    }
+
+    pub fn execute_step(&mut self) -> DebuggerResult<Option<Trap>> {
+        Ok(self.vm_for_run()?.execute_step().err())
+    }
+
+    pub fn execute_step_over(&mut self) -> DebuggerResult<Option<Trap>> {
+        Ok(self.vm_for_run()?.execute_step_over().err())
+    }
+
+    pub fn step_into(&mut self) -> DebuggerResult<Option<Trap>> {
+        Ok(self.vm_for_run()?.step_into().err())
+    }
+
+    pub fn execute_step_out(&mut self) -> DebuggerResult<Option<Trap>> {
+        Ok(self.vm_for_run()?.execute_step_out().err())
+    }
+
+    /// Like `execute_step_out`, but also reports what the current function returned.
+    pub fn finish(&mut self) -> DebuggerResult<FinishResult> {
+        let func_index = self.get_vm()?.ip().func_index;
+        let result_count = self.result_count(func_index)?;
+        let vm = self.vm_for_run()?;
+        let trap = vm.execute_step_out().err();
+        let return_values = match trap {
+            None | Some(Trap::ExecutionFinished) => top_values(vm.value_stack(), result_count),
+            Some(_) => Vec::new(),
+        };
+        Ok(FinishResult { trap, return_values })
+    }
+
+    /// Like `call`, but also collects all the values the function returned.
+    pub fn call_with_results(&mut self, index: u32, args: &[Value]) -> DebuggerResult<FinishResult> {
+        let result_count = self.result_count(index)?;
+        let trap = self.call(index, args)?;
+        let return_values = match trap {
+            Trap::ExecutionFinished => top_values(self.get_vm()?.value_stack(), result_count),
+            _ => Vec::new(),
+        };
+        Ok(FinishResult {
+            trap: Some(trap),
+            return_values,
+        })
+    }
+
+    fn result_count(&self, func_index: u32) -> DebuggerResult<usize> {
+        Ok(self
+            .get_file()?
+            .module()
+            .get_func(func_index)
+            .map_or(0, |func| func.func_type().return_type().into_iter().count()))
+    }
+
+    /// Function indices stored in each slot of the table, `None` for null entries.
+    pub fn table(&self, table_index: u32) -> DebuggerResult<Vec<Option<u32>>> {
+        let table = self
+            .get_vm()?
+            .tables()
+            .get(table_index as usize)
+            .ok_or(DebuggerError::NoTable)?;
+        Ok(table
+            .elements()
+            .iter()
+            .map(|element| match element {
+                TableElement::Func(func_index) => Some(*func_index),
+                TableElement::Null => None,
+            })
+            .collect())
+    }
+
+    pub fn resolve_indirect_target(&self) -> DebuggerResult<Option<u32>> {
+        let vm = self.get_vm()?;
+        vm.default_table().map_err(|_| DebuggerError::NoTable)?;
+        Ok(vm.indirect_call_target())
+    }
+
+    /// Whether the `call_indirect` about to execute calls a function of the type it expects.
+    /// `false` means it will trap with `IndirectCallTypeMismatch`.
+    pub fn check_indirect_signature(&self) -> DebuggerResult<bool> {
+        let vm = self.get_vm()?;
+        vm.default_table().map_err(|_| DebuggerError::NoTable)?;
+        vm.indirect_call_type_matches()
+            .ok_or(DebuggerError::NoIndirectCallTarget)
+    }
+
+    pub fn would_trap(&self) -> DebuggerResult<Option<TrapCategory>> {
+        Ok(self.get_vm()?.would_trap())
+    }
+
+    /// Executes one line of the command language in the `commands` module, e.g. `break func 3 0`,
+    /// `continue`, `print g0` or `mem 0x10 16`, and returns what it printed.
+    pub fn execute_command(&mut self, cmd: &str) -> DebuggerResult<String> {
+        commands::execute(self, cmd)
+    }
+
+    /// A text report of the current instance for bug reports and logs.
+    pub fn dump_state(&self) -> DebuggerResult<String> {
+        self.dump_state_with_limit(DEFAULT_DUMP_LIMIT)
+    }
+
+    /// Like `dump_state` but lists (backtrace, stack, locals, globals) are cut off after `limit` entries.
+    /// The value stack is listed top first.
+    pub fn dump_state_with_limit(&self, limit: usize) -> DebuggerResult<String> {
+        let vm = self.get_vm()?;
+        let mut lines = Vec::new();
+
+        let ip = vm.ip();
+        lines.push(match self.function_name(ip.func_index) {
+            Some(name) => format!("ip: {} in {}", ip, name),
+            None => format!("ip: {}", ip),
+        });
+        if let Some(trap) = vm.trap() {
+            lines.push(format!("trap: {}", trap));
+        }
+
+        let backtrace = self
+            .backtrace_detailed()?
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let mut line = format!("#{} {}:{}", i, frame.func_index, frame.instr_index);
+                if let Some(name) = &frame.func_name {
+                    line += &format!(" {}", name);
+                }
+                if let Some(source) = &frame.source {
+                    line += &format!(" at {}", source);
+                }
+                line
+            })
+            .collect();
+        push_truncated(&mut lines, "backtrace", backtrace, limit);
+
+        let stack = vm
+            .value_stack()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, value)| format!("{}: {}", i, value))
+            .collect();
+        push_truncated(&mut lines, "value stack", stack, limit);
+
+        if let Ok(locals) = vm.locals() {
+            let locals = locals
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match self.local_name(ip.func_index, i as u32) {
+                    Some(name) => format!("{} {}: {}", i, name, value),
+                    None => format!("{}: {}", i, value),
+                })
+                .collect();
+            push_truncated(&mut lines, "locals", locals, limit);
+        }
+
+        let globals = vm
+            .globals()
+            .iter()
+            .enumerate()
+            .map(|(i, value)| match self.global_name(i as u32) {
+                Some(name) => format!("{} {}: {}", i, name, value),
+                None => format!("{}: {}", i, value),
+            })
+            .collect();
+        push_truncated(&mut lines, "globals", globals, limit);
+
+        lines.push(match vm.default_memory() {
+            Ok(memory) => format!(
+                "memory: {} pages ({:#x} bytes)",
+                memory.page_count(),
+                memory.data().len()
+            ),
+            Err(_) => "memory: none".to_owned(),
+        });
+
+        Ok(lines.join("\n"))
+    }
+
+    fn create_vm(&mut self) -> DebuggerResult<&mut VM<F>> {
+        let file = self.file.as_ref().ok_or(DebuggerError::NoFileLoaded)?;
+        let module = Arc::clone(file.module());
+        let breakpoints = Arc::clone(file.breakpoints());
+        let import_function_handler = self.import_function_handler.clone();
+        let init_pattern = self.memory_init_pattern;
+        let mut vm = VM::with_memory_init_pattern(module, breakpoints, import_function_handler.clone(), init_pattern)
+            .map_err(DebuggerError::InitError)?;
+
+        // Every library gets one instance that all modules importing from it share
+        let (main_links, library_links) = self.resolve_links()?;
+        let instances = self
+            .libraries
+            .iter()
+            .map(|library| {
+                VM::with_memory_init_pattern(
+                    Arc::clone(library.module()),
+                    Arc::clone(library.breakpoints()),
+                    import_function_handler.clone(),
+                    init_pattern,
+                )
+                .map(|instance| Arc::new(Mutex::new(instance)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (instance, links) in instances.iter().zip(library_links) {
+            let mut instance = instance.lock().unwrap();
+            for link in links {
+                instance.link_function(link.import_index, Arc::clone(&instances[link.library]), link.func_index);
+            }
+        }
+        for link in main_links {
+            vm.link_function(link.import_index, Arc::clone(&instances[link.library]), link.func_index);
+        }
+
+        vm.set_interrupt_handle(self.interrupt.clone());
+        vm.set_cost_model(self.cost_model.clone());
+        if let Some(fuel) = self.fuel {
+            vm.set_fuel(fuel);
+        }
+        if self.profiling {
+            vm.enable_profiling();
+        }
+        if self.coverage {
+            vm.enable_coverage();
+        }
+        vm.set_break_on_trap(self.break_on_trap);
+        vm.set_max_memory_pages(self.max_memory_pages);
+        vm.set_max_call_depth(self.max_call_depth);
+        vm.set_pre_instruction_hook(self.pre_instruction_hook.clone());
+        vm.set_trace(self.trace.clone());
+        self.interrupt.clear();
+        self.vm = Some(vm);
+        Ok(self.vm.as_mut().unwrap())
+    }
+
+    fn ensure_vm(&mut self) -> DebuggerResult<&mut VM<F>> {
+        if self.vm.is_none() {
+            return self.create_vm();
+        }
+        self.vm_for_run()
+    }
+
+    /// The VM for a run command. Interrupts requested before the command are dropped.
+    fn vm_for_run(&mut self) -> DebuggerResult<&mut VM<F>> {
+        self.interrupt.clear();
+        self.get_vm_mut()
+    }
+
+    pub fn get_vm(&self) -> DebuggerResult<&VM<F>> {
+        if let Some(ref vm) = self.vm {
+            Ok(vm)
+        } else {
+            Err(DebuggerError::NoRunningInstance)
+        }
+    }
+
+    pub fn get_vm_mut(&mut self) -> DebuggerResult<&mut VM<F>> {
+        if let Some(ref mut vm) = self.vm {
+            Ok(vm)
+        } else {
+            Err(DebuggerError::NoRunningInstance)
+        }
+    }
+
+    pub fn get_file(&self) -> DebuggerResult<&File> {
+        if let Some(ref file) = self.file {
+            Ok(file)
+        } else {
+            Err(DebuggerError::NoFileLoaded)
+        }
+    }
+
+    pub fn get_file_mut(&mut self) -> DebuggerResult<&mut File> {
+        if let Some(ref mut file) = self.file {
+            Ok(file)
+        } else {
+            Err(DebuggerError::NoFileLoaded)
+        }
+    }
+}
+
+impl Debugger<RegistryImportFunctionHandler> {
+    /// Registers a host function with the given signature for the import `module.field`. Fails
+    /// if the loaded module imports it with another signature. Also affects a running VM.
+    pub fn register_host_function<H>(
+        &mut self,
+        module: &str,
+        field: &str,
+        param_types: &[ValueType],
+        return_types: &[ValueType],
+        function: H,
+    ) -> DebuggerResult<()>
+    where
+        H: FnMut(&mut VM<RegistryImportFunctionHandler>, &[Value]) -> VMResult<Vec<Value>> + Send + 'static,
+    {
+        if self.file.is_some() {
+            let import = self
+                .imports()?
+                .into_iter()
+                .find(|import| import.kind == ExportKind::Function && import.module == module && import.field == field);
+            if let Some(import) = import {
+                if import.param_types != param_types || import.return_types != return_types {
+                    return Err(DebuggerError::HostFunctionSignatureMismatch {
+                        import: format!("{}.{}", module, field),
+                        expected: (import.param_types, import.return_types),
+                        found: (param_types.to_vec(), return_types.to_vec()),
+                    });
+                }
+            }
+        }
+        self.import_function_handler
+            .registry()
+            .register(module, field, param_types, return_types, Box::new(function));
+        Ok(())
+    }
+
+    /// The function imports that calling would trap on because no host function is registered
+    /// for them and they aren't linked to a library either.
+    pub fn missing_host_functions(&self) -> DebuggerResult<Vec<ImportInfo>> {
+        let imports = self.imports()?;
+        let (links, _) = self.resolve_links()?;
+        let registry = self.import_function_handler.registry();
+        Ok(imports
+            .into_iter()
+            .filter(|import| import.kind == ExportKind::Function)
+            .enumerate()
+            .filter(|(import_index, import)| {
+                !links.iter().any(|link| link.import_index == *import_index as u32)
+                    && !registry.contains(&import.module, &import.field)
+            })
+            .map(|(_, import)| import)
+            .collect())
+    }
+}
+
+impl Debugger<WasiImportFunctionHandler> {
+    /// See `WasiImportFunctionHandler::set_output_sink`. Also affects a running VM.
+    pub fn set_output_sink(&mut self, sink: Box<dyn Write + Send>) {
+        self.import_function_handler.set_output_sink(sink);
+        self.share_io_with_vm();
+    }
+
+    /// See `WasiImportFunctionHandler::capture_output`. Also affects a running VM.
+    pub fn capture_output(&mut self) {
+        self.import_function_handler.capture_output();
+        self.share_io_with_vm();
+    }
+
+    pub fn take_output(&self) -> String {
+        self.import_function_handler.take_output()
+    }
+
+    /// See `WasiImportFunctionHandler::set_input`. A running VM reads `data` from the start.
+    pub fn set_input(&mut self, data: Vec<u8>) {
+        if let Some(vm) = &mut self.vm {
+            vm.import_function_handler_mut().set_input(data.clone());
+        }
+        self.import_function_handler.set_input(data);
+    }
+
+    fn share_io_with_vm(&mut self) {
+        if let Some(vm) = &mut self.vm {
+            vm.import_function_handler_mut().share_io(&self.import_function_handler);
+        }
+    }
+}
+
+/// The text of a segment offset expression and its value if it is an `i32` constant.
+fn describe_offset(init_expr: &InitExpr) -> (String, Option<u32>) {
+    match *init_expr {
+        InitExpr::I32Const(val) => (format!("i32.const {}", val), Some(val as u32)),
+        InitExpr::I64Const(val) => (format!("i64.const {}", val), None),
+        InitExpr::F32Const(val) => (format!("f32.const {}", Value::from(val)), None),
+        InitExpr::F64Const(val) => (format!("f64.const {}", Value::from(val)), None),
+        InitExpr::Global(global) => (format!("global.get {}", global), None),
+    }
+}
+
+/// The results of a function that just returned, in the order they were pushed.
+fn top_values(stack: &[Value], count: usize) -> Vec<Value> {
+    stack[stack.len().saturating_sub(count)..].to_vec()
+}
+
+/// Splits the text of an instruction into the mnemonic and the operands.
+fn split_instruction_text(text: &str) -> (&str, &str) {
+    match text.find(' ') {
+        Some(split) => (&text[..split], text[split + 1..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Appends `title (count):` and the first `limit` items, replacing the rest with an ellipsis.
+fn push_truncated(lines: &mut Vec<String>, title: &str, items: Vec<String>, limit: usize) {
+    lines.push(format!("{} ({}):", title, items.len()));
+    let hidden = items.len().saturating_sub(limit);
+    lines.extend(items.into_iter().take(limit).map(|item| format!("  {}", item)));
+    if hidden > 0 {
+        lines.push(format!("  ... {} more", hidden));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::vm::{GlobalChange, MemoryChange, WatchpointLocation};
+    use crate::{BufferSink, EdgeKind, ValueCond};
+
+    #[test]
+    fn instruction_limit_persists_until_reset() {
+        let mut dbg = load(&single_func_module(&[], &[], &[], INFINITE_LOOP, true));
+        assert_eq!(dbg.start().unwrap(), None);
+
+        assert_eq!(
+            dbg.continue_with_limit(1000).unwrap(),
+            Some(Trap::InstructionLimitReached(1000))
+        );
+        assert_eq!(dbg.get_vm().unwrap().executed_instructions(), 1000);
+        assert_eq!(
+            dbg.continue_with_limit(1000).unwrap(),
+            Some(Trap::InstructionLimitReached(1000))
+        );
+        assert_eq!(
+            dbg.continue_with_limit(1500).unwrap(),
+            Some(Trap::InstructionLimitReached(1500))
+        );
+        assert_eq!(dbg.get_vm().unwrap().executed_instructions(), 1500);
+        assert!(dbg.get_vm().unwrap().trap().is_none());
+
+        dbg.reset_vm().unwrap();
+        assert_eq!(dbg.start().unwrap(), None);
+        assert_eq!(dbg.get_vm().unwrap().executed_instructions(), 0);
+    }
+
+    fn memory_module(limits: Vec<u8>) -> Vec<u8> {
+        module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[limits])),
+            section(START_SECTION, &leb(0)),
+            section(CODE_SECTION, &vector(&[func_body(&[], &[0x0b])])),
+        ])
+    }
+
+    #[test]
+    fn grow_memory() {
+        let mut dbg = load(&memory_module(vec![0x00, 0x01]));
+        dbg.start().unwrap();
+        assert_eq!(dbg.memory_size().unwrap(), 1);
+        assert_eq!(dbg.grow_memory(2).unwrap(), 1);
+        assert_eq!(dbg.memory_size().unwrap(), 3);
+    }
+
+    #[test]
+    fn grow_memory_respects_maximum() {
+        let mut dbg = load(&memory_module(vec![0x01, 0x01, 0x02]));
+        dbg.start().unwrap();
+        assert_eq!(dbg.grow_memory(2).unwrap(), -1);
+        assert_eq!(dbg.grow_memory(1).unwrap(), 1);
+        assert_eq!(dbg.memory_size().unwrap(), 2);
+    }
+
+    fn division_module(divisor: u8) -> Vec<u8> {
+        // i32.const 7, i32.const divisor, i32.div_s, drop
+        single_func_module(&[], &[], &[], &[0x41, 0x07, 0x41, divisor, 0x6d, 0x1a, 0x0b], true)
+    }
+
+    #[test]
+    fn would_trap_on_zero_divisor() {
+        let mut dbg = load(&division_module(0));
+        dbg.start().unwrap();
+        assert_eq!(dbg.would_trap().unwrap(), None);
+        dbg.execute_step().unwrap();
+        dbg.execute_step().unwrap();
+        assert_eq!(dbg.would_trap().unwrap(), Some(TrapCategory::DivisionByZero));
+        assert_eq!(dbg.execute_step().unwrap(), Some(Trap::DivisionByZero));
+    }
+
+    #[test]
+    fn would_not_trap_on_safe_division() {
+        let mut dbg = load(&division_module(2));
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        dbg.execute_step().unwrap();
+        assert_eq!(dbg.would_trap().unwrap(), None);
+        assert_eq!(dbg.execute_step().unwrap(), None);
+    }
+
+    fn import_module() -> Vec<u8> {
+        let mut import_entry = name("env");
+        import_entry.extend(name("add_one"));
+        import_entry.extend(&[0x00, 0x00]);
+        module(&[
+            section(
+                TYPE_SECTION,
+                &vector(&[func_type(&[I32], &[I32]), func_type(&[], &[I32])]),
+            ),
+            section(IMPORT_SECTION, &vector(&[import_entry])),
+            section(FUNCTION_SECTION, &vector(&[leb(1)])),
+            // i32.const 41, call 0
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x29, 0x10, 0x00, 0x0b])]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn registered_host_function_is_called() {
+        let mut dbg: RegistryDebugger = load_with(&import_module());
+        dbg.register_host_function("env", "add_one", &[ValueType::I32], &[ValueType::I32], |_vm, args| {
+            Ok(vec![Value::from(args[0].to::<i32>().unwrap() + 1)])
+        })
+        .unwrap();
+        assert_eq!(dbg.call(1, &[]).unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(42)]);
+    }
+
+    #[test]
+    fn host_function_signature_is_checked() {
+        let mut dbg: RegistryDebugger = load_with(&import_module());
+        assert!(matches!(
+            dbg.register_host_function("env", "add_one", &[], &[ValueType::I32], |_vm, _args| Ok(Vec::new())),
+            Err(DebuggerError::HostFunctionSignatureMismatch { .. })
+        ));
+        assert!(!dbg.import_function_handler_mut().registry().contains("env", "add_one"));
+
+        // Registered while another module was loaded
+        dbg.import_function_handler_mut().registry().register(
+            "env",
+            "add_one",
+            &[],
+            &[],
+            Box::new(|_vm, _args| Ok(Vec::new())),
+        );
+        let message = "env.add_one is registered as [] -> [], the module imports it as [i32] -> [i32]";
+        assert_eq!(
+            dbg.call(1, &[]).unwrap(),
+            Trap::ImportProtocolError(String::from(message))
+        );
+
+        dbg.register_host_function("env", "add_one", &[ValueType::I32], &[ValueType::I32], |_vm, _args| {
+            Ok(vec![Value::I64(0)])
+        })
+        .unwrap();
+        assert_eq!(
+            dbg.call(1, &[]).unwrap(),
+            Trap::ImportProtocolError(String::from("env.add_one returned [i64], expected [i32]"))
+        );
+    }
+
+    #[test]
+    fn host_function_uses_registry() {
+        let mut dbg: RegistryDebugger = load_with(&import_module());
+        dbg.register_host_function("env", "add_one", &[ValueType::I32], &[ValueType::I32], |vm, args| {
+            let registry = vm.import_function_handler_mut().registry().clone();
+            assert!(registry.contains("env", "add_one"));
+            registry.register("env", "other", &[], &[], Box::new(|_vm, _args| Ok(Vec::new())));
+            Ok(vec![Value::from(args[0].to::<i32>().unwrap() + 1)])
+        })
+        .unwrap();
+        assert_eq!(dbg.call(1, &[]).unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(42)]);
+        assert!(dbg.import_function_handler_mut().registry().contains("env", "other"));
+    }
+
+    #[test]
+    fn reentrant_host_function_traps() {
+        let mut dbg: RegistryDebugger = load_with(&import_module());
+        dbg.register_host_function("env", "add_one", &[ValueType::I32], &[ValueType::I32], |vm, _args| {
+            Err(vm.run_func(1, &[]))
+        })
+        .unwrap();
+        assert_eq!(
+            dbg.call(1, &[]).unwrap(),
+            Trap::ImportCallFailed {
+                import: String::from("env.add_one"),
+                reason: String::from("re-entrant host call"),
+            }
+        );
+    }
+
+    /// Adds one to the argument on another thread, like a handler awaiting a remote client.
+    #[derive(Default, Clone)]
+    struct ThreadedAddOne {}
+
+    struct AddOneOnThread {
+        arg: i32,
+        result: Arc<Mutex<Option<i32>>>,
+        spawned: bool,
+    }
+
+    impl std::future::Future for AddOneOnThread {
+        type Output = i32;
+
+        fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<i32> {
+            if let Some(result) = self.result.lock().unwrap().take() {
+                return std::task::Poll::Ready(result);
+            }
+            if !self.spawned {
+                self.spawned = true;
+                let (arg, result, waker) = (self.arg, Arc::clone(&self.result), cx.waker().clone());
+                std::thread::spawn(move || {
+                    *result.lock().unwrap() = Some(arg + 1);
+                    waker.wake();
+                });
+            }
+            std::task::Poll::Pending
+        }
+    }
+
+    impl import_func::ImportFunctionHandler for ThreadedAddOne {
+        fn handle_import_function(vm: &mut VM<Self>) -> import_func::ImportFuture<'_> {
+            Box::pin(async move {
+                let arg = vm.locals()?[0].to::<i32>().unwrap();
+                let result = AddOneOnThread {
+                    arg,
+                    result: Arc::default(),
+                    spawned: false,
+                }
+                .await;
+                vm.push(Value::I32(result))
+            })
+        }
+    }
+
+    #[test]
+    fn async_import_handler() {
+        let mut dbg: Debugger<ThreadedAddOne> = load_with(&import_module());
+        assert_eq!(dbg.call(1, &[]).unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(42)]);
+    }
+
+    #[test]
+    fn unregistered_host_function_traps() {
+        let mut dbg: RegistryDebugger = load_with(&import_module());
+        assert_eq!(dbg.call(1, &[]).unwrap(), Trap::UnsupportedCallToImportedFunction(0));
+    }
+
+    #[test]
+    fn breakpoint_limit() {
+        let bytes = single_func_module(&[], &[], &[], INFINITE_LOOP, true);
+        let mut dbg = load(&bytes);
+        dbg.set_breakpoint_limit(Some(2));
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 1))).unwrap();
+        assert!(matches!(
+            dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 2))),
+            Err(DebuggerError::TooManyBreakpoints(2))
+        ));
+        assert_eq!(dbg.breakpoints().unwrap().len(), 2);
+
+        assert!(dbg.delete_breakpoint(0).unwrap());
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 2))).unwrap();
+
+        dbg.load_bytes("reloaded", &bytes).unwrap();
+        assert_eq!(dbg.breakpoints().unwrap().limit(), Some(2));
+    }
+
+    #[test]
+    fn disassemble_function() {
+        // i32.const 5, drop
+        let mut dbg = load(&single_func_module(&[], &[], &[], &[0x41, 0x05, 0x1a, 0x0b], false));
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 1))).unwrap();
+        let instr = |instr_index, mnemonic: &str, operands: &str, has_breakpoint| DisassembledInstr {
+            instr_index,
+            mnemonic: mnemonic.to_owned(),
+            operands: operands.to_owned(),
+            has_breakpoint,
+        };
+        assert_eq!(
+            dbg.disassemble_function(0).unwrap(),
+            vec![
+                instr(0, "i32.const", "5", false),
+                instr(1, "drop", "", true),
+                instr(2, "end", "", false)
+            ]
+        );
+        assert!(matches!(
+            dbg.disassemble_function(1),
+            Err(DebuggerError::NoFunctionWithIndex(1))
+        ));
+    }
+
+    #[test]
+    fn list_functions() {
+        let dbg: RegistryDebugger = load_with(&import_module());
+        let functions = dbg.list_functions().unwrap();
+        assert_eq!(
+            functions,
+            vec![
+                FunctionInfo {
+                    index: 0,
+                    name: None,
+                    param_types: vec![ValueType::I32],
+                    return_types: vec![ValueType::I32],
+                    is_imported: true,
+                },
+                FunctionInfo {
+                    index: 1,
+                    name: None,
+                    param_types: vec![],
+                    return_types: vec![ValueType::I32],
+                    is_imported: false,
+                },
+            ]
+        );
+    }
+
+    fn named_functions_module() -> Vec<u8> {
+        let mut export_entry = name("answer");
+        export_entry.extend(&[0x00, 0x01]);
+        module(&[
+            section(
+                TYPE_SECTION,
+                &vector(&[func_type(&[I32], &[I32]), func_type(&[], &[I32])]),
+            ),
+            section(FUNCTION_SECTION, &vector(&[leb(0), leb(1)])),
+            section(EXPORT_SECTION, &vector(&[export_entry])),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x20, 0x00, 0x0b]), func_body(&[], &[0x41, 0x2a, 0x0b])]),
+            ),
+            name_section(&[(0, "identity")]),
+        ])
+    }
+
+    #[test]
+    fn call_by_name() {
+        let mut dbg = load(&named_functions_module());
+        assert_eq!(dbg.call_by_name("answer", &[]).unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(42)]);
+        assert_eq!(
+            dbg.call_by_name("identity", &[Value::I32(7)]).unwrap(),
+            Trap::ExecutionFinished
+        );
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(7)]);
+        assert!(matches!(
+            dbg.call_by_name("missing", &[]),
+            Err(DebuggerError::FunctionNotFound(name)) if name == "missing"
+        ));
+        assert!(matches!(
+            dbg.call_by_name("identity", &[Value::I64(7)]),
+            Err(DebuggerError::ArgumentMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn call_checks_arguments() {
+        let mut dbg = load(&named_functions_module());
+        assert!(matches!(
+            dbg.call(0, &[]),
+            Err(DebuggerError::ArgumentMismatch { expected, got }) if expected == [ValueType::I32] && got.is_empty()
+        ));
+        assert!(matches!(
+            dbg.call(0, &[Value::from(1.0f32)]),
+            Err(DebuggerError::ArgumentMismatch { got, .. }) if got == [ValueType::F32]
+        ));
+        assert!(matches!(
+            dbg.call(0, &[Value::V128([0; 16])]),
+            Err(DebuggerError::V128Argument(0))
+        ));
+        assert!(matches!(
+            dbg.call_with_str_args(0, &["abc"]),
+            Err(DebuggerError::InvalidArgument {
+                value_type: ValueType::I32,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn call_with_str_args_coerces_to_f64() {
+        // (f64, f64) -> f64: local.get 0, local.get 1, f64.add
+        let bytes = single_func_module(&[F64, F64], &[F64], &[], &[0x20, 0x00, 0x20, 0x01, 0xa0, 0x0b], false);
+        let mut dbg = load(&bytes);
+        assert_eq!(
+            dbg.call_with_str_args(0, &["42", "0.5"]).unwrap(),
+            Trap::ExecutionFinished
+        );
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::from(42.5f64)]);
+    }
+
+    #[test]
+    fn memory_range_watchpoint() {
+        // i32.load from 16, then an i64 store to 12 that straddles the watched range
+        let code = [
+            0x41, 0x10, 0x28, 0x02, 0x00, 0x1a, 0x41, 0x0c, 0x42, 0x01, 0x37, 0x03, 0x00, 0x0b,
+        ];
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(START_SECTION, &leb(0)),
+            section(CODE_SECTION, &vector(&[func_body(&[], &code)])),
+        ]);
+        let mut dbg = load(&bytes);
+        let index = dbg.add_memory_watchpoint(16, 8, BreakpointTrigger::Write).unwrap();
+        assert_eq!(dbg.run().unwrap(), Trap::WatchpointReached(index));
+        assert_eq!(dbg.get_vm().unwrap().ip().instr_index, 6);
+        assert_eq!(
+            dbg.last_watchpoint_hit().unwrap().location,
+            WatchpointLocation::Memory {
+                address: 12,
+                size: 8,
+                write: true
+            }
+        );
+    }
+
+    #[test]
+    fn global_watchpoint_reports_values() {
+        // (global (mut i32) (i32.const 5)), global.set 0 (i32.const 6)
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(GLOBAL_SECTION, &vector(&[vec![I32, 0x01, 0x41, 0x05, 0x0b]])),
+            section(START_SECTION, &leb(0)),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x06, 0x24, 0x00, 0x0b])]),
+            ),
+        ]);
+        let mut dbg = load(&bytes);
+        let index = dbg
+            .add_breakpoint(Breakpoint::Global {
+                trigger: BreakpointTrigger::Write,
+                index: 0,
+                cond: None,
+            })
+            .unwrap();
+        assert_eq!(dbg.last_watchpoint_hit(), None);
+        assert_eq!(dbg.run().unwrap(), Trap::WatchpointReached(index));
+        assert_eq!(
+            dbg.last_watchpoint_hit(),
+            Some(WatchpointHit {
+                breakpoint_index: index,
+                location: WatchpointLocation::Global {
+                    index: 0,
+                    old_value: Value::I32(5),
+                    new_value: Value::I32(6),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        // i32.const 1, i32.store at 0 (i32.const 42), end
+        let code = [0x41, 0x00, 0x41, 0x2a, 0x36, 0x02, 0x00, 0x0b];
+        let mut dbg = load(&module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(START_SECTION, &leb(0)),
+            section(CODE_SECTION, &vector(&[func_body(&[], &code)])),
+        ]));
+        dbg.start().unwrap();
+        let snapshot = dbg.save_state().unwrap();
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.memory().unwrap().load::<u32>(0).unwrap(), 42);
+
+        dbg.load_state(&snapshot).unwrap();
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, 0));
+        assert_eq!(dbg.memory().unwrap().load::<u32>(0).unwrap(), 0);
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.memory().unwrap().load::<u32>(0).unwrap(), 42);
+
+        let mut other = load(&memory_module(vec![0x00, 0x01]));
+        assert!(matches!(
+            other.load_state(&snapshot),
+            Err(DebuggerError::SnapshotMismatch)
+        ));
+    }
+
+    /// func 0 is a recursive factorial, func 1 is the start function computing `factorial(5)`.
+    fn factorial_module() -> Vec<u8> {
+        let factorial = [
+            0x20, 0x00, 0x45, 0x04, I32, 0x41, 0x01, 0x05, 0x20, 0x00, 0x20, 0x00, 0x41, 0x01, 0x6b, 0x10, 0x00, 0x6c,
+            0x0b, 0x0b,
+        ];
+        module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[I32], &[I32]), func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0), leb(1)])),
+            section(START_SECTION, &leb(1)),
+            section(
+                CODE_SECTION,
+                &vector(&[
+                    func_body(&[], &factorial),
+                    func_body(&[], &[0x41, 0x05, 0x10, 0x00, 0x1a, 0x0b]),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn step_over_recursive_call() {
+        let mut dbg = load(&factorial_module());
+        dbg.start().unwrap();
+        assert_eq!(dbg.execute_step().unwrap(), None);
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(1, 1));
+        assert_eq!(dbg.execute_step_over().unwrap(), None);
+        let vm = dbg.get_vm().unwrap();
+        assert_eq!(vm.ip(), CodePosition::new(1, 2));
+        assert_eq!(vm.function_stack().len(), 1);
+        assert_eq!(vm.value_stack(), &[Value::I32(120)]);
+    }
+
+    #[test]
+    fn step_into_call() {
+        let mut dbg = load(&factorial_module());
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        assert_eq!(dbg.step_into().unwrap(), None);
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, 0));
+        assert_eq!(dbg.get_vm().unwrap().function_stack().len(), 2);
+    }
+
+    #[test]
+    fn step_into_imported_call() {
+        let mut import_entry = name("env");
+        import_entry.extend(name("add_one"));
+        import_entry.extend(&[0x00, 0x00]);
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[I32], &[I32]), func_type(&[], &[])])),
+            section(IMPORT_SECTION, &vector(&[import_entry])),
+            section(FUNCTION_SECTION, &vector(&[leb(1)])),
+            section(START_SECTION, &leb(1)),
+            // i32.const 41, call 0, drop
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x29, 0x10, 0x00, 0x1a, 0x0b])]),
+            ),
+        ]);
+        let mut dbg: RegistryDebugger = load_with(&bytes);
+        dbg.register_host_function("env", "add_one", &[ValueType::I32], &[ValueType::I32], |_vm, args| {
+            Ok(vec![Value::from(args[0].to::<i32>().unwrap() + 1)])
+        })
+        .unwrap();
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        assert_eq!(dbg.step_into().unwrap(), None);
+        let vm = dbg.get_vm().unwrap();
+        assert_eq!(vm.ip(), CodePosition::new(1, 2));
+        assert_eq!(vm.value_stack(), &[Value::I32(42)]);
+    }
+
+    /// Pauses on a `call_indirect` with `table_index` on the stack.
+    /// Table slot 0 holds function 0, slot 1 is null.
+    fn paused_on_call_indirect(table_index: u8) -> DefaultDebugger {
+        paused_on_call_indirect_of_type(table_index, 0)
+    }
+
+    /// Like `paused_on_call_indirect`, but the `call_indirect` expects type `type_index`. Type 0
+    /// is the type of function 0, type 1 has no result.
+    fn paused_on_call_indirect_of_type(table_index: u8, type_index: u8) -> DefaultDebugger {
+        let mut code = vec![0x41, table_index, 0x11, type_index, 0x00];
+        if type_index == 0 {
+            code.push(0x1a);
+        }
+        code.push(0x0b);
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[I32]), func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0), leb(1)])),
+            section(TABLE_SECTION, &vector(&[vec![0x70, 0x00, 0x02]])),
+            section(START_SECTION, &leb(1)),
+            section(ELEMENT_SECTION, &vector(&[vec![0x00, 0x41, 0x00, 0x0b, 0x01, 0x00]])),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x07, 0x0b]), func_body(&[], &code)]),
+            ),
+        ]);
+        let mut dbg = load(&bytes);
+        dbg.start().unwrap();
+        assert_eq!(dbg.resolve_indirect_target().unwrap(), None);
+        dbg.execute_step().unwrap();
+        dbg
+    }
+
+    #[test]
+    fn table_contents() {
+        let dbg = paused_on_call_indirect(0);
+        assert_eq!(dbg.table(0).unwrap(), vec![Some(0), None]);
+        assert!(matches!(dbg.table(1), Err(DebuggerError::NoTable)));
+    }
+
+    #[test]
+    fn resolve_indirect_target() {
+        assert_eq!(paused_on_call_indirect(0).resolve_indirect_target().unwrap(), Some(0));
+        // null entry
+        assert_eq!(paused_on_call_indirect(1).resolve_indirect_target().unwrap(), None);
+        // out of range
+        assert_eq!(paused_on_call_indirect(5).resolve_indirect_target().unwrap(), None);
+    }
+
+    #[test]
+    fn check_indirect_signature() {
+        let dbg = paused_on_call_indirect(0);
+        assert!(dbg.check_indirect_signature().unwrap());
+        assert_eq!(dbg.would_trap().unwrap(), None);
+
+        let mut dbg = paused_on_call_indirect_of_type(0, 1);
+        assert!(!dbg.check_indirect_signature().unwrap());
+        assert_eq!(dbg.would_trap().unwrap(), Some(TrapCategory::IndirectCallTypeMismatch));
+        assert_eq!(dbg.execute_step().unwrap(), Some(Trap::IndirectCallTypeMismatch));
+
+        assert!(matches!(
+            paused_on_call_indirect(1).check_indirect_signature(),
+            Err(DebuggerError::NoIndirectCallTarget)
+        ));
+    }
+
+    #[test]
+    fn read_typed_big_endian() {
+        let mut dbg = load(&memory_module(vec![0x00, 0x01]));
+        dbg.start().unwrap();
+        dbg.write_memory(0x100, &[0x01, 0x02, 0x03, 0x04]).unwrap();
+        assert_eq!(
+            dbg.read_typed_endian(0x100, ValueType::I32, 1, Endianness::Little)
+                .unwrap(),
+            vec![Value::I32(0x0403_0201)]
+        );
+        assert_eq!(
+            dbg.read_typed_endian(0x100, ValueType::I32, 1, Endianness::Big)
+                .unwrap(),
+            vec![Value::I32(0x0102_0304)]
+        );
+        assert_eq!(
+            dbg.read_typed(0x100, ValueType::I32, 1).unwrap(),
+            vec![Value::I32(0x0403_0201)]
+        );
+        assert!(matches!(
+            dbg.read_typed_endian(0xfffe, ValueType::I32, 1, Endianness::Big),
+            Err(DebuggerError::MemoryOutOfBounds {
+                address: 0xfffe,
+                len: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn read_typed_f64_array() {
+        let mut dbg = load(&memory_module(vec![0x00, 0x01]));
+        dbg.start().unwrap();
+        let values = [1.5f64, -2.25, 1e100];
+        let bytes: Vec<u8> = values.iter().flat_map(|val| val.to_le_bytes().to_vec()).collect();
+        dbg.write_memory(0x100, &bytes).unwrap();
+        assert_eq!(dbg.read_memory(0x100, 8).unwrap(), &bytes[..8]);
+        assert_eq!(
+            dbg.read_typed(0x100, ValueType::F64, 3).unwrap(),
+            values.iter().map(|val| Value::from(*val)).collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            dbg.read_typed(0xfff8, ValueType::F64, 2),
+            Err(DebuggerError::MemoryOutOfBounds {
+                address: 0xfff8,
+                len: 16
+            })
+        ));
+        assert!(matches!(
+            dbg.write_memory(0xffff, &[0, 0]),
+            Err(DebuggerError::MemoryOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn search_memory() {
+        let mut dbg = load(&memory_module(vec![0x00, 0x01]));
+        dbg.start().unwrap();
+        dbg.write_memory(0x20, b"hello").unwrap();
+        dbg.write_memory(0x80, b"hello").unwrap();
+        assert_eq!(dbg.search_memory(b"hello", 0).unwrap(), Some(0x20));
+        assert_eq!(dbg.search_memory(b"hello", 0x21).unwrap(), Some(0x80));
+        assert_eq!(dbg.search_memory(b"hello", 0x81).unwrap(), None);
+        assert_eq!(dbg.search_memory(b"", 0x42).unwrap(), Some(0x42));
+        assert_eq!(dbg.search_memory(&[0; 0x10001], 0).unwrap(), None);
+        assert_eq!(dbg.search_memory(b"h", 0x20000).unwrap(), None);
+    }
+
+    #[test]
+    fn reload_keeps_valid_breakpoints() {
+        // nop, nop, nop, nop, end
+        let path = write_temp_file(&single_func_module(
+            &[],
+            &[],
+            &[],
+            &[0x01, 0x01, 0x01, 0x01, 0x0b],
+            true,
+        ));
+        let mut dbg = DefaultDebugger::new();
+        dbg.load_file(&path).unwrap();
+        let kept = dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 1))).unwrap();
+        let dropped = dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 3))).unwrap();
+        dbg.start().unwrap();
+
+        // nop, end
+        std::fs::write(&path, single_func_module(&[], &[], &[], &[0x01, 0x0b], true)).unwrap();
+        let removed = dbg.reload().unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(
+            matches!(removed[0], (index, Breakpoint::Code(pos)) if index == dropped && pos == CodePosition::new(0, 3))
+        );
+        assert!(dbg.get_vm().is_err());
+        let breakpoints = dbg.breakpoints().unwrap();
+        assert_eq!(breakpoints.len(), 1);
+        assert_eq!(breakpoints.find_code(CodePosition::new(0, 1)), Some(kept));
+    }
+
+    #[test]
+    fn load_bytes() {
+        let mut dbg = DefaultDebugger::new();
+        dbg.load_bytes("named.wasm", &named_functions_module()).unwrap();
+        assert_eq!(dbg.get_file().unwrap().file_path(), "named.wasm");
+        assert_eq!(dbg.function_name(0).map(String::as_str), Some("identity"));
+        assert_eq!(dbg.call(1, &[]).unwrap(), Trap::ExecutionFinished);
+    }
+
+    #[test]
+    fn corrupt_name_section() {
+        let mut payload = name("name");
+        // function names subsection claiming five names but containing none
+        payload.extend(&[0x01, 0x01, 0x05]);
+        let mut bytes = single_func_module(&[], &[], &[], &[0x0b], false);
+        bytes.extend(section(CUSTOM_SECTION, &payload));
+
+        let mut dbg = DefaultDebugger::new();
+        dbg.load_bytes("corrupt.wasm", &bytes).unwrap();
+        assert_eq!(dbg.function_name(0), None);
+        assert!(!dbg.load_warnings().is_empty());
+        assert_eq!(dbg.call(0, &[]).unwrap(), Trap::ExecutionFinished);
+    }
+
+    #[test]
+    fn backtrace_detailed() {
+        let mut dbg = load(&module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[I32], &[]), func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0), leb(1)])),
+            section(START_SECTION, &leb(1)),
+            // func0: loop forever; func1: call func0 with 7
+            section(
+                CODE_SECTION,
+                &vector(&[
+                    func_body(&[(2, I64)], INFINITE_LOOP),
+                    func_body(&[], &[0x41, 0x07, 0x10, 0x00, 0x0b]),
+                ]),
+            ),
+            name_section(&[(0, "spin"), (1, "main")]),
+        ]));
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 1))).unwrap();
+        assert_eq!(dbg.run().unwrap(), Trap::BreakpointReached(0));
+
+        let frames = dbg.backtrace_detailed().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].func_name.as_deref(), Some("spin"));
+        assert_eq!((frames[0].func_index, frames[0].instr_index), (0, 1));
+        assert_eq!(frames[0].locals_count, 3);
+        assert_eq!(frames[1].func_name.as_deref(), Some("main"));
+        assert_eq!((frames[1].func_index, frames[1].instr_index), (1, 2));
+        assert_eq!(frames[1].locals_count, 0);
+        assert_eq!(frames[1].source, None);
+    }
+
+    #[test]
+    fn interrupt_from_another_thread() {
+        let mut dbg = load(&single_func_module(&[], &[], &[], INFINITE_LOOP, true));
+        dbg.start().unwrap();
+
+        let interrupt = dbg.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            interrupt.request();
+        });
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::Interrupted);
+        interrupter.join().unwrap();
+
+        // an interrupt requested before a run command doesn't stop it
+        dbg.request_interrupt();
+        assert_eq!(dbg.execute_step().unwrap(), None);
+        // but one requested between two continue slices does
+        assert_eq!(dbg.continue_execution_for(10).unwrap(), None);
+        dbg.request_interrupt();
+        assert_eq!(dbg.continue_execution_for(10).unwrap(), Some(Trap::Interrupted));
+        assert_eq!(dbg.continue_execution_for(10).unwrap(), None);
+    }
+
+    #[test]
+    fn fuel() {
+        let mut dbg = load(&single_func_module(&[], &[], &[], INFINITE_LOOP, true));
+        dbg.set_fuel(10);
+        assert_eq!(dbg.run().unwrap(), Trap::OutOfFuel);
+        assert_eq!(dbg.fuel_remaining().unwrap(), Some(0));
+        assert_eq!(dbg.get_vm().unwrap().executed_instructions(), 10);
+
+        dbg.set_fuel(5);
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::OutOfFuel);
+        assert_eq!(dbg.get_vm().unwrap().executed_instructions(), 15);
+    }
+
+    #[test]
+    fn fuel_cost_model() {
+        // i32.const 1, memory.grow, drop, end
+        let mut dbg = load(&module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(START_SECTION, &leb(0)),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x01, 0x40, 0x00, 0x1a, 0x0b])]),
+            ),
+        ]));
+        dbg.set_fuel(50);
+        assert_eq!(dbg.run().unwrap(), Trap::OutOfFuel);
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, 1));
+        assert_eq!(dbg.fuel_remaining().unwrap(), Some(49));
+
+        dbg.set_cost_model(CostModel::new(1).with_cost(&bwasm::Instruction::Drop, 100));
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::OutOfFuel);
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, 2));
+        assert_eq!(dbg.fuel_remaining().unwrap(), Some(48));
+    }
+
+    #[test]
+    fn profile_counts_loop_iterations() {
+        // loop (local.tee 0 (i32.add (local.get 0) (i32.const 1))) (br_if 0 (i32.lt_s ... (i32.const 5))) end
+        let code = [
+            0x03, 0x40, 0x20, 0x00, 0x41, 0x01, 0x6a, 0x22, 0x00, 0x41, 0x05, 0x48, 0x0d, 0x00, 0x0b, 0x0b,
+        ];
+        let mut dbg = load(&single_func_module(&[], &[], &[(1, I32)], &code, true));
+        assert!(dbg.profile_report().is_empty());
+        dbg.enable_profiling();
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+
+        let report = dbg.profile_report();
+        let count = |instr_index| {
+            report
+                .iter()
+                .find(|(pos, _)| *pos == CodePosition::new(0, instr_index))
+                .map(|(_, count)| *count)
+        };
+        assert_eq!(count(0), Some(1));
+        assert_eq!(count(1), Some(5));
+        assert_eq!(count(7), Some(5));
+        assert_eq!(count(9), Some(1));
+        assert_eq!(report[0], (CodePosition::new(0, 1), 5));
+        assert_eq!(report.len(), 10);
+    }
+
+    #[test]
+    fn coverage() {
+        // i32.const 0, if, unreachable, end, end
+        let code = [0x41, 0x00, 0x04, 0x40, 0x00, 0x0b, 0x0b];
+        let mut dbg = load(&module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0), leb(0)])),
+            section(START_SECTION, &leb(0)),
+            section(CODE_SECTION, &vector(&[func_body(&[], &code), func_body(&[], &[0x0b])])),
+        ]));
+        dbg.enable_coverage();
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+
+        let report = dbg.coverage();
+        assert_eq!(report.functions.len(), 2);
+        assert_eq!(report.functions[0].instr_count, 5);
+        assert_eq!(report.functions[0].reached, vec![0, 1, 3, 4]);
+        assert_eq!(report.functions[0].percentage(), 80.0);
+        assert!(report.functions[1].reached.is_empty());
+        assert_eq!(report.percentage(), 4.0 * 100.0 / 6.0);
+    }
+
+    #[test]
+    fn function_breakpoint_on_recursive_calls() {
+        let mut dbg = load(&factorial_module());
+        assert!(matches!(
+            dbg.add_function_breakpoint(2),
+            Err(DebuggerError::NoFunctionWithIndex(2))
+        ));
+        let index = dbg.add_function_breakpoint(0).unwrap();
+        assert_eq!(dbg.run().unwrap(), Trap::BreakpointReached(index));
+        let mut depths = Vec::new();
+        loop {
+            assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, 0));
+            depths.push(dbg.get_vm().unwrap().function_stack().len());
+            match dbg.continue_execution().unwrap() {
+                Trap::BreakpointReached(hit) => assert_eq!(hit, index),
+                trap => {
+                    assert_eq!(trap, Trap::ExecutionFinished);
+                    break;
+                }
+            }
+        }
+        // factorial(5) down to factorial(0)
+        assert_eq!(depths, vec![2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn function_breakpoint_on_indirect_call() {
+        let mut dbg = paused_on_call_indirect(0);
+        let index = dbg.add_function_breakpoint(0).unwrap();
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::BreakpointReached(index));
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, 0));
+    }
+
+    #[test]
+    fn break_on_trap() {
+        // i32.const 1, i32.const 0, i32.div_s, drop
+        let bytes = single_func_module(&[], &[], &[], &[0x41, 0x01, 0x41, 0x00, 0x6d, 0x1a, 0x0b], true);
+        let mut dbg = load(&bytes);
+        dbg.set_break_on_trap(true);
+        assert_eq!(dbg.run().unwrap(), Trap::Caught(Box::new(Trap::DivisionByZero)));
+        let vm = dbg.get_vm().unwrap();
+        assert_eq!(vm.ip(), CodePosition::new(0, 2));
+        assert_eq!(vm.value_stack(), &[Value::I32(1), Value::I32(0)]);
+        assert_eq!(vm.executed_instructions(), 2);
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::DivisionByZero);
+
+        // Fixing the divisor before resuming avoids the trap
+        dbg.run().unwrap();
+        dbg.get_vm_mut().unwrap().value_stack_mut()[1] = Value::I32(1);
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+    }
+
+    #[test]
+    fn break_on_trap_restores_popped_operands() {
+        let mut dbg = paused_on_call_indirect(1);
+        dbg.set_break_on_trap(true);
+        assert_eq!(
+            dbg.continue_execution().unwrap(),
+            Trap::Caught(Box::new(Trap::IndirectCalleeAbsent))
+        );
+        let vm = dbg.get_vm().unwrap();
+        assert_eq!(vm.ip(), CodePosition::new(1, 1));
+        assert_eq!(vm.value_stack(), &[Value::I32(1)]);
+    }
+
+    #[test]
+    fn out_of_bounds_trap_reports_access() {
+        let load_module = |code: &[u8]| {
+            module(&[
+                section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+                section(FUNCTION_SECTION, &vector(&[leb(0)])),
+                section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+                section(START_SECTION, &leb(0)),
+                section(CODE_SECTION, &vector(&[func_body(&[], code)])),
+            ])
+        };
+        // i32.const 0xfffe, i32.load offset=0x42, drop
+        let mut dbg = load(&load_module(&[0x41, 0xfe, 0xff, 0x03, 0x28, 0x02, 0x42, 0x1a, 0x0b]));
+        let trap = dbg.run().unwrap();
+        assert_eq!(
+            trap,
+            Trap::MemoryAccessOutOfBounds {
+                addr: 0x10040,
+                size: 4,
+                mem_size: 0x10000
+            }
+        );
+        assert_eq!(
+            trap.to_string(),
+            "Out of bounds memory access at 0x10040 (size 4, memory 0x10000)"
+        );
+
+        // The effective address overflows u32: i32.const -1, i32.load offset=1, drop
+        let mut dbg = load(&load_module(&[0x41, 0x7f, 0x28, 0x02, 0x01, 0x1a, 0x0b]));
+        assert_eq!(
+            dbg.run().unwrap(),
+            Trap::MemoryAccessOutOfBounds {
+                addr: 0x1_0000_0000,
+                size: 4,
+                mem_size: 0x10000
+            }
+        );
+    }
+
+    #[test]
+    fn dump_state() {
+        let mut dbg = load(&factorial_module());
+        let index = dbg.add_function_breakpoint(0).unwrap();
+        assert_eq!(dbg.run().unwrap(), Trap::BreakpointReached(index));
+        dbg.continue_execution().unwrap();
+        dbg.continue_execution().unwrap();
+
+        let dump = dbg.dump_state().unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "ip: 0:0");
+        assert!(lines.contains(&"backtrace (4):"));
+        assert!(lines.contains(&"  #3 1:2"));
+        assert!(lines.contains(&"value stack (2):"));
+        assert!(lines.contains(&"  1: i32 : 0x00000004 = 4"));
+        assert!(lines.contains(&"locals (1):"));
+        assert!(lines.contains(&"  0: i32 : 0x00000003 = 3"));
+        assert!(lines.contains(&"globals (0):"));
+        assert_eq!(lines.last(), Some(&"memory: none"));
+
+        let dump = dbg.dump_state_with_limit(1).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert!(lines.contains(&"  #0 0:0"));
+        assert!(lines.contains(&"  ... 3 more"));
+        assert!(!lines.contains(&"  #1 0:10"));
+        assert!(lines.contains(&"  ... 1 more"));
+    }
+
+    #[test]
+    fn diff_since() {
+        // Stores local 0 at address 4 and into global 0, returns it incremented
+        let code = [
+            0x41, 0x04, 0x20, 0x00, 0x36, 0x02, 0x00, 0x20, 0x00, 0x24, 0x00, 0x20, 0x00, 0x41, 0x01, 0x6a, 0x0b,
+        ];
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[I32], &[I32]), func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0), leb(1)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(GLOBAL_SECTION, &vector(&[vec![I32, 0x01, 0x41, 0x00, 0x0b]])),
+            section(START_SECTION, &leb(1)),
+            section(
+                CODE_SECTION,
+                &vector(&[
+                    func_body(&[], &code),
+                    func_body(&[], &[0x41, 0x05, 0x41, 0xff, 0x01, 0x10, 0x00, 0x1a, 0x1a, 0x0b]),
+                ]),
+            ),
+        ]);
+        let mut dbg = load(&bytes);
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        dbg.execute_step().unwrap();
+        let snapshot = dbg.save_state().unwrap();
+        assert!(dbg.diff_since(&snapshot).unwrap().is_empty());
+
+        assert_eq!(dbg.execute_step_over().unwrap(), None);
+        let diff = dbg.diff_since(&snapshot).unwrap();
+        assert_eq!(
+            diff.globals,
+            vec![GlobalChange {
+                index: 0,
+                old_value: Value::I32(0),
+                new_value: Value::I32(0xff),
+            }]
+        );
+        assert_eq!(
+            diff.memory,
+            vec![MemoryChange {
+                memory_index: 0,
+                start: 4,
+                len: 1,
+            }]
+        );
+        assert_eq!(diff.popped, vec![Value::I32(0xff)]);
+        assert_eq!(diff.pushed, vec![Value::I32(0x100)]);
+    }
+
+    #[test]
+    fn memory_file_round_trip() {
+        let mut dbg = load(&memory_module(vec![0x00, 0x01]));
+        dbg.start().unwrap();
+        dbg.write_memory(0x10, b"wasm").unwrap();
+
+        let path = write_temp_file(&[]);
+        dbg.dump_memory_to_file(0x10, 4, &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"wasm");
+        assert!(matches!(
+            dbg.dump_memory_to_file(0xfffe, 4, &path),
+            Err(DebuggerError::MemoryOutOfBounds { .. })
+        ));
+
+        dbg.load_memory_from_file(&path, 0x100).unwrap();
+        assert_eq!(dbg.read_memory(0x100, 4).unwrap(), b"wasm");
+        assert!(matches!(
+            dbg.load_memory_from_file(&path, 0xfffe),
+            Err(DebuggerError::MemoryOutOfBounds {
+                address: 0xfffe,
+                len: 4
+            })
+        ));
+        assert_eq!(dbg.read_memory(0xfffe, 2).unwrap(), &[0, 0]);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            dbg.load_memory_from_file(&path, 0),
+            Err(DebuggerError::FileAccess { .. })
+        ));
+    }
+
+    #[test]
+    fn current_position() {
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(START_SECTION, &leb(0)),
+            section(CODE_SECTION, &vector(&[func_body(&[], &[0x41, 0x07, 0x1a, 0x0b])])),
+            name_section(&[(0, "main")]),
+        ]);
+        let mut dbg = load(&bytes);
+        assert!(matches!(dbg.current_position(), Err(DebuggerError::NoRunningInstance)));
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        let position = dbg.current_position().unwrap();
+        assert_eq!(position.func_index, 0);
+        assert_eq!(position.func_name.as_deref(), Some("main"));
+        assert_eq!(position.instr_index, 1);
+        assert_eq!(position.instruction_text.as_deref(), Some("drop"));
+        assert_eq!(position.source, None);
+    }
+
+    /// Exports `add_one: [i32] -> [i32]`
+    fn add_one_library() -> Vec<u8> {
+        let mut export_entry = name("add_one");
+        export_entry.extend(&[0x00, 0x00]);
+        module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[I32], &[I32])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(EXPORT_SECTION, &vector(&[export_entry])),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x20, 0x00, 0x41, 0x01, 0x6a, 0x0b])]),
+            ),
+        ])
+    }
+
+    /// Imports `module.field: [i32] -> [i32]` and calls it with 41 from the start function.
+    fn importing_module(module_name: &str, field: &str) -> Vec<u8> {
+        let mut import_entry = name(module_name);
+        import_entry.extend(name(field));
+        import_entry.extend(&[0x00, 0x00]);
+        module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[I32], &[I32]), func_type(&[], &[])])),
+            section(IMPORT_SECTION, &vector(&[import_entry])),
+            section(FUNCTION_SECTION, &vector(&[leb(1)])),
+            section(START_SECTION, &leb(1)),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x29, 0x10, 0x00, 0x1a, 0x0b])]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn linked_library() {
+        let mut dbg = load(&importing_module("env", "add_one"));
+        dbg.load_additional(&write_temp_file(&add_one_library())).unwrap();
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        assert_eq!(dbg.step_into().unwrap(), None);
+        let vm = dbg.get_vm().unwrap();
+        assert_eq!(vm.ip(), CodePosition::new(1, 2));
+        assert_eq!(vm.value_stack(), &[Value::I32(42)]);
+
+        // A second library exporting the same function makes the import ambiguous
+        assert!(matches!(
+            dbg.load_additional(&write_temp_file(&add_one_library())),
+            Err(DebuggerError::LinkError(LinkError::SymbolClash { .. }))
+        ));
+        assert_eq!(dbg.additional_files().len(), 1);
+    }
+
+    #[test]
+    fn unresolved_library_import() {
+        let path = write_temp_file(&add_one_library());
+        let mut dbg = load(&importing_module(&linker::module_name(&path), "sub_one"));
+        assert_eq!(
+            dbg.load_additional(&path).unwrap_err().to_string(),
+            format!(
+                "Failed to link modules: Module \"{}\" has no function export \"sub_one\"",
+                linker::module_name(&path)
+            )
+        );
+        assert!(matches!(
+            dbg.load_additional("/nonexistent/lib.wasm"),
+            Err(DebuggerError::LoadFailed(_))
+        ));
+        // Unlinked imports still go to the import function handler
+        assert_eq!(dbg.run().unwrap(), Trap::UnsupportedCallToImportedFunction(0));
+    }
+
+    #[test]
+    fn exports() {
+        let export = |field: &str, kind: u8| {
+            let mut entry = name(field);
+            entry.extend(&[kind, 0x00]);
+            entry
+        };
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(GLOBAL_SECTION, &vector(&[vec![I32, 0x00, 0x41, 0x07, 0x0b]])),
+            section(
+                EXPORT_SECTION,
+                &vector(&[
+                    export("main", 0x00),
+                    export("_start", 0x00),
+                    export("memory", 0x02),
+                    export("answer", 0x03),
+                ]),
+            ),
+            section(CODE_SECTION, &vector(&[func_body(&[], &[0x0b])])),
+        ]);
+        let dbg = load(&bytes);
+        let export = |name: &str, kind, index| ExportInfo {
+            name: name.to_owned(),
+            kind,
+            index,
+        };
+        assert_eq!(
+            dbg.exports().unwrap(),
+            vec![
+                export("main", ExportKind::Function, 0),
+                export("_start", ExportKind::Function, 0),
+                export("memory", ExportKind::Memory, 0),
+                export("answer", ExportKind::Global, 0),
+            ]
+        );
+        assert!(matches!(
+            DefaultDebugger::new().exports(),
+            Err(DebuggerError::NoFileLoaded)
+        ));
+    }
+
+    #[test]
+    fn start_function() {
+        let mut dbg = load(&factorial_module());
+        assert_eq!(dbg.start_function().unwrap(), Some(1));
+        assert_eq!(dbg.start().unwrap(), None);
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(1, 0));
+
+        dbg.set_skip_start(true);
+        assert_eq!(dbg.start().unwrap(), Some(Trap::ExecutionFinished));
+        let index = dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        assert_eq!(dbg.start().unwrap(), Some(Trap::BreakpointReached(index)));
+
+        let dbg = load(&single_func_module(&[], &[], &[], &[0x0b], false));
+        assert_eq!(dbg.start_function().unwrap(), None);
+        assert!(matches!(
+            DefaultDebugger::new().start_function(),
+            Err(DebuggerError::NoFileLoaded)
+        ));
+    }
+
+    /// Executes `i32.const 1, memory.grow` twice.
+    fn growing_module(limits: Vec<u8>) -> Vec<u8> {
+        module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[limits])),
+            section(START_SECTION, &leb(0)),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x01, 0x40, 0x00, 0x41, 0x01, 0x40, 0x00, 0x0b])]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn memory_grow_instruction_respects_maximum() {
+        let mut dbg = load(&growing_module(vec![0x01, 0x01, 0x02]));
+        dbg.start().unwrap();
+        for _ in 0..4 {
+            assert_eq!(dbg.execute_step().unwrap(), None);
+        }
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(1), Value::I32(-1)]);
+        assert_eq!(dbg.memory_size_pages().unwrap(), 2);
+        assert_eq!(dbg.grow_memory(u32::MAX).unwrap(), -1);
+    }
+
+    #[test]
+    fn max_memory_pages() {
+        let mut dbg = load(&growing_module(vec![0x00, 0x01]));
+        dbg.set_max_memory_pages(Some(2));
+        assert_eq!(dbg.run().unwrap(), Trap::MemoryGrowthDenied { pages: 3, cap: 2 });
+        assert_eq!(dbg.memory_size_pages().unwrap(), 2);
+        assert_eq!(dbg.grow_memory(1).unwrap(), -1);
+        dbg.set_max_memory_pages(Some(3));
+        assert_eq!(dbg.grow_memory(1).unwrap(), 2);
+        assert_eq!(dbg.memory_size_pages().unwrap(), 3);
+    }
+
+    #[test]
+    fn frame_locals() {
+        let mut dbg = load(&factorial_module());
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        dbg.step_into().unwrap();
+        assert_eq!(
+            dbg.frame_locals(0).unwrap(),
+            FrameLocals {
+                func_index: 0,
+                locals: vec![(None, Value::I32(5))],
+            }
+        );
+        assert_eq!(
+            dbg.frame_locals(1).unwrap(),
+            FrameLocals {
+                func_index: 1,
+                locals: Vec::new(),
+            }
+        );
+        assert!(matches!(
+            dbg.frame_locals(2),
+            Err(DebuggerError::InvalidFrameIndex { index: 2, depth: 2 })
+        ));
+    }
+
+    #[test]
+    fn value_stack_annotated() {
+        let mut dbg = load(&factorial_module());
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        assert_eq!(
+            dbg.value_stack_annotated().unwrap(),
+            vec![StackSlot {
+                index: 0,
+                depth: 0,
+                value: Value::I32(5),
+                call_argument: Some(CallArgument {
+                    func_index: 0,
+                    param_index: 0,
+                }),
+            }]
+        );
+
+        dbg.step_into().unwrap();
+        dbg.execute_step().unwrap();
+        assert_eq!(
+            dbg.value_stack_annotated().unwrap(),
+            vec![StackSlot {
+                index: 0,
+                depth: 0,
+                value: Value::I32(5),
+                call_argument: None,
+            }]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_and_load_breakpoints() {
+        let mut dbg = load(&factorial_module());
+        let module_path = dbg.file().unwrap().file_path().clone();
+        let session_path = format!("{}.breakpoints.json", module_path);
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 3))).unwrap();
+        let disabled = dbg.add_breakpoint(Breakpoint::Function(1)).unwrap();
+        dbg.set_breakpoint_enabled(disabled, false).unwrap();
+        dbg.add_breakpoint(Breakpoint::MemoryRange {
+            start: 4,
+            len: 8,
+            access: BreakpointTrigger::Write,
+        })
+        .unwrap();
+        dbg.save_breakpoints(&session_path).unwrap();
+
+        let mut restored: DefaultDebugger = Debugger::new();
+        restored.load_file(&module_path).unwrap();
+        assert!(restored.load_breakpoints(&session_path).unwrap().is_empty());
+        let without_hits = |dbg: &DefaultDebugger| -> Vec<(Breakpoint, bool)> {
+            dbg.list_breakpoints()
+                .unwrap()
+                .into_iter()
+                .map(|info| (info.breakpoint, info.enabled))
+                .collect()
+        };
+        assert_eq!(without_hits(&restored), without_hits(&dbg));
+
+        // Saving another module keeps the entry of this one. Breakpoints that don't resolve
+        // in the module anymore are skipped.
+        let mut other = load(&single_func_module(&[], &[], &[], &[0x01, 0x0b], false));
+        other.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 1))).unwrap();
+        other.save_breakpoints(&session_path).unwrap();
+        let other_path = other.file().unwrap().file_path().clone();
+        std::fs::write(&other_path, single_func_module(&[], &[], &[], &[0x0b], false)).unwrap();
+        other.reload().unwrap();
+        assert_eq!(
+            other.load_breakpoints(&session_path).unwrap(),
+            vec![Breakpoint::Code(CodePosition::new(0, 1))]
+        );
+        restored.clear_breakpoints().unwrap();
+        assert!(restored.load_breakpoints(&session_path).unwrap().is_empty());
+        assert_eq!(without_hits(&restored), without_hits(&dbg));
+
+        std::fs::write(&session_path, "not json").unwrap();
+        assert!(matches!(
+            dbg.load_breakpoints(&session_path),
+            Err(DebuggerError::FileAccess { .. })
+        ));
+        std::fs::remove_file(&session_path).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn state_json() {
+        let mut dbg = load(&factorial_module());
+        assert!(matches!(dbg.state_json(), Err(DebuggerError::NoRunningInstance)));
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        let state: serde_json::Value = serde_json::from_str(&dbg.state_json().unwrap()).unwrap();
+        assert_eq!(
+            state,
+            serde_json::json!({
+                "ip": { "func_index": 1, "instr_index": 1 },
+                "backtrace": [{ "func_index": 1, "instr_index": 1 }],
+                "value_stack": [{ "type": "i32", "value": 5 }],
+                "locals": [],
+                "globals": [],
+                "memory_pages": null,
+                "executed_instructions": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn unreachable_trap_position() {
+        // nop, unreachable
+        let mut dbg = load(&single_func_module(&[], &[], &[], &[0x01, 0x00, 0x0b], true));
+        assert_eq!(dbg.run().unwrap(), Trap::ReachedUnreachable);
+        assert_eq!(dbg.trap_position().unwrap(), Some(CodePosition::new(0, 1)));
+        assert_eq!(dbg.execute_step().unwrap(), Some(Trap::ReachedUnreachable));
+        assert_eq!(dbg.trap_position().unwrap(), Some(CodePosition::new(0, 1)));
+
+        dbg.start().unwrap();
+        assert_eq!(dbg.trap_position().unwrap(), None);
+    }
+
+    #[test]
+    fn max_call_depth() {
+        // call 0
+        let mut dbg = load(&single_func_module(&[], &[], &[], &[0x10, 0x00, 0x0b], true));
+        assert_eq!(dbg.run().unwrap(), Trap::CallStackExhausted);
+        assert_eq!(dbg.backtrace().unwrap().len(), DEFAULT_MAX_CALL_DEPTH);
+
+        dbg.set_max_call_depth(100);
+        assert_eq!(dbg.run().unwrap(), Trap::CallStackExhausted);
+        assert_eq!(dbg.backtrace().unwrap().len(), 100);
+        assert_eq!(dbg.trap_position().unwrap(), Some(CodePosition::new(0, 0)));
+    }
+
+    #[test]
+    fn current_instruction() {
+        let mut bytes = factorial_module();
+        bytes.extend(name_section(&[(0, "factorial")]));
+        let mut dbg = load(&bytes);
+        dbg.start().unwrap();
+        let instr = dbg.current_instruction().unwrap();
+        assert_eq!(instr.operands, vec![Operand::Const(Value::I32(5))]);
+        assert_eq!(instr.to_string(), "i32.const 5");
+
+        dbg.execute_step().unwrap();
+        let instr = dbg.current_instruction().unwrap();
+        assert_eq!(
+            instr,
+            InstructionInfo {
+                position: CodePosition::new(1, 1),
+                mnemonic: "call".to_owned(),
+                operands: vec![Operand::Function {
+                    index: 0,
+                    name: Some("factorial".to_owned()),
+                }],
+            }
+        );
+        assert_eq!(instr.to_string(), "call $factorial (func 0)");
+
+        dbg.step_into().unwrap();
+        assert_eq!(
+            dbg.current_instruction().unwrap().operands,
+            vec![Operand::Local { index: 0, name: None }]
+        );
+    }
+
+    #[test]
+    fn finish() {
+        let mut dbg = load(&factorial_module());
+        dbg.start().unwrap();
+        dbg.execute_step().unwrap();
+        dbg.step_into().unwrap();
+        assert_eq!(
+            dbg.finish().unwrap(),
+            FinishResult {
+                trap: None,
+                return_values: vec![Value::I32(120)],
+            }
+        );
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(1, 2));
+        assert_eq!(
+            dbg.finish().unwrap(),
+            FinishResult {
+                trap: Some(Trap::ExecutionFinished),
+                return_values: Vec::new(),
+            }
+        );
+
+        let mut dbg = load(&single_func_module(&[], &[], &[], &[0x00, 0x0b], true));
+        dbg.start().unwrap();
+        assert_eq!(
+            dbg.finish().unwrap(),
+            FinishResult {
+                trap: Some(Trap::ReachedUnreachable),
+                return_values: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn replace_code_breakpoints() {
+        let mut dbg = load(&factorial_module());
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        let function_breakpoint = dbg.add_function_breakpoint(0).unwrap();
+        let results = dbg
+            .replace_code_breakpoints(&[
+                CodePosition::new(1, 0),
+                CodePosition::new(1, 100),
+                CodePosition::new(0, 1),
+            ])
+            .unwrap();
+        assert!(matches!(
+            results.as_slice(),
+            [Ok(2), Err(DebuggerError::InvalidBreakpointPosition), Ok(3)]
+        ));
+
+        let file = dbg.file().unwrap();
+        let breakpoints = file.breakpoints_and_unlock();
+        assert_eq!(breakpoints.len(), 3);
+        assert_eq!(breakpoints.find_code(CodePosition::new(0, 0)), None);
+        assert_eq!(breakpoints.find_code(CodePosition::new(0, 1)), Some(3));
+        assert_eq!(breakpoints.find_function(0), Some(function_breakpoint));
+    }
+
+    #[test]
+    fn list_breakpoints() {
+        let mut dbg = load(&factorial_module());
+        let code = dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        let memory = dbg
+            .add_breakpoint(Breakpoint::Memory(BreakpointTrigger::Write, 8))
+            .unwrap();
+        dbg.start().unwrap();
+        dbg.continue_execution().unwrap();
+        dbg.continue_execution().unwrap();
+        assert_eq!(
+            dbg.list_breakpoints().unwrap(),
+            vec![
+                BreakpointInfo {
+                    index: code,
+                    breakpoint: Breakpoint::Code(CodePosition::new(0, 0)),
+                    enabled: true,
+                    hit_count: 2,
+                },
+                BreakpointInfo {
+                    index: memory,
+                    breakpoint: Breakpoint::Memory(BreakpointTrigger::Write, 8),
+                    enabled: true,
+                    hit_count: 0,
+                },
+            ]
+        );
+
+        dbg.delete_breakpoint(code).unwrap();
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        assert_eq!(dbg.list_breakpoints().unwrap()[1].hit_count, 0);
+    }
+
+    #[test]
+    fn disabled_breakpoints() {
+        let mut dbg = load(&factorial_module());
+        let first = dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        let second = dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        dbg.set_breakpoint_enabled(first, false).unwrap();
+        dbg.start().unwrap();
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::BreakpointReached(second));
+
+        dbg.set_breakpoint_enabled(second, false).unwrap();
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+        let list = dbg.list_breakpoints().unwrap();
+        assert!(list.iter().all(|info| !info.enabled));
+        assert_eq!(list[1].hit_count, 1);
+
+        dbg.set_breakpoint_enabled(first, true).unwrap();
+        dbg.start().unwrap();
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::BreakpointReached(first));
+        assert!(matches!(
+            dbg.set_breakpoint_enabled(7, true),
+            Err(DebuggerError::NoBreakpointWithIndex(7))
+        ));
+    }
+
+    #[test]
+    fn conditional_global_watchpoint() {
+        // global.set 0 (i32.const 6), global.set 0 (i32.const -1), end
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(GLOBAL_SECTION, &vector(&[vec![I32, 0x01, 0x41, 0x05, 0x0b]])),
+            section(START_SECTION, &leb(0)),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x41, 0x06, 0x24, 0x00, 0x41, 0x7f, 0x24, 0x00, 0x0b])]),
+            ),
+        ]);
+        let mut dbg = load(&bytes);
+        let index = dbg
+            .add_breakpoint(Breakpoint::Global {
+                trigger: BreakpointTrigger::Write,
+                index: 0,
+                cond: Some(ValueCond::Lt(Value::I32(0))),
+            })
+            .unwrap();
+        assert_eq!(dbg.run().unwrap(), Trap::WatchpointReached(index));
+        assert_eq!(
+            dbg.last_watchpoint_hit().unwrap().location,
+            WatchpointLocation::Global {
+                index: 0,
+                old_value: Value::I32(6),
+                new_value: Value::I32(-1),
+            }
+        );
+
+        assert!(matches!(
+            dbg.add_breakpoint(Breakpoint::Global {
+                trigger: BreakpointTrigger::Write,
+                index: 0,
+                cond: Some(ValueCond::Eq(Value::I64(0))),
+            }),
+            Err(DebuggerError::WatchpointConditionTypeMismatch(0))
+        ));
+    }
+
+    #[test]
+    fn control_flow_graph() {
+        // block, loop, local.get 0, br_if 1, br 0, end, end, end
+        let code = [
+            0x02, 0x40, 0x03, 0x40, 0x20, 0x00, 0x0d, 0x01, 0x0c, 0x00, 0x0b, 0x0b, 0x0b,
+        ];
+        let dbg = load(&single_func_module(&[I32], &[], &[], &code, false));
+        let cfg = dbg.control_flow_graph(0).unwrap();
+        let blocks: Vec<_> = cfg.blocks.iter().map(|block| (block.start, block.end)).collect();
+        assert_eq!(blocks, [(0, 2), (2, 4), (4, 5), (5, 6), (6, 8)]);
+        let edges: Vec<_> = cfg.edges.iter().map(|edge| (edge.from, edge.to, edge.kind)).collect();
+        assert_eq!(
+            edges,
+            [
+                (0, 1, EdgeKind::Fallthrough),
+                (1, 4, EdgeKind::BranchIf),
+                (1, 2, EdgeKind::Fallthrough),
+                (2, 1, EdgeKind::Branch),
+                (3, 4, EdgeKind::Fallthrough),
+            ]
+        );
+        assert_eq!(cfg.block_containing(3), Some(1));
+
+        // local.get 0, if, nop, else, unreachable, end, end
+        let code = [0x20, 0x00, 0x04, 0x40, 0x01, 0x05, 0x00, 0x0b, 0x0b];
+        let dbg = load(&single_func_module(&[I32], &[], &[], &code, false));
+        let cfg = dbg.control_flow_graph(0).unwrap();
+        let edges: Vec<_> = cfg.edges.iter().map(|edge| (edge.from, edge.to, edge.kind)).collect();
+        assert_eq!(
+            edges,
+            [
+                (0, 1, EdgeKind::IfTrue),
+                (0, 2, EdgeKind::IfFalse),
+                (1, 3, EdgeKind::Branch)
+            ]
+        );
+
+        let dbg = load(&import_module());
+        assert!(matches!(
+            dbg.control_flow_graph(0),
+            Err(DebuggerError::ImportedFunction(0))
+        ));
+    }
+
+    #[test]
+    fn call_sites() {
+        let dbg = load(&factorial_module());
+        assert_eq!(
+            dbg.call_sites(0).unwrap(),
+            vec![CodePosition::new(0, 9), CodePosition::new(1, 1)]
+        );
+        assert_eq!(dbg.call_sites(1).unwrap(), vec![]);
+        assert!(matches!(dbg.call_sites(2), Err(DebuggerError::NoFunctionWithIndex(2))));
+
+        let dbg = paused_on_call_indirect(0);
+        assert_eq!(dbg.call_sites(0).unwrap(), vec![]);
+        assert_eq!(dbg.indirect_call_sites(0).unwrap(), vec![CodePosition::new(1, 1)]);
+        assert_eq!(dbg.indirect_call_sites(1).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn data_segments() {
+        let mut first = vec![0x00, 0x41, 0x10, 0x0b, 0x03];
+        first.extend(b"abc");
+        let mut second = vec![0x00, 0x41, 0x80, 0x02, 0x0b, 0x01];
+        second.push(0xff);
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(CODE_SECTION, &vector(&[func_body(&[], &[0x0b])])),
+            section(DATA_SECTION, &vector(&[first, second])),
+        ]);
+        let dbg = load(&bytes);
+        let segments = dbg.data_segments().unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                DataSegmentInfo {
+                    index: 0,
+                    memory_index: 0,
+                    offset_expr: "i32.const 16".to_owned(),
+                    offset: Some(16),
+                    len: 3,
+                },
+                DataSegmentInfo {
+                    index: 1,
+                    memory_index: 0,
+                    offset_expr: "i32.const 256".to_owned(),
+                    offset: Some(256),
+                    len: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn element_segments() {
+        let dbg = paused_on_call_indirect(0);
+        assert_eq!(
+            dbg.element_segments().unwrap(),
+            vec![ElementSegmentInfo {
+                index: 0,
+                table_index: 0,
+                offset_expr: "i32.const 0".to_owned(),
+                offset: Some(0),
+                functions: vec![0],
+            }]
+        );
+        assert_eq!(load(&factorial_module()).element_segments().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn is_finished() {
+        let mut dbg = load(&factorial_module());
+        assert!(!dbg.is_finished());
+        dbg.start().unwrap();
+        assert!(!dbg.is_finished());
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+        assert!(dbg.is_finished());
+        dbg.start().unwrap();
+        assert!(!dbg.is_finished());
+        dbg.run().unwrap();
+        assert!(dbg.is_finished());
+        dbg.reset_vm().unwrap();
+        assert!(!dbg.is_finished());
+    }
+
+    #[test]
+    fn restart() {
+        let mut dbg = load(&factorial_module());
+        assert!(matches!(dbg.restart(), Err(DebuggerError::NothingToRestart)));
+
+        assert_eq!(dbg.call(0, &[Value::I32(3)]).unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(6)]);
+        assert_eq!(dbg.restart().unwrap(), Some(Trap::ExecutionFinished));
+        assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(6)]);
+
+        dbg.start().unwrap();
+        let index = dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::BreakpointReached(index));
+        assert_eq!(dbg.restart().unwrap(), None);
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(1, 0));
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::BreakpointReached(index));
+        assert_eq!(dbg.last_run(), Some(&RunEntry::Start));
+    }
+
+    #[test]
+    fn pre_instruction_hook() {
+        // i32.const 1, i32.const 2, i32.add, drop, end
+        let code = [0x41, 0x01, 0x41, 0x02, 0x6a, 0x1a, 0x0b];
+        let mut dbg = load(&single_func_module(&[], &[], &[], &code, true));
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let hook_trace = Arc::clone(&trace);
+        dbg.set_pre_instruction_hook(Box::new(move |vm, instr| {
+            hook_trace
+                .lock()
+                .unwrap()
+                .push((vm.value_stack().len(), format!("{:?}", instr)));
+        }));
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+        assert_eq!(
+            *trace.lock().unwrap(),
+            vec![
+                (0, "I32Const(1)".to_owned()),
+                (1, "I32Const(2)".to_owned()),
+                (2, "I32Add".to_owned()),
+                (1, "Drop".to_owned()),
+                (0, "End".to_owned()),
+            ]
+        );
+
+        dbg.clear_pre_instruction_hook();
+        dbg.run().unwrap();
+        assert_eq!(trace.lock().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn trace() {
+        // i32.const 1, i32.const 2, i32.add, drop, end
+        let code = [0x41, 0x01, 0x41, 0x02, 0x6a, 0x1a, 0x0b];
+        let mut dbg = load(&single_func_module(&[], &[], &[], &code, true));
+        let out = BufferSink::default();
+        dbg.enable_trace(Box::new(out.clone()));
+        dbg.run().unwrap();
+        let trace = out.take();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("0:0 ") && lines[0].ends_with(" -> i32:1"));
+        assert!(lines[2].starts_with("0:2 ") && lines[2].ends_with(" -> i32:3"));
+        assert!(lines[3].ends_with(" -> -"));
+
+        dbg.set_trace_limit(2);
+        dbg.enable_trace(Box::new(out.clone()));
+        dbg.run().unwrap();
+        let trace = out.take();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2], "trace stopped after 2 instructions");
+
+        dbg.disable_trace();
+        dbg.run().unwrap();
+        assert_eq!(out.take(), "");
+    }
+
+    #[test]
+    fn custom_sections() {
+        let custom_section = |section_name: &str, data: &[u8]| {
+            let mut payload = name(section_name);
+            payload.extend_from_slice(data);
+            section(CUSTOM_SECTION, &payload)
+        };
+        let mut bytes = single_func_module(&[], &[], &[], &[0x0b], false);
+        bytes.extend(custom_section("producers", &[0x00]));
+        bytes.extend(custom_section("meta", b"first"));
+        bytes.extend(custom_section("meta", b"second!"));
+
+        let mut dbg = DefaultDebugger::new();
+        assert!(matches!(dbg.custom_sections(), Err(DebuggerError::NoFileLoaded)));
+        dbg.load_bytes("custom.wasm", &bytes).unwrap();
+        assert_eq!(
+            dbg.custom_sections().unwrap(),
+            vec![
+                (String::from("producers"), 1),
+                (String::from("meta"), 5),
+                (String::from("meta"), 7)
+            ]
+        );
+        assert_eq!(dbg.custom_section("meta").unwrap(), Some(&b"first"[..]));
+        assert_eq!(dbg.custom_section("producers").unwrap(), Some(&[0x00][..]));
+        assert_eq!(dbg.custom_section("target_features").unwrap(), None);
+    }
+
+    #[test]
+    fn module_summary() {
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(
+                IMPORT_SECTION,
+                &vector(&[[name("env"), name("f"), vec![0x00, 0x00]].concat()]),
+            ),
+            section(FUNCTION_SECTION, &vector(&[leb(0), leb(0)])),
+            section(TABLE_SECTION, &vector(&[vec![0x70, 0x01, 0x01, 0x04]])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x02]])),
+            section(GLOBAL_SECTION, &vector(&[vec![I32, 0x01, 0x41, 0x00, 0x0b]])),
+            section(EXPORT_SECTION, &vector(&[[name("main"), vec![0x00, 0x01]].concat()])),
+            section(START_SECTION, &leb(2)),
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[], &[0x0b]), func_body(&[], &[0x0b])]),
+            ),
+        ]);
+        let dbg = load(&bytes);
+        assert_eq!(
+            dbg.module_summary().unwrap(),
+            ModuleSummary {
+                imported_functions: 1,
+                defined_functions: 2,
+                globals: 1,
+                memories: vec![ResourceLimits { min: 2, max: None }],
+                tables: vec![ResourceLimits { min: 1, max: Some(4) }],
+                imports: 1,
+                exports: 1,
+                has_start: true,
+            }
+        );
+        assert!(matches!(
+            DefaultDebugger::new().module_summary(),
+            Err(DebuggerError::NoFileLoaded)
+        ));
+    }
+
+    #[test]
+    fn validate() {
+        assert!(matches!(
+            DefaultDebugger::new().validate(),
+            Err(DebuggerError::NoFileLoaded)
+        ));
+        load(&factorial_module()).validate().unwrap();
+        load(&import_module()).validate().unwrap();
+        load(&memory_module(vec![0x00, 0x01])).validate().unwrap();
+    }
+
+    #[test]
+    fn call_with_results() {
+        let mut dbg = load(&factorial_module());
+        assert_eq!(
+            dbg.call_with_results(0, &[Value::I32(5)]).unwrap(),
+            FinishResult {
+                trap: Some(Trap::ExecutionFinished),
+                return_values: vec![Value::I32(120)],
+            }
+        );
+        assert_eq!(dbg.call_with_results(1, &[]).unwrap().return_values, Vec::new());
+
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        let result = dbg.call_with_results(0, &[Value::I32(5)]).unwrap();
+        assert!(matches!(result.trap, Some(Trap::BreakpointReached(_))));
+        assert!(result.return_values.is_empty());
+    }
+
+    #[test]
+    fn top_values_of_two_results() {
+        // bwasm can't parse function types with more than one result, so this can't be a module
+        let stack = [Value::I64(7), Value::I32(1), Value::I32(2)];
+        assert_eq!(top_values(&stack, 2), vec![Value::I32(1), Value::I32(2)]);
+        assert_eq!(top_values(&stack[..1], 2), vec![Value::I64(7)]);
+    }
+
+    #[test]
+    fn reload_runs_new_code() {
+        let const_module = |value| single_func_module(&[], &[I32], &[], &[0x41, value, 0x0b], false);
+        let mut dbg = load(&const_module(1));
+        assert_eq!(
+            dbg.call_with_results(0, &[]).unwrap().return_values,
+            vec![Value::I32(1)]
+        );
+        dbg.load_file(&write_temp_file(&const_module(2))).unwrap();
+        assert_eq!(
+            dbg.call_with_results(0, &[]).unwrap().return_values,
+            vec![Value::I32(2)]
+        );
+    }
+
+    #[test]
+    fn memory_init_pattern() {
+        let mut dbg = load(&growing_module(vec![0x00, 0x01]));
+        dbg.set_memory_init_pattern(MemInitPattern::Poison(0xcd));
+        dbg.run().unwrap();
+        assert_eq!(dbg.memory_size_pages().unwrap(), 3);
+        assert!(dbg.memory().unwrap().data().iter().all(|byte| *byte == 0xcd));
+
+        // Data segments still apply on top of the pattern
+        let mut bytes = growing_module(vec![0x00, 0x01]);
+        bytes.extend(section(
+            DATA_SECTION,
+            &vector(&[vec![0x00, 0x41, 0x01, 0x0b, 0x02, 0x11, 0x22]]),
+        ));
+        let mut dbg = load(&bytes);
+        dbg.set_memory_init_pattern(MemInitPattern::Poison(0xcd));
+        dbg.start().unwrap();
+        assert_eq!(&dbg.memory().unwrap().data()[..4], &[0xcd, 0x11, 0x22, 0xcd]);
+
+        dbg.set_memory_init_pattern(MemInitPattern::Zero);
+        dbg.run().unwrap();
+        assert_eq!(dbg.memory().unwrap().data()[0], 0);
+    }
+
+    #[test]
+    fn function_bytes() {
+        let code = [0x41, 0x07, 0x1a, 0x0b];
+        let dbg = load(&single_func_module(&[], &[], &[(2, I64)], &code, false));
+        assert_eq!(
+            dbg.function_bytes(0).unwrap(),
+            vec![0x01, 0x02, I64, 0x41, 0x07, 0x1a, 0x0b]
+        );
+        assert!(matches!(
+            dbg.function_bytes(1),
+            Err(DebuggerError::NoFunctionWithIndex(1))
+        ));
+
+        let dbg = load(&import_module());
+        assert!(matches!(dbg.function_bytes(0), Err(DebuggerError::ImportedFunction(0))));
+        assert_eq!(dbg.function_bytes(1).unwrap(), vec![0x00, 0x41, 0x29, 0x10, 0x00, 0x0b]);
+    }
+
+    #[test]
+    fn patch_const() {
+        let mut dbg = load(&factorial_module());
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        // The `i32.const 1` returned for 0
+        let pos = CodePosition::new(0, 3);
+        assert!(matches!(
+            dbg.patch_const(pos, Value::I64(1)),
+            Err(DebuggerError::ConstTypeMismatch {
+                expected: ValueType::I32,
+                ..
+            })
+        ));
+        assert!(matches!(
+            dbg.patch_const(CodePosition::new(0, 0), Value::I32(1)),
+            Err(DebuggerError::NoConstAt(_))
+        ));
+        assert!(matches!(
+            dbg.patch_const(CodePosition::new(0, 100), Value::I32(1)),
+            Err(DebuggerError::NoInstructionAt(_))
+        ));
+        // 64 needs a second LEB byte
+        assert!(matches!(
+            dbg.patch_const(pos, Value::I32(64)),
+            Err(DebuggerError::ConstTooLong { len: 1, .. })
+        ));
+
+        let body = dbg.function_bytes(0).unwrap();
+        dbg.patch_const(pos, Value::I32(2)).unwrap();
+        assert!(dbg.get_vm().is_ok());
+        assert_eq!(dbg.function_bytes(0).unwrap().len(), body.len());
+        assert_eq!(dbg.breakpoints().unwrap().len(), 1);
+        dbg.delete_breakpoint(0).unwrap();
+        assert_eq!(
+            dbg.call_with_results(0, &[Value::I32(3)]).unwrap().return_values,
+            vec![Value::I32(12)]
+        );
+    }
+
+    #[test]
+    fn imports() {
+        let import = |field: &str, external: &[u8]| [name("env"), name(field), external.to_vec()].concat();
+        let bytes = module(&[
+            section(
+                TYPE_SECTION,
+                &vector(&[func_type(&[I32, I64], &[F32]), func_type(&[], &[])]),
+            ),
+            section(
+                IMPORT_SECTION,
+                &vector(&[
+                    import("memory", &[0x02, 0x00, 0x01]),
+                    import("f", &[0x00, 0x00]),
+                    import("g", &[0x00, 0x01]),
+                    import("answer", &[0x03, I32, 0x00]),
+                ]),
+            ),
+        ]);
+        let mut dbg: RegistryDebugger = load_with(&bytes);
+        let info = |field: &str, kind, param_types, return_types| ImportInfo {
+            module: String::from("env"),
+            field: field.to_owned(),
+            kind,
+            param_types,
+            return_types,
+        };
+        let f = info(
+            "f",
+            ExportKind::Function,
+            vec![ValueType::I32, ValueType::I64],
+            vec![ValueType::F32],
+        );
+        let g = info("g", ExportKind::Function, Vec::new(), Vec::new());
+        assert_eq!(
+            dbg.imports().unwrap(),
+            vec![
+                info("memory", ExportKind::Memory, Vec::new(), Vec::new()),
+                f.clone(),
+                g.clone(),
+                info("answer", ExportKind::Global, Vec::new(), Vec::new()),
+            ]
+        );
+
+        assert_eq!(dbg.missing_host_functions().unwrap(), vec![f, g.clone()]);
+        dbg.register_host_function(
+            "env",
+            "f",
+            &[ValueType::I32, ValueType::I64],
+            &[ValueType::F32],
+            |_vm, _args| Ok(vec![Value::F32(F32::from_bits(0))]),
+        )
+        .unwrap();
+        assert_eq!(dbg.missing_host_functions().unwrap(), vec![g]);
+    }
+
+    #[test]
+    fn step_over_br_table() {
+        // (block (block (block (br_table 0 1 2 (local.get 0))) (return (i32.const 10)))
+        //   (return (i32.const 20))) (i32.const 30)
+        let code = [
+            0x02, 0x40, 0x02, 0x40, 0x02, 0x40, 0x20, 0x00, 0x0e, 0x02, 0x00, 0x01, 0x02, 0x0b, 0x41, 0x0a, 0x0f, 0x0b,
+            0x41, 0x14, 0x0f, 0x0b, 0x41, 0x1e, 0x0b,
+        ];
+        let mut dbg = load(&single_func_module(&[I32], &[I32], &[], &code, false));
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 4))).unwrap();
+        for (index, target, result) in [(0, 5, 10), (1, 8, 20), (2, 11, 30), (7, 11, 30)] {
+            assert_eq!(dbg.call(0, &[Value::I32(index)]).unwrap(), Trap::BreakpointReached(0));
+            assert_eq!(dbg.execute_step_over().unwrap(), None);
+            assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, target));
+            assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+            assert_eq!(dbg.get_vm().unwrap().value_stack(), &[Value::I32(result)]);
+        }
+
+        // The default targets the label of the function
+        let code = [0x02, 0x40, 0x41, 0x05, 0x0e, 0x01, 0x00, 0x01, 0x0b, 0x01, 0x0b];
+        let mut dbg = load(&single_func_module(&[], &[], &[], &code, false));
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 2))).unwrap();
+        assert_eq!(dbg.call(0, &[]).unwrap(), Trap::BreakpointReached(0));
+        assert_eq!(dbg.execute_step_over().unwrap(), None);
+        assert_eq!(dbg.get_vm().unwrap().ip(), CodePosition::new(0, 5));
+        assert_eq!(dbg.continue_execution().unwrap(), Trap::ExecutionFinished);
+    }
+
+    #[test]
+    fn frame_local_types() {
+        let mut dbg = load(&single_func_module(&[I64], &[], &[(2, F32), (1, I32)], &[0x0b], false));
+        assert!(matches!(
+            dbg.frame_local_types(0),
+            Err(DebuggerError::NoRunningInstance)
+        ));
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 0))).unwrap();
+        assert_eq!(dbg.call(0, &[Value::I64(3)]).unwrap(), Trap::BreakpointReached(0));
+        let types = dbg.frame_local_types(0).unwrap();
+        assert_eq!(
+            types,
+            vec![ValueType::I64, ValueType::F32, ValueType::F32, ValueType::I32]
+        );
+        let locals = dbg.frame_locals(0).unwrap().locals;
+        assert!(locals
+            .iter()
+            .zip(&types)
+            .all(|((_, value), value_type)| value.value_type() == Some(*value_type)));
+        assert!(matches!(
+            dbg.frame_local_types(1),
+            Err(DebuggerError::InvalidFrameIndex { index: 1, depth: 1 })
+        ));
+    }
+
+    #[test]
+    fn instr_offsets() {
+        // i32.const 300, drop, end
+        let dbg = load(&single_func_module(
+            &[],
+            &[],
+            &[(1, I32)],
+            &[0x41, 0xac, 0x02, 0x1a, 0x0b],
+            false,
+        ));
+        assert_eq!(dbg.instr_offset(0, 0).unwrap(), 5);
+        assert_eq!(dbg.instr_offset(0, 1).unwrap(), 8);
+        assert!(matches!(dbg.instr_offset(0, 3), Err(DebuggerError::NoInstructionAt(_))));
+        assert_eq!(dbg.offset_to_instr(8).unwrap(), CodePosition::new(0, 1));
+        assert_eq!(dbg.offset_to_instr(6).unwrap(), CodePosition::new(0, 0));
+        assert!(matches!(
+            dbg.offset_to_instr(2),
+            Err(DebuggerError::NoInstructionAtOffset(2))
+        ));
+
+        // Imported functions have no code
+        let dbg = load(&import_module());
+        assert!(matches!(dbg.instr_offset(0, 0), Err(DebuggerError::NoInstructionAt(_))));
+        let offset = dbg.instr_offset(1, 1).unwrap();
+        assert_eq!(dbg.offset_to_instr(offset).unwrap(), CodePosition::new(1, 1));
+    }
+
+    #[test]
+    fn trap_explanations() {
+        assert_eq!(Trap::DivisionByZero.to_string(), "Division by zero");
+        assert_eq!(
+            Trap::DivisionByZero.explanation(),
+            "integer division or remainder with a zero divisor"
+        );
+        let caught = Trap::Caught(Box::new(Trap::IndirectCallTypeMismatch));
+        assert_eq!(caught.explanation(), Trap::IndirectCallTypeMismatch.explanation());
     }
 }
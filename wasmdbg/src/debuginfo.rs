@@ -1,28 +1,86 @@
-use parity_wasm::elements::deserialize_file;
+use gimli::{ColumnType, Dwarf, EndianSlice, RunTimeEndian, SectionId};
+use parity_wasm::deserialize_buffer;
 use std::collections::HashMap;
+use std::fmt;
+
+use crate::binary::{BinaryLayout, Reader};
+use crate::vm::CodePosition;
 
 type FuncIndex = u32;
 type LocalIndex = u32;
 
+// Subsections of the extended name section that parity-wasm doesn't parse
+const MEMORY_NAME_SUBSECTION: u8 = 6;
+const GLOBAL_NAME_SUBSECTION: u8 = 7;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+struct LineRow {
+    address: u64,
+    /// `None` marks the end of a sequence
+    location: Option<SourceLocation>,
+}
+
 pub struct DebugInfo {
     function_name_map: HashMap<FuncIndex, String>,
     local_name_map: HashMap<FuncIndex, HashMap<LocalIndex, String>>,
+    global_name_map: HashMap<u32, String>,
+    memory_name_map: HashMap<u32, String>,
+    layout: BinaryLayout,
+    line_rows: Vec<LineRow>,
+    warnings: Vec<String>,
 }
 
 impl DebugInfo {
-    pub fn new(file_path: &str) -> Self {
-        let mut info = DebugInfo {
-            function_name_map: HashMap::new(),
-            local_name_map: HashMap::new(),
-        };
-        let module = deserialize_file(file_path).expect("module invalid");
+    /// Fails only if `bytes` isn't a wasm module. Broken debug sections are skipped and
+    /// reported through `warnings`.
+    pub fn new(bytes: &[u8]) -> Result<Self, String> {
+        let module: parity_wasm::elements::Module = deserialize_buffer(bytes).map_err(|err| err.to_string())?;
+        let mut warnings = Vec::new();
+        let layout = BinaryLayout::parse(bytes).unwrap_or_else(|| {
+            warnings.push(String::from("Failed to locate instructions in the code section"));
+            BinaryLayout::default()
+        });
+        let line_rows = parse_line_rows(&layout).unwrap_or_else(|err| {
+            warnings.push(format!("Failed to parse DWARF line info: {}", err));
+            Vec::new()
+        });
         let module = match module.parse_names() {
             Ok(module) => module,
-            Err((err, module)) => {
-                println!("{:?}", err);
+            Err((errors, module)) => {
+                for (_, err) in errors {
+                    warnings.push(format!("Failed to parse name section: {}", err));
+                }
                 module
             }
         };
+        let mut global_name_map = HashMap::new();
+        let mut memory_name_map = HashMap::new();
+        if let Some(payload) = layout.custom_section("name") {
+            if parse_extended_names(payload, &mut global_name_map, &mut memory_name_map).is_none() {
+                warnings.push(String::from("Failed to parse global and memory names"));
+            }
+        }
+        let mut info = DebugInfo {
+            function_name_map: HashMap::new(),
+            local_name_map: HashMap::new(),
+            global_name_map,
+            memory_name_map,
+            layout,
+            line_rows,
+            warnings,
+        };
         if let Some(name_section) = module.names_section() {
             if let Some(function_name_section) = name_section.functions() {
                 function_name_section.names().iter().for_each(|item| {
@@ -42,7 +100,11 @@ impl DebugInfo {
                     });
             }
         }
-        info
+        Ok(info)
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
     }
 
     pub fn function_name_map(&self) -> &HashMap<FuncIndex, String> {
@@ -51,4 +113,278 @@ impl DebugInfo {
     pub fn local_name_map(&self) -> &HashMap<FuncIndex, HashMap<LocalIndex, String>> {
         &self.local_name_map
     }
+    pub fn global_name_map(&self) -> &HashMap<u32, String> {
+        &self.global_name_map
+    }
+    pub fn memory_name_map(&self) -> &HashMap<u32, String> {
+        &self.memory_name_map
+    }
+
+    /// Name and payload of every custom section in module order.
+    pub fn custom_sections(&self) -> &[(String, Vec<u8>)] {
+        &self.layout.custom_sections
+    }
+
+    /// The encoded body of a defined function, as found in the code section.
+    pub fn function_bytes(&self, func_index: FuncIndex) -> Option<&[u8]> {
+        Some(&self.layout.function_body(func_index)?.bytes)
+    }
+
+    /// The offset of an instruction relative to the code section payload, which DWARF addresses
+    /// are relative to as well.
+    pub fn instruction_offset(&self, func_index: FuncIndex, instr_index: u32) -> Option<u32> {
+        self.layout.instruction_offset(func_index, instr_index)
+    }
+
+    /// The instruction whose encoding contains the code section offset `offset`.
+    pub fn instruction_at_offset(&self, offset: u32) -> Option<CodePosition> {
+        let (func_index, instr_index) = self.layout.instruction_at(offset)?;
+        Some(CodePosition::new(func_index, instr_index))
+    }
+
+    pub fn source_location(&self, func_index: FuncIndex, instr_offset: u32) -> Option<SourceLocation> {
+        let address = u64::from(self.layout.instruction_offset(func_index, instr_offset)?);
+        let index = self.line_rows.partition_point(|row| row.address <= address);
+        self.line_rows.get(index.checked_sub(1)?)?.location.clone()
+    }
+
+    /// Finds the first instruction of every function that belongs to `line` or, if there is
+    /// none, to the nearest following line of the file. `file` may be a path suffix.
+    pub fn code_positions_at_line(&self, file: &str, line: u32) -> Vec<CodePosition> {
+        let mut candidates = Vec::new();
+        for (body_index, body) in self.layout.bodies.iter().enumerate() {
+            let func_index = self.layout.imported_function_count + body_index as u32;
+            for instr_index in 0..body.instr_offsets.len() as u32 {
+                if let Some(location) = self.source_location(func_index, instr_index) {
+                    if location.line >= line && is_same_file(&location.file, file) {
+                        candidates.push((location.line, CodePosition::new(func_index, instr_index)));
+                    }
+                }
+            }
+        }
+        let nearest_line = match candidates.iter().map(|(line, _)| *line).min() {
+            Some(line) => line,
+            None => return Vec::new(),
+        };
+        let mut positions: Vec<CodePosition> = Vec::new();
+        for (candidate_line, pos) in candidates {
+            if candidate_line == nearest_line && !positions.iter().any(|p| p.func_index == pos.func_index) {
+                positions.push(pos);
+            }
+        }
+        positions
+    }
+}
+
+fn is_same_file(path: &str, file: &str) -> bool {
+    path == file || path.ends_with(&format!("/{}", file))
+}
+
+fn parse_extended_names(
+    payload: &[u8],
+    global_name_map: &mut HashMap<u32, String>,
+    memory_name_map: &mut HashMap<u32, String>,
+) -> Option<()> {
+    let mut reader = Reader::new(payload);
+    while !reader.is_empty() {
+        let id = reader.read_u8()?;
+        let size = reader.read_leb_u32()? as usize;
+        let subsection = reader.read_bytes(size)?;
+        let map = match id {
+            GLOBAL_NAME_SUBSECTION => &mut *global_name_map,
+            MEMORY_NAME_SUBSECTION => &mut *memory_name_map,
+            _ => continue,
+        };
+        let mut subsection = Reader::new(subsection);
+        for _ in 0..subsection.read_leb_u32()? {
+            let index = subsection.read_leb_u32()?;
+            map.insert(index, subsection.read_name()?);
+        }
+    }
+    Some(())
+}
+
+/// Collects the rows of all line programs sorted by address.
+/// Addresses are offsets relative to the code section payload.
+fn parse_line_rows(layout: &BinaryLayout) -> Result<Vec<LineRow>, gimli::Error> {
+    let load_section = |id: SectionId| -> Result<EndianSlice<RunTimeEndian>, gimli::Error> {
+        let data = layout.custom_section(id.name()).unwrap_or(&[]);
+        Ok(EndianSlice::new(data, RunTimeEndian::Little))
+    };
+    let dwarf = Dwarf::load(load_section)?;
+
+    let mut rows = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let program = match unit.line_program.clone() {
+            Some(program) => program,
+            None => continue,
+        };
+        let mut program_rows = program.rows();
+        while let Some((header, row)) = program_rows.next_row()? {
+            if row.end_sequence() {
+                rows.push(LineRow {
+                    address: row.address(),
+                    location: None,
+                });
+                continue;
+            }
+            let file = match row.file(header) {
+                Some(file) => file,
+                None => continue,
+            };
+            let mut path = String::new();
+            if let Some(dir) = file.directory(header) {
+                path.push_str(&dwarf.attr_string(&unit, dir)?.to_string_lossy());
+            }
+            let name = dwarf.attr_string(&unit, file.path_name())?.to_string_lossy();
+            if path.is_empty() || name.starts_with('/') {
+                path = name.into_owned();
+            } else {
+                if !path.ends_with('/') {
+                    path.push('/');
+                }
+                path.push_str(&name);
+            }
+            rows.push(LineRow {
+                address: row.address(),
+                location: Some(SourceLocation {
+                    file: path,
+                    line: row.line().map_or(0, |line| line.get() as u32),
+                    column: match row.column() {
+                        ColumnType::LeftEdge => 0,
+                        ColumnType::Column(column) => column.get() as u32,
+                    },
+                }),
+            });
+        }
+    }
+    // Stable sort so that an end of sequence stays before a sequence starting at the same address
+    rows.sort_by_key(|row| row.address);
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use gimli::write::{Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Sections};
+
+    fn module_with_dwarf() -> Vec<u8> {
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 4,
+        };
+        let mut program = LineProgram::new(
+            encoding,
+            gimli::LineEncoding::default(),
+            LineString::String(b"/src".to_vec()),
+            LineString::String(b"main.c".to_vec()),
+            None,
+        );
+        let dir = program.default_directory();
+        let file = program.add_file(LineString::String(b"main.c".to_vec()), dir, None);
+        program.begin_sequence(Some(Address::Constant(0)));
+        for (address, line) in &[(3, 10), (5, 11)] {
+            program.row().file = file;
+            program.row().address_offset = *address;
+            program.row().line = *line;
+            program.row().column = 5;
+            program.generate_row();
+        }
+        program.end_sequence(7);
+
+        let mut dwarf = DwarfUnit::new(encoding);
+        dwarf.unit.line_program = program;
+        let root = dwarf.unit.root();
+        let root = dwarf.unit.get_mut(root);
+        root.set(gimli::DW_AT_comp_dir, AttributeValue::String(b"/src".to_vec()));
+        root.set(gimli::DW_AT_stmt_list, AttributeValue::LineProgramRef);
+        let mut sections = Sections::new(EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+
+        // i32.const 1, drop, end
+        let mut bytes = single_func_module(&[], &[], &[], &[0x41, 0x01, 0x1a, 0x0b], false);
+        sections
+            .for_each(|id, data| -> Result<(), ()> {
+                if !data.slice().is_empty() {
+                    let mut payload = name(id.name());
+                    payload.extend_from_slice(data.slice());
+                    bytes.extend(section(0, &payload));
+                }
+                Ok(())
+            })
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn source_location_from_dwarf() {
+        let info = DebugInfo::new(&module_with_dwarf()).unwrap();
+        let location = |instr| {
+            info.source_location(0, instr)
+                .map(|location| (location.file, location.line))
+        };
+        assert_eq!(location(0), Some((String::from("/src/main.c"), 10)));
+        assert_eq!(location(1), Some((String::from("/src/main.c"), 11)));
+        assert_eq!(location(2), Some((String::from("/src/main.c"), 11)));
+        assert_eq!(location(3), None);
+    }
+
+    #[test]
+    fn code_positions_at_line() {
+        let info = DebugInfo::new(&module_with_dwarf()).unwrap();
+        assert_eq!(info.code_positions_at_line("main.c", 10), vec![CodePosition::new(0, 0)]);
+        assert_eq!(
+            info.code_positions_at_line("/src/main.c", 11),
+            vec![CodePosition::new(0, 1)]
+        );
+        assert_eq!(info.code_positions_at_line("main.c", 2), vec![CodePosition::new(0, 0)]);
+        assert!(info.code_positions_at_line("main.c", 12).is_empty());
+        assert!(info.code_positions_at_line("other.c", 10).is_empty());
+    }
+
+    #[test]
+    fn source_location_without_dwarf() {
+        let bytes = single_func_module(&[], &[], &[], &[0x41, 0x01, 0x1a, 0x0b], false);
+        let info = DebugInfo::new(&bytes).unwrap();
+        assert_eq!(info.source_location(0, 0), None);
+    }
+
+    #[test]
+    fn global_and_memory_names() {
+        let names = |entries: &[(u32, &str)]| {
+            let entries: Vec<Vec<u8>> = entries
+                .iter()
+                .map(|(index, entry_name)| {
+                    let mut bytes = leb(*index);
+                    bytes.extend(name(entry_name));
+                    bytes
+                })
+                .collect();
+            vector(&entries)
+        };
+        let mut payload = name("name");
+        for (id, subsection) in &[
+            (MEMORY_NAME_SUBSECTION, names(&[(0, "heap")])),
+            (GLOBAL_NAME_SUBSECTION, names(&[(0, "__stack_pointer"), (2, "counter")])),
+        ] {
+            payload.push(*id);
+            payload.extend(leb(subsection.len() as u32));
+            payload.extend(subsection);
+        }
+        let mut bytes = single_func_module(&[], &[], &[], &[0x0b], false);
+        bytes.extend(section(CUSTOM_SECTION, &payload));
+
+        let info = DebugInfo::new(&bytes).unwrap();
+        assert_eq!(info.memory_name_map().get(&0).map(String::as_str), Some("heap"));
+        assert_eq!(
+            info.global_name_map().get(&0).map(String::as_str),
+            Some("__stack_pointer")
+        );
+        assert_eq!(info.global_name_map().get(&2).map(String::as_str), Some("counter"));
+        assert_eq!(info.global_name_map().get(&1), None);
+    }
 }
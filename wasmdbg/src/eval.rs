@@ -0,0 +1,488 @@
+//! Evaluator for the small expression language used by watch expressions.
+//!
+//! Operands:
+//! - number literals including `inf` and `nan`, typed like `Value::parse_inferred` does
+//! - `l<N>`: local N of the current function frame
+//! - `g<N>`: global N
+//! - `mem[<expr>]` or `mem[<expr>]:<type>`: a load from the default memory. The type is one of
+//!   `i32` (the default), `i64`, `f32` and `f64`.
+//! - `reinterpret<<type>>(<expr>)`: the bits of a value as another type of the same width, e.g.
+//!   `reinterpret<f32>(l0)`
+//! - parenthesized expressions
+//!
+//! Operators, from lowest to highest precedence. Binary operators are left associative.
+//! 1. `==`, `!=`, `<`, `<=`, `>`, `>=`
+//! 2. `+`, `-`
+//! 3. `*`, `/`, `%`
+//! 4. unary `-`
+//!
+//! Both operands of a binary operator are promoted to the wider of their types, where the order
+//! is `i32` < `i64` < `f32` < `f64`. `i32` operands are sign extended to `i64`. Integer arithmetic
+//! wraps and integer division and comparison are signed, like the `_s` instructions of wasm,
+//! including the trap when dividing the minimum value by -1.
+//! Comparisons produce an `i32` of 0 or 1.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use bwasm::ValueType;
+use thiserror::Error;
+
+use crate::vm::import_func::ImportFunctionHandler;
+use crate::vm::{Trap, VM};
+use crate::{ArithmeticError, Value};
+
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum EvalError {
+    #[error("Unexpected end of expression")]
+    UnexpectedEnd,
+    #[error("Unexpected \"{0}\"")]
+    UnexpectedToken(String),
+    #[error("Invalid number literal \"{0}\"")]
+    InvalidLiteral(String),
+    #[error("Unknown identifier \"{0}\"")]
+    UnknownIdentifier(String),
+    #[error("No local with index {0}")]
+    NoLocal(u32),
+    #[error("No global with index {0}")]
+    NoGlobal(u32),
+    #[error("Invalid memory address {0}")]
+    InvalidAddress(String),
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Can't reinterpret {value} as {value_type}")]
+    InvalidReinterpretation { value: String, value_type: ValueType },
+    #[error("Operator \"{0}\" can't be applied to v128 values")]
+    UnsupportedOperand(&'static str),
+    #[error("{0}")]
+    Arithmetic(ArithmeticError),
+    #[error("{0}")]
+    Trap(#[from] Trap),
+}
+
+pub type EvalResult<T> = Result<T, EvalError>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(s) | Token::Ident(s) => write!(f, "{}", s),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+const OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "(", ")", "[", "]", ":",
+];
+
+fn tokenize(expr: &str) -> EvalResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() || c == '.' {
+            let mut end = 0;
+            let mut prev = None;
+            for (i, c) in rest.char_indices() {
+                let is_exponent_sign = (c == '-' || c == '+')
+                    && matches!(prev, Some('e') | Some('E'))
+                    && !rest[..i].to_ascii_lowercase().starts_with("0x");
+                if !(c.is_ascii_alphanumeric() || c == '.' || is_exponent_sign) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                prev = Some(c);
+            }
+            tokens.push(Token::Number(rest[..end].to_owned()));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_owned()));
+            rest = &rest[end..];
+        } else {
+            let op = OPERATORS
+                .iter()
+                .copied()
+                .find(|op| rest.starts_with(op))
+                .ok_or_else(|| EvalError::UnexpectedToken(c.to_string()))?;
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a, F: ImportFunctionHandler> {
+    vm: &'a VM<F>,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a, F: ImportFunctionHandler> Parser<'a, F> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> EvalResult<Token> {
+        let token = self.tokens.get(self.pos).cloned().ok_or(EvalError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect_op(&mut self, op: &'static str) -> EvalResult<()> {
+        match self.next()? {
+            Token::Op(found) if found == op => Ok(()),
+            token => Err(EvalError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn comparison(&mut self) -> EvalResult<Value> {
+        let lhs = self.additive()?;
+        match self.peek_op() {
+            Some(op) if ["==", "!=", "<", "<=", ">", ">="].contains(&op) => {
+                self.pos += 1;
+                let rhs = self.additive()?;
+                compare(op, lhs, rhs)
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn additive(&mut self) -> EvalResult<Value> {
+        let mut lhs = self.multiplicative()?;
+        while let Some(op) = self.peek_op().filter(|op| ["+", "-"].contains(op)) {
+            self.pos += 1;
+            let rhs = self.multiplicative()?;
+            lhs = arithmetic(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn multiplicative(&mut self) -> EvalResult<Value> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek_op().filter(|op| ["*", "/", "%"].contains(op)) {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            lhs = arithmetic(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> EvalResult<Value> {
+        if self.peek_op() == Some("-") {
+            self.pos += 1;
+            return Ok(match self.unary()? {
+                Value::I32(val) => Value::I32(val.wrapping_neg()),
+                Value::I64(val) => Value::I64(val.wrapping_neg()),
+                Value::F32(val) => Value::F32(-val),
+                Value::F64(val) => Value::F64(-val),
+                Value::V128(_) => return Err(EvalError::UnsupportedOperand("-")),
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> EvalResult<Value> {
+        match self.next()? {
+            Token::Number(literal) => Value::parse_inferred(&literal).ok_or(EvalError::InvalidLiteral(literal)),
+            Token::Op("(") => {
+                let val = self.comparison()?;
+                self.expect_op(")")?;
+                Ok(val)
+            }
+            Token::Ident(ident) if ["inf", "infinity", "nan"].contains(&ident.as_str()) => {
+                Value::parse_inferred(&ident).ok_or(EvalError::InvalidLiteral(ident))
+            }
+            Token::Ident(ident) if ident == "mem" => self.memory_load(),
+            Token::Ident(ident) if ident == "reinterpret" => self.reinterpret(),
+            Token::Ident(ident) => self.variable(ident),
+            token => Err(EvalError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn variable(&self, ident: String) -> EvalResult<Value> {
+        let index = match ident.get(1..).and_then(|index| index.parse::<u32>().ok()) {
+            Some(index) => index,
+            None => return Err(EvalError::UnknownIdentifier(ident)),
+        };
+        match &ident[..1] {
+            "l" => self
+                .vm
+                .locals()?
+                .get(index as usize)
+                .copied()
+                .ok_or(EvalError::NoLocal(index)),
+            "g" => self
+                .vm
+                .globals()
+                .get(index as usize)
+                .copied()
+                .ok_or(EvalError::NoGlobal(index)),
+            _ => Err(EvalError::UnknownIdentifier(ident)),
+        }
+    }
+
+    fn memory_load(&mut self) -> EvalResult<Value> {
+        self.expect_op("[")?;
+        let address = match self.comparison()? {
+            Value::I32(address) => address as u32,
+            Value::I64(address) if (0..=i64::from(u32::MAX)).contains(&address) => address as u32,
+            address => return Err(EvalError::InvalidAddress(address.to_string())),
+        };
+        self.expect_op("]")?;
+        let value_type = if self.peek_op() == Some(":") {
+            self.pos += 1;
+            self.value_type()?
+        } else {
+            ValueType::I32
+        };
+        let values = self.vm.default_memory()?.load_values(address, value_type, 1)?;
+        Ok(values[0])
+    }
+
+    fn reinterpret(&mut self) -> EvalResult<Value> {
+        self.expect_op("<")?;
+        let value_type = self.value_type()?;
+        self.expect_op(">")?;
+        self.expect_op("(")?;
+        let value = self.comparison()?;
+        self.expect_op(")")?;
+        value
+            .reinterpret(value_type)
+            .ok_or_else(|| EvalError::InvalidReinterpretation {
+                value: value.to_string(),
+                value_type,
+            })
+    }
+
+    fn value_type(&mut self) -> EvalResult<ValueType> {
+        match self.next()? {
+            Token::Ident(ident) if ident == "i32" => Ok(ValueType::I32),
+            Token::Ident(ident) if ident == "i64" => Ok(ValueType::I64),
+            Token::Ident(ident) if ident == "f32" => Ok(ValueType::F32),
+            Token::Ident(ident) if ident == "f64" => Ok(ValueType::F64),
+            token => Err(EvalError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+/// Ranks the types for promotion. `None` for v128.
+fn rank(val: Value) -> Option<u8> {
+    match val {
+        Value::I32(_) => Some(0),
+        Value::I64(_) => Some(1),
+        Value::F32(_) => Some(2),
+        Value::F64(_) => Some(3),
+        Value::V128(_) => None,
+    }
+}
+
+fn promote(val: Value, rank: u8) -> Value {
+    match (val, rank) {
+        (Value::I32(val), 1) => Value::I64(i64::from(val)),
+        (Value::I32(val), 2) => Value::from(val as f32),
+        (Value::I64(val), 2) => Value::from(val as f32),
+        (Value::F32(val), 3) => Value::from(f64::from(val.to_float())),
+        (Value::I32(val), 3) => Value::from(f64::from(val)),
+        (Value::I64(val), 3) => Value::from(val as f64),
+        (val, _) => val,
+    }
+}
+
+fn promote_pair(op: &'static str, lhs: Value, rhs: Value) -> EvalResult<(Value, Value)> {
+    match (rank(lhs), rank(rhs)) {
+        (Some(lhs_rank), Some(rhs_rank)) => {
+            let rank = lhs_rank.max(rhs_rank);
+            Ok((promote(lhs, rank), promote(rhs, rank)))
+        }
+        _ => Err(EvalError::UnsupportedOperand(op)),
+    }
+}
+
+fn arithmetic(op: &'static str, lhs: Value, rhs: Value) -> EvalResult<Value> {
+    let (lhs, rhs) = promote_pair(op, lhs, rhs)?;
+    let result = match op {
+        "+" => lhs.add(&rhs),
+        "-" => lhs.sub(&rhs),
+        "*" => lhs.mul(&rhs),
+        "/" => lhs.div(&rhs),
+        _ => lhs.rem(&rhs),
+    };
+    result.map_err(|err| match err {
+        ArithmeticError::Trap(Trap::DivisionByZero) => EvalError::DivisionByZero,
+        ArithmeticError::Trap(trap) => EvalError::Trap(trap),
+        ArithmeticError::UnsupportedV128 => EvalError::UnsupportedOperand(op),
+        err => EvalError::Arithmetic(err),
+    })
+}
+
+fn compare(op: &'static str, lhs: Value, rhs: Value) -> EvalResult<Value> {
+    let ordering = match promote_pair(op, lhs, rhs)? {
+        (Value::I32(lhs), Value::I32(rhs)) => Some(lhs.cmp(&rhs)),
+        (Value::I64(lhs), Value::I64(rhs)) => Some(lhs.cmp(&rhs)),
+        (Value::F32(lhs), Value::F32(rhs)) => lhs.to_float().partial_cmp(&rhs.to_float()),
+        (Value::F64(lhs), Value::F64(rhs)) => lhs.to_float().partial_cmp(&rhs.to_float()),
+        _ => unreachable!("operands were promoted to the same type"),
+    };
+    // Every comparison except != is false if an operand is NaN
+    let result = match (op, ordering) {
+        ("!=", ordering) => ordering != Some(Ordering::Equal),
+        (_, None) => false,
+        ("==", Some(ordering)) => ordering == Ordering::Equal,
+        ("<", Some(ordering)) => ordering == Ordering::Less,
+        ("<=", Some(ordering)) => ordering != Ordering::Greater,
+        (">", Some(ordering)) => ordering == Ordering::Greater,
+        (_, Some(ordering)) => ordering != Ordering::Less,
+    };
+    Ok(Value::I32(result as i32))
+}
+
+/// Evaluates `expr` against the current state of `vm`. See the module documentation for the syntax.
+pub fn evaluate<F: ImportFunctionHandler>(vm: &VM<F>, expr: &str) -> EvalResult<Value> {
+    let mut parser = Parser {
+        vm,
+        tokens: tokenize(expr)?,
+        pos: 0,
+    };
+    let val = parser.comparison()?;
+    match parser.tokens.get(parser.pos) {
+        Some(token) => Err(EvalError::UnexpectedToken(token.to_string())),
+        None => Ok(val),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::vm::CodePosition;
+    use crate::Breakpoint;
+
+    /// Pauses in a function with locals `[5, 2.5f64]`, a global `-3i64` and memory holding
+    /// `0x2a` at address 0x10 and `1.5f32` at address 0x20.
+    fn paused_debugger() -> crate::DefaultDebugger {
+        let mut data_segment = vec![0x00, 0x41, 0x10, 0x0b, 0x14];
+        data_segment.extend(&[0x2a, 0, 0, 0]);
+        data_segment.extend(&[0; 12]);
+        data_segment.extend(&1.5f32.to_le_bytes());
+        let mut dbg = load(&module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(GLOBAL_SECTION, &vector(&[vec![I64, 0x00, 0x42, 0x7d, 0x0b]])),
+            section(START_SECTION, &leb(0)),
+            // local.set 0 (i32.const 5), local.set 1 (f64.const 2.5), then loop forever
+            section(
+                CODE_SECTION,
+                &vector(&[func_body(&[(1, I32), (1, F64)], &{
+                    let mut code = vec![0x41, 0x05, 0x21, 0x00, 0x44];
+                    code.extend(&2.5f64.to_le_bytes());
+                    code.extend(&[0x21, 0x01]);
+                    code.extend(INFINITE_LOOP);
+                    code
+                })]),
+            ),
+            section(DATA_SECTION, &vector(&[data_segment])),
+        ]));
+        dbg.add_breakpoint(Breakpoint::Code(CodePosition::new(0, 5))).unwrap();
+        dbg.run().unwrap();
+        dbg
+    }
+
+    fn eval(dbg: &crate::DefaultDebugger, expr: &str) -> EvalResult<Value> {
+        evaluate(dbg.get_vm().unwrap(), expr)
+    }
+
+    #[test]
+    fn operands() {
+        let dbg = paused_debugger();
+        assert_eq!(eval(&dbg, "l0"), Ok(Value::I32(5)));
+        assert_eq!(eval(&dbg, "l1"), Ok(Value::from(2.5f64)));
+        assert_eq!(eval(&dbg, "g0"), Ok(Value::I64(-3)));
+        assert_eq!(eval(&dbg, "mem[0x10]"), Ok(Value::I32(0x2a)));
+        assert_eq!(eval(&dbg, "mem[0x10]:i64"), Ok(Value::I64(0x2a)));
+        assert_eq!(eval(&dbg, "mem[l0 * 4 + 12]:f32"), Ok(Value::from(1.5f32)));
+        assert_eq!(eval(&dbg, "l2"), Err(EvalError::NoLocal(2)));
+        assert_eq!(eval(&dbg, "g1"), Err(EvalError::NoGlobal(1)));
+        assert_eq!(eval(&dbg, "x0"), Err(EvalError::UnknownIdentifier("x0".to_owned())));
+        assert_eq!(
+            eval(&dbg, "mem[0x10000]"),
+            Err(EvalError::Trap(Trap::MemoryAccessOutOfBounds {
+                addr: 0x10000,
+                size: 4,
+                mem_size: 0x10000
+            }))
+        );
+    }
+
+    #[test]
+    fn precedence_and_promotion() {
+        let dbg = paused_debugger();
+        assert_eq!(eval(&dbg, "1 + 2 * 3"), Ok(Value::I32(7)));
+        assert_eq!(eval(&dbg, "(1 + 2) * 3"), Ok(Value::I32(9)));
+        assert_eq!(eval(&dbg, "10 - 4 - 3"), Ok(Value::I32(3)));
+        assert_eq!(eval(&dbg, "-7 / 2"), Ok(Value::I32(-3)));
+        assert_eq!(eval(&dbg, "l0 + g0"), Ok(Value::I64(2)));
+        assert_eq!(eval(&dbg, "l0 * l1"), Ok(Value::from(12.5f64)));
+        assert_eq!(eval(&dbg, "mem[0x20]:f32 + 1"), Ok(Value::from(2.5f32)));
+        assert_eq!(eval(&dbg, "1e-1 * 10"), Ok(Value::from(1.0f64)));
+        assert_eq!(eval(&dbg, "l0 % 0"), Err(EvalError::DivisionByZero));
+    }
+
+    #[test]
+    fn comparisons() {
+        let dbg = paused_debugger();
+        assert_eq!(eval(&dbg, "l0 + 1 == 6"), Ok(Value::I32(1)));
+        assert_eq!(eval(&dbg, "g0 < 0"), Ok(Value::I32(1)));
+        assert_eq!(eval(&dbg, "l1 >= 3"), Ok(Value::I32(0)));
+        assert_eq!(eval(&dbg, "nan != nan"), Ok(Value::I32(1)));
+        assert_eq!(eval(&dbg, "nan == nan"), Ok(Value::I32(0)));
+    }
+
+    #[test]
+    fn reinterpret() {
+        let dbg = paused_debugger();
+        assert_eq!(eval(&dbg, "reinterpret<f32>(mem[0x20])"), Ok(Value::from(1.5f32)));
+        assert_eq!(
+            eval(&dbg, "reinterpret<i32>(mem[0x20]:f32)"),
+            Ok(Value::I32(0x3fc0_0000))
+        );
+        assert_eq!(eval(&dbg, "reinterpret<i64>(l1) > 0"), Ok(Value::I32(1)));
+        assert_eq!(
+            eval(&dbg, "reinterpret<f64>(l0)"),
+            Err(EvalError::InvalidReinterpretation {
+                value: Value::I32(5).to_string(),
+                value_type: ValueType::F64
+            })
+        );
+        assert_eq!(
+            eval(&dbg, "reinterpret(l0)"),
+            Err(EvalError::UnexpectedToken("(".to_owned()))
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let dbg = paused_debugger();
+        assert_eq!(eval(&dbg, "1 +"), Err(EvalError::UnexpectedEnd));
+        assert_eq!(eval(&dbg, "(1"), Err(EvalError::UnexpectedEnd));
+        assert_eq!(eval(&dbg, "1 2"), Err(EvalError::UnexpectedToken("2".to_owned())));
+        assert_eq!(
+            eval(&dbg, "mem[0]:u8"),
+            Err(EvalError::UnexpectedToken("u8".to_owned()))
+        );
+        assert_eq!(eval(&dbg, "1 $ 2"), Err(EvalError::UnexpectedToken("$".to_owned())));
+    }
+}
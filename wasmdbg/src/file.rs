@@ -1,13 +1,42 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex, MutexGuard};
 
-use bwasm::Module;
+use bwasm::{Instruction, Module};
 
+use crate::vm::CodePosition;
 use crate::Breakpoints;
 
+/// Every `call` by callee and every `call_indirect` by type index, in code order.
+#[derive(Default)]
+pub(crate) struct CallSites {
+    pub direct: HashMap<u32, Vec<CodePosition>>,
+    pub indirect: HashMap<u32, Vec<CodePosition>>,
+}
+
+impl CallSites {
+    fn scan(module: &Module) -> Self {
+        let mut call_sites = CallSites::default();
+        for (func_index, func) in module.functions().iter().enumerate() {
+            for (instr_index, instr) in func.instructions().iter().enumerate() {
+                let position = CodePosition::new(func_index as u32, instr_index as u32);
+                match instr {
+                    Instruction::Call(callee) => call_sites.direct.entry(*callee).or_default().push(position),
+                    Instruction::CallIndirect(signature, _) => {
+                        call_sites.indirect.entry(*signature).or_default().push(position)
+                    }
+                    _ => (),
+                }
+            }
+        }
+        call_sites
+    }
+}
+
 pub struct File {
     file_path: String,
     module: Arc<Module>,
     breakpoints: Arc<Mutex<Breakpoints>>,
+    call_sites: Mutex<Option<CallSites>>,
 }
 
 impl File {
@@ -16,6 +45,16 @@ impl File {
             file_path,
             module: Arc::new(module),
             breakpoints: Arc::new(Mutex::new(Breakpoints::new())),
+            call_sites: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn with_breakpoints(file_path: String, module: Module, breakpoints: Arc<Mutex<Breakpoints>>) -> Self {
+        File {
+            file_path,
+            module: Arc::new(module),
+            breakpoints,
+            call_sites: Mutex::new(None),
         }
     }
 
@@ -33,4 +72,10 @@ impl File {
     pub fn breakpoints_and_unlock(&self) -> MutexGuard<Breakpoints> {
         self.breakpoints.lock().unwrap()
     }
+
+    /// Scans the module on first use. Loading or reloading creates a new `File`.
+    pub(crate) fn call_sites<T>(&self, f: impl FnOnce(&CallSites) -> T) -> T {
+        let mut call_sites = self.call_sites.lock().unwrap();
+        f(call_sites.get_or_insert_with(|| CallSites::scan(&self.module)))
+    }
 }
@@ -1,12 +1,25 @@
+mod binary;
 mod breakpoints;
+mod cfg;
+mod commands;
 mod debugger;
+pub mod eval;
 mod file;
+mod linker;
+mod validate;
 pub mod vm;
-// mod wasi;
+mod wasi;
 mod debuginfo;
 mod wasm;
 
+#[cfg(test)]
+mod test_utils;
+
 pub use breakpoints::*;
+pub use cfg::{BasicBlock, Cfg, CfgEdge, EdgeKind};
 pub use debugger::*;
+pub use debuginfo::SourceLocation;
 pub use file::*;
+pub use linker::LinkError;
+pub use wasi::*;
 pub use wasm::*;
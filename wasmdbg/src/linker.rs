@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use bwasm::{External, Internal, Module};
+use thiserror::Error;
+
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum LinkError {
+    #[error("A module named \"{0}\" is already loaded")]
+    DuplicateModuleName(String),
+    #[error("Import \"{module}.{field}\" is exported by several modules: {}", .candidates.join(", "))]
+    SymbolClash {
+        module: String,
+        field: String,
+        candidates: Vec<String>,
+    },
+    #[error("Module \"{module}\" has no function export \"{field}\"")]
+    UnresolvedImport { module: String, field: String },
+    #[error("Signature of import \"{module}.{field}\" doesn't match the export")]
+    SignatureMismatch { module: String, field: String },
+}
+
+pub type LinkResult<T> = Result<T, LinkError>;
+
+/// A function import resolved to a function of the library with index `library`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FunctionLink {
+    pub import_index: u32,
+    pub library: usize,
+    pub func_index: u32,
+}
+
+/// The name imports refer to a library by: its file name without extension.
+pub fn module_name(file_path: &str) -> String {
+    Path::new(file_path)
+        .file_stem()
+        .map_or_else(|| file_path.to_owned(), |stem| stem.to_string_lossy().into_owned())
+}
+
+fn exported_function(module: &Module, field: &str) -> Option<u32> {
+    module.exports().iter().find_map(|entry| match entry.internal() {
+        Internal::Function(index) if entry.field() == field => Some(*index),
+        _ => None,
+    })
+}
+
+/// Resolves the function imports of `importer` against `libraries`, given as `(name, module)`.
+/// `skip` excludes one library, the importer itself if it is a library.
+///
+/// An import whose module name is the name of a library must be exported by that library.
+/// Any other import links to the one library exporting a function with its field name, or
+/// is left to the import function handler if no library does.
+pub fn resolve(
+    importer: &Module,
+    libraries: &[(String, &Module)],
+    skip: Option<usize>,
+) -> LinkResult<Vec<FunctionLink>> {
+    let mut links = Vec::new();
+    let function_imports = importer
+        .imports()
+        .iter()
+        .filter(|entry| matches!(entry.external(), External::Function(_)));
+    for (import_index, entry) in function_imports.enumerate() {
+        let import_index = import_index as u32;
+        let candidates = libraries
+            .iter()
+            .enumerate()
+            .filter(|(library, _)| Some(*library) != skip);
+        let (module, field) = (entry.module(), entry.field());
+        let found: Vec<_> = match candidates.clone().find(|(_, (name, _))| name == module) {
+            Some((library, (_, exporter))) => match exported_function(exporter, field) {
+                Some(func_index) => vec![(library, func_index)],
+                None => {
+                    return Err(LinkError::UnresolvedImport {
+                        module: module.to_owned(),
+                        field: field.to_owned(),
+                    })
+                }
+            },
+            None => candidates
+                .filter_map(|(library, (_, exporter))| Some((library, exported_function(exporter, field)?)))
+                .collect(),
+        };
+        let (library, func_index) = match found.as_slice() {
+            [] => continue,
+            [link] => *link,
+            _ => {
+                return Err(LinkError::SymbolClash {
+                    module: module.to_owned(),
+                    field: field.to_owned(),
+                    candidates: found.iter().map(|(library, _)| libraries[*library].0.clone()).collect(),
+                })
+            }
+        };
+
+        let import_type = importer.get_func(import_index).unwrap().func_type();
+        let export_type = libraries[library].1.get_func(func_index).unwrap().func_type();
+        if import_type.params() != export_type.params() || import_type.return_type() != export_type.return_type() {
+            return Err(LinkError::SignatureMismatch {
+                module: module.to_owned(),
+                field: field.to_owned(),
+            });
+        }
+        links.push(FunctionLink {
+            import_index,
+            library,
+            func_index,
+        });
+    }
+    Ok(links)
+}
@@ -0,0 +1,141 @@
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::vm::import_func::ImportFunctionHandler;
+use crate::{Debugger, DefaultDebugger};
+
+pub const I32: u8 = 0x7f;
+pub const I64: u8 = 0x7e;
+pub const F32: u8 = 0x7d;
+pub const F64: u8 = 0x7c;
+
+pub const CUSTOM_SECTION: u8 = 0;
+pub const TYPE_SECTION: u8 = 1;
+pub const IMPORT_SECTION: u8 = 2;
+pub const FUNCTION_SECTION: u8 = 3;
+pub const TABLE_SECTION: u8 = 4;
+pub const MEMORY_SECTION: u8 = 5;
+pub const GLOBAL_SECTION: u8 = 6;
+pub const EXPORT_SECTION: u8 = 7;
+pub const START_SECTION: u8 = 8;
+pub const ELEMENT_SECTION: u8 = 9;
+pub const CODE_SECTION: u8 = 10;
+pub const DATA_SECTION: u8 = 11;
+
+pub fn leb(mut value: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+pub fn name(name: &str) -> Vec<u8> {
+    let mut bytes = leb(name.len() as u32);
+    bytes.extend_from_slice(name.as_bytes());
+    bytes
+}
+
+pub fn vector(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = leb(items.len() as u32);
+    for item in items {
+        bytes.extend_from_slice(item);
+    }
+    bytes
+}
+
+pub fn section(id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![id];
+    bytes.extend(leb(payload.len() as u32));
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+pub fn func_type(params: &[u8], results: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x60];
+    bytes.extend(leb(params.len() as u32));
+    bytes.extend_from_slice(params);
+    bytes.extend(leb(results.len() as u32));
+    bytes.extend_from_slice(results);
+    bytes
+}
+
+/// Encodes a function body. `code` must include the final `end`.
+pub fn func_body(locals: &[(u32, u8)], code: &[u8]) -> Vec<u8> {
+    let mut body = leb(locals.len() as u32);
+    for (count, value_type) in locals {
+        body.extend(leb(*count));
+        body.push(*value_type);
+    }
+    body.extend_from_slice(code);
+    let mut bytes = leb(body.len() as u32);
+    bytes.extend(body);
+    bytes
+}
+
+/// A custom "name" section with a function name subsection.
+pub fn name_section(function_names: &[(u32, &str)]) -> Vec<u8> {
+    let names: Vec<Vec<u8>> = function_names
+        .iter()
+        .map(|(index, function_name)| {
+            let mut bytes = leb(*index);
+            bytes.extend(name(function_name));
+            bytes
+        })
+        .collect();
+    let subsection = vector(&names);
+    let mut payload = name("name");
+    payload.push(1);
+    payload.extend(leb(subsection.len() as u32));
+    payload.extend(subsection);
+    section(CUSTOM_SECTION, &payload)
+}
+
+pub fn module(sections: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    for section in sections {
+        bytes.extend_from_slice(section);
+    }
+    bytes
+}
+
+/// A module with a single non-imported function of type `[params] -> [results]`.
+/// If `start` is set the function is also the start function.
+pub fn single_func_module(params: &[u8], results: &[u8], locals: &[(u32, u8)], code: &[u8], start: bool) -> Vec<u8> {
+    let mut sections = vec![
+        section(TYPE_SECTION, &vector(&[func_type(params, results)])),
+        section(FUNCTION_SECTION, &vector(&[leb(0)])),
+    ];
+    if start {
+        sections.push(section(START_SECTION, &leb(0)));
+    }
+    sections.push(section(CODE_SECTION, &vector(&[func_body(locals, code)])));
+    module(&sections)
+}
+
+pub fn write_temp_file(bytes: &[u8]) -> String {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let path = std::env::temp_dir().join(format!("wasmdbg-test-{}-{}.wasm", std::process::id(), id));
+    std::fs::write(&path, bytes).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+pub fn load(bytes: &[u8]) -> DefaultDebugger {
+    load_with(bytes)
+}
+
+pub fn load_with<F: ImportFunctionHandler>(bytes: &[u8]) -> Debugger<F> {
+    let mut dbg = Debugger::new();
+    dbg.load_file(&write_temp_file(bytes)).unwrap();
+    dbg
+}
+
+/// `loop br 0 end end`
+pub const INFINITE_LOOP: &[u8] = &[0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b];
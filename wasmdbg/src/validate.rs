@@ -0,0 +1,551 @@
+use bwasm::{BlockType, Instruction, Module, ValueType};
+
+const I32: ValueType = ValueType::I32;
+const I64: ValueType = ValueType::I64;
+const F32: ValueType = ValueType::F32;
+const F64: ValueType = ValueType::F64;
+
+type Check<T = ()> = Result<T, String>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+struct Frame {
+    kind: FrameKind,
+    result: Option<ValueType>,
+    /// Height of the operand stack when the frame was entered.
+    height: usize,
+    /// Set after an unconditional branch. The rest of the frame can pop anything.
+    unreachable: bool,
+}
+
+impl Frame {
+    /// The type a branch to the label of this frame carries.
+    fn label_type(&self) -> Option<ValueType> {
+        match self.kind {
+            FrameKind::Loop => None,
+            _ => self.result,
+        }
+    }
+}
+
+struct Validator<'a> {
+    module: &'a Module,
+    locals: Vec<ValueType>,
+    result: Option<ValueType>,
+    /// `None` is a value of unknown type, popped from an unreachable frame.
+    stack: Vec<Option<ValueType>>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> Validator<'a> {
+    fn push(&mut self, value_type: Option<ValueType>) {
+        self.stack.push(value_type);
+    }
+
+    fn pop(&mut self, expected: Option<ValueType>) -> Check<Option<ValueType>> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() == frame.height {
+            return match (frame.unreachable, expected) {
+                (true, _) => Ok(expected),
+                (false, Some(expected)) => Err(format!("Expected {} but the stack is empty", expected)),
+                (false, None) => Err(String::from("Pop from empty stack")),
+            };
+        }
+        match (expected, self.stack.pop().unwrap()) {
+            (Some(expected), Some(found)) if expected != found => {
+                Err(format!("Type mismatch. Expected {}, found {}", expected, found))
+            }
+            (Some(expected), _) => Ok(Some(expected)),
+            (None, found) => Ok(found),
+        }
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn push_frame(&mut self, kind: FrameKind, block_type: BlockType) {
+        let result = match block_type {
+            BlockType::Value(value_type) => Some(value_type),
+            BlockType::NoResult => None,
+        };
+        self.frames.push(Frame {
+            kind,
+            result,
+            height: self.stack.len(),
+            unreachable: false,
+        });
+    }
+
+    fn pop_frame(&mut self) -> Check<Frame> {
+        let frame = self.frames.last().unwrap();
+        if let Some(result) = frame.result {
+            self.pop(Some(result))?;
+        }
+        let frame = self.frames.pop().unwrap();
+        match self.stack.len() - frame.height {
+            0 => Ok(frame),
+            left => Err(format!("{} values left on the stack at the end of the block", left)),
+        }
+    }
+
+    fn label(&self, depth: u32) -> Check<Option<ValueType>> {
+        match self.frames.len().checked_sub(depth as usize + 1) {
+            Some(frame) => Ok(self.frames[frame].label_type()),
+            None => Err(format!("Invalid branch depth {}", depth)),
+        }
+    }
+
+    fn local(&self, index: u32) -> Check<ValueType> {
+        self.locals
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("No local with index {}", index))
+    }
+
+    fn global(&self, index: u32) -> Check<ValueType> {
+        self.module
+            .globals()
+            .get(index as usize)
+            .map(|global| global.value_type())
+            .ok_or_else(|| format!("No global with index {}", index))
+    }
+
+    fn call(&mut self, params: &[ValueType], result: Option<ValueType>) -> Check {
+        for param in params.iter().rev() {
+            self.pop(Some(*param))?;
+        }
+        if let Some(result) = result {
+            self.push(Some(result));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, instr: &Instruction) -> Check {
+        match instr {
+            Instruction::Unreachable => self.set_unreachable(),
+            Instruction::Nop => (),
+            Instruction::Block(block_type) => self.push_frame(FrameKind::Block, *block_type),
+            Instruction::Loop(block_type) => self.push_frame(FrameKind::Loop, *block_type),
+            Instruction::If(block_type) => {
+                self.pop(Some(I32))?;
+                self.push_frame(FrameKind::If, *block_type);
+            }
+            Instruction::Else => {
+                if self.frames.last().unwrap().kind != FrameKind::If {
+                    return Err(String::from("else outside of an if"));
+                }
+                let frame = self.pop_frame()?;
+                self.frames.push(Frame {
+                    kind: FrameKind::Else,
+                    height: self.stack.len(),
+                    unreachable: false,
+                    ..frame
+                });
+            }
+            Instruction::End => {
+                let frame = self.pop_frame()?;
+                if frame.kind == FrameKind::If && frame.result.is_some() {
+                    return Err(String::from("if without else can't produce a value"));
+                }
+                match frame.result {
+                    Some(result) if frame.kind != FrameKind::Function => self.push(Some(result)),
+                    _ => (),
+                }
+            }
+            Instruction::Br(depth) => {
+                if let Some(label_type) = self.label(*depth)? {
+                    self.pop(Some(label_type))?;
+                }
+                self.set_unreachable();
+            }
+            Instruction::BrIf(depth) => {
+                self.pop(Some(I32))?;
+                if let Some(label_type) = self.label(*depth)? {
+                    self.pop(Some(label_type))?;
+                    self.push(Some(label_type));
+                }
+            }
+            Instruction::BrTable(table_data) => {
+                self.pop(Some(I32))?;
+                let label_type = self.label(table_data.default)?;
+                for depth in table_data.table.iter() {
+                    if self.label(*depth)? != label_type {
+                        return Err(String::from("Targets of br_table carry different types"));
+                    }
+                }
+                if let Some(label_type) = label_type {
+                    self.pop(Some(label_type))?;
+                }
+                self.set_unreachable();
+            }
+            Instruction::Return => {
+                if let Some(result) = self.result {
+                    self.pop(Some(result))?;
+                }
+                self.set_unreachable();
+            }
+            Instruction::Call(index) => {
+                let module = self.module;
+                let func = module
+                    .get_func(*index)
+                    .ok_or_else(|| format!("No function with index {}", index))?;
+                let func_type = func.func_type();
+                self.call(func_type.params(), func_type.return_type())?;
+            }
+            Instruction::CallIndirect(signature, _) => {
+                if self.module.tables().is_empty() {
+                    return Err(String::from("No table present"));
+                }
+                let module = self.module;
+                let func_type = module
+                    .types()
+                    .get(*signature as usize)
+                    .ok_or_else(|| format!("No type with index {}", signature))?;
+                self.pop(Some(I32))?;
+                self.call(func_type.params(), func_type.return_type())?;
+            }
+            Instruction::Drop => {
+                self.pop(None)?;
+            }
+            Instruction::Select => {
+                self.pop(Some(I32))?;
+                let second = self.pop(None)?;
+                let first = self.pop(second)?;
+                self.push(first.or(second));
+            }
+            Instruction::GetLocal(index) => self.push(Some(self.local(*index)?)),
+            Instruction::SetLocal(index) => {
+                self.pop(Some(self.local(*index)?))?;
+            }
+            Instruction::TeeLocal(index) => {
+                let local = self.local(*index)?;
+                self.pop(Some(local))?;
+                self.push(Some(local));
+            }
+            Instruction::GetGlobal(index) => self.push(Some(self.global(*index)?)),
+            Instruction::SetGlobal(index) => {
+                self.pop(Some(self.global(*index)?))?;
+            }
+            _ => {
+                if accesses_memory(instr) && self.module.memories().is_empty() {
+                    return Err(String::from("No memory present"));
+                }
+                let (params, result) = signature(instr).ok_or_else(|| format!("Unsupported instruction {}", instr))?;
+                self.call(params, result)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn accesses_memory(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::I32Load(..)
+            | Instruction::I64Load(..)
+            | Instruction::F32Load(..)
+            | Instruction::F64Load(..)
+            | Instruction::I32Load8S(..)
+            | Instruction::I32Load8U(..)
+            | Instruction::I32Load16S(..)
+            | Instruction::I32Load16U(..)
+            | Instruction::I64Load8S(..)
+            | Instruction::I64Load8U(..)
+            | Instruction::I64Load16S(..)
+            | Instruction::I64Load16U(..)
+            | Instruction::I64Load32S(..)
+            | Instruction::I64Load32U(..)
+            | Instruction::I32Store(..)
+            | Instruction::I64Store(..)
+            | Instruction::F32Store(..)
+            | Instruction::F64Store(..)
+            | Instruction::I32Store8(..)
+            | Instruction::I32Store16(..)
+            | Instruction::I64Store8(..)
+            | Instruction::I64Store16(..)
+            | Instruction::I64Store32(..)
+            | Instruction::CurrentMemory(_)
+            | Instruction::GrowMemory(_)
+    )
+}
+
+/// Operand types, in the order they are pushed, and the result of the instructions that don't
+/// depend on the module or the enclosing blocks.
+fn signature(instr: &Instruction) -> Option<(&'static [ValueType], Option<ValueType>)> {
+    let signature: (&'static [ValueType], ValueType) = match instr {
+        Instruction::I32Load(..)
+        | Instruction::I32Load8S(..)
+        | Instruction::I32Load8U(..)
+        | Instruction::I32Load16S(..)
+        | Instruction::I32Load16U(..) => (&[I32], I32),
+        Instruction::I64Load(..)
+        | Instruction::I64Load8S(..)
+        | Instruction::I64Load8U(..)
+        | Instruction::I64Load16S(..)
+        | Instruction::I64Load16U(..)
+        | Instruction::I64Load32S(..)
+        | Instruction::I64Load32U(..) => (&[I32], I64),
+        Instruction::F32Load(..) => (&[I32], F32),
+        Instruction::F64Load(..) => (&[I32], F64),
+
+        Instruction::I32Store(..) | Instruction::I32Store8(..) | Instruction::I32Store16(..) => {
+            return Some((&[I32, I32], None))
+        }
+        Instruction::I64Store(..)
+        | Instruction::I64Store8(..)
+        | Instruction::I64Store16(..)
+        | Instruction::I64Store32(..) => return Some((&[I32, I64], None)),
+        Instruction::F32Store(..) => return Some((&[I32, F32], None)),
+        Instruction::F64Store(..) => return Some((&[I32, F64], None)),
+
+        Instruction::CurrentMemory(_) => (&[], I32),
+        Instruction::GrowMemory(_) => (&[I32], I32),
+
+        Instruction::I32Const(_) => (&[], I32),
+        Instruction::I64Const(_) => (&[], I64),
+        Instruction::F32Const(_) => (&[], F32),
+        Instruction::F64Const(_) => (&[], F64),
+
+        Instruction::I32Eqz | Instruction::I32Clz | Instruction::I32Ctz | Instruction::I32Popcnt => (&[I32], I32),
+        Instruction::I32Eq
+        | Instruction::I32Ne
+        | Instruction::I32LtS
+        | Instruction::I32LtU
+        | Instruction::I32GtS
+        | Instruction::I32GtU
+        | Instruction::I32LeS
+        | Instruction::I32LeU
+        | Instruction::I32GeS
+        | Instruction::I32GeU
+        | Instruction::I32Add
+        | Instruction::I32Sub
+        | Instruction::I32Mul
+        | Instruction::I32DivS
+        | Instruction::I32DivU
+        | Instruction::I32RemS
+        | Instruction::I32RemU
+        | Instruction::I32And
+        | Instruction::I32Or
+        | Instruction::I32Xor
+        | Instruction::I32Shl
+        | Instruction::I32ShrS
+        | Instruction::I32ShrU
+        | Instruction::I32Rotl
+        | Instruction::I32Rotr => (&[I32, I32], I32),
+
+        Instruction::I64Eqz => (&[I64], I32),
+        Instruction::I64Eq
+        | Instruction::I64Ne
+        | Instruction::I64LtS
+        | Instruction::I64LtU
+        | Instruction::I64GtS
+        | Instruction::I64GtU
+        | Instruction::I64LeS
+        | Instruction::I64LeU
+        | Instruction::I64GeS
+        | Instruction::I64GeU => (&[I64, I64], I32),
+        Instruction::I64Clz | Instruction::I64Ctz | Instruction::I64Popcnt => (&[I64], I64),
+        Instruction::I64Add
+        | Instruction::I64Sub
+        | Instruction::I64Mul
+        | Instruction::I64DivS
+        | Instruction::I64DivU
+        | Instruction::I64RemS
+        | Instruction::I64RemU
+        | Instruction::I64And
+        | Instruction::I64Or
+        | Instruction::I64Xor
+        | Instruction::I64Shl
+        | Instruction::I64ShrS
+        | Instruction::I64ShrU
+        | Instruction::I64Rotl
+        | Instruction::I64Rotr => (&[I64, I64], I64),
+
+        Instruction::F32Eq
+        | Instruction::F32Ne
+        | Instruction::F32Lt
+        | Instruction::F32Gt
+        | Instruction::F32Le
+        | Instruction::F32Ge => (&[F32, F32], I32),
+        Instruction::F32Abs
+        | Instruction::F32Neg
+        | Instruction::F32Ceil
+        | Instruction::F32Floor
+        | Instruction::F32Trunc
+        | Instruction::F32Nearest
+        | Instruction::F32Sqrt => (&[F32], F32),
+        Instruction::F32Add
+        | Instruction::F32Sub
+        | Instruction::F32Mul
+        | Instruction::F32Div
+        | Instruction::F32Min
+        | Instruction::F32Max
+        | Instruction::F32Copysign => (&[F32, F32], F32),
+
+        Instruction::F64Eq
+        | Instruction::F64Ne
+        | Instruction::F64Lt
+        | Instruction::F64Gt
+        | Instruction::F64Le
+        | Instruction::F64Ge => (&[F64, F64], I32),
+        Instruction::F64Abs
+        | Instruction::F64Neg
+        | Instruction::F64Ceil
+        | Instruction::F64Floor
+        | Instruction::F64Trunc
+        | Instruction::F64Nearest
+        | Instruction::F64Sqrt => (&[F64], F64),
+        Instruction::F64Add
+        | Instruction::F64Sub
+        | Instruction::F64Mul
+        | Instruction::F64Div
+        | Instruction::F64Min
+        | Instruction::F64Max
+        | Instruction::F64Copysign => (&[F64, F64], F64),
+
+        Instruction::I32WrapI64 => (&[I64], I32),
+        Instruction::I32TruncSF32 | Instruction::I32TruncUF32 | Instruction::I32ReinterpretF32 => (&[F32], I32),
+        Instruction::I32TruncSF64 | Instruction::I32TruncUF64 => (&[F64], I32),
+        Instruction::I64ExtendSI32 | Instruction::I64ExtendUI32 => (&[I32], I64),
+        Instruction::I64TruncSF32 | Instruction::I64TruncUF32 => (&[F32], I64),
+        Instruction::I64TruncSF64 | Instruction::I64TruncUF64 | Instruction::I64ReinterpretF64 => (&[F64], I64),
+        Instruction::F32ConvertSI32 | Instruction::F32ConvertUI32 | Instruction::F32ReinterpretI32 => (&[I32], F32),
+        Instruction::F32ConvertSI64 | Instruction::F32ConvertUI64 => (&[I64], F32),
+        Instruction::F32DemoteF64 => (&[F64], F32),
+        Instruction::F64ConvertSI32 | Instruction::F64ConvertUI32 => (&[I32], F64),
+        Instruction::F64ConvertSI64 | Instruction::F64ConvertUI64 | Instruction::F64ReinterpretI64 => (&[I64], F64),
+        Instruction::F64PromoteF32 => (&[F32], F64),
+
+        _ => return None,
+    };
+    Some((signature.0, Some(signature.1)))
+}
+
+/// Type-checks the stack effects of a function body. On failure returns the index of the first
+/// offending instruction and what is wrong with it.
+pub(crate) fn validate_body(
+    module: &Module,
+    params: &[ValueType],
+    result: Option<ValueType>,
+    locals: &[ValueType],
+    instructions: &[Instruction],
+) -> Result<(), (u32, String)> {
+    let mut validator = Validator {
+        module,
+        locals: params.iter().chain(locals).copied().collect(),
+        result,
+        stack: Vec::new(),
+        frames: vec![Frame {
+            kind: FrameKind::Function,
+            result,
+            height: 0,
+            unreachable: false,
+        }],
+    };
+    for (index, instr) in instructions.iter().enumerate() {
+        if validator.frames.is_empty() {
+            return Err((index as u32, String::from("Instruction after the end of the function")));
+        }
+        validator.step(instr).map_err(|message| (index as u32, message))?;
+    }
+    if !validator.frames.is_empty() {
+        let last = instructions.len().saturating_sub(1) as u32;
+        return Err((last, String::from("Function body isn't terminated by end")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{func_body, func_type, leb, module, section, vector};
+    use crate::test_utils::{CODE_SECTION, FUNCTION_SECTION, MEMORY_SECTION, TYPE_SECTION};
+
+    fn check(
+        result: Option<ValueType>,
+        locals: &[ValueType],
+        instructions: &[Instruction],
+    ) -> Result<(), (u32, String)> {
+        // A module with a memory to validate against
+        let bytes = module(&[
+            section(TYPE_SECTION, &vector(&[func_type(&[], &[])])),
+            section(FUNCTION_SECTION, &vector(&[leb(0)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(CODE_SECTION, &vector(&[func_body(&[], &[0x0b])])),
+        ]);
+        let module = Module::from_bytes(&bytes).unwrap();
+        validate_body(&module, &[], result, locals, instructions)
+    }
+
+    #[test]
+    fn valid_bodies() {
+        use Instruction::*;
+        assert_eq!(check(None, &[], &[End]), Ok(()));
+        assert_eq!(
+            check(
+                Some(I64),
+                &[I32],
+                &[
+                    GetLocal(0),
+                    If(BlockType::Value(I64)),
+                    I64Const(1),
+                    Else,
+                    I32Const(0),
+                    I64Load(3, 0),
+                    End,
+                    Return,
+                    End
+                ]
+            ),
+            Ok(())
+        );
+        // anything goes after an unconditional branch
+        assert_eq!(
+            check(
+                Some(F32),
+                &[],
+                &[Block(BlockType::NoResult), Br(0), I32Add, Drop, End, Unreachable, End]
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn invalid_bodies() {
+        use Instruction::*;
+        assert_eq!(
+            check(None, &[], &[I64Const(1), I32Eqz, Drop, End]),
+            Err((1, String::from("Type mismatch. Expected i32, found i64")))
+        );
+        assert_eq!(
+            check(Some(I32), &[], &[End]),
+            Err((0, String::from("Expected i32 but the stack is empty")))
+        );
+        assert_eq!(
+            check(None, &[], &[I32Const(1), I32Const(2), End]),
+            Err((2, String::from("2 values left on the stack at the end of the block")))
+        );
+        assert_eq!(
+            check(None, &[], &[Br(1), End]),
+            Err((0, String::from("Invalid branch depth 1")))
+        );
+        assert_eq!(
+            check(None, &[], &[GetLocal(0), Drop, End]),
+            Err((0, String::from("No local with index 0")))
+        );
+        assert_eq!(
+            check(None, &[], &[End, Nop]),
+            Err((1, String::from("Instruction after the end of the function")))
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use bwasm::Module;
+
+use super::CodePosition;
+
+/// The executed instructions of one non-imported function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionCoverage {
+    pub func_index: u32,
+    pub instr_count: u32,
+    /// Sorted instruction indices
+    pub reached: Vec<u32>,
+}
+
+impl FunctionCoverage {
+    pub fn percentage(&self) -> f64 {
+        if self.instr_count == 0 {
+            return 100.0;
+        }
+        self.reached.len() as f64 * 100.0 / f64::from(self.instr_count)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoverageReport {
+    pub functions: Vec<FunctionCoverage>,
+}
+
+impl CoverageReport {
+    /// Percentage of all instructions of the module that were reached.
+    pub fn percentage(&self) -> f64 {
+        let instr_count: u64 = self.functions.iter().map(|func| u64::from(func.instr_count)).sum();
+        if instr_count == 0 {
+            return 100.0;
+        }
+        let reached: usize = self.functions.iter().map(|func| func.reached.len()).sum();
+        reached as f64 * 100.0 / instr_count as f64
+    }
+}
+
+#[derive(Clone)]
+struct FunctionBits {
+    is_imported: bool,
+    instr_count: u32,
+    bits: Vec<u64>,
+}
+
+/// One bitset per function, sized to its instruction count. Imported functions have empty bitsets.
+#[derive(Clone)]
+pub(crate) struct Coverage {
+    functions: Vec<FunctionBits>,
+}
+
+impl Coverage {
+    pub fn new(module: &Module) -> Self {
+        let functions = module
+            .functions()
+            .iter()
+            .map(|func| {
+                let instr_count = if func.is_imported() {
+                    0
+                } else {
+                    func.instructions().len() as u32
+                };
+                FunctionBits {
+                    is_imported: func.is_imported(),
+                    instr_count,
+                    bits: vec![0; (instr_count as usize).div_ceil(64)],
+                }
+            })
+            .collect();
+        Coverage { functions }
+    }
+
+    pub fn mark(&mut self, pos: CodePosition) {
+        if let Some(func) = self.functions.get_mut(pos.func_index as usize) {
+            if let Some(word) = func.bits.get_mut(pos.instr_index as usize / 64) {
+                *word |= 1 << (pos.instr_index % 64);
+            }
+        }
+    }
+
+    pub fn report(&self) -> CoverageReport {
+        let functions = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, func)| !func.is_imported)
+            .map(|(func_index, func)| FunctionCoverage {
+                func_index: func_index as u32,
+                instr_count: func.instr_count,
+                reached: (0..func.instr_count)
+                    .filter(|instr_index| func.bits[*instr_index as usize / 64] & (1 << (instr_index % 64)) != 0)
+                    .collect(),
+            })
+            .collect();
+        CoverageReport { functions }
+    }
+}
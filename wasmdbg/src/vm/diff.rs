@@ -0,0 +1,93 @@
+use super::Memory;
+use crate::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobalChange {
+    pub index: u32,
+    pub old_value: Value,
+    pub new_value: Value,
+}
+
+/// A run of adjacent changed bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryChange {
+    pub memory_index: u32,
+    pub start: u32,
+    pub len: u32,
+}
+
+/// Changes between two snapshots of the same module, see `VmSnapshot::diff`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateDiff {
+    pub globals: Vec<GlobalChange>,
+    /// Bytes that were added by growing memory count as changed if they're no longer zero.
+    pub memory: Vec<MemoryChange>,
+    /// Values of the old stack above the part both stacks share, bottom first.
+    pub popped: Vec<Value>,
+    /// Values of the new stack above the part both stacks share, bottom first.
+    pub pushed: Vec<Value>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.globals.is_empty() && self.memory.is_empty() && self.popped.is_empty() && self.pushed.is_empty()
+    }
+}
+
+pub(super) fn diff_globals(old: &[Value], new: &[Value]) -> Vec<GlobalChange> {
+    old.iter()
+        .zip(new)
+        .enumerate()
+        .filter(|(_, (old_value, new_value))| old_value != new_value)
+        .map(|(index, (old_value, new_value))| GlobalChange {
+            index: index as u32,
+            old_value: *old_value,
+            new_value: *new_value,
+        })
+        .collect()
+}
+
+pub(super) fn diff_memories(old: &[Memory], new: &[Memory]) -> Vec<MemoryChange> {
+    let mut changes = Vec::new();
+    for (memory_index, (old, new)) in old.iter().zip(new).enumerate() {
+        diff_memory(memory_index as u32, old, new, &mut changes);
+    }
+    changes
+}
+
+/// Returns the popped and pushed values.
+pub(super) fn diff_stacks(old: &[Value], new: &[Value]) -> (Vec<Value>, Vec<Value>) {
+    let shared = old
+        .iter()
+        .zip(new)
+        .take_while(|(old_value, new_value)| old_value == new_value)
+        .count();
+    (old[shared..].to_vec(), new[shared..].to_vec())
+}
+
+fn diff_memory(memory_index: u32, old: &Memory, new: &Memory, changes: &mut Vec<MemoryChange>) {
+    if old.shares_data(new) {
+        return;
+    }
+    let (old, new) = (old.data(), new.data());
+    let mut run_start = None;
+    for addr in 0..=old.len().max(new.len()) {
+        let changed = match (old.get(addr), new.get(addr)) {
+            (Some(a), Some(b)) => a != b,
+            (Some(byte), None) | (None, Some(byte)) => *byte != 0,
+            (None, None) => false,
+        };
+        match (changed, run_start) {
+            (true, None) => run_start = Some(addr),
+            (false, Some(start)) => {
+                changes.push(MemoryChange {
+                    memory_index,
+                    start: start as u32,
+                    len: (addr - start) as u32,
+                });
+                run_start = None;
+            }
+            _ => (),
+        }
+    }
+}
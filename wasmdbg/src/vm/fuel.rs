@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+
+use bwasm::Instruction;
+
+pub const DEFAULT_INSTRUCTION_COST: u64 = 1;
+pub const DEFAULT_MEMORY_GROW_COST: u64 = 100;
+
+/// Fuel consumed by each instruction. Costs are per opcode, immediates are ignored.
+/// A step that executes an imported function costs `default_cost`.
+#[derive(Clone, Debug)]
+pub struct CostModel {
+    default_cost: u64,
+    overrides: HashMap<Discriminant<Instruction>, u64>,
+}
+
+impl CostModel {
+    /// A model where every instruction costs `default_cost`.
+    pub fn new(default_cost: u64) -> Self {
+        CostModel {
+            default_cost,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Sets the cost of all instructions with the opcode of `instr`.
+    pub fn with_cost(mut self, instr: &Instruction, cost: u64) -> Self {
+        self.overrides.insert(mem::discriminant(instr), cost);
+        self
+    }
+
+    pub fn default_cost(&self) -> u64 {
+        self.default_cost
+    }
+
+    pub fn cost(&self, instr: &Instruction) -> u64 {
+        self.overrides
+            .get(&mem::discriminant(instr))
+            .copied()
+            .unwrap_or(self.default_cost)
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel::new(DEFAULT_INSTRUCTION_COST).with_cost(&Instruction::GrowMemory(0), DEFAULT_MEMORY_GROW_COST)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_ignore_immediates() {
+        let model = CostModel::default();
+        assert_eq!(model.cost(&Instruction::I32Add), DEFAULT_INSTRUCTION_COST);
+        assert_eq!(model.cost(&Instruction::GrowMemory(0)), DEFAULT_MEMORY_GROW_COST);
+
+        let model = CostModel::new(2).with_cost(&Instruction::Call(0), 10);
+        assert_eq!(model.cost(&Instruction::Call(7)), 10);
+        assert_eq!(model.cost(&Instruction::GrowMemory(0)), 2);
+    }
+}
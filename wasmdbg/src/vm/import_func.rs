@@ -1,16 +1,215 @@
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use bwasm::{External, Module, ValueType};
+
 use super::{Trap, VMResult, VM};
+use crate::Value;
+
+/// The future of an import call. It may borrow the VM until it is done.
+pub type ImportFuture<'a> = Pin<Box<dyn Future<Output = VMResult<()>> + 'a>>;
 
 pub trait ImportFunctionHandler
 where
-    Self: Sized + Default,
+    Self: Sized + Default + Clone,
 {
-    fn handle_import_function(vm: &mut VM<Self>) -> VMResult<()>;
+    /// Handles a call to the imported function `vm.ip().func_index`. The arguments are the
+    /// locals of the top frame, results are pushed to the value stack.
+    fn handle_import_function(vm: &mut VM<Self>) -> ImportFuture<'_>;
+
+    /// Called by the VM, which executes synchronously. Defaults to polling
+    /// `handle_import_function` to completion on the calling thread.
+    fn handle_import_function_blocking(vm: &mut VM<Self>) -> VMResult<()> {
+        block_on(Self::handle_import_function(vm))
+    }
 }
 
-#[derive(Default)]
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` on the current thread, parking it while the future is pending.
+fn block_on<T>(future: impl Future<Output = T>) -> T {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(result) => return result,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Resolves a function index to the `(module, field)` name of its import entry.
+pub fn import_function_name(module: &Module, func_index: u32) -> Option<(&str, &str)> {
+    module
+        .imports()
+        .iter()
+        .filter(|entry| matches!(entry.external(), External::Function(_)))
+        .nth(func_index as usize)
+        .map(|entry| (entry.module(), entry.field()))
+}
+
+#[derive(Default, Clone)]
 pub struct DefaultImportFunctionHandler {}
 impl ImportFunctionHandler for DefaultImportFunctionHandler {
-    fn handle_import_function(vm: &mut VM<Self>) -> VMResult<()> {
-        Err(Trap::UnsupportedCallToImportedFunction(vm.ip().func_index))
+    fn handle_import_function(vm: &mut VM<Self>) -> ImportFuture<'_> {
+        Box::pin(future::ready(Err(Trap::UnsupportedCallToImportedFunction(
+            vm.ip().func_index,
+        ))))
+    }
+}
+
+/// Returns the results of the call, which are pushed in order.
+pub type HostFunction = Box<dyn FnMut(&mut VM<RegistryImportFunctionHandler>, &[Value]) -> VMResult<Vec<Value>> + Send>;
+
+#[derive(Clone)]
+struct RegisteredFunction {
+    param_types: Vec<ValueType>,
+    return_types: Vec<ValueType>,
+    // Every function has its own lock so that the registry isn't locked while one runs
+    function: Arc<Mutex<HostFunction>>,
+}
+
+/// Host functions keyed by their `(module, field)` import name.
+/// Clones share the same functions.
+#[derive(Default, Clone)]
+pub struct HostFunctionRegistry {
+    functions: Arc<Mutex<HashMap<(String, String), RegisteredFunction>>>,
+}
+
+impl HostFunctionRegistry {
+    /// Calls of an import with another signature than `param_types` and `return_types` trap
+    /// with `ImportProtocolError` instead of calling `function`.
+    pub fn register(
+        &self,
+        module: &str,
+        field: &str,
+        param_types: &[ValueType],
+        return_types: &[ValueType],
+        function: HostFunction,
+    ) {
+        let function = RegisteredFunction {
+            param_types: param_types.to_vec(),
+            return_types: return_types.to_vec(),
+            function: Arc::new(Mutex::new(function)),
+        };
+        self.functions
+            .lock()
+            .unwrap()
+            .insert((module.to_owned(), field.to_owned()), function);
+    }
+
+    pub fn unregister(&self, module: &str, field: &str) -> bool {
+        self.functions
+            .lock()
+            .unwrap()
+            .remove(&(module.to_owned(), field.to_owned()))
+            .is_some()
+    }
+
+    pub fn contains(&self, module: &str, field: &str) -> bool {
+        self.functions
+            .lock()
+            .unwrap()
+            .contains_key(&(module.to_owned(), field.to_owned()))
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct RegistryImportFunctionHandler {
+    registry: HostFunctionRegistry,
+}
+
+impl RegistryImportFunctionHandler {
+    pub fn registry(&self) -> &HostFunctionRegistry {
+        &self.registry
+    }
+
+    fn call_host_function(vm: &mut VM<Self>) -> VMResult<()> {
+        let func_index = vm.ip().func_index;
+        let key = match import_function_name(vm.module(), func_index) {
+            Some((module, field)) => (module.to_owned(), field.to_owned()),
+            None => return Err(Trap::UnsupportedCallToImportedFunction(func_index)),
+        };
+        let func_type = vm
+            .module()
+            .get_func(func_index)
+            .ok_or(Trap::NoFunctionWithIndex(func_index))?
+            .func_type();
+        let param_types = func_type.params().to_vec();
+        let return_types: Vec<_> = func_type.return_type().into_iter().collect();
+        let args = vm.locals()?.to_vec();
+        let registered = vm
+            .import_function_handler_mut()
+            .registry
+            .functions
+            .lock()
+            .unwrap()
+            .get(&key)
+            .cloned()
+            .ok_or(Trap::UnsupportedCallToImportedFunction(func_index))?;
+
+        let import_name = format!("{}.{}", key.0, key.1);
+        if registered.param_types != param_types || registered.return_types != return_types {
+            return Err(Trap::ImportProtocolError(format!(
+                "{} is registered as {} -> {}, the module imports it as {} -> {}",
+                import_name,
+                type_list(&registered.param_types),
+                type_list(&registered.return_types),
+                type_list(&param_types),
+                type_list(&return_types)
+            )));
+        }
+        let results = match registered.function.try_lock() {
+            Ok(mut function) => (&mut *function)(vm, &args)?,
+            // The function called itself through the VM, waiting for the lock would never return
+            Err(TryLockError::WouldBlock) => {
+                return Err(Trap::ImportCallFailed {
+                    import: import_name,
+                    reason: String::from("re-entrant host call"),
+                })
+            }
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
+        };
+        if results
+            .iter()
+            .map(Value::value_type)
+            .ne(return_types.iter().copied().map(Some))
+        {
+            return Err(Trap::ImportProtocolError(format!(
+                "{} returned {}, expected {}",
+                import_name,
+                type_names(results.iter().map(Value::type_name)),
+                type_list(&return_types)
+            )));
+        }
+        for result in results {
+            vm.push(result)?;
+        }
+        Ok(())
+    }
+}
+
+fn type_names<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    format!("[{}]", names.collect::<Vec<_>>().join(", "))
+}
+
+fn type_list(types: &[ValueType]) -> String {
+    type_names(types.iter().map(|value_type| Value::default(*value_type).type_name()))
+}
+
+impl ImportFunctionHandler for RegistryImportFunctionHandler {
+    fn handle_import_function(vm: &mut VM<Self>) -> ImportFuture<'_> {
+        Box::pin(future::ready(Self::call_host_function(vm)))
     }
 }
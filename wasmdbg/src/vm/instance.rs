@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use bwasm::{Function, Instruction, Module};
@@ -5,24 +6,79 @@ use bwasm::{Function, Instruction, Module};
 use crate::value::{ExtendTo, Integer, LittleEndianConvert, Number, WrapTo};
 use crate::{Breakpoints, Value, F32, F64};
 
-use super::{eval_init_expr, import_func, CodePosition, InitError, Memory, Table, TableElement, Trap, VMResult};
+use super::{
+    diff, eval_init_expr, import_func, CodePosition, CostModel, Coverage, CoverageReport, InitError, InterruptHandle,
+    MemInitPattern, Memory, Table, TableElement, Trap, TrapCategory, VMResult, WatchpointHit, WatchpointLocation,
+};
 
 pub const VALUE_STACK_LIMIT: usize = 1024 * 1024;
 pub const LABEL_STACK_LIMIT: usize = 64 * 1024;
-pub const FUNCTION_STACK_LIMIT: usize = 1024;
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Label {
     Bound(u32),
     Unbound,
     Return,
 }
 
+#[derive(Clone)]
 pub struct FunctionFrame {
     pub ret_addr: CodePosition,
     pub locals: Vec<Value>,
 }
 
+/// Execution state captured by `VM::snapshot`. Memories are shared with the VM
+/// until either side writes to them. The import function handler is not included.
+#[derive(Clone)]
+pub struct VmSnapshot {
+    module: Arc<Module>,
+    memories: Vec<Memory>,
+    tables: Vec<Table>,
+    ip: CodePosition,
+    globals: Vec<Value>,
+    value_stack: Vec<Value>,
+    label_stack: Vec<Label>,
+    function_stack: Vec<FunctionFrame>,
+    trap: Option<Trap>,
+    trap_position: Option<CodePosition>,
+    executed_instructions: u64,
+}
+
+impl VmSnapshot {
+    pub fn module(&self) -> &Arc<Module> {
+        &self.module
+    }
+
+    pub const fn ip(&self) -> CodePosition {
+        self.ip
+    }
+
+    /// What changed from this snapshot to the later snapshot `other` of the same module.
+    pub fn diff(&self, other: &VmSnapshot) -> StateDiff {
+        let (popped, pushed) = diff::diff_stacks(&self.value_stack, &other.value_stack);
+        StateDiff {
+            globals: diff::diff_globals(&self.globals, &other.globals),
+            memory: diff::diff_memories(&self.memories, &other.memories),
+            popped,
+            pushed,
+        }
+    }
+}
+
+/// State before the current step, used to roll it back when it traps with break on trap enabled.
+/// Trapping instructions only pop values, so restoring those is enough to undo them.
+#[derive(Default)]
+struct StepUndo {
+    ip: CodePosition,
+    executed_instructions: u64,
+    fuel: Option<u64>,
+    value_stack_len: usize,
+    label_stack_len: usize,
+    function_stack_len: usize,
+    popped: Vec<Value>,
+}
+
 pub struct VM<ImportHandler>
 where
     ImportHandler: import_func::ImportFunctionHandler,
@@ -38,6 +94,46 @@ where
     trap: Option<Trap>,
     breakpoints: Arc<Mutex<Breakpoints>>,
     import_function_handler: ImportHandler,
+    executed_instructions: u64,
+    last_watchpoint_hit: Option<WatchpointHit>,
+    interrupt: InterruptHandle,
+    fuel: Option<u64>,
+    cost_model: CostModel,
+    profile: Option<HashMap<CodePosition, u64>>,
+    coverage: Option<Coverage>,
+    break_on_trap: bool,
+    step_undo: StepUndo,
+    caught_trap: Option<Trap>,
+    trap_position: Option<CodePosition>,
+    max_call_depth: usize,
+    links: HashMap<u32, LinkedFunction<ImportHandler>>,
+    max_memory_pages: Option<u32>,
+    pre_instruction_hook: Option<Arc<Mutex<InstructionHook<ImportHandler>>>>,
+    trace: Option<Arc<Mutex<Trace>>>,
+}
+
+/// Called with the VM and the instruction it is about to execute, see `VM::set_pre_instruction_hook`.
+pub type InstructionHook<ImportHandler> = Box<dyn FnMut(&VM<ImportHandler>, &Instruction) + Send>;
+
+/// A function of another instance that an import is linked to.
+pub struct LinkedFunction<ImportHandler>
+where
+    ImportHandler: import_func::ImportFunctionHandler,
+{
+    instance: Arc<Mutex<VM<ImportHandler>>>,
+    func_index: u32,
+}
+
+impl<ImportHandler> Clone for LinkedFunction<ImportHandler>
+where
+    ImportHandler: import_func::ImportFunctionHandler,
+{
+    fn clone(&self) -> Self {
+        LinkedFunction {
+            instance: Arc::clone(&self.instance),
+            func_index: self.func_index,
+        }
+    }
 }
 
 impl<ImportHandler> VM<ImportHandler>
@@ -45,18 +141,37 @@ where
     ImportHandler: import_func::ImportFunctionHandler,
 {
     pub fn new(module: Arc<Module>, breakpoints: Arc<Mutex<Breakpoints>>) -> Result<Self, InitError> {
+        Self::with_import_function_handler(module, breakpoints, ImportHandler::default())
+    }
+
+    pub fn with_import_function_handler(
+        module: Arc<Module>,
+        breakpoints: Arc<Mutex<Breakpoints>>,
+        import_function_handler: ImportHandler,
+    ) -> Result<Self, InitError> {
+        Self::with_memory_init_pattern(module, breakpoints, import_function_handler, MemInitPattern::Zero)
+    }
+
+    /// Like `with_import_function_handler`, with memories filled with `init_pattern` wherever
+    /// data segments don't initialize them and when they grow.
+    pub fn with_memory_init_pattern(
+        module: Arc<Module>,
+        breakpoints: Arc<Mutex<Breakpoints>>,
+        import_function_handler: ImportHandler,
+        init_pattern: MemInitPattern,
+    ) -> Result<Self, InitError> {
         let mut globals = Vec::with_capacity(module.globals().len());
         for global in module.globals() {
             let val = eval_init_expr(global.init_expr())?;
-            if val.value_type() != global.value_type() {
+            if val.value_type() != Some(global.value_type()) {
                 return Err(InitError::MismatchedType {
                     expected: global.value_type(),
-                    found: val.value_type(),
+                    found: val.type_name(),
                 });
             }
             globals.push(val);
         }
-        let memories = Memory::from_module(&module)?;
+        let memories = Memory::from_module_with_init_pattern(&module, init_pattern)?;
         let tables = Table::from_module(&module)?;
 
         Ok(Self {
@@ -70,7 +185,23 @@ where
             function_stack: Vec::new(),
             trap: None,
             breakpoints,
-            import_function_handler: ImportHandler::default(),
+            import_function_handler,
+            executed_instructions: 0,
+            last_watchpoint_hit: None,
+            interrupt: InterruptHandle::default(),
+            fuel: None,
+            cost_model: CostModel::default(),
+            profile: None,
+            coverage: None,
+            break_on_trap: false,
+            step_undo: StepUndo::default(),
+            caught_trap: None,
+            trap_position: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            links: HashMap::new(),
+            pre_instruction_hook: None,
+            trace: None,
+            max_memory_pages: None,
         })
     }
 
@@ -78,6 +209,10 @@ where
         self.breakpoints.lock().unwrap()
     }
 
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
     pub fn value_stack(&self) -> &[Value] {
         &self.value_stack
     }
@@ -102,6 +237,163 @@ where
         self.trap.as_ref()
     }
 
+    /// Whether the top-level invocation returned. Cleared when a new one starts.
+    pub fn is_finished(&self) -> bool {
+        self.trap == Some(Trap::ExecutionFinished)
+    }
+
+    pub const fn executed_instructions(&self) -> u64 {
+        self.executed_instructions
+    }
+
+    pub fn interrupt_handle(&self) -> &InterruptHandle {
+        &self.interrupt
+    }
+
+    pub fn set_interrupt_handle(&mut self, interrupt: InterruptHandle) {
+        self.interrupt = interrupt;
+    }
+
+    /// If enabled, an instruction that faults is rolled back and execution stops with
+    /// `Trap::Caught` with the ip still pointing at it. The next step executes it again
+    /// and delivers the trap, unless the state was changed so that it no longer faults.
+    pub fn set_break_on_trap(&mut self, enabled: bool) {
+        self.break_on_trap = enabled;
+    }
+
+    /// Runs calls of the imported function `import_index` as calls of `func_index` in `instance`
+    /// instead of passing them to the import function handler. The callee runs to completion,
+    /// it can't be stepped through.
+    pub fn link_function(&mut self, import_index: u32, instance: Arc<Mutex<VM<ImportHandler>>>, func_index: u32) {
+        self.links.insert(import_index, LinkedFunction { instance, func_index });
+    }
+
+    /// Calls trap with `Trap::CallStackExhausted` once `max_depth` functions are on the stack.
+    pub fn set_max_call_depth(&mut self, max_depth: usize) {
+        self.max_call_depth = max_depth;
+    }
+
+    /// The instruction that raised the fault the VM is stopped with. The ip already points past it.
+    pub const fn trap_position(&self) -> Option<CodePosition> {
+        self.trap_position
+    }
+
+    /// The trap the VM stopped before, until execution is resumed.
+    pub fn caught_trap(&self) -> Option<&Trap> {
+        self.caught_trap.as_ref()
+    }
+
+    /// Limits execution to `fuel`. Once the next instruction costs more than the remaining
+    /// fuel, execution stops with `Trap::OutOfFuel` and can be resumed after refueling.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    /// `None` if fuel metering is off.
+    pub const fn fuel_remaining(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Traps with `Trap::MemoryGrowthDenied` when `memory.grow` would grow the memory beyond
+    /// `max_pages`, regardless of the maximum the module declares.
+    pub fn set_max_memory_pages(&mut self, max_pages: Option<u32>) {
+        self.max_memory_pages = max_pages;
+    }
+
+    pub fn set_cost_model(&mut self, cost_model: CostModel) {
+        self.cost_model = cost_model;
+    }
+
+    /// Starts counting how often each instruction is executed. Imported functions aren't counted.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(HashMap::new);
+    }
+
+    /// Execution count per instruction. `None` if profiling is off.
+    pub fn profile(&self) -> Option<&HashMap<CodePosition, u64>> {
+        self.profile.as_ref()
+    }
+
+    /// Starts tracking which instructions are executed.
+    pub fn enable_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(Coverage::new(&self.module));
+        }
+    }
+
+    /// `None` if coverage tracking is off.
+    pub fn coverage(&self) -> Option<CoverageReport> {
+        self.coverage.as_ref().map(Coverage::report)
+    }
+
+    fn next_instruction_cost(&self) -> u64 {
+        let func = self.module.get_func(self.ip.func_index).unwrap();
+        if func.is_imported() {
+            return self.cost_model.default_cost();
+        }
+        self.cost_model.cost(&func.instructions()[self.ip.instr_index as usize])
+    }
+
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            module: Arc::clone(&self.module),
+            memories: self.memories.clone(),
+            tables: self.tables.clone(),
+            ip: self.ip,
+            globals: self.globals.clone(),
+            value_stack: self.value_stack.clone(),
+            label_stack: self.label_stack.clone(),
+            function_stack: self.function_stack.clone(),
+            trap: self.trap.clone(),
+            trap_position: self.trap_position,
+            executed_instructions: self.executed_instructions,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &VmSnapshot) {
+        self.module = Arc::clone(&snapshot.module);
+        self.memories = snapshot.memories.clone();
+        self.tables = snapshot.tables.clone();
+        self.ip = snapshot.ip;
+        self.globals = snapshot.globals.clone();
+        self.value_stack = snapshot.value_stack.clone();
+        self.label_stack = snapshot.label_stack.clone();
+        self.function_stack = snapshot.function_stack.clone();
+        self.trap = snapshot.trap.clone();
+        self.trap_position = snapshot.trap_position;
+        self.executed_instructions = snapshot.executed_instructions;
+        self.last_watchpoint_hit = None;
+        self.caught_trap = None;
+    }
+
+    pub fn last_watchpoint_hit(&self) -> Option<&WatchpointHit> {
+        self.last_watchpoint_hit.as_ref()
+    }
+
+    fn breakpoint_reached(&self, breakpoint_index: u32) -> Trap {
+        self.breakpoints_and_unlock().record_hit(breakpoint_index);
+        Trap::BreakpointReached(breakpoint_index)
+    }
+
+    fn watchpoint_reached(&mut self, breakpoint_index: u32, location: WatchpointLocation) -> Trap {
+        self.breakpoints_and_unlock().record_hit(breakpoint_index);
+        self.last_watchpoint_hit = Some(WatchpointHit {
+            breakpoint_index,
+            location,
+        });
+        Trap::WatchpointReached(breakpoint_index)
+    }
+
+    fn check_memory_watchpoint(&mut self, address: u32, size: u32, write: bool) -> VMResult<()> {
+        let found = self.breakpoints_and_unlock().find_memory(address, size, write);
+        match found {
+            Some(break_index) => {
+                Err(self.watchpoint_reached(break_index, WatchpointLocation::Memory { address, size, write }))
+            }
+            None => Ok(()),
+        }
+    }
+
     pub const fn ip(&self) -> CodePosition {
         self.ip
     }
@@ -131,14 +423,18 @@ where
     }
 
     pub(crate) fn pop(&mut self) -> VMResult<Value> {
-        self.value_stack.pop().ok_or(Trap::PopFromEmptyStack)
+        let val = self.value_stack.pop().ok_or(Trap::PopFromEmptyStack)?;
+        if self.break_on_trap {
+            self.step_undo.popped.push(val);
+        }
+        Ok(val)
     }
 
     pub(crate) fn pop_as<T: Number>(&mut self) -> VMResult<T> {
         let val = self.pop()?;
         val.to::<T>().ok_or_else(|| Trap::TypeError {
             expected: T::value_type(),
-            found: val.value_type(),
+            found: val.type_name(),
         })
     }
 
@@ -174,15 +470,25 @@ where
         self.tables.get(0).ok_or(Trap::NoTable)
     }
 
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
     fn branch(&mut self, mut index: u32) -> VMResult<()> {
-        self.label_stack.truncate(self.label_stack.len() - index as usize);
-        match self.label_stack.last().unwrap() {
+        let len = self
+            .label_stack
+            .len()
+            .checked_sub(index as usize)
+            .ok_or(Trap::InvalidBranchIndex)?;
+        self.label_stack.truncate(len);
+        match self.label_stack.last().ok_or(Trap::InvalidBranchIndex)? {
             Label::Bound(target) => self.ip.instr_index = *target,
             Label::Unbound => {
                 index += 1;
+                let curr_code = self.curr_func()?.instructions();
+                let mut instr_index = self.ip.instr_index;
                 loop {
-                    let curr_code = self.curr_func()?.instructions();
-                    match curr_code[self.ip.instr_index as usize] {
+                    match curr_code[instr_index as usize] {
                         Instruction::Block(_) => index += 1,
                         Instruction::Loop(_) => index += 1,
                         Instruction::If(_) => index += 1,
@@ -194,25 +500,30 @@ where
                         break;
                     }
 
-                    self.ip.instr_index += 1;
+                    instr_index += 1;
                 }
+                self.ip.instr_index = instr_index;
+            }
+            // A branch to the label of the function continues at its final `end`, which returns
+            Label::Return => {
+                self.ip.instr_index = self.curr_func()?.instructions().len() as u32 - 1;
             }
-            _ => return Err(Trap::InvalidBranchIndex),
         }
         Ok(())
     }
 
     fn branch_else(&mut self) -> VMResult<()> {
         let mut index = 1;
+        let curr_code = self.curr_func()?.instructions();
+        let mut instr_index = self.ip.instr_index;
         loop {
-            let curr_code = self.curr_func()?.instructions();
-            match curr_code[self.ip.instr_index as usize] {
+            match curr_code[instr_index as usize] {
                 Instruction::Block(_) => index += 1,
                 Instruction::Loop(_) => index += 1,
                 Instruction::If(_) => index += 1,
                 Instruction::Else => {
                     if index == 1 {
-                        self.ip.instr_index += 1;
+                        instr_index += 1;
                         break;
                     }
                 }
@@ -224,45 +535,48 @@ where
                 break;
             }
 
-            self.ip.instr_index += 1;
+            instr_index += 1;
         }
+        self.ip.instr_index = instr_index;
         Ok(())
     }
 
+    /// Pops the base address of an access of `size` bytes and adds the static offset.
+    fn pop_address(&mut self, offset: u32, size: u32) -> VMResult<u32> {
+        let base = self.pop_as::<u32>()?;
+        match base.checked_add(offset) {
+            Some(address) => Ok(address),
+            None => Err(self
+                .default_memory()?
+                .out_of_bounds(u64::from(base) + u64::from(offset), size)),
+        }
+    }
+
     fn perform_load<T: Number + LittleEndianConvert>(&mut self, offset: u32) -> VMResult<()> {
-        let address = self.pop_as::<u32>()? + offset;
-        self.push(self.default_memory()?.load::<T>(address)?.into())?;
         let size = core::mem::size_of::<T>() as u32;
-        if let Some(break_index) = self.breakpoints_and_unlock().find_memory(address, size, false) {
-            return Err(Trap::WatchpointReached(break_index));
-        }
-        Ok(())
+        let address = self.pop_address(offset, size)?;
+        self.push(self.default_memory()?.load::<T>(address)?.into())?;
+        self.check_memory_watchpoint(address, size, false)
     }
 
     fn perform_load_extend<T: LittleEndianConvert, U: Number>(&mut self, offset: u32) -> VMResult<()>
     where
         T: ExtendTo<U>,
     {
-        let address = self.pop_as::<u32>()? + offset;
+        let size = core::mem::size_of::<T>() as u32;
+        let address = self.pop_address(offset, size)?;
         let val: T = self.default_memory()?.load(address)?;
         let val: U = val.extend_to();
         self.push(val.into())?;
-        let size = core::mem::size_of::<T>() as u32;
-        if let Some(break_index) = self.breakpoints_and_unlock().find_memory(address, size, false) {
-            return Err(Trap::WatchpointReached(break_index));
-        }
-        Ok(())
+        self.check_memory_watchpoint(address, size, false)
     }
 
     fn perform_store<T: Number + LittleEndianConvert>(&mut self, offset: u32) -> VMResult<()> {
+        let size = core::mem::size_of::<T>() as u32;
         let value = self.pop_as::<T>()?;
-        let address = self.pop_as::<u32>()? + offset;
+        let address = self.pop_address(offset, size)?;
         self.default_memory_mut()?.store(address, value)?;
-        let size = core::mem::size_of::<T>() as u32;
-        if let Some(break_index) = self.breakpoints_and_unlock().find_memory(address, size, true) {
-            return Err(Trap::WatchpointReached(break_index));
-        }
-        Ok(())
+        self.check_memory_watchpoint(address, size, true)
     }
 
     fn perform_store_wrap<T: LittleEndianConvert, U: Number>(&mut self, offset: u32) -> VMResult<()>
@@ -271,13 +585,10 @@ where
     {
         let value: U = self.pop_as()?;
         let value: T = value.wrap_to();
-        let address = self.pop_as::<u32>()? + offset;
-        self.default_memory_mut()?.store(address, value)?;
         let size = core::mem::size_of::<T>() as u32;
-        if let Some(break_index) = self.breakpoints_and_unlock().find_memory(address, size, true) {
-            return Err(Trap::WatchpointReached(break_index));
-        }
-        Ok(())
+        let address = self.pop_address(offset, size)?;
+        self.default_memory_mut()?.store(address, value)?;
+        self.check_memory_watchpoint(address, size, true)
     }
 
     fn unop<T: Number, R: Number, F: Fn(T) -> R>(&mut self, fun: F) -> VMResult<()> {
@@ -329,8 +640,8 @@ where
         }
         self.label_stack.push(Label::Return);
 
-        if self.function_stack.len() >= FUNCTION_STACK_LIMIT {
-            return Err(Trap::FunctionStackOverflow);
+        if self.function_stack.len() >= self.max_call_depth {
+            return Err(Trap::CallStackExhausted);
         }
         self.function_stack.push(FunctionFrame {
             ret_addr: self.ip,
@@ -345,6 +656,15 @@ where
         Ok(())
     }
 
+    /// Checked after entering `index` through `call`, `call_indirect` or `run_func`.
+    fn check_function_breakpoint(&self, index: u32) -> VMResult<()> {
+        let found = self.breakpoints_and_unlock().find_function(index);
+        if let Some(breakpoint_index) = found {
+            return Err(self.breakpoint_reached(breakpoint_index));
+        }
+        Ok(())
+    }
+
     pub fn start(&mut self) -> VMResult<()> {
         if let Some(start_function) = self.module.start_func() {
             self.run_func_paused(start_function, &[])
@@ -358,6 +678,8 @@ where
         self.label_stack.clear();
         self.value_stack.clear();
         self.trap = None;
+        self.caught_trap = None;
+        self.trap_position = None;
         self.ip = CodePosition::default();
         for arg in args {
             self.push(*arg)?
@@ -377,34 +699,75 @@ where
         if let Err(trap) = self.run_func_paused(index, args) {
             return trap;
         }
-        if let Some(index) = self.breakpoints_and_unlock().find_code(self.ip) {
-            return Trap::BreakpointReached(index);
+        if let Err(trap) = self.check_function_breakpoint(index) {
+            return trap;
+        }
+        let found = self.breakpoints_and_unlock().find_code(self.ip);
+        if let Some(index) = found {
+            return self.breakpoint_reached(index);
         }
         self.continue_execution()
     }
 
     pub fn continue_execution(&mut self) -> Trap {
+        let step = self.step_fn();
         loop {
-            if let Err(trap) = self.execute_step() {
+            if let Err(trap) = step(self) {
                 return trap;
             }
         }
     }
 
+    /// Like `continue_execution` but gives control back to the caller after `max_steps`
+    /// instructions. Returns `Ok(())` if execution can be resumed.
+    pub fn continue_execution_for(&mut self, max_steps: u64) -> VMResult<()> {
+        let step = self.step_fn();
+        for _ in 0..max_steps {
+            step(self)?;
+        }
+        Ok(())
+    }
+
+    /// Like `continue_execution` but traps with `InstructionLimitReached` once the VM has
+    /// executed `max_instrs` instructions in total.
+    pub fn run_with_limit(&mut self, max_instrs: u64) -> VMResult<()> {
+        let step = self.step_fn();
+        loop {
+            if self.executed_instructions >= max_instrs {
+                return Err(Trap::InstructionLimitReached(max_instrs));
+            }
+            step(self)?;
+        }
+    }
+
+    /// Runs until the function stack is back at its current depth, so recursive
+    /// calls made by the callee don't stop the step.
     pub fn execute_step_over(&mut self) -> VMResult<()> {
         let curr_frame_index = self.function_stack.len();
+        let step = self.step_fn();
         loop {
-            self.execute_step()?;
+            step(self)?;
             if curr_frame_index >= self.function_stack.len() {
                 return Ok(());
             }
         }
     }
 
+    /// Like `execute_step`, but a call into an imported function is executed
+    /// together with the import handler instead of pausing at the import.
+    pub fn step_into(&mut self) -> VMResult<()> {
+        self.execute_step()?;
+        if self.curr_func()?.is_imported() {
+            self.execute_step()?;
+        }
+        Ok(())
+    }
+
     pub fn execute_step_out(&mut self) -> VMResult<()> {
         let curr_frame_index = self.function_stack.len();
+        let step = self.step_fn();
         loop {
-            self.execute_step()?;
+            step(self)?;
             if curr_frame_index - 1 == self.function_stack.len() {
                 return Ok(());
             }
@@ -412,14 +775,109 @@ where
     }
 
     pub fn execute_step(&mut self) -> VMResult<()> {
+        self.step_fn()(self)
+    }
+
+    /// The hook runs in the hot path before every instruction and should be cheap. Instructions
+    /// of imported functions aren't reported.
+    pub fn set_pre_instruction_hook(&mut self, hook: Option<Arc<Mutex<InstructionHook<ImportHandler>>>>) {
+        self.pre_instruction_hook = hook;
+    }
+
+    /// Records every executed instruction of this instance, see `Trace`.
+    pub fn set_trace(&mut self, trace: Option<Arc<Mutex<Trace>>>) {
+        self.trace = trace;
+    }
+
+    /// Picks the step function once per run command so that profiling, coverage tracking,
+    /// hooks and tracing cost nothing when they are off.
+    fn step_fn(&self) -> fn(&mut Self) -> VMResult<()> {
+        if self.pre_instruction_hook.is_some() || self.trace.is_some() {
+            Self::step_hooked
+        } else if self.profile.is_some() || self.coverage.is_some() {
+            Self::step_instrumented
+        } else {
+            Self::step
+        }
+    }
+
+    fn step_hooked(&mut self) -> VMResult<()> {
+        let pos = self.ip;
+        let module = Arc::clone(&self.module);
+        let instr = match self.trap {
+            Some(_) => None,
+            None => module
+                .get_func(pos.func_index)
+                .filter(|func| !func.is_imported())
+                .and_then(|func| func.instructions().get(pos.instr_index as usize)),
+        };
+        if let (Some(instr), Some(hook)) = (instr, &self.pre_instruction_hook) {
+            let mut hook = hook.lock().unwrap();
+            (*hook)(self, instr);
+        }
+        let executed_instructions = self.executed_instructions;
+        let result = if self.profile.is_some() || self.coverage.is_some() {
+            self.step_instrumented()
+        } else {
+            self.step()
+        };
+        if let (Some(instr), Some(trace)) = (instr, &self.trace) {
+            if self.executed_instructions != executed_instructions {
+                trace.lock().unwrap().record(pos, instr, self.value_stack.last());
+            }
+        }
+        result
+    }
+
+    fn step_instrumented(&mut self) -> VMResult<()> {
+        let pos = self.ip;
+        let executed_instructions = self.executed_instructions;
+        let result = self.step();
+        let is_imported = self.module.get_func(pos.func_index).map_or(true, Function::is_imported);
+        if self.executed_instructions != executed_instructions && !is_imported {
+            if let Some(profile) = &mut self.profile {
+                *profile.entry(pos).or_insert(0) += 1;
+            }
+            if let Some(coverage) = &mut self.coverage {
+                coverage.mark(pos);
+            }
+        }
+        result
+    }
+
+    fn step(&mut self) -> VMResult<()> {
         if let Some(trap) = &self.trap {
             return Err(trap.to_owned());
         }
+        if self.interrupt.take() {
+            return Err(Trap::Interrupted);
+        }
+        if self.break_on_trap {
+            self.record_step_undo();
+        }
+        if let Some(fuel) = self.fuel {
+            let cost = self.next_instruction_cost();
+            if cost > fuel {
+                return Err(Trap::OutOfFuel);
+            }
+            self.fuel = Some(fuel - cost);
+        }
 
+        self.executed_instructions = self.executed_instructions.saturating_add(1);
+        let pos = self.ip;
+        let deliver_caught = self.caught_trap.take().is_some();
         if let Err(trap) = self.execute_step_internal() {
             match trap {
                 Trap::BreakpointReached(_) | Trap::WatchpointReached(_) => return Err(trap),
+                _ if self.break_on_trap && !deliver_caught && trap.is_fault() => {
+                    self.undo_step();
+                    self.caught_trap = Some(trap.clone());
+                    return Err(Trap::Caught(Box::new(trap)));
+                }
                 _ => {
+                    if trap.is_fault() {
+                        self.trap_position = Some(pos);
+                    }
                     self.trap = Some(trap.clone());
                     return Err(trap);
                 }
@@ -429,14 +887,194 @@ where
         Ok(())
     }
 
+    fn record_step_undo(&mut self) {
+        self.step_undo.ip = self.ip;
+        self.step_undo.executed_instructions = self.executed_instructions;
+        self.step_undo.fuel = self.fuel;
+        self.step_undo.value_stack_len = self.value_stack.len();
+        self.step_undo.label_stack_len = self.label_stack.len();
+        self.step_undo.function_stack_len = self.function_stack.len();
+        self.step_undo.popped.clear();
+    }
+
+    fn undo_step(&mut self) {
+        let popped = std::mem::take(&mut self.step_undo.popped);
+        self.value_stack.truncate(self.step_undo.value_stack_len - popped.len());
+        self.value_stack.extend(popped.into_iter().rev());
+        self.label_stack.truncate(self.step_undo.label_stack_len);
+        self.function_stack.truncate(self.step_undo.function_stack_len);
+        self.ip = self.step_undo.ip;
+        self.executed_instructions = self.step_undo.executed_instructions;
+        self.fuel = self.step_undo.fuel;
+    }
+
+    /// Predicts whether executing the next instruction is guaranteed to trap given the current
+    /// operands. Returns `None` if no trap can be determined.
+    pub fn would_trap(&self) -> Option<TrapCategory> {
+        if self.trap.is_some() {
+            return None;
+        }
+        let func = self.module.get_func(self.ip.func_index)?;
+        if func.is_imported() {
+            return None;
+        }
+        match func.instructions().get(self.ip.instr_index as usize)? {
+            Instruction::Unreachable => Some(TrapCategory::Unreachable),
+            Instruction::I32DivU | Instruction::I32RemU | Instruction::I32RemS => {
+                self.would_divide_by_zero(self.peek_as::<u32>(0)? as u64)
+            }
+            Instruction::I64DivU | Instruction::I64RemU | Instruction::I64RemS => {
+                self.would_divide_by_zero(self.peek_as::<u64>(0)?)
+            }
+            Instruction::I32DivS => {
+                let b = self.peek_as::<i32>(0)?;
+                let a = self.peek_as::<i32>(1)?;
+                self.would_divide_by_zero(b as u64).or_else(|| {
+                    if a == i32::MIN && b == -1 {
+                        Some(TrapCategory::SignedIntegerOverflow)
+                    } else {
+                        None
+                    }
+                })
+            }
+            Instruction::I64DivS => {
+                let b = self.peek_as::<i64>(0)?;
+                let a = self.peek_as::<i64>(1)?;
+                self.would_divide_by_zero(b as u64).or_else(|| {
+                    if a == i64::MIN && b == -1 {
+                        Some(TrapCategory::SignedIntegerOverflow)
+                    } else {
+                        None
+                    }
+                })
+            }
+            Instruction::I32Load8S(_flag, offset)
+            | Instruction::I32Load8U(_flag, offset)
+            | Instruction::I64Load8S(_flag, offset)
+            | Instruction::I64Load8U(_flag, offset) => self.would_access_out_of_bounds(0, *offset, 1),
+            Instruction::I32Load16S(_flag, offset)
+            | Instruction::I32Load16U(_flag, offset)
+            | Instruction::I64Load16S(_flag, offset)
+            | Instruction::I64Load16U(_flag, offset) => self.would_access_out_of_bounds(0, *offset, 2),
+            Instruction::I32Load(_flag, offset)
+            | Instruction::F32Load(_flag, offset)
+            | Instruction::I64Load32S(_flag, offset)
+            | Instruction::I64Load32U(_flag, offset) => self.would_access_out_of_bounds(0, *offset, 4),
+            Instruction::I64Load(_flag, offset) | Instruction::F64Load(_flag, offset) => {
+                self.would_access_out_of_bounds(0, *offset, 8)
+            }
+            Instruction::I32Store8(_flag, offset) | Instruction::I64Store8(_flag, offset) => {
+                self.would_access_out_of_bounds(1, *offset, 1)
+            }
+            Instruction::I32Store16(_flag, offset) | Instruction::I64Store16(_flag, offset) => {
+                self.would_access_out_of_bounds(1, *offset, 2)
+            }
+            Instruction::I32Store(_flag, offset)
+            | Instruction::F32Store(_flag, offset)
+            | Instruction::I64Store32(_flag, offset) => self.would_access_out_of_bounds(1, *offset, 4),
+            Instruction::I64Store(_flag, offset) | Instruction::F64Store(_flag, offset) => {
+                self.would_access_out_of_bounds(1, *offset, 8)
+            }
+            Instruction::CallIndirect(..) => match self.indirect_call_type_matches()? {
+                true => None,
+                false => Some(TrapCategory::IndirectCallTypeMismatch),
+            },
+            _ => None,
+        }
+    }
+
+    /// Resolves the callee of the `call_indirect` the VM is paused on. Returns `None` if the
+    /// next instruction is no `call_indirect` or the table entry is null or out of bounds.
+    pub fn indirect_call_target(&self) -> Option<u32> {
+        let func = self.module.get_func(self.ip.func_index)?;
+        if func.is_imported() {
+            return None;
+        }
+        match func.instructions().get(self.ip.instr_index as usize)? {
+            Instruction::CallIndirect(..) => match self.default_table().ok()?.get(self.peek_as::<u32>(0)?) {
+                TableElement::Func(func_index) => Some(func_index),
+                TableElement::Null => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether the callee of the `call_indirect` the VM is paused on has the type the
+    /// instruction expects, compared like executing it does. `None` if `indirect_call_target`
+    /// doesn't find a callee.
+    pub fn indirect_call_type_matches(&self) -> Option<bool> {
+        let target = self.indirect_call_target()?;
+        let func = self.module.get_func(self.ip.func_index)?;
+        match func.instructions().get(self.ip.instr_index as usize)? {
+            Instruction::CallIndirect(signature, _) => {
+                Some(self.module.get_func(target)?.func_type().type_ref() == *signature)
+            }
+            _ => None,
+        }
+    }
+
+    fn peek_as<T: Number>(&self, depth: usize) -> Option<T> {
+        let index = self.value_stack.len().checked_sub(depth + 1)?;
+        self.value_stack[index].to::<T>()
+    }
+
+    fn would_divide_by_zero(&self, divisor: u64) -> Option<TrapCategory> {
+        if divisor == 0 {
+            Some(TrapCategory::DivisionByZero)
+        } else {
+            None
+        }
+    }
+
+    fn would_access_out_of_bounds(&self, address_depth: usize, offset: u32, size: u64) -> Option<TrapCategory> {
+        let address = self.peek_as::<u32>(address_depth)? as u64 + offset as u64;
+        let memory_size = self.default_memory().ok()?.data().len() as u64;
+        if address + size > memory_size {
+            Some(TrapCategory::MemoryOutOfBounds)
+        } else {
+            None
+        }
+    }
+
     fn execute_import_function(&mut self) -> VMResult<()> {
-        ImportHandler::handle_import_function(self)?;
+        match self.links.get(&self.ip.func_index).cloned() {
+            Some(link) => self.call_linked_function(&link)?,
+            None => ImportHandler::handle_import_function_blocking(self)?,
+        }
         self.label_stack.pop();
         let frame = self.function_stack.pop().unwrap();
         self.ip = frame.ret_addr;
         Ok(())
     }
 
+    fn call_linked_function(&mut self, link: &LinkedFunction<ImportHandler>) -> VMResult<()> {
+        let args = self.locals()?.to_vec();
+        let results = {
+            // Fails for calls that re-enter an instance, e.g. through cyclic links
+            let mut instance = link
+                .instance
+                .try_lock()
+                .map_err(|_| Trap::UnsupportedCallToImportedFunction(self.ip.func_index))?;
+            match instance.run_func(link.func_index, &args) {
+                Trap::ExecutionFinished => (),
+                trap => return Err(trap),
+            }
+            let stack = instance.value_stack();
+            let has_result = instance
+                .module
+                .get_func(link.func_index)
+                .unwrap()
+                .func_type()
+                .return_type()
+                .is_some();
+            stack[stack.len() - usize::from(has_result)..].to_vec()
+        };
+        for result in results {
+            self.push(result)?;
+        }
+        Ok(())
+    }
+
     #[allow(clippy::float_cmp, clippy::redundant_closure)]
     fn execute_step_internal(&mut self) -> VMResult<()> {
         let func = self.module.get_func(self.ip.func_index).unwrap();
@@ -488,7 +1126,10 @@ where
                 },
 
                 // Calls
-                Instruction::Call(index) => self.call(index)?,
+                Instruction::Call(index) => {
+                    self.call(index)?;
+                    self.check_function_breakpoint(index)?;
+                }
                 Instruction::CallIndirect(signature, _) => {
                     let callee = self.pop_as::<u32>()?;
                     let func_index = match self.default_table()?.get(callee) {
@@ -505,6 +1146,7 @@ where
                     }
 
                     self.call(func_index)?;
+                    self.check_function_breakpoint(func_index)?;
                 }
                 Instruction::Drop => {
                     self.pop()?;
@@ -534,15 +1176,27 @@ where
                 Instruction::GetGlobal(index) => {
                     let val = self.globals[index as usize];
                     self.push(val)?;
-                    if let Some(break_index) = self.breakpoints_and_unlock().find_global(index, false) {
-                        return Err(Trap::WatchpointReached(break_index));
+                    let found = self.breakpoints_and_unlock().find_global(index, false, val);
+                    if let Some(break_index) = found {
+                        let location = WatchpointLocation::Global {
+                            index,
+                            old_value: val,
+                            new_value: val,
+                        };
+                        return Err(self.watchpoint_reached(break_index, location));
                     }
                 }
                 Instruction::SetGlobal(index) => {
                     let val = self.pop()?;
-                    self.globals[index as usize] = val;
-                    if let Some(break_index) = self.breakpoints_and_unlock().find_global(index, true) {
-                        return Err(Trap::WatchpointReached(break_index));
+                    let old_value = std::mem::replace(&mut self.globals[index as usize], val);
+                    let found = self.breakpoints_and_unlock().find_global(index, true, val);
+                    if let Some(break_index) = found {
+                        let location = WatchpointLocation::Global {
+                            index,
+                            old_value,
+                            new_value: val,
+                        };
+                        return Err(self.watchpoint_reached(break_index, location));
                     }
                 }
 
@@ -576,7 +1230,15 @@ where
                 Instruction::CurrentMemory(_) => self.push(Value::I32(self.default_memory()?.page_count() as i32))?,
                 Instruction::GrowMemory(_) => {
                     let delta = self.pop_as::<u32>()?;
-                    let result = self.default_memory_mut()?.grow(delta);
+                    let cap = self.max_memory_pages;
+                    let memory = self.default_memory_mut()?;
+                    if let Some(cap) = cap {
+                        let pages = u64::from(memory.page_count()) + u64::from(delta);
+                        if pages > u64::from(cap) {
+                            return Err(Trap::MemoryGrowthDenied { pages, cap });
+                        }
+                    }
+                    let result = memory.grow(delta);
                     self.push(Value::I32(result))?;
                 }
 
@@ -740,8 +1402,9 @@ where
             return Err(Trap::ExecutionFinished);
         }
 
-        if let Some(index) = self.breakpoints_and_unlock().find_code(self.ip) {
-            return Err(Trap::BreakpointReached(index));
+        let found = self.breakpoints_and_unlock().find_code(self.ip);
+        if let Some(index) = found {
+            return Err(self.breakpoint_reached(index));
         }
 
         Ok(())
@@ -1,39 +1,87 @@
-use bwasm::{ResizableLimits, PAGE_SIZE};
+use std::sync::Arc;
+
+use bwasm::{ResizableLimits, ValueType, PAGE_SIZE};
 
 use super::{eval_init_expr, InitError, Trap, VMResult};
 use crate::value::LittleEndianConvert;
+use crate::{Value, F32, F64};
 
 pub const MEMORY_MAX_PAGES: u32 = 0x10000;
 
-#[derive(Debug)]
+/// What fresh memory is filled with, both at instantiation and when it grows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemInitPattern {
+    Zero,
+    /// Fills memory with a recognizable byte such as 0xCD, so that reads of memory the program
+    /// never wrote to stand out.
+    Poison(u8),
+}
+
+impl MemInitPattern {
+    pub fn byte(self) -> u8 {
+        match self {
+            MemInitPattern::Zero => 0,
+            MemInitPattern::Poison(byte) => byte,
+        }
+    }
+}
+
+impl Default for MemInitPattern {
+    fn default() -> Self {
+        MemInitPattern::Zero
+    }
+}
+
+/// Clones share their data until one of them is written to.
+#[derive(Clone, Debug)]
 pub struct Memory {
-    data: Vec<u8>,
+    data: Arc<Vec<u8>>,
     limits: ResizableLimits,
+    init_pattern: MemInitPattern,
 }
 
 impl Memory {
     pub fn new(memory: &bwasm::Memory) -> Memory {
+        Memory::with_init_pattern(memory, MemInitPattern::Zero)
+    }
+
+    pub fn with_init_pattern(memory: &bwasm::Memory, init_pattern: MemInitPattern) -> Memory {
+        let size = (memory.limits().initial() * PAGE_SIZE) as usize;
         Memory {
-            data: vec![0; (memory.limits().initial() * PAGE_SIZE) as usize],
+            data: Arc::new(vec![init_pattern.byte(); size]),
             limits: *memory.limits(),
+            init_pattern,
         }
     }
 
     pub fn from_module(module: &bwasm::Module) -> Result<Vec<Memory>, InitError> {
-        let mut memories: Vec<_> = module.memories().iter().map(Memory::new).collect();
+        Memory::from_module_with_init_pattern(module, MemInitPattern::Zero)
+    }
+
+    /// Like `from_module`, with everything data segments don't initialize filled with `init_pattern`.
+    pub fn from_module_with_init_pattern(
+        module: &bwasm::Module,
+        init_pattern: MemInitPattern,
+    ) -> Result<Vec<Memory>, InitError> {
+        let mut memories: Vec<_> = module
+            .memories()
+            .iter()
+            .map(|memory| Memory::with_init_pattern(memory, init_pattern))
+            .collect();
 
         for init in module.memory_inits() {
             let memory = &mut memories[init.index() as usize];
             let offset = eval_init_expr(init.offset())?;
             let offset = match offset.to::<u32>() {
                 Some(val) => val as usize,
-                None => return Err(InitError::OffsetInvalidType(offset.value_type())),
+                None => return Err(InitError::OffsetInvalidType(offset.type_name())),
             };
             let len = init.data().len();
-            if offset + len > memory.data.len() {
-                memory.data.resize(offset + len, 0);
+            let data = Arc::make_mut(&mut memory.data);
+            if offset + len > data.len() {
+                data.resize(offset + len, init_pattern.byte());
             }
-            memory.data[offset..offset + len].copy_from_slice(init.data());
+            data[offset..offset + len].copy_from_slice(init.data());
         }
 
         Ok(memories)
@@ -43,44 +91,96 @@ impl Memory {
         self.data.len() as u32 / PAGE_SIZE
     }
 
+    /// Grows by `delta` pages and returns the previous page count, or -1 if that would exceed
+    /// the declared maximum.
     pub fn grow(&mut self, delta: u32) -> i32 {
         let page_count = self.page_count();
-        if let Some(max) = self.limits.maximum() {
-            if page_count + delta > max {
-                return -1i32;
-            }
-        } else if page_count + delta > MEMORY_MAX_PAGES {
-            return -1i32;
-        }
-        self.data.resize(((page_count + delta) * PAGE_SIZE) as usize, 0);
+        let max = self.limits.maximum().unwrap_or(MEMORY_MAX_PAGES);
+        let new_page_count = match page_count.checked_add(delta) {
+            Some(new_page_count) if new_page_count <= max => new_page_count,
+            _ => return -1i32,
+        };
+        let fill = self.init_pattern.byte();
+        Arc::make_mut(&mut self.data).resize(new_page_count as usize * PAGE_SIZE as usize, fill);
         page_count as i32
     }
 
+    /// Whether both still share their data after cloning, in which case they're equal.
+    pub(crate) fn shares_data(&self, other: &Memory) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+
+    pub fn init_pattern(&self) -> MemInitPattern {
+        self.init_pattern
+    }
+
     pub fn data(&self) -> &[u8] {
         &self.data
     }
     pub fn data_mut(&mut self) -> &mut [u8] {
-        &mut self.data
+        Arc::make_mut(&mut self.data)
+    }
+
+    /// The trap for an access of `size` bytes at `addr` that doesn't fit into this memory.
+    pub fn out_of_bounds(&self, addr: u64, size: u32) -> Trap {
+        Trap::MemoryAccessOutOfBounds {
+            addr,
+            size,
+            mem_size: self.data.len() as u64,
+        }
     }
 
     pub fn load<T: LittleEndianConvert>(&self, address: u32) -> VMResult<T> {
         let size = core::mem::size_of::<T>();
-        let address = address as usize;
         let bytes = self
             .data
-            .get(address..address + size)
-            .ok_or_else(|| Trap::MemoryAccessOutOfRange((address + size) as u32))?;
+            .get(address as usize..address as usize + size)
+            .ok_or_else(|| self.out_of_bounds(u64::from(address), size as u32))?;
         Ok(T::from_little_endian(bytes))
     }
 
     pub fn store<T: LittleEndianConvert>(&mut self, address: u32, value: T) -> VMResult<()> {
         let size = core::mem::size_of::<T>();
-        let address = address as usize;
-        let bytes = self
-            .data
-            .get_mut(address..address + size)
-            .ok_or_else(|| Trap::MemoryAccessOutOfRange((address + size) as u32))?;
+        let mem_size = self.data.len() as u64;
+        let bytes = Arc::make_mut(&mut self.data)
+            .get_mut(address as usize..address as usize + size)
+            .ok_or_else(|| Trap::MemoryAccessOutOfBounds {
+                addr: u64::from(address),
+                size: size as u32,
+                mem_size,
+            })?;
         value.to_little_endian(bytes);
         Ok(())
     }
+
+    pub fn load_value(&self, address: u32, value_type: ValueType) -> VMResult<Value> {
+        Ok(match value_type {
+            ValueType::I32 => Value::from(self.load::<u32>(address)?),
+            ValueType::I64 => Value::from(self.load::<u64>(address)?),
+            ValueType::F32 => Value::from(self.load::<F32>(address)?),
+            ValueType::F64 => Value::from(self.load::<F64>(address)?),
+        })
+    }
+
+    /// Loads `count` consecutive values of `value_type` starting at `address`.
+    pub fn load_values(&self, address: u32, value_type: ValueType, count: u32) -> VMResult<Vec<Value>> {
+        let size = value_type_size(value_type);
+        let end = u64::from(address) + u64::from(count) * u64::from(size);
+        let mem_size = self.data.len() as u64;
+        if end > mem_size {
+            // Report the first value that doesn't fit
+            let in_bounds = mem_size.saturating_sub(u64::from(address)) / u64::from(size);
+            return Err(self.out_of_bounds(u64::from(address) + in_bounds * u64::from(size), size));
+        }
+        (0..count)
+            .map(|i| self.load_value(address + i * size, value_type))
+            .collect()
+    }
+}
+
+pub fn value_type_size(value_type: ValueType) -> u32 {
+    match value_type {
+        ValueType::I32 | ValueType::F32 => 4,
+        ValueType::I64 | ValueType::F64 => 8,
+    }
 }
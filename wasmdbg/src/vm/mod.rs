@@ -1,22 +1,32 @@
 use crate::Value;
 use bwasm::{InitExpr, ValueType};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
+mod coverage;
+mod diff;
+mod fuel;
 pub mod import_func;
 mod instance;
 mod memory;
 mod table;
+mod trace;
+pub use coverage::{CoverageReport, FunctionCoverage};
+pub use diff::{GlobalChange, MemoryChange, StateDiff};
+pub use fuel::*;
 pub use instance::*;
 pub use memory::*;
 pub use table::*;
+pub use trace::{Trace, DEFAULT_TRACE_LIMIT};
 
 #[derive(Error, Clone, Debug)]
 pub enum InitError {
     #[error("Initalizer contains global.get which requires imports (unimplemented)")]
     GlobalGetUnimplemented,
     #[error("Initializer type mismatch. Expected \"{expected}\", found \"{found}\"")]
-    MismatchedType { expected: ValueType, found: ValueType },
+    MismatchedType { expected: ValueType, found: &'static str },
     #[error("Offset expr has invalid type. Expected \"i32\", found \"{0}\"")]
-    OffsetInvalidType(ValueType),
+    OffsetInvalidType(&'static str),
 }
 
 #[derive(Error, Clone, Debug, PartialEq)]
@@ -30,7 +40,8 @@ pub enum Trap {
     #[error("Execution finished")]
     ExecutionFinished,
     #[error("Type error. Expected \"{expected}\", found \"{found}\"")]
-    TypeError { expected: ValueType, found: ValueType },
+    /// `found` is a type name because the value may be a v128, which has no `ValueType`.
+    TypeError { expected: ValueType, found: &'static str },
     #[error("Division by zero")]
     DivisionByZero,
     #[error("Signed integer overflow")]
@@ -55,23 +66,178 @@ pub enum Trap {
     WatchpointReached(u32),
     #[error("Invalid branch index")]
     InvalidBranchIndex,
-    #[error("Out of range memory access at address {0:#08x}")]
-    MemoryAccessOutOfRange(u32),
+    /// `addr` is the effective address, which can exceed `u32::MAX` with a static offset.
+    #[error("Out of bounds memory access at {addr:#x} (size {size}, memory {mem_size:#x})")]
+    MemoryAccessOutOfBounds { addr: u64, size: u32, mem_size: u64 },
     #[error("Tried to call unsupported imported function: {0}")]
     UnsupportedCallToImportedFunction(u32),
+    #[error("Invalid reply from the import function handler: {0}")]
+    ImportProtocolError(String),
+    #[error("Missing import handler for {0}")]
+    MissingImportHandler(String),
+    #[error("Call of the import {import} failed: {reason}")]
+    ImportCallFailed { import: String, reason: String },
     #[error("Value stack overflow")]
     ValueStackOverflow,
     #[error("Label stack overflow")]
     LabelStackOverflow,
-    #[error("Function stack overflow")]
-    FunctionStackOverflow,
+    #[error("Call stack exhausted")]
+    CallStackExhausted,
     #[error("WASI process exited with exitcode {0}")]
-    WasiExit(u32),
+    ProcExit(i32),
+    #[error("Reached instruction limit of {0}")]
+    InstructionLimitReached(u64),
+    #[error("Interrupted")]
+    Interrupted,
+    #[error("Out of fuel")]
+    OutOfFuel,
+    #[error("Growing memory to {pages} pages exceeds the cap of {cap} pages")]
+    MemoryGrowthDenied { pages: u64, cap: u32 },
+    #[error("Stopped before trap: {0}")]
+    Caught(Box<Trap>),
+}
+
+impl Trap {
+    /// What the trap means and what commonly causes it, to show after the terse `Display`.
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            Trap::ReachedUnreachable => {
+                "an `unreachable` instruction was executed. Compilers emit it for code that must never run, \
+                 e.g. after a panic, a failed assertion or the end of a function that should have returned"
+            }
+            Trap::PopFromEmptyStack => {
+                "an instruction needed more operands than the value stack held, which a valid module can't cause"
+            }
+            Trap::NoFunctionFrame => "execution needed a function frame but the call stack was empty",
+            Trap::ExecutionFinished => "the function returned and there is nothing left to execute",
+            Trap::TypeError { .. } => {
+                "a value on the stack had another type than the instruction expects, which a valid module can't cause"
+            }
+            Trap::DivisionByZero => "integer division or remainder with a zero divisor",
+            Trap::SignedIntegerOverflow => {
+                "signed integer division of the minimum value by -1, whose result doesn't fit the type"
+            }
+            Trap::InvalidConversionToInt => {
+                "float to integer truncation of NaN, infinity or a value outside of the range of the integer type"
+            }
+            Trap::NoTable => "a `call_indirect` in a module without a table",
+            Trap::NoMemory => "a memory instruction in a module without a memory",
+            Trap::IndirectCalleeAbsent => {
+                "the table index of a `call_indirect` is out of bounds or refers to an empty slot, \
+                 usually a call through a null or corrupted function pointer"
+            }
+            Trap::IndirectCallTypeMismatch => {
+                "the function a `call_indirect` resolved to has another signature than the call expects, \
+                 usually a function pointer cast to the wrong type"
+            }
+            Trap::NoFunctionWithIndex(_) => "a call referred to a function index the module doesn't define",
+            Trap::NoStartFunction => "the module has no start function to run, call an exported function instead",
+            Trap::BreakpointReached(_) => "execution paused at a breakpoint",
+            Trap::WatchpointReached(_) => "execution paused because a watched global or memory location was accessed",
+            Trap::InvalidBranchIndex => {
+                "a branch targeted more labels than enclose it, which a valid module can't cause"
+            }
+            Trap::MemoryAccessOutOfBounds { .. } => {
+                "a load or store went past the end of the linear memory, usually a null, dangling or \
+                 out of bounds pointer, or memory that was never grown"
+            }
+            Trap::UnsupportedCallToImportedFunction(_) => {
+                "the module called an imported function that neither a linked module nor the import handler provides"
+            }
+            Trap::ImportProtocolError(_) => {
+                "the import function handler replied with data that doesn't fit the module, \
+                 e.g. more globals than it has, memory of another size or results of the wrong type"
+            }
+            Trap::MissingImportHandler(_) => "the client that implements the imports has no handler for this one",
+            Trap::ImportCallFailed { .. } => {
+                "the import function handler couldn't complete the call, e.g. because the client that \
+                 implements the imports is unreachable"
+            }
+            Trap::ValueStackOverflow => "the value stack exceeded its limit, usually because of unbounded recursion",
+            Trap::LabelStackOverflow => "too many blocks were entered, usually because of unbounded recursion",
+            Trap::CallStackExhausted => "the call depth exceeded its limit, usually because of unbounded recursion",
+            Trap::ProcExit(_) => "the program exited by calling the WASI function `proc_exit`",
+            Trap::InstructionLimitReached(_) => "execution stopped after the requested number of instructions",
+            Trap::Interrupted => "execution was interrupted on request",
+            Trap::OutOfFuel => "the instance used up the fuel it was given",
+            Trap::MemoryGrowthDenied { .. } => "`memory.grow` would exceed the memory page cap set in the debugger",
+            Trap::Caught(trap) => trap.explanation(),
+        }
+    }
+
+    /// Whether the trap is an actual runtime error rather than a reason for pausing or
+    /// finishing execution.
+    pub fn is_fault(&self) -> bool {
+        !matches!(
+            self,
+            Trap::ExecutionFinished
+                | Trap::BreakpointReached(_)
+                | Trap::WatchpointReached(_)
+                | Trap::ProcExit(_)
+                | Trap::InstructionLimitReached(_)
+                | Trap::Interrupted
+                | Trap::OutOfFuel
+                | Trap::Caught(_)
+        )
+    }
 }
 
 pub type VMResult<T> = Result<T, Trap>;
 
-#[derive(Default, Copy, Clone, PartialEq, Eq, Hash)]
+/// Kind of trap predicted by `VM::would_trap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapCategory {
+    Unreachable,
+    DivisionByZero,
+    SignedIntegerOverflow,
+    MemoryOutOfBounds,
+    IndirectCallTypeMismatch,
+}
+
+/// Stops a VM that is running on another thread with `Trap::Interrupted`.
+/// Clones share the same flag.
+#[derive(Clone, Default, Debug)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Drops a pending interrupt request.
+    pub fn clear(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Details about the watchpoint that caused the last `Trap::WatchpointReached`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchpointHit {
+    pub breakpoint_index: u32,
+    pub location: WatchpointLocation,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchpointLocation {
+    /// For reads `old_value` and `new_value` are the same.
+    Global {
+        index: u32,
+        old_value: Value,
+        new_value: Value,
+    },
+    Memory {
+        address: u32,
+        size: u32,
+        write: bool,
+    },
+}
+
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CodePosition {
     pub func_index: u32,
     pub instr_index: u32,
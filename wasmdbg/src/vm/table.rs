@@ -14,6 +14,7 @@ impl Default for TableElement {
     }
 }
 
+#[derive(Clone)]
 pub struct Table {
     elements: Vec<TableElement>,
 }
@@ -28,6 +29,10 @@ impl Table {
         self.elements.get(index as usize).copied().unwrap_or_default()
     }
 
+    pub fn elements(&self) -> &[TableElement] {
+        &self.elements
+    }
+
     pub fn from_module(module: &bwasm::Module) -> Result<Vec<Table>, InitError> {
         let mut tables: Vec<_> = module.tables().iter().map(Table::new).collect();
 
@@ -36,7 +41,7 @@ impl Table {
             let offset = eval_init_expr(init.offset())?;
             let offset = match offset.to::<i32>() {
                 Some(val) => val as usize,
-                None => return Err(InitError::OffsetInvalidType(offset.value_type())),
+                None => return Err(InitError::OffsetInvalidType(offset.type_name())),
             };
             for (i, ele) in init.entries().iter().enumerate() {
                 let ele = TableElement::Func(*ele);
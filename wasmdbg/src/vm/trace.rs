@@ -0,0 +1,53 @@
+use std::io::Write;
+
+use bwasm::Instruction;
+
+use super::CodePosition;
+use crate::{DisplayMode, Value};
+
+pub const DEFAULT_TRACE_LIMIT: u64 = 1_000_000;
+
+/// Writes one line per executed instruction, `FUNC:INSTR INSTRUCTION -> TOP`, where `TOP` is the
+/// top of the value stack after the instruction, like `i32:42`, or `-` if the stack is empty.
+/// Stops with a final note once `max_lines` instructions have been written.
+pub struct Trace {
+    out: Box<dyn Write + Send>,
+    lines: u64,
+    max_lines: u64,
+}
+
+impl Trace {
+    pub fn new(out: Box<dyn Write + Send>, max_lines: u64) -> Self {
+        Trace {
+            out,
+            lines: 0,
+            max_lines,
+        }
+    }
+
+    pub fn set_max_lines(&mut self, max_lines: u64) {
+        self.max_lines = max_lines;
+    }
+
+    pub(crate) fn record(&mut self, pos: CodePosition, instr: &Instruction, top: Option<&Value>) {
+        if self.lines >= self.max_lines {
+            return;
+        }
+        self.lines += 1;
+        let top = match top {
+            Some(value) if value.is_v128() => format!("v128:{}", value.format(DisplayMode::Hex)),
+            Some(value) => format!("{}:{}", value.type_name(), value.format(DisplayMode::Decimal)),
+            None => "-".to_owned(),
+        };
+        // Write errors are ignored, tracing must not change how the program runs
+        let _ = writeln!(self.out, "{}:{} {} -> {}", pos.func_index, pos.instr_index, instr, top);
+        if self.lines == self.max_lines {
+            let _ = writeln!(self.out, "trace stopped after {} instructions", self.max_lines);
+            let _ = self.out.flush();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
@@ -1,83 +1,438 @@
+use std::future;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bwasm::Module;
+
+use crate::value::Number;
+use crate::vm::import_func::{import_function_name, ImportFunctionHandler, ImportFuture};
 use crate::vm::{Trap, VMResult, VM};
+use crate::Value;
+
+const ERRNO_SUCCESS: u32 = 0;
+const ERRNO_BADF: u32 = 8;
+const ERRNO_INVAL: u32 = 28;
+const ERRNO_IO: u32 = 29;
+
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_MONOTONIC: u32 = 1;
 
 #[derive(Clone, Copy)]
-pub enum WasiFunction {
-    FdFdstatGet,
-    FdPrestatGet,
-    FdPrestatDirName,
-    FdSeek,
-    FdRead,
+enum WasiFunction {
     FdWrite,
-    FdClose,
-    PathOpen,
+    FdRead,
     ProcExit,
-    EnvironSizesGet,
-    EnvironGet,
     ArgsSizesGet,
     ArgsGet,
+    EnvironSizesGet,
+    EnvironGet,
     RandomGet,
+    ClockTimeGet,
 }
 
 impl WasiFunction {
-    pub fn from_name(name: &str) -> Option<Self> {
+    fn from_name(module: &str, name: &str) -> Option<Self> {
+        if module != "wasi_snapshot_preview1" && module != "wasi_unstable" {
+            return None;
+        }
         Some(match name {
-            // "fd_fdstat_get" => WasiFunction::FdFdstatGet,
-            // "fd_prestat_get" => WasiFunction::FdPrestatGet,
-            // "fd_prestat_dir_name" => WasiFunction::FdPrestatDirName,
-            // "fd_seek" => WasiFunction::FdSeek,
-            // "fd_read" => WasiFunction::FdRead,
             "fd_write" => WasiFunction::FdWrite,
-            // "fd_close" => WasiFunction::FdClose,
-            // "path_open" => WasiFunction::PathOpen,
+            "fd_read" => WasiFunction::FdRead,
             "proc_exit" => WasiFunction::ProcExit,
-            // "environ_sizes_get" => WasiFunction::EnvironSizesGet,
-            // "environ_get" => WasiFunction::EnvironGet,
-            // "args_sizes_get" => WasiFunction::ArgsSizesGet,
-            // "args_get" => WasiFunction::ArgsGet,
-            // "random_get" => WasiFunction::RandomGet,
+            "args_sizes_get" => WasiFunction::ArgsSizesGet,
+            "args_get" => WasiFunction::ArgsGet,
+            "environ_sizes_get" => WasiFunction::EnvironSizesGet,
+            "environ_get" => WasiFunction::EnvironGet,
+            "random_get" => WasiFunction::RandomGet,
+            "clock_time_get" => WasiFunction::ClockTimeGet,
             _ => return None,
         })
     }
 
-    pub fn handle(self, vm: &mut VM) -> VMResult<()> {
+    fn from_func_index(module: &Module, func_index: u32) -> Option<Self> {
+        import_function_name(module, func_index).and_then(|(module, field)| WasiFunction::from_name(module, field))
+    }
+
+    /// Executes the function and returns the WASI errno.
+    fn handle(self, vm: &mut VM<WasiImportFunctionHandler>) -> VMResult<u32> {
         match self {
-            WasiFunction::FdFdstatGet => (),
-            WasiFunction::FdPrestatGet => (),
-            WasiFunction::FdPrestatDirName => (),
-            WasiFunction::FdSeek => (),
-            WasiFunction::FdRead => (),
             WasiFunction::FdWrite => {
-                let fd = vm.locals()?[0].to::<u32>().unwrap();
-                let iovs = vm.locals()?[1].to::<u32>().unwrap();
-                let iovs_len = vm.locals()?[2].to::<u32>().unwrap();
-                let nwritten_out = vm.locals()?[3].to::<u32>().unwrap();
-                let mut nwritten = 0;
-                if fd != 1 {
-                    panic!("wasi.fd_write call with fd != stdout");
+                let fd: u32 = param(vm, 0)?;
+                let iovs: u32 = param(vm, 1)?;
+                let iovs_len: u32 = param(vm, 2)?;
+                let nwritten_ptr: u32 = param(vm, 3)?;
+                if fd != 1 && fd != 2 {
+                    return Ok(ERRNO_BADF);
                 }
+                let mut bytes = Vec::new();
+                let memory = vm.default_memory()?;
                 for i in 0..iovs_len {
-                    let iov = iovs + i * 8;
-                    let str_addr: u32 = vm.memory().load(iov)?;
-                    let len: u32 = vm.memory().load(iov + 4)?;
-                    let start = str_addr as usize;
-                    let end = (str_addr + len) as usize;
-                    let s = &vm.memory().data()[start..end];
-                    print!("{}", String::from_utf8(s.to_vec()).unwrap());
-                    nwritten += len;
+                    let iov = iovs.wrapping_add(i * 8);
+                    let buf: u32 = memory.load(iov)?;
+                    let len: u32 = memory.load(iov.wrapping_add(4))?;
+                    let end = buf as usize + len as usize;
+                    let data = memory
+                        .data()
+                        .get(buf as usize..end)
+                        .ok_or_else(|| memory.out_of_bounds(u64::from(buf), len))?;
+                    bytes.extend_from_slice(data);
+                }
+                let result = match vm.import_function_handler_mut().output.clone() {
+                    Some(output) => output.lock().unwrap().write_all(&bytes),
+                    None if fd == 1 => io::stdout().write_all(&bytes),
+                    None => io::stderr().write_all(&bytes),
+                };
+                if result.is_err() {
+                    return Ok(ERRNO_IO);
+                }
+                vm.default_memory_mut()?.store(nwritten_ptr, bytes.len() as u32)?;
+                Ok(ERRNO_SUCCESS)
+            }
+            WasiFunction::FdRead => {
+                let fd: u32 = param(vm, 0)?;
+                let iovs: u32 = param(vm, 1)?;
+                let iovs_len: u32 = param(vm, 2)?;
+                let nread_ptr: u32 = param(vm, 3)?;
+                if fd != 0 {
+                    return Ok(ERRNO_BADF);
+                }
+                let mut nread = 0;
+                for i in 0..iovs_len {
+                    let iov = iovs.wrapping_add(i * 8);
+                    let memory = vm.default_memory()?;
+                    let buf: u32 = memory.load(iov)?;
+                    let len: u32 = memory.load(iov.wrapping_add(4))?;
+                    let handler = vm.import_function_handler_mut();
+                    let start = handler.input_cursor;
+                    let end = handler.input.len().min(start + len as usize);
+                    let bytes = handler.input[start..end].to_vec();
+                    write_bytes(vm, buf, &bytes)?;
+                    vm.import_function_handler_mut().input_cursor = end;
+                    nread += bytes.len() as u32;
+                    if bytes.len() < len as usize {
+                        break;
+                    }
+                }
+                vm.default_memory_mut()?.store(nread_ptr, nread)?;
+                Ok(ERRNO_SUCCESS)
+            }
+            WasiFunction::ProcExit => Err(Trap::ProcExit(param(vm, 0)?)),
+            WasiFunction::ArgsSizesGet => {
+                let args = vm.import_function_handler_mut().args.clone();
+                write_sizes(vm, &args)
+            }
+            WasiFunction::ArgsGet => {
+                let args = vm.import_function_handler_mut().args.clone();
+                write_string_list(vm, &args)
+            }
+            WasiFunction::EnvironSizesGet => {
+                let env = vm.import_function_handler_mut().env.clone();
+                write_sizes(vm, &env)
+            }
+            WasiFunction::EnvironGet => {
+                let env = vm.import_function_handler_mut().env.clone();
+                write_string_list(vm, &env)
+            }
+            WasiFunction::RandomGet => {
+                let buf: u32 = param(vm, 0)?;
+                let len: u32 = param(vm, 1)?;
+                let memory = vm.default_memory()?;
+                if u64::from(buf) + u64::from(len) > memory.data().len() as u64 {
+                    return Err(memory.out_of_bounds(u64::from(buf), len));
+                }
+                let mut bytes = vec![0; len as usize];
+                for chunk in bytes.chunks_mut(8) {
+                    let random = vm.import_function_handler_mut().next_random().to_le_bytes();
+                    chunk.copy_from_slice(&random[..chunk.len()]);
                 }
-                let errno: u32 = 0;
-                vm.push(errno.into())?;
-                vm.memory_mut().store(nwritten_out, nwritten)?;
+                write_bytes(vm, buf, &bytes)?;
+                Ok(ERRNO_SUCCESS)
+            }
+            WasiFunction::ClockTimeGet => {
+                let clock_id: u32 = param(vm, 0)?;
+                let time_ptr: u32 = param(vm, 2)?;
+                let time = match clock_id {
+                    CLOCK_REALTIME => SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|duration| duration.as_nanos() as u64)
+                        .unwrap_or(0),
+                    CLOCK_MONOTONIC => vm.import_function_handler_mut().monotonic_epoch.0.elapsed().as_nanos() as u64,
+                    _ => return Ok(ERRNO_INVAL),
+                };
+                vm.default_memory_mut()?.store(time_ptr, time)?;
+                Ok(ERRNO_SUCCESS)
             }
-            WasiFunction::FdClose => (),
-            WasiFunction::PathOpen => (),
-            WasiFunction::ProcExit => return Err(Trap::WasiExit(vm.pop_as()?)),
-            WasiFunction::EnvironSizesGet => (),
-            WasiFunction::EnvironGet => (),
-            WasiFunction::ArgsSizesGet => (),
-            WasiFunction::ArgsGet => (),
-            WasiFunction::RandomGet => (),
         }
+    }
+}
+
+fn param<T: Number>(vm: &VM<WasiImportFunctionHandler>, index: usize) -> VMResult<T> {
+    let val = *vm
+        .locals()?
+        .get(index)
+        .ok_or_else(|| Trap::UnsupportedCallToImportedFunction(vm.ip().func_index))?;
+    val.to::<T>().ok_or_else(|| Trap::TypeError {
+        expected: T::value_type(),
+        found: val.type_name(),
+    })
+}
+
+fn write_bytes(vm: &mut VM<WasiImportFunctionHandler>, address: u32, bytes: &[u8]) -> VMResult<()> {
+    let memory = vm.default_memory_mut()?;
+    if address as usize + bytes.len() > memory.data().len() {
+        return Err(memory.out_of_bounds(u64::from(address), bytes.len() as u32));
+    }
+    memory.data_mut()[address as usize..address as usize + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+fn write_sizes(vm: &mut VM<WasiImportFunctionHandler>, strings: &[String]) -> VMResult<u32> {
+    let count_ptr: u32 = param(vm, 0)?;
+    let buf_size_ptr: u32 = param(vm, 1)?;
+    let buf_size: usize = strings.iter().map(|s| s.len() + 1).sum();
+    let memory = vm.default_memory_mut()?;
+    memory.store(count_ptr, strings.len() as u32)?;
+    memory.store(buf_size_ptr, buf_size as u32)?;
+    Ok(ERRNO_SUCCESS)
+}
+
+fn write_string_list(vm: &mut VM<WasiImportFunctionHandler>, strings: &[String]) -> VMResult<u32> {
+    let ptrs: u32 = param(vm, 0)?;
+    let mut buf: u32 = param(vm, 1)?;
+    for (i, string) in strings.iter().enumerate() {
+        vm.default_memory_mut()?.store(ptrs.wrapping_add(i as u32 * 4), buf)?;
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.push(0);
+        write_bytes(vm, buf, &bytes)?;
+        buf = buf.wrapping_add(bytes.len() as u32);
+    }
+    Ok(ERRNO_SUCCESS)
+}
+
+/// Collects the output written to it. Clones share the buffer.
+#[derive(Clone, Default)]
+pub struct BufferSink(Arc<Mutex<Vec<u8>>>);
+
+impl BufferSink {
+    /// Takes the output written so far, replacing invalid UTF-8.
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for BufferSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
+
+/// The start of `CLOCK_MONOTONIC`: when the handler was created.
+#[derive(Clone, Copy)]
+struct MonotonicEpoch(Instant);
+
+impl Default for MonotonicEpoch {
+    fn default() -> Self {
+        MonotonicEpoch(Instant::now())
+    }
+}
+
+/// Services WASI preview1 imports. Unknown imports trap with `UnsupportedCallToImportedFunction`.
+///
+/// `random_get` is backed by a deterministic generator so runs can be reproduced.
+/// Clones share the output sink but read stdin independently.
+#[derive(Default, Clone)]
+pub struct WasiImportFunctionHandler {
+    args: Vec<String>,
+    env: Vec<String>,
+    random_state: u64,
+    output: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+    captured_output: Option<BufferSink>,
+    input: Vec<u8>,
+    input_cursor: usize,
+    monotonic_epoch: MonotonicEpoch,
+}
+
+impl WasiImportFunctionHandler {
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// Sets the environment as `KEY=VALUE` strings.
+    pub fn set_env(&mut self, env: Vec<String>) {
+        self.env = env;
+    }
+
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random_state = seed;
+    }
+
+    /// Writes what the program writes to stdout and stderr to `sink` instead.
+    pub fn set_output_sink(&mut self, sink: Box<dyn Write + Send>) {
+        self.output = Some(Arc::new(Mutex::new(sink)));
+        self.captured_output = None;
+    }
+
+    /// Writes the output of the program into a buffer that `take_output` drains.
+    pub fn capture_output(&mut self) {
+        let buffer = BufferSink::default();
+        self.set_output_sink(Box::new(buffer.clone()));
+        self.captured_output = Some(buffer);
+    }
+
+    /// The output captured since the last call. Empty if the output isn't captured.
+    pub fn take_output(&self) -> String {
+        self.captured_output.as_ref().map(BufferSink::take).unwrap_or_default()
+    }
+
+    /// Makes `fd_read` on stdin return `data`, followed by EOF.
+    pub fn set_input(&mut self, data: Vec<u8>) {
+        self.input = data;
+        self.input_cursor = 0;
+    }
+
+    /// Shares the output sink of `other`.
+    pub(crate) fn share_io(&mut self, other: &Self) {
+        self.output = other.output.clone();
+        self.captured_output = other.captured_output.clone();
+    }
+
+    // splitmix64
+    fn next_random(&mut self) -> u64 {
+        self.random_state = self.random_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn call_wasi_function(vm: &mut VM<Self>) -> VMResult<()> {
+        let func_index = vm.ip().func_index;
+        let function = WasiFunction::from_func_index(vm.module(), func_index)
+            .ok_or(Trap::UnsupportedCallToImportedFunction(func_index))?;
+        let errno = function.handle(vm)?;
+        vm.push(Value::from(errno))
+    }
+}
+
+impl ImportFunctionHandler for WasiImportFunctionHandler {
+    fn handle_import_function(vm: &mut VM<Self>) -> ImportFuture<'_> {
+        Box::pin(future::ready(Self::call_wasi_function(vm)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::WasiDebugger;
+
+    fn wasi_module(import: &str, import_type: Vec<u8>, code: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut import_entry = name("wasi_snapshot_preview1");
+        import_entry.extend(name(import));
+        import_entry.extend(&[0x00, 0x00]);
+        let mut data_segment = vec![0x00, 0x41, 0x00, 0x0b];
+        data_segment.extend(leb(data.len() as u32));
+        data_segment.extend_from_slice(data);
+        module(&[
+            section(TYPE_SECTION, &vector(&[import_type, func_type(&[], &[])])),
+            section(IMPORT_SECTION, &vector(&[import_entry])),
+            section(FUNCTION_SECTION, &vector(&[leb(1)])),
+            section(MEMORY_SECTION, &vector(&[vec![0x00, 0x01]])),
+            section(START_SECTION, &leb(1)),
+            section(CODE_SECTION, &vector(&[func_body(&[], code)])),
+            section(DATA_SECTION, &vector(&[data_segment])),
+        ])
+    }
+
+    #[test]
+    fn fd_write_hello() {
+        // iov { buf: 8, len: 5 } at address 0, "hello" at address 8
+        let data = [8, 0, 0, 0, 5, 0, 0, 0, b'h', b'e', b'l', b'l', b'o'];
+        // fd_write(1, 0, 1, 16) followed by drop
+        let code = [0x41, 0x01, 0x41, 0x00, 0x41, 0x01, 0x41, 0x10, 0x10, 0x00, 0x1a, 0x0b];
+        let bytes = wasi_module("fd_write", func_type(&[I32, I32, I32, I32], &[I32]), &code, &data);
+
+        let mut dbg: WasiDebugger = load_with(&bytes);
+        dbg.capture_output();
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+        assert_eq!(dbg.memory().unwrap().load::<u32>(16).unwrap(), 5);
+        assert_eq!(dbg.take_output(), "hello");
+        assert_eq!(dbg.take_output(), "");
+
+        let sink = BufferSink::default();
+        dbg.set_output_sink(Box::new(sink.clone()));
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+        assert_eq!(sink.take(), "hello");
+        assert_eq!(dbg.take_output(), "");
+    }
+
+    #[test]
+    fn proc_exit() {
+        let code = [0x41, 0x03, 0x10, 0x00, 0x0b];
+        let bytes = wasi_module("proc_exit", func_type(&[I32], &[]), &code, &[]);
+
+        let mut dbg: WasiDebugger = load_with(&bytes);
+        assert_eq!(dbg.run().unwrap(), Trap::ProcExit(3));
+    }
+
+    #[test]
+    fn fd_read_stdin() {
+        // iov { buf: 16, len: 8 } at address 0
+        let data = [16, 0, 0, 0, 8, 0, 0, 0];
+        // fd_read(0, 0, 1, 32), drop, fd_read(0, 0, 1, 36), drop
+        let code = [
+            0x41, 0x00, 0x41, 0x00, 0x41, 0x01, 0x41, 0x20, 0x10, 0x00, 0x1a, 0x41, 0x00, 0x41, 0x00, 0x41, 0x01, 0x41,
+            0x24, 0x10, 0x00, 0x1a, 0x0b,
+        ];
+        let bytes = wasi_module("fd_read", func_type(&[I32, I32, I32, I32], &[I32]), &code, &data);
+
+        let mut dbg: WasiDebugger = load_with(&bytes);
+        dbg.set_input(b"hi".to_vec());
+        for _ in 0..2 {
+            assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+            let memory = dbg.memory().unwrap();
+            assert_eq!(&memory.data()[16..18], b"hi");
+            assert_eq!(memory.load::<u32>(32).unwrap(), 2);
+            assert_eq!(memory.load::<u32>(36).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn random_get_out_of_bounds() {
+        // random_get(0, 0xffff_ffff), drop
+        let code = [0x41, 0x00, 0x41, 0x7f, 0x10, 0x00, 0x1a, 0x0b];
+        let bytes = wasi_module("random_get", func_type(&[I32, I32], &[I32]), &code, &[]);
+
+        let mut dbg: WasiDebugger = load_with(&bytes);
+        assert!(matches!(
+            dbg.run().unwrap(),
+            Trap::MemoryAccessOutOfBounds {
+                addr: 0,
+                size: u32::MAX,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn monotonic_clock() {
+        // clock_time_get(CLOCK_MONOTONIC, 0, 8), drop
+        let code = [0x41, 0x01, 0x42, 0x00, 0x41, 0x08, 0x10, 0x00, 0x1a, 0x0b];
+        let bytes = wasi_module("clock_time_get", func_type(&[I32, I64, I32], &[I32]), &code, &[]);
+
+        let mut dbg: WasiDebugger = load_with(&bytes);
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+        let first = dbg.memory().unwrap().load::<u64>(8).unwrap();
+        assert_eq!(dbg.run().unwrap(), Trap::ExecutionFinished);
+        let second = dbg.memory().unwrap().load::<u64>(8).unwrap();
+        // Counted from the creation of the handler, not from the Unix epoch
+        assert!(first <= second && second < 3_600_000_000_000);
+    }
+}
@@ -2,4 +2,4 @@ mod nan_preserving_float;
 pub mod value;
 
 pub use nan_preserving_float::{F32, F64};
-pub use value::Value;
+pub use value::{ArithmeticError, ArithmeticResult, DisplayMode, Endianness, Value};
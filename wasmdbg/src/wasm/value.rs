@@ -1,17 +1,70 @@
+use std::cell::Cell;
+use std::cmp::Ordering;
 use std::fmt;
 use std::str::FromStr;
 
 use bwasm::ValueType;
+use thiserror::Error;
 
 use crate::vm::{Trap, VMResult};
 use crate::{F32, F64};
 
+/// How `Value::format` renders a value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisplayMode {
+    /// The `Display` output: type, hex and decimal.
+    Detailed,
+    Decimal,
+    /// Two's complement for integers, the raw bits for floats.
+    Hex,
+    /// Two's complement for integers, the raw bits for floats.
+    Binary,
+    /// Integers are reinterpreted as floats of the same width.
+    Float,
+    /// An i32 as a unicode scalar value. Falls back to `Hex` for anything else.
+    Char,
+}
+
+impl Default for DisplayMode {
+    fn default() -> Self {
+        DisplayMode::Detailed
+    }
+}
+
+/// The byte order memory is displayed in. The VM itself is always little-endian.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Default for Endianness {
+    fn default() -> Self {
+        Endianness::Little
+    }
+}
+
+/// Why `Value::add` and the other arithmetic methods failed.
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum ArithmeticError {
+    #[error("Operands have different types: {lhs} and {rhs}")]
+    TypeMismatch { lhs: &'static str, rhs: &'static str },
+    #[error("Arithmetic on v128 values isn't supported")]
+    UnsupportedV128,
+    #[error("{0}")]
+    Trap(#[from] Trap),
+}
+
+pub type ArithmeticResult = Result<Value, ArithmeticError>;
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Value {
     I32(i32),
     I64(i64),
     F32(F32),
     F64(F64),
+    /// The bytes in memory order. The VM doesn't execute SIMD instructions yet.
+    V128([u8; 16]),
 }
 
 impl Value {
@@ -24,13 +77,42 @@ impl Value {
         }
     }
 
-    pub fn value_type(&self) -> ValueType {
+    /// `None` for `V128` because `bwasm::ValueType` has no v128 type. Use `type_name` to show
+    /// the type of any value.
+    pub fn value_type(&self) -> Option<ValueType> {
+        match self {
+            Value::I32(_) => Some(ValueType::I32),
+            Value::I64(_) => Some(ValueType::I64),
+            Value::F32(_) => Some(ValueType::F32),
+            Value::F64(_) => Some(ValueType::F64),
+            Value::V128(_) => None,
+        }
+    }
+
+    /// The name of the type as written in the text format, e.g. `"i32"` or `"v128"`.
+    pub fn type_name(&self) -> &'static str {
         match self {
-            Value::I32(_) => ValueType::I32,
-            Value::I64(_) => ValueType::I64,
-            Value::F32(_) => ValueType::F32,
-            Value::F64(_) => ValueType::F64,
+            Value::I32(_) => "i32",
+            Value::I64(_) => "i64",
+            Value::F32(_) => "f32",
+            Value::F64(_) => "f64",
+            Value::V128(_) => "v128",
+        }
+    }
+
+    pub fn is_v128(&self) -> bool {
+        matches!(self, Value::V128(_))
+    }
+
+    /// Parses a v128 from up to 32 hex digits, optionally prefixed with `0x`.
+    /// The number is stored little endian, like a `v128.const` immediate.
+    pub fn parse_v128(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+        if digits.starts_with('+') {
+            return None;
         }
+        Some(Value::V128(u128::from_str_radix(digits, 16).ok()?.to_le_bytes()))
     }
 
     pub fn to<T: Number>(&self) -> Option<T> {
@@ -41,31 +123,466 @@ impl Value {
         Some(match value_type {
             ValueType::I32 => Value::I32(i64::from_str(s).ok()? as i32),
             ValueType::I64 => Value::I64(i128::from_str(s).ok()? as i64),
-            ValueType::F32 => Value::from(f32::from_str(s).ok()?),
-            ValueType::F64 => Value::from(f64::from_str(s).ok()?),
+            ValueType::F32 => Value::F32(F32::from_bits(parse_float_bits(s, 32)? as u32)),
+            ValueType::F64 => Value::F64(F64::from_bits(parse_float_bits(s, 64)?)),
+        })
+    }
+
+    /// Parses a literal whose type isn't known, inferring the type from its form:
+    /// - A decimal point, an exponent, `inf`, `infinity` or `nan` make an `f64`.
+    /// - Everything else, including `0x`, `0o` and `0b` prefixed literals, is an integer.
+    ///   It becomes an `i32` if it fits into 32 bits (signed or unsigned) and an `i64` if it
+    ///   fits into 64 bits. Larger integers are rejected.
+    pub fn parse_inferred(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let magnitude = s.strip_prefix(|c| c == '-' || c == '+').unwrap_or(s);
+        let has_radix_prefix = matches!(
+            magnitude.get(..2).map(|prefix| prefix.to_lowercase()).as_deref(),
+            Some("0x") | Some("0o") | Some("0b")
+        );
+        let is_float = !has_radix_prefix
+            && (magnitude.contains(|c| c == '.' || c == 'e' || c == 'E')
+                || ["inf", "infinity"]
+                    .iter()
+                    .any(|special| magnitude.eq_ignore_ascii_case(special))
+                || magnitude.get(..3).map_or(false, |nan| nan.eq_ignore_ascii_case("nan")));
+        if is_float {
+            return Some(Value::F64(F64::from_bits(parse_float_bits(s, 64)?)));
+        }
+
+        let val = parse_integer(s)?;
+        if val >= i128::from(i32::MIN) && val <= i128::from(u32::MAX) {
+            Some(Value::I32(val as i32))
+        } else if val >= i128::from(i64::MIN) && val <= i128::from(u64::MAX) {
+            Some(Value::I64(val as i64))
+        } else {
+            None
+        }
+    }
+
+    /// Parses a literal with an explicit type, `TYPE:LITERAL`:
+    /// - `i32` and `i64` take an optionally signed integer in decimal or with a `0x`, `0o` or `0b`
+    ///   prefix. It must fit into the type as either a signed or an unsigned number.
+    /// - `f32` and `f64` take the float literals accepted by `from_str`.
+    /// - `v128` takes hex digits as described in `parse_v128`.
+    ///
+    /// Returns `None` for unknown types and invalid literals.
+    pub fn parse_typed(s: &str) -> Option<Self> {
+        let (prefix, literal) = s.trim().split_once(':')?;
+        let literal = literal.trim();
+        Some(match prefix.trim() {
+            "i32" => {
+                let val = parse_integer(literal)?;
+                if val < i128::from(i32::MIN) || val > i128::from(u32::MAX) {
+                    return None;
+                }
+                Value::I32(val as i32)
+            }
+            "i64" => {
+                let val = parse_integer(literal)?;
+                if val < i128::from(i64::MIN) || val > i128::from(u64::MAX) {
+                    return None;
+                }
+                Value::I64(val as i64)
+            }
+            "f32" => Value::F32(F32::from_bits(parse_float_bits(literal, 32)? as u32)),
+            "f64" => Value::F64(F64::from_bits(parse_float_bits(literal, 64)?)),
+            "v128" => Value::parse_v128(literal)?,
+            _ => return None,
+        })
+    }
+}
+
+const fn mantissa_bits(width: u32) -> u32 {
+    if width == 32 {
+        23
+    } else {
+        52
+    }
+}
+
+/// Parses a float of `width` bits to its bit pattern. Accepts what `f64::from_str` accepts and
+/// NaNs as `[+-]nan` or `[+-]nan:0xPAYLOAD` like the wasm text format, keeping sign and payload.
+fn parse_float_bits(s: &str, width: u32) -> Option<u64> {
+    let s = s.trim();
+    let magnitude = s.strip_prefix(|c| c == '-' || c == '+').unwrap_or(s);
+    if magnitude.get(..3).map_or(false, |nan| nan.eq_ignore_ascii_case("nan")) {
+        let mantissa_bits = mantissa_bits(width);
+        let payload = match &magnitude[3..] {
+            "" => 1 << (mantissa_bits - 1),
+            payload => {
+                let digits = payload.strip_prefix(":0x")?;
+                if digits.starts_with('+') {
+                    return None;
+                }
+                u64::from_str_radix(digits, 16).ok()?
+            }
+        };
+        if payload == 0 || payload >> mantissa_bits != 0 {
+            return None;
+        }
+        let sign = u64::from(s.starts_with('-')) << (width - 1);
+        let exponent = ((1 << (width - 1 - mantissa_bits)) - 1) << mantissa_bits;
+        return Some(sign | exponent | payload);
+    }
+    if width == 32 {
+        Some(u64::from(f32::from_str(s).ok()?.to_bits()))
+    } else {
+        Some(f64::from_str(s).ok()?.to_bits())
+    }
+}
+
+/// `inf` or `nan` with the sign, and the payload if it isn't the canonical one.
+/// `None` for finite floats.
+fn special_float_text(bits: u64, width: u32) -> Option<String> {
+    let mantissa_bits = mantissa_bits(width);
+    let exponent_mask = ((1 << (width - 1 - mantissa_bits)) - 1) << mantissa_bits;
+    if bits & exponent_mask != exponent_mask {
+        return None;
+    }
+    let sign = if (bits >> (width - 1)) & 1 == 1 { "-" } else { "" };
+    let payload = bits & ((1 << mantissa_bits) - 1);
+    Some(if payload == 0 {
+        format!("{}inf", sign)
+    } else if payload == 1 << (mantissa_bits - 1) {
+        format!("{}nan", sign)
+    } else {
+        format!("{}nan:0x{:x}", sign, payload)
+    })
+}
+
+/// An optionally signed integer with an optional radix prefix after the sign.
+fn parse_integer(s: &str) -> Option<i128> {
+    let (negative, magnitude) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if magnitude.starts_with(|c| c == '-' || c == '+') {
+        return None;
+    }
+    let magnitude = i128::from_str_with_radix(magnitude).ok()?;
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+thread_local! {
+    static PADDED_HEX: Cell<bool> = Cell::new(true);
+}
+
+impl Value {
+    /// Whether `Display` pads hex numbers with zeros to the width of their type on the current
+    /// thread. Enabled by default.
+    pub fn set_padded_hex(padded: bool) {
+        PADDED_HEX.with(|padded_hex| padded_hex.set(padded));
+    }
+
+    /// Compares values of the same type, integers as signed numbers. Returns `None` for values of
+    /// different types, if either float is NaN and for unequal v128 values, which have no order.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (*self, *other) {
+            (Value::I32(a), Value::I32(b)) => Some(a.cmp(&b)),
+            (Value::I64(a), Value::I64(b)) => Some(a.cmp(&b)),
+            (Value::F32(a), Value::F32(b)) => a.to_float().partial_cmp(&b.to_float()),
+            (Value::F64(a), Value::F64(b)) => a.to_float().partial_cmp(&b.to_float()),
+            (Value::V128(a), Value::V128(b)) if a == b => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+
+    /// Wraps on integer overflow like wasm. Both operands must have the same type.
+    pub fn add(&self, other: &Value) -> ArithmeticResult {
+        self.arithmetic(
+            other,
+            |a, b| Ok(a.wrapping_add(b)),
+            |a, b| Ok(a.wrapping_add(b)),
+            |a, b| a + b,
+            |a, b| a + b,
+        )
+    }
+
+    /// Wraps on integer overflow like wasm. Both operands must have the same type.
+    pub fn sub(&self, other: &Value) -> ArithmeticResult {
+        self.arithmetic(
+            other,
+            |a, b| Ok(a.wrapping_sub(b)),
+            |a, b| Ok(a.wrapping_sub(b)),
+            |a, b| a - b,
+            |a, b| a - b,
+        )
+    }
+
+    /// Wraps on integer overflow like wasm. Both operands must have the same type.
+    pub fn mul(&self, other: &Value) -> ArithmeticResult {
+        self.arithmetic(
+            other,
+            |a, b| Ok(a.wrapping_mul(b)),
+            |a, b| Ok(a.wrapping_mul(b)),
+            |a, b| a * b,
+            |a, b| a * b,
+        )
+    }
+
+    /// Signed integer division, which traps like `div_s` on a zero divisor and on overflow.
+    pub fn div(&self, other: &Value) -> ArithmeticResult {
+        self.arithmetic(other, Integer::div, Integer::div, |a, b| a / b, |a, b| a / b)
+    }
+
+    /// Signed integer remainder, which traps like `rem_s` on a zero divisor.
+    pub fn rem(&self, other: &Value) -> ArithmeticResult {
+        self.arithmetic(other, Integer::rem, Integer::rem, |a, b| a % b, |a, b| a % b)
+    }
+
+    fn arithmetic(
+        &self,
+        other: &Value,
+        i32_op: impl FnOnce(i32, i32) -> VMResult<i32>,
+        i64_op: impl FnOnce(i64, i64) -> VMResult<i64>,
+        f32_op: impl FnOnce(F32, F32) -> F32,
+        f64_op: impl FnOnce(F64, F64) -> F64,
+    ) -> ArithmeticResult {
+        Ok(match (*self, *other) {
+            (Value::I32(a), Value::I32(b)) => Value::I32(i32_op(a, b)?),
+            (Value::I64(a), Value::I64(b)) => Value::I64(i64_op(a, b)?),
+            (Value::F32(a), Value::F32(b)) => Value::F32(f32_op(a, b)),
+            (Value::F64(a), Value::F64(b)) => Value::F64(f64_op(a, b)),
+            (Value::V128(_), _) | (_, Value::V128(_)) => return Err(ArithmeticError::UnsupportedV128),
+            (a, b) => {
+                return Err(ArithmeticError::TypeMismatch {
+                    lhs: a.type_name(),
+                    rhs: b.type_name(),
+                })
+            }
+        })
+    }
+
+    /// Both `0.0` and `-0.0` are zero.
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Value::I32(val) => val == 0,
+            Value::I64(val) => val == 0,
+            Value::F32(val) => val.to_float() == 0.0,
+            Value::F64(val) => val.to_float() == 0.0,
+            Value::V128(bytes) => bytes == [0; 16],
+        }
+    }
+
+    /// The same bits as a value of `value_type`. `None` if the widths differ.
+    pub fn reinterpret(&self, value_type: ValueType) -> Option<Value> {
+        Some(match (*self, value_type) {
+            (Value::I32(val), ValueType::F32) => Value::F32(F32::from_bits(val as u32)),
+            (Value::F32(val), ValueType::I32) => Value::I32(val.to_bits() as i32),
+            (Value::I64(val), ValueType::F64) => Value::F64(F64::from_bits(val as u64)),
+            (Value::F64(val), ValueType::I64) => Value::I64(val.to_bits() as i64),
+            (val, value_type) if val.value_type() == Some(value_type) => val,
+            _ => return None,
+        })
+    }
+
+    /// The bits as hex, zero-padded to the width of the type if `padded` is set.
+    pub fn to_hex(&self, padded: bool) -> String {
+        let (bits, digits) = match *self {
+            Value::I32(val) => (u128::from(val as u32), 8),
+            Value::I64(val) => (u128::from(val as u64), 16),
+            Value::F32(val) => (u128::from(val.to_bits()), 8),
+            Value::F64(val) => (u128::from(val.to_bits()), 16),
+            Value::V128(bytes) => (u128::from_le_bytes(bytes), 32),
+        };
+        if padded {
+            format!("0x{:0width$x}", bits, width = digits)
+        } else {
+            format!("0x{:x}", bits)
+        }
+    }
+
+    /// The value with its bytes in reverse order. A `V128` is reversed as a whole.
+    pub fn byte_swapped(self) -> Value {
+        fn swap<T: LittleEndianConvert>(val: T) -> T {
+            let mut buffer = vec![0; std::mem::size_of::<T>()];
+            val.to_little_endian(&mut buffer);
+            buffer.reverse();
+            T::from_little_endian(&buffer)
+        }
+        match self {
+            Value::I32(val) => Value::I32(swap(val)),
+            Value::I64(val) => Value::I64(swap(val)),
+            Value::F32(val) => Value::F32(swap(val)),
+            Value::F64(val) => Value::F64(swap(val)),
+            Value::V128(mut bytes) => {
+                bytes.reverse();
+                Value::V128(bytes)
+            }
+        }
+    }
+
+    pub fn format(&self, mode: DisplayMode) -> String {
+        match (mode, *self) {
+            (DisplayMode::Detailed, _) => self.to_string(),
+            (DisplayMode::Hex, Value::V128(_)) | (DisplayMode::Char, Value::V128(_)) => self.to_hex(true),
+            (DisplayMode::Binary, Value::V128(bytes)) => format!("0b{:0128b}", u128::from_le_bytes(bytes)),
+            (_, Value::V128(_)) => self.to_string(),
+            (DisplayMode::Decimal, Value::I32(val)) => val.to_string(),
+            (DisplayMode::Decimal, Value::I64(val)) => val.to_string(),
+            (DisplayMode::Decimal, Value::F32(val)) | (DisplayMode::Float, Value::F32(val)) => {
+                val.to_float().to_string()
+            }
+            (DisplayMode::Decimal, Value::F64(val)) | (DisplayMode::Float, Value::F64(val)) => {
+                val.to_float().to_string()
+            }
+            (DisplayMode::Hex, _) => self.to_hex(true),
+            (DisplayMode::Binary, Value::I32(val)) => format!("0b{:032b}", val),
+            (DisplayMode::Binary, Value::I64(val)) => format!("0b{:064b}", val),
+            (DisplayMode::Binary, Value::F32(val)) => format!("0b{:032b}", val.to_bits()),
+            (DisplayMode::Binary, Value::F64(val)) => format!("0b{:064b}", val.to_bits()),
+            (DisplayMode::Float, Value::I32(val)) => f32::from_bits(val as u32).to_string(),
+            (DisplayMode::Float, Value::I64(val)) => f64::from_bits(val as u64).to_string(),
+            (DisplayMode::Char, Value::I32(val)) => match std::char::from_u32(val as u32) {
+                Some(c) => format!("{:?}", c),
+                None => self.format(DisplayMode::Hex),
+            },
+            (DisplayMode::Char, _) => self.format(DisplayMode::Hex),
+        }
+    }
+}
+
+/// Serializes as `{"type": "i32", "value": 42}`. Floats additionally have their bits as a hex
+/// string in `bits` since JSON numbers can't represent every float exactly, NaNs in particular.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let is_float = matches!(self, Value::F32(_) | Value::F64(_));
+        let mut state = serializer.serialize_struct("Value", if is_float { 3 } else { 2 })?;
+        match *self {
+            Value::I32(val) => {
+                state.serialize_field("type", "i32")?;
+                state.serialize_field("value", &val)?;
+            }
+            Value::I64(val) => {
+                state.serialize_field("type", "i64")?;
+                state.serialize_field("value", &val)?;
+            }
+            Value::F32(val) => {
+                state.serialize_field("type", "f32")?;
+                state.serialize_field("value", &val.to_float())?;
+                state.serialize_field("bits", &format!("0x{:08x}", val.to_bits()))?;
+            }
+            Value::F64(val) => {
+                state.serialize_field("type", "f64")?;
+                state.serialize_field("value", &val.to_float())?;
+                state.serialize_field("bits", &format!("0x{:016x}", val.to_bits()))?;
+            }
+            Value::V128(bytes) => {
+                state.serialize_field("type", "v128")?;
+                state.serialize_field("value", &format!("0x{:032x}", u128::from_le_bytes(bytes)))?;
+            }
+        }
+        state.end()
+    }
+}
+
+/// Reads the format written by `Serialize`. Floats are taken from `bits` if it is present.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Value {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        use std::convert::TryFrom;
+
+        #[derive(serde::Deserialize)]
+        struct RawValue {
+            #[serde(rename = "type")]
+            value_type: String,
+            value: serde_json::Value,
+            bits: Option<String>,
+        }
+
+        let raw = RawValue::deserialize(deserializer)?;
+        let invalid = || D::Error::custom(format!("invalid {} value {}", raw.value_type, raw.value));
+        let bits = |digits: u32| -> Result<Option<u64>, D::Error> {
+            match &raw.bits {
+                Some(bits) => {
+                    let hex = bits.trim_start_matches("0x");
+                    if hex.len() > digits as usize {
+                        return Err(invalid());
+                    }
+                    u64::from_str_radix(hex, 16).map(Some).map_err(|_| invalid())
+                }
+                None => Ok(None),
+            }
+        };
+        Ok(match raw.value_type.as_str() {
+            "i32" => Value::I32(
+                raw.value
+                    .as_i64()
+                    .and_then(|value| i32::try_from(value).ok())
+                    .ok_or_else(invalid)?,
+            ),
+            "i64" => Value::I64(raw.value.as_i64().ok_or_else(invalid)?),
+            "f32" => Value::F32(match bits(8)? {
+                Some(bits) => F32::from_bits(bits as u32),
+                None => F32::from(raw.value.as_f64().ok_or_else(invalid)? as f32),
+            }),
+            "f64" => Value::F64(match bits(16)? {
+                Some(bits) => F64::from_bits(bits),
+                None => F64::from(raw.value.as_f64().ok_or_else(invalid)?),
+            }),
+            "v128" => raw.value.as_str().and_then(Value::parse_v128).ok_or_else(invalid)?,
+            other => return Err(D::Error::custom(format!("unknown value type {}", other))),
         })
     }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = self.to_hex(PADDED_HEX.with(Cell::get));
         match *self {
             Value::I32(val) => {
-                if (val as i32) < 0 {
-                    write!(f, "i32 : 0x{0:08x} = {0} = {1}", val, val as i32)
+                if val < 0 {
+                    write!(f, "i32 : {} = {} = {}", hex, val as u32, val)
                 } else {
-                    write!(f, "i32 : 0x{0:08x} = {0}", val)
+                    write!(f, "i32 : {} = {}", hex, val)
                 }
             }
             Value::I64(val) => {
-                if (val as i64) < 0 {
-                    write!(f, "i64 : 0x{0:016x} = {0} = {1}", val, val as i64)
+                if val < 0 {
+                    write!(f, "i64 : {} = {} = {}", hex, val as u64, val)
                 } else {
-                    write!(f, "i64 : 0x{0:016x} = {0}", val)
+                    write!(f, "i64 : {} = {}", hex, val)
+                }
+            }
+            Value::F32(val) => match special_float_text(u64::from(val.to_bits()), 32) {
+                Some(text) => write!(f, "f32 : {} = {}", hex, text),
+                None => write!(f, "f32 : {} ~ {:.8}", hex, val.to_float()),
+            },
+            Value::F64(val) => match special_float_text(val.to_bits(), 64) {
+                Some(text) => write!(f, "f64 : {} = {}", hex, text),
+                None => write!(f, "f64 : {} ~ {:.16}", hex, val.to_float()),
+            },
+            Value::V128(bytes) => {
+                write!(f, "v128 :")?;
+                for byte in &bytes {
+                    write!(f, " {:02x}", byte)?;
                 }
+                let bytes = &bytes[..];
+                let lanes = move |size: usize| {
+                    bytes.chunks(size).map(move |lane| {
+                        let mut buf = [0; 8];
+                        buf[..size].copy_from_slice(lane);
+                        u64::from_le_bytes(buf)
+                    })
+                };
+                let i32x4: Vec<_> = lanes(4).map(|lane| (lane as u32 as i32).to_string()).collect();
+                let i64x2: Vec<_> = lanes(8).map(|lane| (lane as i64).to_string()).collect();
+                let f32x4: Vec<_> = lanes(4).map(|lane| f32::from_bits(lane as u32).to_string()).collect();
+                let f64x2: Vec<_> = lanes(8).map(|lane| f64::from_bits(lane).to_string()).collect();
+                write!(
+                    f,
+                    " = i32x4 [{}] = i64x2 [{}] ~ f32x4 [{}] ~ f64x2 [{}]",
+                    i32x4.join(", "),
+                    i64x2.join(", "),
+                    f32x4.join(", "),
+                    f64x2.join(", ")
+                )
             }
-            Value::F32(val) => write!(f, "f32 : 0x{:08x} ~ {:.8}", val.to_bits(), val.to_float()),
-            Value::F64(val) => write!(f, "f64 : 0x{:016x} ~ {:.16}", val.to_bits(), val.to_float()),
         }
     }
 }
@@ -100,6 +617,11 @@ impl From<f64> for Value {
         Value::F64(F64::from(val))
     }
 }
+impl From<[u8; 16]> for Value {
+    fn from(val: [u8; 16]) -> Self {
+        Value::V128(val)
+    }
+}
 impl From<F32> for Value {
     fn from(val: F32) -> Self {
         Value::F32(val)
@@ -346,3 +868,295 @@ impl_wrap_to!(u32, u16);
 impl_wrap_to!(u64, u8);
 impl_wrap_to!(u64, u16);
 impl_wrap_to!(u64, u32);
+
+#[cfg(test)]
+mod tests {
+    use super::{DisplayMode, Value};
+    use crate::{F32, F64};
+    use bwasm::ValueType;
+
+    #[test]
+    fn parse_inferred() {
+        assert_eq!(Value::parse_inferred("42"), Some(Value::I32(42)));
+        assert_eq!(Value::parse_inferred("-42"), Some(Value::I32(-42)));
+        assert_eq!(Value::parse_inferred("4294967295"), Some(Value::I32(-1)));
+        assert_eq!(Value::parse_inferred("4294967296"), Some(Value::I64(4_294_967_296)));
+        assert_eq!(
+            Value::parse_inferred("3.14"),
+            Some(Value::from("3.14".parse::<f64>().unwrap()))
+        );
+        assert_eq!(Value::parse_inferred("0xff"), Some(Value::I32(255)));
+        assert_eq!(Value::parse_inferred("1e10"), Some(Value::from(1e10f64)));
+        assert_eq!(Value::parse_inferred("0x1e10"), Some(Value::I32(0x1e10)));
+        assert_eq!(Value::parse_inferred("18446744073709551616"), None);
+        assert_eq!(Value::parse_inferred("--1"), None);
+        assert_eq!(Value::parse_inferred("abc"), None);
+    }
+
+    #[test]
+    fn parse_typed() {
+        assert_eq!(Value::parse_typed("i32:42"), Some(Value::I32(42)));
+        assert_eq!(Value::parse_typed("i32:-0x10"), Some(Value::I32(-16)));
+        assert_eq!(Value::parse_typed("i32:0xffffffff"), Some(Value::I32(-1)));
+        assert_eq!(Value::parse_typed("i32:0x100000000"), None);
+        assert_eq!(Value::parse_typed("i64:0xff"), Some(Value::I64(255)));
+        assert_eq!(Value::parse_typed("i64:0b101"), Some(Value::I64(5)));
+        assert_eq!(Value::parse_typed("f64:2.5"), Some(Value::from(2.5f64)));
+        assert_eq!(Value::parse_typed("f32:1"), Some(Value::from(1f32)));
+        assert_eq!(Value::parse_typed("v128:0x1"), Value::parse_v128("1"));
+        assert_eq!(Value::parse_typed("u32:1"), None);
+        assert_eq!(Value::parse_typed("42"), None);
+        assert_eq!(Value::parse_typed("i32:abc"), None);
+    }
+
+    #[test]
+    fn special_floats() {
+        let parse = |s, value_type| Value::from_str(s, value_type).unwrap();
+        assert_eq!(parse("nan", ValueType::F32), Value::F32(F32::from_bits(0x7fc0_0000)));
+        assert_eq!(
+            parse("-NaN", ValueType::F64),
+            Value::F64(F64::from_bits(0xfff8_0000_0000_0000))
+        );
+        assert_eq!(parse("-inf", ValueType::F32), Value::from(f32::NEG_INFINITY));
+        assert_eq!(parse("infinity", ValueType::F64), Value::from(f64::INFINITY));
+        // signaling NaN
+        assert_eq!(
+            parse("nan:0x200000", ValueType::F32),
+            Value::F32(F32::from_bits(0x7fa0_0000))
+        );
+        assert_eq!(Value::from_str("nan:0x0", ValueType::F32), None);
+        assert_eq!(Value::from_str("nan:0x800000", ValueType::F32), None);
+        assert_eq!(
+            Value::parse_typed("f64:-nan:0x1"),
+            Some(Value::F64(F64::from_bits(0xfff0_0000_0000_0001)))
+        );
+        assert_eq!(
+            Value::parse_inferred("nan:0x1"),
+            Some(Value::F64(F64::from_bits(0x7ff0_0000_0000_0001)))
+        );
+
+        for (text, value_type) in [
+            ("nan", ValueType::F32),
+            ("-nan", ValueType::F32),
+            ("nan:0x200000", ValueType::F32),
+            ("-inf", ValueType::F32),
+            ("inf", ValueType::F64),
+            ("-nan:0x4", ValueType::F64),
+        ] {
+            let val = parse(text, value_type);
+            let display = val.to_string();
+            assert!(display.ends_with(&format!(" = {}", text)), "{}", display);
+            assert_eq!(parse(display.rsplit(' ').next().unwrap(), value_type), val);
+        }
+        assert_eq!(
+            Value::F32(F32::from_bits(0x7fa0_0000)).to_string(),
+            "f32 : 0x7fa00000 = nan:0x200000"
+        );
+    }
+
+    #[test]
+    fn format_negative_values() {
+        let val = Value::I32(-2);
+        assert_eq!(val.format(DisplayMode::Detailed), val.to_string());
+        assert_eq!(val.format(DisplayMode::Decimal), "-2");
+        assert_eq!(val.format(DisplayMode::Hex), "0xfffffffe");
+        assert_eq!(val.format(DisplayMode::Binary), format!("0b{}0", "1".repeat(31)));
+        assert_eq!(val.format(DisplayMode::Float), "NaN");
+        assert_eq!(val.format(DisplayMode::Char), "0xfffffffe");
+
+        let val = Value::I64(-1);
+        assert_eq!(val.format(DisplayMode::Decimal), "-1");
+        assert_eq!(val.format(DisplayMode::Hex), "0xffffffffffffffff");
+        assert_eq!(val.format(DisplayMode::Binary), format!("0b{}", "1".repeat(64)));
+        assert_eq!(val.format(DisplayMode::Char), "0xffffffffffffffff");
+
+        let val = Value::from(-1.5f32);
+        assert_eq!(val.format(DisplayMode::Decimal), "-1.5");
+        assert_eq!(val.format(DisplayMode::Float), "-1.5");
+        assert_eq!(val.format(DisplayMode::Hex), "0xbfc00000");
+
+        let val = Value::from(-0.5f64);
+        assert_eq!(val.format(DisplayMode::Decimal), "-0.5");
+        assert_eq!(val.format(DisplayMode::Hex), "0xbfe0000000000000");
+        assert_eq!(
+            val.format(DisplayMode::Binary),
+            format!("0b101111111110{}", "0".repeat(52))
+        );
+    }
+
+    #[test]
+    fn format_char() {
+        assert_eq!(Value::I32(0x41).format(DisplayMode::Char), "'A'");
+        assert_eq!(Value::I32(0x1f600).format(DisplayMode::Char), "'\u{1f600}'");
+        // surrogates are no valid chars
+        assert_eq!(Value::I32(0xd800).format(DisplayMode::Char), "0x0000d800");
+    }
+
+    #[test]
+    fn v128() {
+        let val = Value::parse_v128("0x000000020000000000000000ffffffff").unwrap();
+        assert!(val.is_v128());
+        assert_eq!(val.value_type(), None);
+        assert_eq!(val.type_name(), "v128");
+        assert_eq!(
+            val,
+            Value::V128([0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0])
+        );
+        assert_eq!(val.format(DisplayMode::Hex), "0x000000020000000000000000ffffffff");
+        assert!(val
+            .to_string()
+            .starts_with("v128 : ff ff ff ff 00 00 00 00 00 00 00 00 02 00 00 00 = i32x4 [-1, 0, 0, 2]"));
+        assert_eq!(val.to::<i32>(), None);
+        assert_eq!(Value::parse_v128("1"), Some(Value::V128(1u128.to_le_bytes())));
+        assert_eq!(Value::parse_v128("0x1ffffffffffffffffffffffffffffffff"), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let json = |value: Value| serde_json::to_string(&value).unwrap();
+        assert_eq!(json(Value::I32(-1)), r#"{"type":"i32","value":-1}"#);
+        assert_eq!(
+            json(Value::F32(F32::from(2.5))),
+            r#"{"type":"f32","value":2.5,"bits":"0x40200000"}"#
+        );
+        assert_eq!(
+            json(Value::F64(F64::from_bits(0x7ff8_0000_0000_0001))),
+            r#"{"type":"f64","value":null,"bits":"0x7ff8000000000001"}"#
+        );
+        assert_eq!(
+            json(Value::V128(1u128.to_le_bytes())),
+            r#"{"type":"v128","value":"0x00000000000000000000000000000001"}"#
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize() {
+        let values = [
+            Value::I32(-1),
+            Value::I64(i64::MIN),
+            Value::F32(F32::from(2.5)),
+            Value::F64(F64::from_bits(0x7ff8_0000_0000_0001)),
+            Value::V128(1u128.to_le_bytes()),
+        ];
+        for value in &values {
+            let json = serde_json::to_string(value).unwrap();
+            // Compared as JSON since the NaN isn't equal to itself
+            let parsed: Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        }
+        let parse = |json: &str| serde_json::from_str::<Value>(json);
+        assert_eq!(
+            parse(r#"{"type":"f64","value":0.5}"#).unwrap(),
+            Value::F64(F64::from(0.5))
+        );
+        assert!(parse(r#"{"type":"i32","value":4294967296}"#).is_err());
+        assert!(parse(r#"{"type":"i8","value":1}"#).is_err());
+    }
+
+    #[test]
+    fn hex_padding() {
+        assert_eq!(Value::I32(42).to_hex(true), "0x0000002a");
+        assert_eq!(Value::I32(42).to_hex(false), "0x2a");
+        assert_eq!(Value::I64(-1).to_hex(false), "0xffffffffffffffff");
+        assert_eq!(Value::I32(42).to_string(), "i32 : 0x0000002a = 42");
+        assert_eq!(Value::I32(-2).to_string(), "i32 : 0xfffffffe = 4294967294 = -2");
+
+        Value::set_padded_hex(false);
+        assert_eq!(Value::I32(42).to_string(), "i32 : 0x2a = 42");
+        assert_eq!(
+            Value::I64(-2).to_string(),
+            "i64 : 0xfffffffffffffffe = 18446744073709551614 = -2"
+        );
+        assert_eq!(
+            Value::F32(F32::from_float(1.0)).to_string(),
+            "f32 : 0x3f800000 ~ 1.00000000"
+        );
+        assert_eq!(Value::I32(42).format(DisplayMode::Hex), "0x0000002a");
+        Value::set_padded_hex(true);
+    }
+
+    #[test]
+    fn compare() {
+        use std::cmp::Ordering;
+
+        assert_eq!(Value::I32(-1).compare(&Value::I32(1)), Some(Ordering::Less));
+        assert_eq!(Value::I64(5).compare(&Value::I64(5)), Some(Ordering::Equal));
+        assert_eq!(Value::I32(1).compare(&Value::I64(1)), None);
+        assert_eq!(Value::F32(F32::from_float(1.0)).compare(&Value::I32(1)), None);
+        let nan = Value::F64(F64::from_float(f64::NAN));
+        assert_eq!(nan.compare(&nan), None);
+        assert_eq!(
+            Value::F64(F64::from_float(-0.0)).compare(&Value::F64(F64::from_float(0.0))),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            Value::V128([1; 16]).compare(&Value::V128([1; 16])),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(Value::V128([1; 16]).compare(&Value::V128([2; 16])), None);
+    }
+
+    #[test]
+    fn is_zero() {
+        assert!(Value::I32(0).is_zero());
+        assert!(!Value::I64(-1).is_zero());
+        assert!(Value::F32(F32::from_float(-0.0)).is_zero());
+        assert!(!Value::F64(F64::from_float(f64::NAN)).is_zero());
+        assert!(Value::V128([0; 16]).is_zero());
+    }
+
+    #[test]
+    fn arithmetic() {
+        use crate::vm::Trap;
+
+        assert_eq!(Value::I32(i32::MAX).add(&Value::I32(1)), Ok(Value::I32(i32::MIN)));
+        assert_eq!(Value::I64(3).sub(&Value::I64(5)), Ok(Value::I64(-2)));
+        assert_eq!(Value::I32(0x10000).mul(&Value::I32(0x10000)), Ok(Value::I32(0)));
+        assert_eq!(Value::I32(-7).div(&Value::I32(2)), Ok(Value::I32(-3)));
+        assert_eq!(Value::I32(-7).rem(&Value::I32(2)), Ok(Value::I32(-1)));
+        assert_eq!(
+            Value::I32(1).div(&Value::I32(0)),
+            Err(ArithmeticError::Trap(Trap::DivisionByZero))
+        );
+        assert_eq!(
+            Value::I64(i64::MIN).div(&Value::I64(-1)),
+            Err(ArithmeticError::Trap(Trap::SignedIntegerOverflow))
+        );
+        assert_eq!(Value::I32(i32::MIN).rem(&Value::I32(-1)), Ok(Value::I32(0)));
+        assert_eq!(
+            Value::F64(F64::from_float(1.0)).div(&Value::F64(F64::from_float(0.0))),
+            Ok(Value::F64(F64::from_float(f64::INFINITY)))
+        );
+        assert_eq!(
+            Value::I32(1).add(&Value::I64(1)),
+            Err(ArithmeticError::TypeMismatch { lhs: "i32", rhs: "i64" })
+        );
+        assert_eq!(
+            Value::V128([0; 16]).add(&Value::V128([0; 16])),
+            Err(ArithmeticError::UnsupportedV128)
+        );
+        assert_eq!(
+            Value::I32(1).add(&Value::V128([0; 16])),
+            Err(ArithmeticError::UnsupportedV128)
+        );
+    }
+
+    #[test]
+    fn reinterpret() {
+        assert_eq!(
+            Value::I32(0x3fc0_0000).reinterpret(ValueType::F32),
+            Some(Value::from(1.5f32))
+        );
+        assert_eq!(
+            Value::from(-2.0f64).reinterpret(ValueType::I64),
+            Some(Value::I64(-0x4000_0000_0000_0000))
+        );
+        assert_eq!(Value::I32(7).reinterpret(ValueType::I32), Some(Value::I32(7)));
+        let nan = Value::F32(F32::from_bits(0x7fc0_0001));
+        assert_eq!(nan.reinterpret(ValueType::I32), Some(Value::I32(0x7fc0_0001)));
+        assert_eq!(Value::I32(1).reinterpret(ValueType::F64), None);
+        assert_eq!(Value::V128([0; 16]).reinterpret(ValueType::I64), None);
+    }
+}